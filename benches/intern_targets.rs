@@ -0,0 +1,95 @@
+//! Counts the allocations of logging from many targets to a channel, with and without
+//! `intern_targets`. Run with `cargo bench --bench intern_targets`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Receiver,
+    },
+    time::{Duration, Instant},
+};
+
+use log::LevelFilter;
+use logging::{LevelScope, LogEvent, LoggingBuilder};
+
+/// The global allocator, counting every allocation.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const TARGETS: [&str; 8] = [
+    "bench::db",
+    "bench::db::pool",
+    "bench::http",
+    "bench::http::router",
+    "bench::cache",
+    "bench::auth",
+    "bench::jobs",
+    "bench::metrics",
+];
+const RECORDS: usize = 200_000;
+
+/// Only the channel gets the records, so the terminal and the file don't count.
+fn builder(dir: &std::path::Path, intern: bool) -> (LoggingBuilder, Receiver<LogEvent>) {
+    LoggingBuilder::new()
+        .app_name("bench")
+        .qualifier("com")
+        .organization("org")
+        .log_dir(dir)
+        .global_level(LevelFilter::Info)
+        .level_for_scoped("bench", LevelFilter::Off, LevelScope::Terminal)
+        .level_for_scoped("bench", LevelFilter::Off, LevelScope::File)
+        .intern_targets(intern)
+        .channel_output()
+}
+
+/// Logs [`RECORDS`] records round-robin from [`TARGETS`], returning the allocations per
+/// record and the time taken.
+fn run(rx: &Receiver<LogEvent>) -> (f64, Duration) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..RECORDS {
+        log::info!(target: TARGETS[i % TARGETS.len()], "record {}", i);
+        if i % 512 == 0 {
+            rx.try_iter().for_each(drop);
+        }
+    }
+    rx.try_iter().for_each(drop);
+    let elapsed = start.elapsed();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    (allocations as f64 / RECORDS as f64, elapsed)
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("logging-bench-{}", std::process::id()));
+    // Warms up first, as the first run is slower whatever the setting.
+    let (warm_up, rx) = builder(&dir, true);
+    let mut handle = warm_up.finish().unwrap();
+    run(&rx);
+
+    for intern in [true, false] {
+        let (builder, rx) = builder(&dir, intern);
+        handle.reconfigure(builder).unwrap();
+        let (allocations, elapsed) = run(&rx);
+        println!("intern_targets({intern}): {allocations:.2} allocations/record, {elapsed:?}");
+    }
+
+    drop(handle);
+    let _ = std::fs::remove_dir_all(&dir);
+}
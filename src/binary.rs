@@ -0,0 +1,230 @@
+//! The [`LogFormat::Binary`](crate::LogFormat::Binary) record encoding and reading it back.
+//!
+//! Each record is a little-endian `u32` length of the rest of the record, followed by:
+//!
+//! - the level as one byte, `1` for `Error` through `5` for `Trace`
+//! - the time as an `i64` of milliseconds since the Unix epoch
+//! - the target as a `u16` length and UTF-8 bytes
+//! - the message as a `u32` length and UTF-8 bytes
+
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use chrono::{Local, TimeZone, Utc};
+use log::{Level, Log, Metadata, Record};
+
+use crate::LogEvent;
+
+/// Encodes `record`, timestamped now.
+pub(crate) fn encode(record: &Record) -> Vec<u8> {
+    encode_parts(
+        record.level(),
+        Utc::now().timestamp_millis(),
+        record.target(),
+        &record.args().to_string(),
+    )
+}
+
+/// The bytes of a record besides its target and message.
+const FIXED_LEN: usize = 1 + 8 + 2 + 4;
+
+fn encode_parts(level: Level, millis: i64, target: &str, message: &str) -> Vec<u8> {
+    let target = truncate(target, u16::MAX.into());
+    // So the length of the whole record fits its `u32` too.
+    let message = truncate(
+        message,
+        (u32::MAX as usize).saturating_sub(FIXED_LEN + target.len()),
+    );
+
+    let len = FIXED_LEN + target.len() + message.len();
+    let mut buf = Vec::with_capacity(4 + len);
+    buf.extend_from_slice(&u32::try_from(len).unwrap().to_le_bytes());
+    buf.push(level as u8);
+    buf.extend_from_slice(&millis.to_le_bytes());
+    buf.extend_from_slice(&u16::try_from(target.len()).unwrap().to_le_bytes());
+    buf.extend_from_slice(target.as_bytes());
+    buf.extend_from_slice(&u32::try_from(message.len()).unwrap().to_le_bytes());
+    buf.extend_from_slice(message.as_bytes());
+
+    buf
+}
+
+/// The longest start of `s` of at most `max` bytes that does not split a character.
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+/// Reads back a log file written with [`LogFormat::Binary`](crate::LogFormat::Binary), oldest
+/// record first.
+///
+/// The events have no module path, file or line, which the format does not store. Fails with
+/// [`io::ErrorKind::InvalidData`] if the file is not a binary log or its last record was cut
+/// short, e.g. by a crash.
+pub fn decode_binary_log(path: impl AsRef<Path>) -> io::Result<Vec<LogEvent>> {
+    decode(&fs::read(path)?)
+}
+
+fn decode(mut bytes: &[u8]) -> io::Result<Vec<LogEvent>> {
+    let mut events = vec![];
+    while !bytes.is_empty() {
+        let len = u32::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap()) as usize;
+        let mut record = take(&mut bytes, len)?;
+        events.push(decode_record(&mut record)?);
+    }
+
+    Ok(events)
+}
+
+fn decode_record(record: &mut &[u8]) -> io::Result<LogEvent> {
+    let level = match take(record, 1)?[0] {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        5 => Level::Trace,
+        other => return Err(invalid(format!("Invalid level {}", other))),
+    };
+    let millis = i64::from_le_bytes(take(record, 8)?.try_into().unwrap());
+    let time = Local
+        .timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| invalid(format!("Invalid time {}", millis)))?;
+    let len = u16::from_le_bytes(take(record, 2)?.try_into().unwrap());
+    let target = string(take(record, len.into())?)?;
+    let len = u32::from_le_bytes(take(record, 4)?.try_into().unwrap());
+    let message = string(take(record, len as usize)?)?;
+
+    Ok(LogEvent {
+        time,
+        level,
+        target: target.into(),
+        message,
+        module_path: None,
+        file: None,
+        line: None,
+        context: vec![],
+    })
+}
+
+/// Splits the first `n` bytes off `bytes`.
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(invalid("Truncated binary log record".to_string()));
+    }
+    let (head, rest) = bytes.split_at(n);
+    *bytes = rest;
+
+    Ok(head)
+}
+
+fn string(bytes: &[u8]) -> io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| invalid(e.to_string()))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes each record to `writer` in the binary encoding, flushing after each one with
+/// [`line_buffered`](crate::LoggingBuilder::line_buffered).
+pub(crate) struct BinaryWriter {
+    writer: Mutex<BufWriter<Box<dyn Write + Send>>>,
+    line_buffered: bool,
+}
+
+impl BinaryWriter {
+    pub(crate) fn new(writer: Box<dyn Write + Send>, line_buffered: bool) -> Self {
+        Self {
+            writer: Mutex::new(BufWriter::new(writer)),
+            line_buffered,
+        }
+    }
+}
+
+impl Log for BinaryWriter {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut result = writer.write_all(&encode(record));
+        if self.line_buffered {
+            result = result.and_then(|_| writer.flush());
+        }
+
+        if let Err(e) = result {
+            eprintln!("Error writing log record: {}", e);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use super::{decode, encode, encode_parts, truncate};
+
+    #[test]
+    fn round_trips() {
+        let mut bytes = encode(
+            &Record::builder()
+                .level(Level::Debug)
+                .target("app::net")
+                .args(format_args!("sent {} bytes\nand a newline", 42))
+                .build(),
+        );
+        bytes.extend(encode(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("logging::non_blocking")
+                .args(format_args!("Dropped 3 log lines"))
+                .build(),
+        ));
+
+        let events = decode(&bytes).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].level, Level::Debug);
+        assert_eq!(&*events[0].target, "app::net");
+        assert_eq!(events[0].message, "sent 42 bytes\nand a newline");
+        assert_eq!(events[1].level, Level::Warn);
+        assert_eq!(&*events[1].target, "logging::non_blocking");
+        assert!(events[0].time <= events[1].time);
+
+        let err = decode(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.to_string(), "Truncated binary log record");
+    }
+
+    #[test]
+    fn truncates_at_char_boundaries() {
+        assert_eq!(truncate("héllo", 2), "h");
+        assert_eq!(truncate("héllo", 3), "hé");
+        assert_eq!(truncate("héllo", 10), "héllo");
+
+        let target = "é".repeat(40_000);
+        let events = decode(&encode_parts(Level::Info, 0, &target, "hello")).unwrap();
+        assert_eq!(&*events[0].target, "é".repeat(32_767));
+        assert_eq!(events[0].message, "hello");
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// The git commit and build time of the application, for
+/// [`LoggingBuilder::build_info`](crate::LoggingBuilder::build_info).
+///
+/// Usually created with [`build_info!`](crate::build_info), but the fields can also be set
+/// from the application's own constants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub git_sha: Option<&'static str>,
+    pub build_timestamp: Option<&'static str>,
+}
+
+impl BuildInfo {
+    /// The fields that are set and not empty, as `(key, value)` pairs.
+    pub(crate) fn fields(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        [
+            ("git_sha", self.git_sha),
+            ("build_timestamp", self.build_timestamp),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.filter(|v| !v.is_empty())?)))
+    }
+}
+
+/// Renders as `git_sha=abc123, build_timestamp=...`, leaving out missing fields.
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.fields().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the calling crate's [`BuildInfo`] from environment variables set at compile time.
+///
+/// The git commit comes from `VERGEN_GIT_SHA`, `GIT_SHA`, `GIT_HASH` or `GITHUB_SHA`, and
+/// the build time from `VERGEN_BUILD_TIMESTAMP` or `BUILD_TIMESTAMP`, whichever is set first.
+/// Fields without a variable are left out.
+///
+/// ```
+/// let builder = logging::LoggingBuilder::new().build_info(logging::build_info!());
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo {
+            git_sha: ::core::option_env!("VERGEN_GIT_SHA")
+                .or(::core::option_env!("GIT_SHA"))
+                .or(::core::option_env!("GIT_HASH"))
+                .or(::core::option_env!("GITHUB_SHA")),
+            build_timestamp: ::core::option_env!("VERGEN_BUILD_TIMESTAMP")
+                .or(::core::option_env!("BUILD_TIMESTAMP")),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BuildInfo;
+
+    #[test]
+    fn omits_missing_fields() {
+        let info = BuildInfo {
+            git_sha: Some("abc123"),
+            build_timestamp: Some("2024-01-02T03:04:05Z"),
+        };
+        assert_eq!(
+            info.to_string(),
+            "git_sha=abc123, build_timestamp=2024-01-02T03:04:05Z"
+        );
+
+        let info = BuildInfo {
+            git_sha: Some(""),
+            build_timestamp: Some("2024-01-02T03:04:05Z"),
+        };
+        assert_eq!(info.to_string(), "build_timestamp=2024-01-02T03:04:05Z");
+        assert_eq!(BuildInfo::default().to_string(), "");
+
+        let _ = crate::build_info!();
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset, Local, Utc};
+
+/// Where the current time comes from, so tests can control file names and rotation.
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<FixedOffset>;
+}
+
+/// The real clock.
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        now(None)
+    }
+}
+
+/// Reads another clock in a fixed offset, for
+/// [`LoggingBuilder::fixed_offset`](crate::LoggingBuilder::fixed_offset).
+pub(crate) struct OffsetClock {
+    pub(crate) inner: Arc<dyn Clock>,
+    pub(crate) offset: FixedOffset,
+}
+
+impl Clock for OffsetClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        self.inner.now().with_timezone(&self.offset)
+    }
+}
+
+/// The current time in `offset`, or in the local time zone.
+pub(crate) fn now(offset: Option<FixedOffset>) -> DateTime<FixedOffset> {
+    match offset {
+        Some(offset) => Utc::now().with_timezone(&offset),
+        None => {
+            let now = Local::now();
+            now.with_timezone(now.offset())
+        }
+    }
+}
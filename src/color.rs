@@ -0,0 +1,194 @@
+//! Colored level tags on the terminal.
+
+use std::io::IsTerminal;
+
+use log::Level;
+
+/// The colors [`LoggingBuilder::color_scheme`](crate::LoggingBuilder::color_scheme) shows
+/// level tags in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScheme {
+    /// Red errors, yellow warnings, green info, blue debug and grey trace.
+    #[default]
+    Default,
+    /// The same hues from the Solarized palette, for terminals themed with it.
+    Solarized,
+    /// Bold, bright colors that stand out on light and dark backgrounds alike.
+    HighContrast,
+}
+
+impl ColorScheme {
+    /// The truecolor value and the 16-color SGR code for `level`.
+    fn color(self, level: Level) -> ((u8, u8, u8), &'static str) {
+        match (self, level) {
+            (Self::Default, Level::Error) => ((205, 49, 49), "31"),
+            (Self::Default, Level::Warn) => ((229, 229, 16), "33"),
+            (Self::Default, Level::Info) => ((13, 188, 121), "32"),
+            (Self::Default, Level::Debug) => ((36, 114, 200), "34"),
+            (Self::Default, Level::Trace) => ((128, 128, 128), "90"),
+            (Self::Solarized, Level::Error) => ((220, 50, 47), "31"),
+            (Self::Solarized, Level::Warn) => ((181, 137, 0), "33"),
+            (Self::Solarized, Level::Info) => ((133, 153, 0), "32"),
+            (Self::Solarized, Level::Debug) => ((38, 139, 210), "34"),
+            (Self::Solarized, Level::Trace) => ((88, 110, 117), "90"),
+            (Self::HighContrast, Level::Error) => ((255, 85, 85), "91"),
+            (Self::HighContrast, Level::Warn) => ((255, 255, 85), "93"),
+            (Self::HighContrast, Level::Info) => ((255, 255, 255), "97"),
+            (Self::HighContrast, Level::Debug) => ((85, 255, 255), "96"),
+            (Self::HighContrast, Level::Trace) => ((255, 85, 255), "95"),
+        }
+    }
+}
+
+/// How many colors the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColorDepth {
+    None,
+    Basic,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Detects the depth of stdout from the usual environment variables.
+    pub(crate) fn detect() -> Self {
+        Self::from_env(std::io::stdout().is_terminal(), |name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    /// `NO_COLOR` or `CLICOLOR=0` disable colors, `CLICOLOR_FORCE` enables them even when
+    /// not writing to a terminal, and `COLORTERM` and `TERM` tell the depth.
+    fn from_env(is_terminal: bool, var: impl Fn(&str) -> Option<String>) -> Self {
+        let set = |name| var(name).filter(|v| !v.is_empty());
+
+        if set("NO_COLOR").is_some() {
+            return Self::None;
+        }
+        let forced = set("CLICOLOR_FORCE").is_some_and(|v| v != "0");
+        let term = var("TERM").unwrap_or_default();
+        if !forced && (!is_terminal || set("CLICOLOR").as_deref() == Some("0") || term == "dumb") {
+            return Self::None;
+        }
+
+        match set("COLORTERM").as_deref() {
+            Some("truecolor" | "24bit") => Self::TrueColor,
+            _ if term.contains("256color") => Self::Ansi256,
+            _ => Self::Basic,
+        }
+    }
+}
+
+/// The escape sequences starting each level's color, computed once for a scheme and depth.
+#[derive(Debug, Clone)]
+pub(crate) struct Palette([String; 5]);
+
+impl Palette {
+    /// The palette for `scheme` at `depth`, or `None` without colors.
+    pub(crate) fn new(scheme: ColorScheme, depth: ColorDepth) -> Option<Self> {
+        let bold = if scheme == ColorScheme::HighContrast {
+            "1;"
+        } else {
+            ""
+        };
+        let code = |level| {
+            let ((r, g, b), basic) = scheme.color(level);
+            match depth {
+                ColorDepth::None => unreachable!(),
+                ColorDepth::Basic => format!("\x1b[{}{}m", bold, basic),
+                ColorDepth::Ansi256 => {
+                    let cube = |v: u8| (u16::from(v) * 5 + 127) / 255;
+                    let index = 16 + 36 * cube(r) + 6 * cube(g) + cube(b);
+                    format!("\x1b[{}38;5;{}m", bold, index)
+                }
+                ColorDepth::TrueColor => format!("\x1b[{}38;2;{};{};{}m", bold, r, g, b),
+            }
+        };
+
+        (depth != ColorDepth::None).then(|| {
+            Self(
+                [
+                    Level::Error,
+                    Level::Warn,
+                    Level::Info,
+                    Level::Debug,
+                    Level::Trace,
+                ]
+                .map(code),
+            )
+        })
+    }
+
+    /// The escape sequence starting `level`'s color. Colors end with [`RESET`].
+    pub(crate) fn start(&self, level: Level) -> &str {
+        &self.0[level as usize - 1]
+    }
+}
+
+pub(crate) const RESET: &str = "\x1b[0m";
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::{ColorDepth, ColorScheme, Palette};
+
+    #[test]
+    fn detects_depth() {
+        let detect = |is_terminal, vars: &[(&str, &str)]| {
+            ColorDepth::from_env(is_terminal, |name| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            })
+        };
+
+        assert_eq!(detect(false, &[]), ColorDepth::None);
+        assert_eq!(detect(true, &[]), ColorDepth::Basic);
+        assert_eq!(detect(true, &[("TERM", "dumb")]), ColorDepth::None);
+        assert_eq!(
+            detect(true, &[("TERM", "xterm-256color")]),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(
+            detect(
+                true,
+                &[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")]
+            ),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(detect(true, &[("NO_COLOR", "1")]), ColorDepth::None);
+        assert_eq!(detect(true, &[("NO_COLOR", "")]), ColorDepth::Basic);
+        assert_eq!(detect(true, &[("CLICOLOR", "0")]), ColorDepth::None);
+        assert_eq!(detect(false, &[("CLICOLOR_FORCE", "1")]), ColorDepth::Basic);
+        assert_eq!(detect(false, &[("CLICOLOR_FORCE", "0")]), ColorDepth::None);
+        assert_eq!(
+            detect(false, &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]),
+            ColorDepth::None
+        );
+    }
+
+    #[test]
+    fn palette_degrades() {
+        let start =
+            |scheme, depth| Palette::new(scheme, depth).map(|p| p.start(Level::Error).to_string());
+
+        assert_eq!(
+            start(ColorScheme::Solarized, ColorDepth::TrueColor).unwrap(),
+            "\x1b[38;2;220;50;47m"
+        );
+        assert_eq!(
+            start(ColorScheme::Solarized, ColorDepth::Ansi256).unwrap(),
+            "\x1b[38;5;167m"
+        );
+        assert_eq!(
+            start(ColorScheme::Solarized, ColorDepth::Basic).unwrap(),
+            "\x1b[31m"
+        );
+        assert_eq!(
+            start(ColorScheme::HighContrast, ColorDepth::Basic).unwrap(),
+            "\x1b[1;91m"
+        );
+        assert!(start(ColorScheme::Default, ColorDepth::None).is_none());
+    }
+}
@@ -0,0 +1,127 @@
+//! Fixed-width fields for the text file format.
+
+use std::fmt::{self, Write};
+
+/// Marks a field cut short to fit its column.
+const TRUNCATED: char = '~';
+
+/// Column widths for the fields of the text log file, for
+/// [`LoggingBuilder::columns`](crate::LoggingBuilder::columns).
+///
+/// Fields without a column are written as usual. Fields wider than their column are cut to
+/// fit, ending in `~`; narrower ones are padded with spaces. Widths count characters.
+///
+/// ```
+/// use logging::{Column, ColumnLayout};
+///
+/// // `[INFO]  2024-01-02_03-04-05 app::db          - hello`
+/// let layout = ColumnLayout::new()
+///     .level(Column::left(7))
+///     .target(Column::left(16));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ColumnLayout {
+    pub(crate) time: Option<Column>,
+    pub(crate) level: Option<Column>,
+    pub(crate) target: Option<Column>,
+}
+
+impl ColumnLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The column for the timestamp.
+    pub fn time(mut self, column: Column) -> Self {
+        self.time = Some(column);
+
+        self
+    }
+
+    /// The column for the level tag, including its brackets, e.g. `[INFO]`.
+    pub fn level(mut self, column: Column) -> Self {
+        self.level = Some(column);
+
+        self
+    }
+
+    /// The column for the target, before the ` - ` separating it from the message.
+    pub fn target(mut self, column: Column) -> Self {
+        self.target = Some(column);
+
+        self
+    }
+}
+
+/// The width and alignment of one field in a [`ColumnLayout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    width: usize,
+    right: bool,
+}
+
+impl Column {
+    /// A column `width` characters wide with the field at its start.
+    pub fn left(width: usize) -> Self {
+        Self {
+            width,
+            right: false,
+        }
+    }
+
+    /// A column `width` characters wide with the field at its end.
+    pub fn right(width: usize) -> Self {
+        Self { width, right: true }
+    }
+}
+
+/// `value` fitted to `column`, or as is without one.
+pub(crate) struct Fitted<T>(pub(crate) Option<Column>, pub(crate) T);
+
+impl<T: fmt::Display> fmt::Display for Fitted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let column = match self.0 {
+            Some(column) => column,
+            None => return write!(f, "{}", self.1),
+        };
+
+        let mut value = String::new();
+        let _ = write!(value, "{}", self.1);
+        let len = value.chars().count();
+
+        if len > column.width {
+            let keep = column.width.saturating_sub(1);
+            f.write_str(&value[..value.char_indices().nth(keep).map_or(0, |(i, _)| i)])?;
+            if column.width > 0 {
+                f.write_char(TRUNCATED)?;
+            }
+            return Ok(());
+        }
+
+        let padding = column.width - len;
+        if column.right {
+            write!(f, "{:padding$}{}", "", value)
+        } else {
+            write!(f, "{}{:padding$}", value, "")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Column, Fitted};
+
+    #[test]
+    fn fits_fields() {
+        let fit = |column, value| Fitted(Some(column), value).to_string();
+
+        assert_eq!(fit(Column::left(7), "[INFO]"), "[INFO] ");
+        assert_eq!(fit(Column::right(7), "[INFO]"), " [INFO]");
+        assert_eq!(fit(Column::left(6), "[INFO]"), "[INFO]");
+        assert_eq!(fit(Column::left(8), "app::database"), "app::da~");
+        assert_eq!(fit(Column::right(4), "äöüßé"), "äöü~");
+        assert_eq!(fit(Column::left(1), "app"), "~");
+        assert_eq!(fit(Column::left(0), "app"), "");
+        assert_eq!(Fitted(None, "app").to_string(), "app");
+    }
+}
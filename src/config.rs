@@ -0,0 +1,581 @@
+//! Turns builder settings into plain decisions, without touching fern or the filesystem.
+
+use std::{fmt, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use log::LevelFilter;
+
+use crate::{
+    color::{ColorDepth, Palette},
+    columns::ColumnLayout,
+    format::{
+        is_gelf_key, is_logfmt_key, write_logfmt_pair, GELF_RESERVED_KEYS, JSON_RESERVED_KEYS,
+        LOGFMT_RESERVED_KEYS,
+    },
+    levels::{Levels, Sink},
+    rotation, FileMode, FormatFn, LogFormat, LoggingBuilder, LoggingError, LOGGER_VERSION_KEY,
+    SCHEMA_KEY, SESSION_KEY,
+};
+
+/// How a sink renders records.
+#[derive(Clone)]
+pub(crate) enum LineFormat {
+    /// The built-in text format with the given parts enabled.
+    Text {
+        show_level: bool,
+        show_time: bool,
+        show_target: bool,
+        /// Colors for the level tag, if the terminal shows them.
+        colors: Option<Palette>,
+        columns: ColumnLayout,
+    },
+    Json,
+    Logfmt,
+    Gelf,
+    /// The [`binary`](crate::binary) encoding. Outputs that cannot take it use the text file
+    /// format instead.
+    Binary,
+    /// The Common or Combined Log Format for access records, `text` for the others.
+    Access {
+        combined: bool,
+        text: Box<LineFormat>,
+    },
+    Custom(Arc<FormatFn>),
+}
+
+impl LineFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            LineFormat::Text { .. } => "text",
+            LineFormat::Json => "json",
+            LineFormat::Logfmt => "logfmt",
+            LineFormat::Gelf => "gelf",
+            LineFormat::Binary => "binary",
+            LineFormat::Access {
+                combined: false, ..
+            } => "common_log",
+            LineFormat::Access { combined: true, .. } => "combined_log",
+            LineFormat::Custom(_) => "custom",
+        }
+    }
+}
+
+/// Everything [`LoggingBuilder::finish`] decides before building dispatches and opening
+/// files.
+pub(crate) struct Config {
+    pub(crate) levels: Levels,
+    pub(crate) terminal: LineFormat,
+    pub(crate) file: LineFormat,
+    /// The validated, possibly sanitized, log file name format.
+    pub(crate) filename_format: String,
+    pub(crate) file_mode: FileMode,
+    /// The resolved TCP endpoint and the level sent to it.
+    pub(crate) tcp: Option<(Vec<SocketAddr>, LevelFilter)>,
+}
+
+/// How logging was set up, for [`LoggingBuilder::log_effective_config`]. Displays as
+/// logfmt pairs, each after a space.
+pub(crate) struct Effective<'a> {
+    pub(crate) config: &'a Config,
+    pub(crate) log_dir: Option<&'a Path>,
+    pub(crate) max_log_files: usize,
+    pub(crate) max_log_age: Option<Duration>,
+    /// The outputs records are written to.
+    pub(crate) sinks: &'a [&'static str],
+}
+
+impl fmt::Display for Effective<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = self.config;
+        let log_dir = match self.log_dir {
+            Some(dir) => dir.display().to_string(),
+            None => "none".to_string(),
+        };
+        let max_log_age = match self.max_log_age {
+            Some(age) => age.as_secs().to_string(),
+            None => "none".to_string(),
+        };
+
+        write_logfmt_pair(f, "log_dir", format_args!("{}", log_dir))?;
+        write_logfmt_pair(f, "file_mode", format_args!("{:?}", config.file_mode))?;
+        let global = config.levels.global().as_str().to_ascii_lowercase();
+        write_logfmt_pair(f, "global_level", format_args!("{}", global))?;
+        write_logfmt_pair(f, "levels", format_args!("{}", config.levels.directives()))?;
+        write_logfmt_pair(
+            f,
+            "terminal_format",
+            format_args!("{}", config.terminal.name()),
+        )?;
+        write_logfmt_pair(f, "file_format", format_args!("{}", config.file.name()))?;
+        write_logfmt_pair(f, "max_log_files", format_args!("{}", self.max_log_files))?;
+        write_logfmt_pair(f, "max_log_age_secs", format_args!("{}", max_log_age))?;
+        write_logfmt_pair(f, "sinks", format_args!("{}", self.sinks.join(",")))
+    }
+}
+
+/// Validates the builder settings and resolves them into a [`Config`].
+pub(crate) fn resolve(builder: &LoggingBuilder) -> anyhow::Result<Config> {
+    let names = [
+        ("app_name", &builder.app_name),
+        ("qualifier", &builder.qualifier),
+        ("organization", &builder.organization),
+    ]
+    .into_iter()
+    .filter(|(_, value)| value.is_empty())
+    .map(|(name, _)| name)
+    .collect::<Vec<_>>();
+    if !names.is_empty() {
+        return Err(LoggingError::MissingField { names }.into());
+    }
+    if builder.max_log_files == 0 {
+        anyhow::bail!("max_log_files must be at least 1")
+    }
+    if builder.max_crash_files == 0 {
+        anyhow::bail!("max_crash_files must be at least 1")
+    }
+    if builder.queue_capacity == 0 {
+        anyhow::bail!("queue_capacity must be at least 1")
+    }
+    if builder.rate_limit == Some(0) {
+        anyhow::bail!("rate_limit must be at least 1")
+    }
+    if builder.heartbeat.is_some_and(|interval| interval.is_zero()) {
+        anyhow::bail!("heartbeat interval must not be zero")
+    }
+    if builder
+        .resource_stats
+        .is_some_and(|interval| interval.is_zero())
+    {
+        anyhow::bail!("resource_stats interval must not be zero")
+    }
+    if builder.max_lines == Some(0) {
+        anyhow::bail!("max_lines must be at least 1")
+    }
+    if builder.max_memory == Some(0) {
+        anyhow::bail!("max_memory must be at least 1")
+    }
+
+    if let Some(e) = &builder.early_error {
+        anyhow::bail!("Unable to capture early logs: {}", e);
+    }
+
+    if let Some(e) = &builder.directives_error {
+        anyhow::bail!("Invalid level directives: {}", e);
+    }
+
+    if let Some((key, _)) = builder
+        .json_fields
+        .iter()
+        .find(|(key, _)| JSON_RESERVED_KEYS.contains(&key.as_str()))
+    {
+        anyhow::bail!("json_field key {:?} is reserved", key);
+    }
+    let formats = [builder.terminal_log_format(), builder.file_log_format()];
+    if formats.contains(&LogFormat::Logfmt) {
+        if let Some((key, _)) = builder
+            .json_fields
+            .iter()
+            .find(|(key, _)| LOGFMT_RESERVED_KEYS.contains(&key.as_str()))
+        {
+            anyhow::bail!("json_field key {:?} is reserved", key);
+        }
+        if let Some((key, _)) = builder
+            .json_fields
+            .iter()
+            .find(|(key, _)| !is_logfmt_key(key))
+        {
+            anyhow::bail!("json_field key {:?} is not a valid logfmt key", key);
+        }
+    }
+    if formats.contains(&LogFormat::Gelf) {
+        let gelf_key = |key: &str| key.strip_prefix('_').unwrap_or(key).to_string();
+        if let Some((key, _)) = builder
+            .json_fields
+            .iter()
+            .find(|(key, _)| GELF_RESERVED_KEYS.contains(&gelf_key(key).as_str()))
+        {
+            anyhow::bail!("json_field key {:?} is reserved", key);
+        }
+        if let Some((key, _)) = builder
+            .json_fields
+            .iter()
+            .find(|(key, _)| !is_gelf_key(&gelf_key(key)))
+        {
+            anyhow::bail!("json_field key {:?} is not a valid GELF field name", key);
+        }
+    }
+    if builder.show_logger_version
+        && builder
+            .json_fields
+            .iter()
+            .any(|(key, _)| key == LOGGER_VERSION_KEY)
+    {
+        anyhow::bail!(
+            "json_field key {:?} is reserved by show_logger_version",
+            LOGGER_VERSION_KEY
+        );
+    }
+    if builder.session_label.is_some()
+        && builder
+            .json_fields
+            .iter()
+            .any(|(key, _)| key == SESSION_KEY)
+    {
+        anyhow::bail!(
+            "json_field key {:?} is reserved by session_label",
+            SESSION_KEY
+        );
+    }
+    if builder.schema_version.is_some()
+        && builder.json_fields.iter().any(|(key, _)| key == SCHEMA_KEY)
+    {
+        anyhow::bail!(
+            "json_field key {:?} is reserved by schema_version",
+            SCHEMA_KEY
+        );
+    }
+
+    if let (Some((hours, minutes)), None) = (builder.fixed_offset, builder.offset()) {
+        anyhow::bail!("fixed_offset({}, {}) is not a valid offset", hours, minutes);
+    }
+
+    let mut filename_format = builder.filename_format.clone();
+    if builder.filename_microseconds && !filename_format.ends_with(rotation::MICROS_SUFFIX) {
+        filename_format.push_str(rotation::MICROS_SUFFIX);
+    }
+    let filename_format = rotation::filename_format(
+        &filename_format,
+        builder.sanitize_filenames,
+        &*builder.file_clock(),
+    )?;
+
+    let tcp = match &builder.tcp_output {
+        Some((Ok(addrs), _)) if addrs.is_empty() => {
+            anyhow::bail!("tcp_output address resolved to nothing")
+        }
+        Some((Ok(addrs), level)) => Some((addrs.clone(), *level)),
+        Some((Err(e), _)) => anyhow::bail!("Unable to resolve tcp_output address: {}", e),
+        None => None,
+    };
+
+    if builder.capture_stdout && !cfg!(unix) {
+        anyhow::bail!("capture_stdout is only supported on Unix");
+    }
+    #[cfg(feature = "android")]
+    if builder.logcat_output.is_some() && !cfg!(target_os = "android") {
+        anyhow::bail!("logcat_output is only supported on Android");
+    }
+    if builder.unix_socket_output.is_some() && !cfg!(unix) {
+        anyhow::bail!("unix_socket_output is only supported on Unix");
+    }
+    if builder.reopen_on_sighup && !cfg!(unix) {
+        anyhow::bail!("reopen_on_sighup is only supported on Unix");
+    }
+    if builder.capture_stdout && builder.file_log_format() == LogFormat::Binary {
+        anyhow::bail!("capture_stdout cannot be used with LogFormat::Binary");
+    }
+    if builder.dump_on_error > 0 && builder.file_log_format() == LogFormat::Binary {
+        anyhow::bail!("dump_on_error cannot be used with LogFormat::Binary");
+    }
+
+    let file_mode = file_mode(builder)?;
+
+    let mut level_for = builder.level_for.clone();
+    if builder.quiet_dependencies {
+        let level = LevelFilter::Warn.min(builder.global_level);
+        for target in &builder.quiet_targets {
+            level_for.entry(target.clone()).or_insert(level);
+        }
+    }
+
+    Ok(Config {
+        levels: Levels::new(builder.global_level, level_for)
+            .with_sink(Sink::Terminal, builder.terminal_level_for.clone())
+            .with_sink(Sink::File, builder.file_level_for.clone())
+            .with_resolver(builder.level_resolver.clone())
+            .with_suppressed(builder.dump_on_error > 0),
+        terminal: terminal_format(builder),
+        file: file_format(builder),
+        filename_format,
+        file_mode,
+        tcp,
+    })
+}
+
+/// The explicit [`FileMode`] or the one from the environment, whichever takes precedence.
+fn file_mode(builder: &LoggingBuilder) -> anyhow::Result<FileMode> {
+    if let (Some(mode), false) = (builder.file_mode, builder.env_override) {
+        return Ok(mode);
+    }
+
+    let var = builder.file_mode_env.clone().unwrap_or_else(|| {
+        format!(
+            "{}_LOG_MODE",
+            builder.app_name.to_uppercase().replace('-', "_")
+        )
+    });
+
+    match std::env::var(&var) {
+        Ok(value) => value.parse().with_context(|| format!("Invalid {}", var)),
+        Err(_) => Ok(builder.file_mode.unwrap_or_default()),
+    }
+}
+
+/// How the terminal renders records.
+pub(crate) fn terminal_format(builder: &LoggingBuilder) -> LineFormat {
+    match (builder.terminal_log_format(), &builder.custom_format) {
+        (LogFormat::Json, _) => LineFormat::Json,
+        (LogFormat::Logfmt, _) => LineFormat::Logfmt,
+        (LogFormat::Gelf, _) => LineFormat::Gelf,
+        (format @ (LogFormat::CommonLog | LogFormat::CombinedLog), _) => LineFormat::Access {
+            combined: format == LogFormat::CombinedLog,
+            text: Box::new(terminal_text(builder)),
+        },
+        (LogFormat::Text | LogFormat::Binary, Some(format)) => LineFormat::Custom(format.clone()),
+        (LogFormat::Text | LogFormat::Binary, None) => terminal_text(builder),
+    }
+}
+
+fn terminal_text(builder: &LoggingBuilder) -> LineFormat {
+    LineFormat::Text {
+        show_level: builder.terminal_show_level,
+        show_time: builder.terminal_timestamp,
+        show_target: builder.terminal_show_target,
+        colors: builder
+            .color_scheme
+            .and_then(|scheme| Palette::new(scheme, ColorDepth::detect())),
+        columns: ColumnLayout::default(),
+    }
+}
+
+/// How the log file, and anything mirroring it, renders records.
+pub(crate) fn file_format(builder: &LoggingBuilder) -> LineFormat {
+    match (builder.file_log_format(), &builder.custom_format) {
+        (LogFormat::Json, _) => LineFormat::Json,
+        (LogFormat::Logfmt, _) => LineFormat::Logfmt,
+        (LogFormat::Gelf, _) => LineFormat::Gelf,
+        (LogFormat::Binary, _) => LineFormat::Binary,
+        (format @ (LogFormat::CommonLog | LogFormat::CombinedLog), _) => LineFormat::Access {
+            combined: format == LogFormat::CombinedLog,
+            text: Box::new(file_text(builder)),
+        },
+        (LogFormat::Text, Some(format)) => LineFormat::Custom(format.clone()),
+        (LogFormat::Text, None) => file_text(builder),
+    }
+}
+
+fn file_text(builder: &LoggingBuilder) -> LineFormat {
+    LineFormat::Text {
+        show_level: true,
+        show_time: true,
+        show_target: true,
+        colors: None,
+        columns: builder.columns.unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter};
+
+    use super::{resolve, LineFormat};
+    use crate::{log_format, FileMode, LogFormat, LoggingBuilder, LoggingError};
+
+    fn builder() -> LoggingBuilder {
+        LoggingBuilder::new()
+            .app_name("app")
+            .qualifier("com")
+            .organization("org")
+    }
+
+    #[test]
+    fn validation() {
+        assert!(resolve(&LoggingBuilder::new()).is_err());
+        assert!(resolve(&builder().max_log_files(0)).is_err());
+        assert!(resolve(&builder().queue_capacity(0)).is_err());
+        assert!(resolve(&builder().rate_limit(0)).is_err());
+        assert!(resolve(&builder().filename_format("%Y-%m-%d")).is_err());
+        assert!(resolve(&builder().json_field("service", "app")).is_ok());
+        assert!(resolve(&builder().json_field("message", "app")).is_err());
+        let logfmt = || builder().format(LogFormat::Logfmt);
+        assert!(resolve(&logfmt().json_field("service", "my app")).is_ok());
+        assert!(resolve(&logfmt().json_field("msg", "app")).is_err());
+        assert!(resolve(&logfmt().json_field("my service", "app")).is_err());
+        let gelf = || builder().format(LogFormat::Gelf);
+        assert!(resolve(&gelf().json_field("service.name", "app")).is_ok());
+        assert!(resolve(&gelf().json_field("_id", "app")).is_err());
+        assert!(resolve(&gelf().json_field("my service", "app")).is_err());
+        assert!(resolve(&gelf().show_logger_version(true)).is_ok());
+        assert!(resolve(&builder()).is_ok());
+    }
+
+    #[test]
+    fn missing_fields() {
+        for mask in 0..8 {
+            let mut builder = LoggingBuilder::new();
+            let mut expected = vec![];
+            for (bit, name) in ["app_name", "qualifier", "organization"]
+                .into_iter()
+                .enumerate()
+            {
+                if mask & (1 << bit) != 0 {
+                    builder = match name {
+                        "app_name" => builder.app_name("app"),
+                        "qualifier" => builder.qualifier("com"),
+                        _ => builder.organization("org"),
+                    };
+                } else {
+                    expected.push(name);
+                }
+            }
+
+            match resolve(&builder) {
+                Ok(_) => assert!(expected.is_empty()),
+                Err(e) => assert_eq!(
+                    e.downcast_ref::<LoggingError>(),
+                    Some(&LoggingError::MissingField { names: expected })
+                ),
+            }
+        }
+
+        let err = resolve(&LoggingBuilder::new().app_name("app"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Missing required fields: qualifier, organization"
+        );
+    }
+
+    #[test]
+    fn level_directives() {
+        let config = resolve(&builder().levels("warn,hyper=error,app::net")).unwrap();
+        assert_eq!(config.levels.level_for("other"), LevelFilter::Warn);
+        assert_eq!(config.levels.level_for("hyper::client"), LevelFilter::Error);
+        assert_eq!(config.levels.level_for("app::net::tcp"), LevelFilter::Trace);
+
+        let err = resolve(&builder().levels("info,app=loud").levels("debug"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid level directives: Invalid level "loud" in directive "app=loud""#
+        );
+    }
+
+    #[test]
+    fn levels() {
+        let config = resolve(
+            &builder()
+                .global_level(LevelFilter::Warn)
+                .level_for("hyper", LevelFilter::Error)
+                .level_for("app::net", LevelFilter::Trace),
+        )
+        .unwrap();
+
+        assert_eq!(config.levels.level_for("other"), LevelFilter::Warn);
+        assert_eq!(config.levels.level_for("hyper::client"), LevelFilter::Error);
+        assert_eq!(config.levels.level_for("app::net::tcp"), LevelFilter::Trace);
+
+        let config = resolve(
+            &builder()
+                .global_level_from(Level::Warn)
+                .level_for_from("app::net", Level::Trace),
+        )
+        .unwrap();
+        assert_eq!(config.levels.level_for("other"), LevelFilter::Warn);
+        assert_eq!(config.levels.level_for("app::net::tcp"), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn quiet_dependencies() {
+        let config = resolve(
+            &builder()
+                .global_level(LevelFilter::Debug)
+                .level_for("reqwest", LevelFilter::Trace)
+                .quiet_dependencies(true)
+                .quiet_dependency("sqlx"),
+        )
+        .unwrap();
+        assert_eq!(config.levels.level_for("hyper::proto"), LevelFilter::Warn);
+        assert_eq!(config.levels.level_for("sqlx"), LevelFilter::Warn);
+        assert_eq!(config.levels.level_for("reqwest"), LevelFilter::Trace);
+        assert_eq!(config.levels.level_for("app"), LevelFilter::Debug);
+
+        let config = resolve(
+            &builder()
+                .global_level(LevelFilter::Error)
+                .quiet_dependencies(true),
+        )
+        .unwrap();
+        assert_eq!(config.levels.level_for("tokio"), LevelFilter::Error);
+
+        let config = resolve(&builder().quiet_dependency("sqlx")).unwrap();
+        assert_eq!(config.levels.level_for("sqlx"), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn file_mode_env() {
+        let mode = |builder: LoggingBuilder| resolve(&builder).map(|config| config.file_mode);
+        let var = "LOGGING_TEST_FILE_MODE_ENV";
+        let with_env = || builder().file_mode_env(var);
+
+        assert_eq!(mode(with_env()).unwrap(), FileMode::Rotate);
+
+        std::env::set_var(var, "append");
+        assert_eq!(mode(with_env()).unwrap(), FileMode::Append);
+        assert_eq!(
+            mode(with_env().file_mode(FileMode::Single)).unwrap(),
+            FileMode::Single
+        );
+        assert_eq!(
+            mode(with_env().file_mode(FileMode::Single).env_override(true)).unwrap(),
+            FileMode::Append
+        );
+
+        std::env::set_var(var, "sometimes");
+        let err = mode(with_env()).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Invalid LOGGING_TEST_FILE_MODE_ENV: "));
+        assert!(mode(with_env().file_mode(FileMode::Single)).is_ok());
+
+        std::env::remove_var(var);
+    }
+
+    #[test]
+    fn format_selection() {
+        let config = resolve(&builder().terminal_show_target(false)).unwrap();
+        assert!(matches!(
+            config.terminal,
+            LineFormat::Text {
+                show_level: true,
+                show_time: false,
+                show_target: false,
+                colors: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            config.file,
+            LineFormat::Text {
+                show_level: true,
+                show_time: true,
+                show_target: true,
+                colors: None,
+                ..
+            }
+        ));
+
+        let config = resolve(&builder().custom_format(log_format!("{msg}"))).unwrap();
+        assert!(matches!(config.terminal, LineFormat::Custom(_)));
+        assert!(matches!(config.file, LineFormat::Custom(_)));
+
+        let config = resolve(
+            &builder()
+                .custom_format(log_format!("{msg}"))
+                .format(LogFormat::Json),
+        )
+        .unwrap();
+        assert!(matches!(config.terminal, LineFormat::Json));
+        assert!(matches!(config.file, LineFormat::Json));
+    }
+}
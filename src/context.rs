@@ -0,0 +1,88 @@
+//! Per-thread fields for [`LoggingHandle::push_context`](crate::LoggingHandle::push_context).
+
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+};
+
+thread_local! {
+    /// The fields pushed on this thread, oldest first, with the id of the guard owning each.
+    static CONTEXT: RefCell<Vec<(u64, String, String)>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Removes its field from the thread's context when dropped, from
+/// [`LoggingHandle::push_context`](crate::LoggingHandle::push_context).
+///
+/// Guards stay on the thread that pushed them, so they are not `Send`.
+#[must_use = "the field is removed as soon as the guard is dropped"]
+pub struct ContextGuard {
+    id: u64,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let _ = CONTEXT.try_with(|context| context.borrow_mut().retain(|(id, ..)| *id != self.id));
+    }
+}
+
+/// Adds `key=value` to the context of the current thread until the guard is dropped.
+pub(crate) fn push(key: String, value: String) -> ContextGuard {
+    let id = NEXT_ID.with(|next| {
+        next.set(next.get() + 1);
+        next.get()
+    });
+    CONTEXT.with(|context| context.borrow_mut().push((id, key, value)));
+
+    ContextGuard {
+        id,
+        _thread: PhantomData,
+    }
+}
+
+/// Calls `f` with each field of the current thread's context, oldest first.
+pub(crate) fn for_each(mut f: impl FnMut(&str, &str)) {
+    let _ = CONTEXT.try_with(|context| {
+        for (_, key, value) in context.borrow().iter() {
+            f(key, value);
+        }
+    });
+}
+
+/// Whether the current thread has any context fields.
+pub(crate) fn is_empty() -> bool {
+    CONTEXT
+        .try_with(|context| context.borrow().is_empty())
+        .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{for_each, push};
+
+    fn fields() -> Vec<String> {
+        let mut fields = vec![];
+        for_each(|key, value| fields.push(format!("{}={}", key, value)));
+        fields
+    }
+
+    #[test]
+    fn nests_and_pops() {
+        let request = push("req_id".to_string(), "42".to_string());
+        {
+            let _user = push("user".to_string(), "bob".to_string());
+            assert_eq!(fields(), ["req_id=42", "user=bob"]);
+
+            let other = std::thread::spawn(fields).join().unwrap();
+            assert!(other.is_empty());
+        }
+        assert_eq!(fields(), ["req_id=42"]);
+
+        let step = push("step".to_string(), "1".to_string());
+        drop(request);
+        assert_eq!(fields(), ["step=1"]);
+        drop(step);
+        assert!(super::is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+use std::{path::Path, process::Command};
+
+use anyhow::Context;
+
+/// The program that opens a directory in the platform's file manager.
+#[cfg(windows)]
+const FILE_MANAGER: &str = "explorer";
+#[cfg(target_os = "macos")]
+const FILE_MANAGER: &str = "open";
+#[cfg(not(any(windows, target_os = "macos")))]
+const FILE_MANAGER: &str = "xdg-open";
+
+/// Opens `dir` in the platform's file manager.
+pub(crate) fn open_dir(dir: &Path) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Log directory {} does not exist", dir.display());
+    }
+
+    let mut command = Command::new(FILE_MANAGER);
+    command.arg(dir);
+
+    // `explorer` exits with an error even when it succeeds, so it is only spawned.
+    if cfg!(windows) {
+        command
+            .spawn()
+            .with_context(|| format!("Unable to run {}", FILE_MANAGER))?;
+
+        return Ok(());
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("Unable to run {}", FILE_MANAGER))?;
+    if !status.success() {
+        anyhow::bail!(
+            "{} could not open {}: {}",
+            FILE_MANAGER,
+            dir.display(),
+            status
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::open_dir;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn missing_dir() {
+        let dir = temp_dir("open-missing-dir");
+
+        let err = open_dir(&dir.join("logs")).unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
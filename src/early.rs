@@ -0,0 +1,358 @@
+//! Buffering records logged before [`LoggingBuilder::finish`](crate::LoggingBuilder::finish)
+//! installs the real logger.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::intern::Targets;
+
+/// How many early records are kept. Older ones are dropped once it is reached.
+const CAPACITY: usize = 1024;
+
+/// The proxy installed as the global logger by [`install`].
+static PROXY: Proxy = Proxy::new();
+/// Whether [`PROXY`] is the global logger.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the capturing proxy as the global logger, unless it already is, keeping at most
+/// `max_bytes` of records.
+pub(crate) fn install(max_bytes: Option<usize>) -> Result<(), log::SetLoggerError> {
+    if INSTALLED.load(Ordering::Acquire) {
+        return Ok(());
+    }
+
+    PROXY.limit(max_bytes.unwrap_or(usize::MAX));
+    log::set_logger(&PROXY)?;
+    log::set_max_level(LevelFilter::Trace);
+    INSTALLED.store(true, Ordering::Release);
+
+    Ok(())
+}
+
+/// Caps the captured records at `max_bytes`, dropping the oldest already over it. Does
+/// nothing unless [`install`] succeeded and the records were not replayed yet.
+pub(crate) fn limit(max_bytes: usize) {
+    if installed() {
+        PROXY.limit(max_bytes);
+    }
+}
+
+/// Whether [`install`] succeeded, so the real logger must go through [`forward`].
+pub(crate) fn installed() -> bool {
+    INSTALLED.load(Ordering::Acquire)
+}
+
+/// Replays the captured records into `logger` and passes all later ones to it.
+pub(crate) fn forward(logger: Box<dyn Log>) -> anyhow::Result<()> {
+    PROXY.forward(logger)
+}
+
+/// An owned copy of a captured record.
+struct Captured {
+    level: Level,
+    /// Shared by the records with the same target.
+    target: Arc<str>,
+    message: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+}
+
+impl Captured {
+    /// The bytes counted against the limit. Targets are shared, so they are left out.
+    fn size(&self) -> usize {
+        self.message.len()
+            + self.module_path.as_ref().map_or(0, String::len)
+            + self.file.as_ref().map_or(0, String::len)
+    }
+}
+
+enum State {
+    Capturing {
+        records: VecDeque<Captured>,
+        dropped: u64,
+        /// The [`Captured::size`] of `records`, and its limit.
+        bytes: usize,
+        max_bytes: usize,
+        /// Shares the targets of `records`, so each is only copied once.
+        targets: Targets,
+    },
+    Forwarding(Box<dyn Log>),
+}
+
+/// A global logger that buffers records until the real one is ready.
+struct Proxy(RwLock<State>);
+
+impl Proxy {
+    const fn new() -> Self {
+        Self(RwLock::new(State::Capturing {
+            records: VecDeque::new(),
+            dropped: 0,
+            bytes: 0,
+            max_bytes: usize::MAX,
+            targets: Targets::new(true),
+        }))
+    }
+
+    fn limit(&self, limit: usize) {
+        if let State::Capturing {
+            records,
+            dropped,
+            bytes,
+            max_bytes,
+            ..
+        } = &mut *self.0.write().unwrap_or_else(|e| e.into_inner())
+        {
+            *max_bytes = limit;
+            while *bytes > limit {
+                if let Some(oldest) = records.pop_front() {
+                    *bytes -= oldest.size();
+                    *dropped += 1;
+                }
+            }
+        }
+    }
+
+    fn forward(&self, logger: Box<dyn Log>) -> anyhow::Result<()> {
+        let mut state = self.0.write().unwrap_or_else(|e| e.into_inner());
+        let (records, dropped) = match &mut *state {
+            State::Capturing {
+                records, dropped, ..
+            } => (std::mem::take(records), *dropped),
+            State::Forwarding(_) => anyhow::bail!("Logging is already initialized"),
+        };
+
+        for captured in &records {
+            logger.log(
+                &Record::builder()
+                    .level(captured.level)
+                    .target(&captured.target)
+                    .args(format_args!("{}", captured.message))
+                    .module_path(captured.module_path.as_deref())
+                    .file(captured.file.as_deref())
+                    .line(captured.line)
+                    .build(),
+            );
+        }
+        if dropped > 0 {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .args(format_args!(
+                        "Dropped {} log records from before logging was set up",
+                        dropped
+                    ))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        *state = State::Forwarding(logger);
+
+        Ok(())
+    }
+}
+
+impl Log for Proxy {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match &*self.0.read().unwrap_or_else(|e| e.into_inner()) {
+            State::Capturing { .. } => true,
+            State::Forwarding(logger) => logger.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if let State::Forwarding(logger) = &*self.0.read().unwrap_or_else(|e| e.into_inner()) {
+            return logger.log(record);
+        }
+
+        let mut state = self.0.write().unwrap_or_else(|e| e.into_inner());
+        match &mut *state {
+            State::Capturing {
+                records,
+                dropped,
+                bytes,
+                max_bytes,
+                targets,
+            } => {
+                let captured = Captured {
+                    level: record.level(),
+                    target: targets.get(record.target()),
+                    message: record.args().to_string(),
+                    module_path: record.module_path().map(str::to_string),
+                    file: record.file().map(str::to_string),
+                    line: record.line(),
+                };
+
+                let size = captured.size();
+                if size > *max_bytes {
+                    *dropped += 1;
+                    return;
+                }
+                while records.len() >= CAPACITY || *bytes + size > *max_bytes {
+                    if let Some(oldest) = records.pop_front() {
+                        *bytes -= oldest.size();
+                        *dropped += 1;
+                    }
+                }
+                *bytes += size;
+                records.push_back(captured);
+            }
+            // Forwarding started while waiting for the lock.
+            State::Forwarding(logger) => logger.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        if let State::Forwarding(logger) = &*self.0.read().unwrap_or_else(|e| e.into_inner()) {
+            logger.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use fern::Dispatch;
+    use log::{Level, Log, Record};
+
+    use super::{Proxy, State, CAPACITY};
+
+    fn log(proxy: &Proxy, level: Level, message: &str) {
+        proxy.log(
+            &Record::builder()
+                .level(level)
+                .target("app")
+                .line(Some(7))
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn replays_then_forwards() {
+        let proxy = Proxy::new();
+        log(&proxy, Level::Debug, "loading config");
+        log(&proxy, Level::Info, "config loaded");
+
+        let (tx, rx) = channel();
+        let (_, logger) = Dispatch::new()
+            .format(|out, message, record| {
+                out.finish(format_args!(
+                    "{} {}:{} {}",
+                    record.level(),
+                    record.target(),
+                    record.line().unwrap_or_default(),
+                    message
+                ))
+            })
+            .level(log::LevelFilter::Info)
+            .chain(tx)
+            .into_log();
+        proxy.forward(logger).unwrap();
+        log(&proxy, Level::Warn, "running");
+
+        let lines = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            ["INFO app:7 config loaded\n", "WARN app:7 running\n"]
+        );
+
+        let (_, logger) = Dispatch::new().into_log();
+        assert!(proxy.forward(logger).is_err());
+    }
+
+    #[test]
+    fn targets_are_shared() {
+        let proxy = Proxy::new();
+        log(&proxy, Level::Info, "one");
+        log(&proxy, Level::Info, "two");
+
+        let state = proxy.0.read().unwrap();
+        match &*state {
+            State::Capturing { records, .. } => {
+                assert!(std::sync::Arc::ptr_eq(
+                    &records[0].target,
+                    &records[1].target
+                ));
+            }
+            State::Forwarding(_) => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn capture_is_bounded() {
+        let proxy = Proxy::new();
+        for i in 0..CAPACITY + 2 {
+            log(&proxy, Level::Info, &i.to_string());
+        }
+
+        let (tx, rx) = channel();
+        let (_, logger) = Dispatch::new().chain(tx).into_log();
+        proxy.forward(logger).unwrap();
+
+        let lines = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(lines.len(), CAPACITY + 1);
+        assert_eq!(lines[0], "2\n");
+        assert_eq!(
+            lines[CAPACITY],
+            "Dropped 2 log records from before logging was set up\n"
+        );
+    }
+
+    #[test]
+    fn capture_is_bounded_by_memory() {
+        let proxy = Proxy::new();
+        proxy.limit(10);
+        for message in ["aaaa", "bbbb", "cccc", "more than ten"] {
+            log(&proxy, Level::Info, message);
+        }
+
+        let (tx, rx) = channel();
+        let (_, logger) = Dispatch::new().chain(tx).into_log();
+        proxy.forward(logger).unwrap();
+
+        let lines = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "bbbb\n",
+                "cccc\n",
+                "Dropped 2 log records from before logging was set up\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_after_capture_started() {
+        let proxy = Proxy::new();
+        for message in ["aaaa", "bbbb", "cccc"] {
+            log(&proxy, Level::Info, message);
+        }
+        proxy.limit(8);
+        log(&proxy, Level::Info, "dddd");
+
+        let (tx, rx) = channel();
+        let (_, logger) = Dispatch::new().chain(tx).into_log();
+        proxy.forward(logger).unwrap();
+
+        let lines = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "cccc\n",
+                "dddd\n",
+                "Dropped 2 log records from before logging was set up\n"
+            ]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! The crate's error type and rendering errors with their whole chain of sources.
+
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::RefCell,
+    error::Error,
+    fmt,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether [`log_error_with_backtrace!`](crate::log_error_with_backtrace) captures
+/// backtraces, set from [`LoggingBuilder::capture_backtrace`](crate::LoggingBuilder::capture_backtrace).
+static CAPTURE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The backtrace of the error being logged on this thread, for the log file.
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+/// Errors from [`LoggingBuilder::finish`](crate::LoggingBuilder::finish) that callers may
+/// want to tell apart, found with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LoggingError {
+    /// Required builder fields were left empty, in the order `app_name`, `qualifier`,
+    /// `organization`.
+    MissingField { names: Vec<&'static str> },
+    /// Another logger in this process already writes to the log directory at `path`, and
+    /// both would name and rotate the same files.
+    DirectoryInUse { path: PathBuf },
+}
+
+impl fmt::Display for LoggingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingField { names } => {
+                write!(f, "Missing required fields: {}", names.join(", "))
+            }
+            Self::DirectoryInUse { path } => write!(
+                f,
+                "The log directory {} is already used by another logger in this process",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for LoggingError {}
+
+/// Displays an error followed by its sources, one per line:
+///
+/// ```text
+/// Failed to load settings
+/// Caused by:
+///     0: Failed to read settings.toml
+///     1: No such file or directory (os error 2)
+/// ```
+///
+/// [`log_error!`](crate::log_error) logs errors this way. An error without sources is
+/// displayed as usual.
+pub struct ErrorChain<'a> {
+    error: &'a (dyn Error + 'a),
+    backtrace: Option<&'a Backtrace>,
+}
+
+impl<'a> ErrorChain<'a> {
+    pub fn new(error: &'a (dyn Error + 'a)) -> Self {
+        Self {
+            error,
+            backtrace: None,
+        }
+    }
+
+    /// Appends `backtrace` after the sources, if it was captured.
+    pub fn with_backtrace(mut self, backtrace: &'a Backtrace) -> Self {
+        self.backtrace = Some(backtrace);
+
+        self
+    }
+}
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+
+        let mut source = self.error.source();
+        if source.is_some() {
+            write!(f, "\nCaused by:")?;
+        }
+        let mut i = 0;
+        while let Some(error) = source {
+            write!(f, "\n    {}: {}", i, error)?;
+            source = error.source();
+            i += 1;
+        }
+
+        if let Some(backtrace) = self
+            .backtrace
+            .filter(|b| b.status() == BacktraceStatus::Captured)
+        {
+            write!(f, "\nBacktrace:\n{}", backtrace)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lets [`log_error!`](crate::log_error) accept `anyhow::Error`, which does not implement
+/// [`Error`].
+#[doc(hidden)]
+pub trait AnyhowErrorChain {
+    fn __error_chain(&self) -> ErrorChain<'_>;
+}
+
+impl AnyhowErrorChain for anyhow::Error {
+    fn __error_chain(&self) -> ErrorChain<'_> {
+        ErrorChain::new(self.as_ref())
+    }
+}
+
+#[doc(hidden)]
+pub trait StdErrorChain {
+    fn __error_chain(&self) -> ErrorChain<'_>;
+}
+
+impl<E: Error> StdErrorChain for E {
+    fn __error_chain(&self) -> ErrorChain<'_> {
+        ErrorChain::new(self)
+    }
+}
+
+impl StdErrorChain for dyn Error + '_ {
+    fn __error_chain(&self) -> ErrorChain<'_> {
+        ErrorChain::new(self)
+    }
+}
+
+impl StdErrorChain for dyn Error + Send + Sync + '_ {
+    fn __error_chain(&self) -> ErrorChain<'_> {
+        ErrorChain::new(self)
+    }
+}
+
+/// Logs an error and its chain of sources as one multi-line [`log::error!`] entry.
+///
+/// Accepts anything implementing [`std::error::Error`], `Box<dyn Error>`, `anyhow::Error` and
+/// references to them. The record's target is the calling module, as with `log::error!`.
+///
+/// ```
+/// use std::io;
+///
+/// let err = io::Error::other("disk on fire");
+/// logging::log_error!(err);
+///
+/// let err = anyhow::Error::new(err).context("Failed to save");
+/// logging::log_error!(&err);
+/// ```
+///
+/// Use [`ErrorChain`] directly to log at another level or to include a backtrace.
+#[macro_export]
+macro_rules! log_error {
+    ($err:expr $(,)?) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::{AnyhowErrorChain as _, StdErrorChain as _};
+        $crate::__private::log::error!("{}", ($err).__error_chain())
+    }};
+}
+
+/// Logs an error like [`log_error!`], with a backtrace of where it was logged appended to
+/// its entry in the log file but not on the terminal.
+///
+/// Backtraces are only captured with
+/// [`LoggingBuilder::capture_backtrace`](crate::LoggingBuilder::capture_backtrace), and then
+/// only when `RUST_LIB_BACKTRACE` or `RUST_BACKTRACE` enable them, as for
+/// [`Backtrace::capture`]. Otherwise the entry is the same as from `log_error!`.
+///
+/// ```
+/// let err = std::io::Error::other("disk on fire");
+/// logging::log_error_with_backtrace!(err);
+/// ```
+#[macro_export]
+macro_rules! log_error_with_backtrace {
+    ($err:expr $(,)?) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::{AnyhowErrorChain as _, StdErrorChain as _};
+        $crate::__private::with_backtrace(|| {
+            $crate::__private::log::error!("{}", ($err).__error_chain())
+        })
+    }};
+}
+
+pub(crate) fn set_capture_backtraces(capture: bool) {
+    CAPTURE_BACKTRACES.store(capture, Ordering::Relaxed);
+}
+
+/// Runs `log` with a backtrace of the caller pending for the log file, if backtraces are
+/// captured.
+#[doc(hidden)]
+pub fn with_backtrace<R>(log: impl FnOnce() -> R) -> R {
+    if !CAPTURE_BACKTRACES.load(Ordering::Relaxed) {
+        return log();
+    }
+
+    let backtrace = Backtrace::capture();
+    if backtrace.status() != BacktraceStatus::Captured {
+        return log();
+    }
+
+    pending(backtrace, log)
+}
+
+/// Runs `log` with `backtrace` pending on this thread.
+pub(crate) fn pending<R>(backtrace: Backtrace, log: impl FnOnce() -> R) -> R {
+    let previous = BACKTRACE.with(|pending| pending.replace(Some(backtrace)));
+    let result = log();
+    BACKTRACE.with(|pending| *pending.borrow_mut() = previous);
+
+    result
+}
+
+/// The backtrace pending on this thread, if any, formatted.
+pub(crate) fn pending_backtrace() -> Option<String> {
+    BACKTRACE
+        .try_with(|pending| pending.borrow().as_ref().map(Backtrace::to_string))
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{backtrace::Backtrace, error::Error, fmt, io};
+
+    use super::ErrorChain;
+
+    #[derive(Debug)]
+    struct Outer(io::Error);
+
+    impl fmt::Display for Outer {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "Failed to load settings")
+        }
+    }
+
+    impl Error for Outer {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn renders_sources() {
+        let err = io::Error::other("disk on fire");
+        assert_eq!(ErrorChain::new(&err).to_string(), "disk on fire");
+
+        let err = Outer(err);
+        assert_eq!(
+            ErrorChain::new(&err).to_string(),
+            "Failed to load settings\nCaused by:\n    0: disk on fire"
+        );
+
+        let err = anyhow::Error::new(err).context("Startup failed");
+        assert_eq!(
+            ErrorChain::new(err.as_ref()).to_string(),
+            "Startup failed\nCaused by:\n    0: Failed to load settings\n    1: disk on fire"
+        );
+
+        let backtrace = Backtrace::force_capture();
+        let line = ErrorChain::new(err.as_ref())
+            .with_backtrace(&backtrace)
+            .to_string();
+        assert!(line.contains("\n    1: disk on fire\nBacktrace:\n"));
+    }
+
+    #[test]
+    fn macro_accepts_error_types() {
+        let err = io::Error::other("disk on fire");
+        crate::log_error!(err);
+        crate::log_error!(&err);
+
+        let err: Box<dyn Error + Send + Sync> = Box::new(err);
+        crate::log_error!(err);
+
+        let err = anyhow::anyhow!("Startup failed");
+        crate::log_error!(err);
+        crate::log_error!(&err);
+    }
+}
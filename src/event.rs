@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use log::{Level, Record};
+
+use crate::intern::Targets;
+
+/// An owned copy of a log record, for sinks that hand records to application code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEvent {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    /// Shared by the events with the same target, unless
+    /// [`intern_targets`](crate::LoggingBuilder::intern_targets) was turned off.
+    pub target: Arc<str>,
+    /// The message as logged, without any formatting applied.
+    pub message: String,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// The [`push_context`](crate::LoggingHandle::push_context) fields of the thread it was
+    /// logged on, oldest first. Always empty when read back from log files.
+    pub context: Vec<(String, String)>,
+}
+
+impl LogEvent {
+    pub(crate) fn from_record(record: &Record, targets: &Targets) -> Self {
+        let mut context = vec![];
+        crate::context::for_each(|key, value| context.push((key.to_string(), value.to_string())));
+
+        Self {
+            time: Local::now(),
+            level: record.level(),
+            target: targets.get(record.target()),
+            message: record.args().to_string(),
+            module_path: record.module_path().map(str::to_string),
+            file: record.file().map(str::to_string),
+            line: record.line(),
+            context,
+        }
+    }
+}
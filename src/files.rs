@@ -0,0 +1,1227 @@
+//! Picking, rotating and opening the log files for a run.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::{rename, DirBuilder, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use chrono::NaiveDate;
+use log::{Level, Log, Metadata, Record};
+
+use crate::{
+    binary::BinaryWriter,
+    clock::Clock,
+    manifest::Manifest,
+    non_blocking::Framing,
+    rotation::{self, RotateCallback, RotateWhen, Rotation, DAILY_FORMAT, HISTORY_NAME},
+    sinks::LineWriter,
+    FileMode, LoggingError, RotationOrder, CHRONO_FORMAT,
+};
+
+/// The log directories of the loggers alive in this process.
+static IN_USE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+pub(crate) type WriterFactory = dyn Fn(&Path) -> io::Result<Box<dyn Write + Send>> + Send + Sync;
+
+/// What other processes may do with the log file while it is open, on Windows, for
+/// [`file_share`](crate::LoggingBuilder::file_share).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileShare {
+    /// Read, write, rename and delete it.
+    #[default]
+    All,
+    /// Only read it, e.g. to follow or copy the log, but not write, rename or delete it.
+    Read,
+    /// Nothing: opening it elsewhere fails until logging stops.
+    None,
+}
+
+impl FileShare {
+    /// The `dwShareMode` flags for `CreateFileW`.
+    #[cfg(windows)]
+    fn mode(self) -> u32 {
+        const FILE_SHARE_READ: u32 = 0x1;
+        const FILE_SHARE_WRITE: u32 = 0x2;
+        const FILE_SHARE_DELETE: u32 = 0x4;
+
+        match self {
+            FileShare::All => FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            FileShare::Read => FILE_SHARE_READ,
+            FileShare::None => 0,
+        }
+    }
+}
+
+/// The settings for setting up the log files, owned so the setup can run on another thread.
+#[derive(Clone)]
+pub(crate) struct FileSetup {
+    pub(crate) log_dir: PathBuf,
+    pub(crate) require_existing_dir: bool,
+    pub(crate) file_mode: FileMode,
+    pub(crate) app_name: String,
+    pub(crate) filename_format: String,
+    pub(crate) max_files: usize,
+    pub(crate) on_rotate: Option<Arc<RotateCallback>>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) max_age: Option<chrono::Duration>,
+    pub(crate) clock_skew: chrono::Duration,
+    pub(crate) history_max_bytes: Option<u64>,
+    /// Write to `<name>.log.tmp` and rename it when logging shuts down.
+    pub(crate) finalize_on_exit: bool,
+    /// Opens the files instead of `fern::log_file`.
+    pub(crate) writer_factory: Option<Arc<WriterFactory>>,
+    /// The file name of the audit log, if enabled.
+    pub(crate) audit_name: Option<String>,
+    /// How many bytes to reserve for the log file.
+    pub(crate) preallocate: Option<u64>,
+    /// Write to a [`DAILY_FORMAT`] file, switching to a new one when the day changes.
+    pub(crate) daily_rollover: bool,
+    /// Switch to a new log file after this many lines.
+    pub(crate) max_lines: Option<u64>,
+    /// How often to re-run the retention policy while logging, if at all.
+    pub(crate) background_rotation: Option<Duration>,
+    /// Create the log directory and files with owner-only access where supported.
+    pub(crate) restrict_permissions: bool,
+    /// The sanitized label new log file names start with.
+    pub(crate) session_label: Option<String>,
+    /// Let the log file be reopened at its path, for `SIGHUP`.
+    pub(crate) reopen_on_sighup: bool,
+    /// Also open a `<stem>.<level>.log` file for each level next to the log file.
+    pub(crate) per_level_files: bool,
+    pub(crate) rotation_order: RotationOrder,
+    pub(crate) rotate_when: RotateWhen,
+    /// How the log files are shared with other processes on Windows.
+    pub(crate) file_share: FileShare,
+    /// Rewritten whenever logging moves to a new file or deletes old ones.
+    pub(crate) manifest: Option<Manifest>,
+}
+
+/// The files opened by [`FileSetup::open`].
+pub(crate) struct LogFiles {
+    /// Where `log_file` writes to first.
+    pub(crate) path: PathBuf,
+    pub(crate) log_file: Box<dyn Write + Send>,
+    pub(crate) history: Option<Box<dyn Write + Send>>,
+    /// The files of [`FileSetup::per_level_files`], most severe level first.
+    pub(crate) per_level: Vec<(Level, Box<dyn Write + Send>)>,
+    pub(crate) audit: Option<AuditFile>,
+    /// Renames the log file on shutdown, with [`FileSetup::finalize_on_exit`].
+    pub(crate) finalize: Option<Finalize>,
+    /// Releases the space reserved by [`FileSetup::preallocate`] on shutdown.
+    pub(crate) preallocated: Option<Preallocated>,
+    /// Runs [`FileSetup::background_rotation`].
+    pub(crate) retention: Option<RetentionThread>,
+    /// Deletes old logs on shutdown, with [`RotateWhen::Shutdown`].
+    pub(crate) shutdown_rotation: Option<ShutdownRotation>,
+    /// Reopens the log file, with [`FileSetup::reopen_on_sighup`].
+    pub(crate) reopen: Option<Arc<Reopen>>,
+    /// Rotation problems to log once logging is installed.
+    pub(crate) warnings: Vec<String>,
+}
+
+impl LogFiles {
+    /// Writes to `file` as it is, without rotation or any of the other files.
+    pub(crate) fn handle(file: File) -> Self {
+        Self {
+            path: PathBuf::new(),
+            log_file: Box::new(file),
+            history: None,
+            per_level: vec![],
+            audit: None,
+            finalize: None,
+            preallocated: None,
+            retention: None,
+            shutdown_rotation: None,
+            reopen: None,
+            warnings: vec![],
+        }
+    }
+}
+
+impl FileSetup {
+    /// Picks the log file for the [`FileMode`], rotating old logs, and opens it along with
+    /// the history log if enabled.
+    pub(crate) fn open(&self) -> anyhow::Result<LogFiles> {
+        let log_dir = &self.log_dir;
+        if self.require_existing_dir && !log_dir.is_dir() {
+            anyhow::bail!("Log directory {} does not exist", log_dir.display());
+        }
+
+        // Without the startup pass, nothing is old enough or beyond the count yet.
+        let rotation = if self.rotate_when.at_startup() {
+            self.rotation()
+        } else {
+            Rotation {
+                max_files: usize::MAX,
+                max_age: None,
+                ..self.rotation()
+            }
+        };
+
+        let path = match self.file_mode {
+            _ if self.daily_rollover => rotation.daily_path(log_dir)?,
+            FileMode::Rotate => {
+                rotation.rotate_logs(log_dir)?;
+                rotation.new_path(log_dir)
+            }
+            FileMode::Append => rotation.append_path(log_dir)?,
+            FileMode::Single => log_dir.join(format!("{}.log", self.app_name)),
+            FileMode::Numbered => {
+                rotation.shift_numbered(log_dir, &format!("{}.log", self.app_name))?
+            }
+        };
+        self.create_dir(log_dir)?;
+        let per_level = if self.per_level_files {
+            self.per_level(&path, &rotation)?
+        } else {
+            vec![]
+        };
+        let current = Arc::new(Mutex::new(path.clone()));
+        let mut reopen = None;
+
+        let (log_file, finalize, written): (Box<dyn Write + Send>, _, _) = if self.finalize_on_exit
+            && self.file_mode != FileMode::Append
+            && !self.daily_rollover
+        {
+            let mut temp = path.clone().into_os_string();
+            temp.push(".tmp");
+            let temp = PathBuf::from(temp);
+
+            let writer = Closable::new(self.open_writer(&temp, true)?);
+            let finalize = Finalize {
+                writer: writer.clone(),
+                temp: temp.clone(),
+                path,
+            };
+
+            (Box::new(writer), Some(finalize), temp)
+        } else if self.daily_rollover {
+            let writer = DailyWriter {
+                date: self.clock.now().date_naive(),
+                file: self.open_writer(&path, false)?,
+                at_line_start: true,
+                setup: self.clone(),
+                current: current.clone(),
+            };
+
+            (Box::new(writer), None, path)
+        } else if let Some(max_lines) = self
+            .max_lines
+            .filter(|_| matches!(self.file_mode, FileMode::Rotate | FileMode::Append))
+        {
+            let writer = LineCountWriter {
+                lines: count_lines(&path),
+                max_lines,
+                file: self.open_writer(&path, false)?,
+                at_line_start: true,
+                setup: self.clone(),
+                current: current.clone(),
+            };
+
+            (Box::new(writer), None, path)
+        } else if self.reopen_on_sighup {
+            let truncate = self.file_mode == FileMode::Single;
+            let writer = Closable::new(self.open_writer(&path, truncate)?);
+            reopen = Some(Arc::new(Reopen {
+                writer: writer.clone(),
+                path: path.clone(),
+                setup: self.clone(),
+            }));
+
+            (Box::new(writer), None, path)
+        } else {
+            let truncate = self.file_mode == FileMode::Single;
+            (self.open_writer(&path, truncate)?, None, path)
+        };
+
+        let mut warnings = rotation.warnings.take();
+
+        let preallocated = match self.preallocate {
+            Some(bytes) if self.writer_factory.is_none() => {
+                match Preallocated::reserve(&written, bytes) {
+                    Ok(preallocated) => Some(preallocated),
+                    Err(e) => {
+                        warnings.push(format!("Unable to preallocate the log file: {}", e));
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let history = if let Some(max_bytes) = self.history_max_bytes {
+            let path = log_dir.join(HISTORY_NAME);
+            if path.metadata().is_ok_and(|m| m.len() > max_bytes) {
+                let history = Rotation {
+                    max_files: 2,
+                    ..rotation
+                };
+                history.shift_numbered(log_dir, HISTORY_NAME)?;
+            }
+
+            Some(self.open_writer(&path, false)?)
+        } else {
+            None
+        };
+
+        let audit = match &self.audit_name {
+            Some(name) => {
+                let path = log_dir.join(name);
+                self.restrict(&path)?;
+                Some(AuditFile::open(&path)?)
+            }
+            None => None,
+        };
+
+        let retention = match (self.background_rotation, self.file_mode) {
+            (_, FileMode::Single | FileMode::Numbered) if !self.daily_rollover => None,
+            (Some(interval), _) => Some(RetentionThread::spawn(
+                self.clone(),
+                current.clone(),
+                interval,
+            )?),
+            (None, _) => None,
+        };
+        let shutdown_rotation = match self.file_mode {
+            _ if !self.rotate_when.at_shutdown() => None,
+            FileMode::Single | FileMode::Numbered if !self.daily_rollover => None,
+            _ => Some(ShutdownRotation {
+                setup: self.clone(),
+                current: current.clone(),
+            }),
+        };
+
+        Ok(LogFiles {
+            path: written,
+            log_file,
+            history,
+            per_level,
+            audit,
+            finalize,
+            preallocated,
+            retention,
+            shutdown_rotation,
+            reopen,
+            warnings,
+        })
+    }
+
+    /// The per-level files of the log at `path`, each opened when first written to. Those
+    /// of [`FileMode::Single`] are emptied and those of [`FileMode::Numbered`] shifted along
+    /// with the log.
+    fn per_level(
+        &self,
+        path: &Path,
+        rotation: &Rotation,
+    ) -> anyhow::Result<Vec<(Level, Box<dyn Write + Send>)>> {
+        let mut files = vec![];
+
+        for level in Level::iter() {
+            let path = rotation::level_path(path, level);
+            match self.file_mode {
+                _ if self.daily_rollover => {}
+                FileMode::Single if path.exists() => File::create(&path).map(drop)?,
+                FileMode::Numbered => {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    rotation.shift_numbered(&self.log_dir, &name)?;
+                }
+                _ => {}
+            }
+
+            let file = LazyFile {
+                path,
+                setup: self.clone(),
+                file: None,
+            };
+            files.push((level, Box::new(file) as Box<dyn Write + Send>));
+        }
+
+        Ok(files)
+    }
+
+    /// How old log files are found and deleted.
+    fn rotation(&self) -> Rotation<'_> {
+        Rotation {
+            filename_format: if self.daily_rollover {
+                DAILY_FORMAT
+            } else {
+                &self.filename_format
+            },
+            max_files: self.max_files,
+            on_rotate: self.on_rotate.as_ref(),
+            clock: &*self.clock,
+            max_age: self.max_age,
+            clock_skew: self.clock_skew,
+            warnings: Default::default(),
+            audit_name: self.audit_name.as_deref(),
+            current: None,
+            label: self.session_label.as_deref(),
+            order: self.rotation_order,
+        }
+    }
+
+    /// Deletes the logs beyond `max_files` or older than `max_age`, never `current`.
+    /// Warnings about future-dated logs are dropped, as the setup already logged them.
+    fn retain(&self, current: &Path) -> anyhow::Result<()> {
+        let rotation = Rotation {
+            current: Some(current),
+            ..self.rotation()
+        };
+
+        let logs = rotation.get_all_logs(&self.log_dir)?;
+        rotation.prune(logs, self.max_files - 1)
+    }
+
+    /// Rewrites the manifest, if kept, after the files in the log directory changed.
+    fn update_manifest(&self) -> io::Result<()> {
+        self.manifest.as_ref().map_or(Ok(()), Manifest::write)
+    }
+
+    /// Creates `dir` and its missing parents, as `0700` on Unix with
+    /// [`restrict_permissions`](Self::restrict_permissions).
+    fn create_dir(&self, dir: &Path) -> io::Result<()> {
+        create_dir(dir, self.restrict_permissions)
+    }
+
+    /// Creates `path` if missing and makes it `0600`, on Unix with
+    /// [`restrict_permissions`](Self::restrict_permissions). A no-op otherwise.
+    fn restrict(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        if self.restrict_permissions {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .mode(0o600)
+                .open(path)?;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        #[cfg(not(unix))]
+        let _ = path;
+
+        Ok(())
+    }
+
+    /// Opens `path` for appending, creating it if needed, after emptying it if `truncate`.
+    fn open_writer(&self, path: &Path, truncate: bool) -> io::Result<Box<dyn Write + Send>> {
+        if self.writer_factory.is_none() {
+            self.restrict(path)?;
+        }
+        if truncate {
+            File::create(path)?;
+        }
+
+        match &self.writer_factory {
+            Some(factory) => factory(path),
+            None => Ok(Box::new(self.open_file(path)?)),
+        }
+    }
+
+    /// Opens `path` for appending like `fern::log_file`, shared as set by
+    /// [`file_share`](Self::file_share) on Windows.
+    fn open_file(&self, path: &Path) -> io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).append(true);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+
+            options.share_mode(self.file_share.mode());
+        }
+        #[cfg(not(windows))]
+        let _ = self.file_share;
+
+        options.open(path)
+    }
+
+    /// Runs [`open`](Self::open) on a helper thread, giving up if it takes longer than
+    /// `timeout`. The thread is left to finish on its own.
+    pub(crate) fn open_within(self, timeout: Duration) -> anyhow::Result<LogFiles> {
+        let (tx, rx) = channel();
+        thread::Builder::new()
+            .name("logging-setup".to_string())
+            .spawn(move || {
+                let _ = tx.send(self.open());
+            })?;
+
+        match rx.recv_timeout(timeout) {
+            Ok(files) => files,
+            Err(RecvTimeoutError::Timeout) => Err(SetupTimeout(timeout).into()),
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Log file setup panicked"),
+        }
+    }
+}
+
+/// A file opened for appending at the first write, so levels that are never logged leave no
+/// empty file behind.
+struct LazyFile {
+    path: PathBuf,
+    setup: FileSetup,
+    file: Option<Box<dyn Write + Send>>,
+}
+
+impl Write for LazyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => self
+                .file
+                .insert(self.setup.open_writer(&self.path, false)?)
+                .write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The log file for [`daily_rollover`](crate::LoggingBuilder::daily_rollover), which moves
+/// to the new day's file at the first line written on that day.
+struct DailyWriter {
+    /// The day `file` is for.
+    date: NaiveDate,
+    file: Box<dyn Write + Send>,
+    /// Whether the last write ended a line, so switching files never splits one.
+    at_line_start: bool,
+    setup: FileSetup,
+    /// The path of `file`, shared with the [`RetentionThread`].
+    current: Arc<Mutex<PathBuf>>,
+}
+
+impl DailyWriter {
+    /// Switches to today's file if the day changed, rotating old logs.
+    fn roll(&mut self) -> io::Result<()> {
+        let today = self.setup.clock.now().date_naive();
+        if today == self.date {
+            return Ok(());
+        }
+
+        self.file.flush()?;
+        let path = self
+            .setup
+            .rotation()
+            .daily_path(&self.setup.log_dir)
+            .map_err(io::Error::other)?;
+        self.file = self.setup.open_writer(&path, false)?;
+        self.date = today;
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = path;
+        // A stale manifest is no reason to fail the write.
+        let _ = self.setup.update_manifest();
+
+        Ok(())
+    }
+}
+
+impl Write for DailyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start {
+            self.roll()?;
+        }
+
+        let n = self.file.write(buf)?;
+        if n > 0 {
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The log file for [`max_lines`](crate::LoggingBuilder::max_lines), which moves to a new
+/// file once `max_lines` complete lines were written to the current one.
+struct LineCountWriter {
+    /// How many lines `file` has.
+    lines: u64,
+    max_lines: u64,
+    file: Box<dyn Write + Send>,
+    /// Whether the last write ended a line, so switching files never splits one.
+    at_line_start: bool,
+    setup: FileSetup,
+    /// The path of `file`, shared with the [`RetentionThread`].
+    current: Arc<Mutex<PathBuf>>,
+}
+
+impl LineCountWriter {
+    /// Switches to a new file, rotating old logs.
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let rotation = self.setup.rotation();
+        rotation
+            .rotate_logs(&self.setup.log_dir)
+            .map_err(io::Error::other)?;
+        let path = rotation.new_path(&self.setup.log_dir);
+        self.file = self.setup.open_writer(&path, false)?;
+        self.lines = 0;
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = path;
+        // A stale manifest is no reason to fail the write.
+        let _ = self.setup.update_manifest();
+
+        Ok(())
+    }
+}
+
+impl Write for LineCountWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start && self.lines >= self.max_lines {
+            self.roll()?;
+        }
+
+        // Only up to the line that fills the file, or that ends an unfinished one.
+        let room = self.max_lines.saturating_sub(self.lines).max(1) as usize;
+        let end = buf
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .nth(room - 1)
+            .map_or(buf.len(), |(i, _)| i + 1);
+
+        let n = self.file.write(&buf[..end])?;
+        self.lines += buf[..n].iter().filter(|b| **b == b'\n').count() as u64;
+        if n > 0 {
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// How many lines the file at `path` already has, `0` if it cannot be read.
+fn count_lines(path: &Path) -> u64 {
+    let Ok(file) = File::open(path) else {
+        return 0;
+    };
+
+    BufReader::new(file)
+        .split(b'\n')
+        .map_while(Result::ok)
+        .count() as u64
+}
+
+/// Re-runs the retention policy every
+/// [`background_rotation`](crate::LoggingBuilder::background_rotation) interval until
+/// dropped, which stops the thread and waits for it.
+pub(crate) struct RetentionThread {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RetentionThread {
+    fn spawn(
+        setup: FileSetup,
+        current: Arc<Mutex<PathBuf>>,
+        interval: Duration,
+    ) -> io::Result<Self> {
+        let (stop, stopped) = channel::<()>();
+        let thread = thread::Builder::new()
+            .name("logging-rotation".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let current = current.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    if let Err(e) = setup.retain(&current) {
+                        log::warn!("Unable to rotate the log files: {:#}", e);
+                    }
+                    if let Err(e) = setup.update_manifest() {
+                        log::warn!("Unable to write the log manifest: {}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for RetentionThread {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The retention pass of [`RotateWhen::Shutdown`], run once the log file is closed.
+pub(crate) struct ShutdownRotation {
+    setup: FileSetup,
+    current: Arc<Mutex<PathBuf>>,
+}
+
+impl ShutdownRotation {
+    /// Deletes the logs beyond `max_files` or older than `max_age`, keeping the last one
+    /// written.
+    pub(crate) fn run(&self) -> anyhow::Result<()> {
+        let current = self
+            .current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+
+        self.setup.retain(&current)
+    }
+}
+
+/// Disk space reserved past the end of the log file, for
+/// [`preallocate`](crate::LoggingBuilder::preallocate).
+pub(crate) struct Preallocated(File);
+
+impl Preallocated {
+    /// Reserves `bytes` after the current end of `path` without changing its size, so
+    /// appending does not have to grow the file. A no-op outside Linux.
+    fn reserve(path: &Path, bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).open(path)?;
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+
+            let offset = file.metadata()?.len();
+            let to_off_t = |n: u64| {
+                libc::off_t::try_from(n).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+            };
+            // SAFETY: `file` is open for writing; fallocate does not touch our memory.
+            let result = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_KEEP_SIZE,
+                    to_off_t(offset)?,
+                    to_off_t(bytes)?,
+                )
+            };
+            if result == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = bytes;
+
+        Ok(Self(file))
+    }
+
+    /// Frees the reserved space the log did not use.
+    pub(crate) fn release(&self) -> io::Result<()> {
+        self.0.set_len(self.0.metadata()?.len())
+    }
+}
+
+/// The [`audit_file`](crate::LoggingBuilder::audit_file), opened for appending only and
+/// synced to disk on every flush, which fern does after each line.
+pub(crate) struct AuditFile(File);
+
+impl AuditFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map(Self)
+    }
+}
+
+impl Write for AuditFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.sync_data()
+    }
+}
+
+/// The [`crash_files`](crate::LoggingBuilder::crash_files) directory, which gets a new
+/// `<time>-<n>.txt` file for everything written between two flushes, so one per line as fern
+/// flushes after each. Only the newest `max_files` are kept.
+pub(crate) struct CrashFiles {
+    dir: PathBuf,
+    max_files: usize,
+    clock: Arc<dyn Clock>,
+    restrict_permissions: bool,
+    /// The number of the next file, counting up within the process.
+    next: u64,
+    pending: Vec<u8>,
+}
+
+impl CrashFiles {
+    pub(crate) fn new(
+        dir: PathBuf,
+        max_files: usize,
+        clock: Arc<dyn Clock>,
+        restrict_permissions: bool,
+    ) -> Self {
+        Self {
+            dir,
+            max_files,
+            clock,
+            restrict_permissions,
+            next: 1,
+            pending: vec![],
+        }
+    }
+
+    /// Writes `pending` to a new file, skipping names left by an earlier run.
+    fn write_file(&mut self) -> io::Result<()> {
+        create_dir(&self.dir, self.restrict_permissions)?;
+
+        let time = self.clock.now().format(CHRONO_FORMAT).to_string();
+        let mut file = loop {
+            let path = self.dir.join(format!("{}-{}.txt", time, self.next));
+            self.next += 1;
+
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            if self.restrict_permissions {
+                use std::os::unix::fs::OpenOptionsExt;
+
+                options.mode(0o600);
+            }
+            match options.open(path) {
+                Ok(file) => break file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        file.write_all(&self.pending)?;
+
+        self.prune()
+    }
+
+    /// Deletes the oldest crash files beyond `max_files`, by the time and number in their
+    /// names. Other files are left alone.
+    fn prune(&self) -> io::Result<()> {
+        let mut crashes = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(key) = name.to_str().and_then(crash_key) else {
+                continue;
+            };
+            crashes.push((key, self.dir.join(&name)));
+        }
+        crashes.sort_unstable_by_key(|(key, _)| std::cmp::Reverse(*key));
+
+        for (_, path) in crashes.into_iter().skip(self.max_files) {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The time and number of a `<time>-<n>.txt` crash file name, in the order they were written.
+fn crash_key(name: &str) -> Option<(chrono::NaiveDateTime, u64)> {
+    let (time, n) = name.strip_suffix(".txt")?.rsplit_once('-')?;
+
+    Some((
+        chrono::NaiveDateTime::parse_from_str(time, CHRONO_FORMAT).ok()?,
+        n.parse().ok()?,
+    ))
+}
+
+impl Write for CrashFiles {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.write_file();
+        self.pending.clear();
+
+        result
+    }
+}
+
+/// The subdirectories of the log directory for
+/// [`split_by_target_segment`](crate::LoggingBuilder::split_by_target_segment), each set up
+/// like the log directory, without the extra files, when its first record arrives.
+pub(crate) struct SegmentFiles {
+    setup: FileSetup,
+    framing: Framing,
+    line_buffered: bool,
+    /// The app's own crate, whose records stay in the log directory.
+    root: String,
+    /// The writer of each subdirectory, or `None` if it could not be set up.
+    open: Mutex<HashMap<String, Option<Box<dyn Log>>>>,
+}
+
+impl SegmentFiles {
+    pub(crate) fn new(setup: &FileSetup, framing: Framing, line_buffered: bool) -> Self {
+        Self {
+            setup: FileSetup {
+                history_max_bytes: None,
+                finalize_on_exit: false,
+                audit_name: None,
+                preallocate: None,
+                background_rotation: None,
+                reopen_on_sighup: false,
+                per_level_files: false,
+                manifest: None,
+                ..setup.clone()
+            },
+            framing,
+            line_buffered,
+            root: setup.app_name.replace('-', "_"),
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn open_segment(&self, segment: &str) -> anyhow::Result<Box<dyn Log>> {
+        let setup = FileSetup {
+            log_dir: self.setup.log_dir.join(segment),
+            ..self.setup.clone()
+        };
+        let files = setup.open()?;
+        // Logging them from inside the logger would come back here.
+        for warning in files.warnings {
+            eprintln!("{}", warning);
+        }
+
+        Ok(match self.framing {
+            Framing::Lines | Framing::Nul => {
+                Box::new(LineWriter::new(files.log_file, self.line_buffered))
+            }
+            Framing::Binary => Box::new(BinaryWriter::new(files.log_file, self.line_buffered)),
+        })
+    }
+}
+
+impl Log for SegmentFiles {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let segment = match target_segment(record.target(), &self.root) {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        let sink =
+            open.entry(segment)
+                .or_insert_with_key(|segment| match self.open_segment(segment) {
+                    Ok(sink) => Some(sink),
+                    Err(e) => {
+                        eprintln!("Unable to open the log file for {}: {:#}", segment, e);
+                        None
+                    }
+                });
+        if let Some(sink) = sink {
+            sink.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        let open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        for sink in open.values().flatten() {
+            sink.flush();
+        }
+    }
+}
+
+/// The [`SegmentFiles`] subdirectory for records with `target`, `None` for those that stay
+/// in the log directory: targets without `::` and those inside the `root` crate.
+pub(crate) fn target_segment(target: &str, root: &str) -> Option<String> {
+    let (segment, _) = target.split_once("::")?;
+    if segment == root {
+        return None;
+    }
+
+    rotation::sanitize_label(segment)
+}
+
+/// A writer that [`Finalize`] can close while the dispatch still holds it, also shared with
+/// the [`StdoutTee`](crate::stdout::StdoutTee). Writes after closing are discarded.
+#[derive(Clone)]
+pub(crate) struct Closable(Arc<Mutex<Option<Box<dyn Write + Send>>>>);
+
+impl Closable {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self(Arc::new(Mutex::new(Some(writer))))
+    }
+
+    /// A writer that discards everything until [`replace`](Self::replace) opens it.
+    pub(crate) fn closed() -> Self {
+        Self(Arc::new(Mutex::new(None)))
+    }
+
+    /// Swaps in `writer`, returning the previous one.
+    pub(crate) fn replace(
+        &self,
+        writer: Option<Box<dyn Write + Send>>,
+    ) -> Option<Box<dyn Write + Send>> {
+        std::mem::replace(
+            &mut *self.0.lock().unwrap_or_else(|e| e.into_inner()),
+            writer,
+        )
+    }
+}
+
+impl Write for Closable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.0.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(writer) => writer.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.0.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Retries transient write errors after a pause that doubles each time, for
+/// [`LoggingBuilder::write_retries`](crate::LoggingBuilder::write_retries).
+///
+/// After a permanent error, or once the retries run out, it returns the error and then
+/// discards everything, setting `gave_up`.
+pub(crate) struct RetryingWriter {
+    inner: Box<dyn Write + Send>,
+    retries: u32,
+    backoff: Duration,
+    gave_up: Arc<AtomicBool>,
+}
+
+impl RetryingWriter {
+    pub(crate) fn new(
+        inner: Box<dyn Write + Send>,
+        retries: u32,
+        backoff: Duration,
+        gave_up: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            inner,
+            retries,
+            backoff,
+            gave_up,
+        }
+    }
+
+    /// Runs `op` until it succeeds or fails for good, or returns `discarded` if an earlier
+    /// call gave up.
+    fn retry<T>(
+        &mut self,
+        discarded: T,
+        mut op: impl FnMut(&mut dyn Write) -> io::Result<T>,
+    ) -> io::Result<T> {
+        if self.gave_up.load(Ordering::Relaxed) {
+            return Ok(discarded);
+        }
+
+        let mut attempt = 0;
+        let mut pause = self.backoff;
+        loop {
+            match op(&mut *self.inner) {
+                Err(e) if is_transient(&e) && attempt < self.retries => {
+                    thread::sleep(pause);
+                    attempt += 1;
+                    pause = pause.saturating_mul(2);
+                }
+                Err(e) => {
+                    self.gave_up.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+                Ok(value) => return Ok(value),
+            }
+        }
+    }
+}
+
+/// Whether a write failing with `e` may work when tried again. Others, like a full disk or
+/// a missing permission, are not going away by themselves.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+impl Write for RetryingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.retry(buf.len(), |inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.retry((), |inner| inner.flush())
+    }
+}
+
+/// Opens the log file again at its path, for external rotation tools like logrotate that
+/// rename it and ask the process to continue in a new one.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) struct Reopen {
+    writer: Closable,
+    path: PathBuf,
+    setup: FileSetup,
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+impl Reopen {
+    /// Switches the writer to a file opened at the log file's path, creating it if it was
+    /// moved away, and flushes and closes the previous one.
+    pub(crate) fn reopen(&self) -> io::Result<()> {
+        let file = self.setup.open_writer(&self.path, false)?;
+        if let Some(mut previous) = self.writer.replace(Some(file)) {
+            previous.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Moves a run's temporary log file to its final name.
+pub(crate) struct Finalize {
+    writer: Closable,
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl Finalize {
+    /// Closes the temporary file and renames it, replacing any file with the final name.
+    pub(crate) fn run(&self) -> io::Result<()> {
+        if let Some(mut writer) = self
+            .writer
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            writer.flush()?;
+        }
+
+        rename(&self.temp, &self.path)
+    }
+}
+
+/// Creates `dir` and its missing parents, as `0700` on Unix if `restrict`.
+fn create_dir(dir: &Path, restrict: bool) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if restrict {
+        use std::os::unix::fs::DirBuilderExt;
+
+        builder.mode(0o700);
+    }
+    #[cfg(not(unix))]
+    let _ = restrict;
+
+    builder.create(dir)
+}
+
+/// Checks that files can be created in `dir`, creating it first unless `require_existing`.
+pub(crate) fn probe_dir(dir: &Path, require_existing: bool, restrict: bool) -> io::Result<()> {
+    if require_existing && !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the directory does not exist",
+        ));
+    }
+    create_dir(dir, restrict)?;
+
+    let probe = dir.join(".logging-probe");
+    File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+/// Claims a log directory for one logger until dropped, so a second logger in the process
+/// fails with [`LoggingError::DirectoryInUse`] instead of rotating the same files.
+pub(crate) struct DirLock(PathBuf);
+
+impl DirLock {
+    pub(crate) fn acquire(dir: &Path) -> Result<Self, LoggingError> {
+        let path = resolve_dir(dir);
+        let mut in_use = IN_USE.lock().unwrap_or_else(|e| e.into_inner());
+        if in_use.contains(&path) {
+            return Err(LoggingError::DirectoryInUse { path });
+        }
+        in_use.push(path.clone());
+
+        Ok(Self(path))
+    }
+
+    /// Gives up the claim, returning the directory.
+    pub(crate) fn release(self) -> PathBuf {
+        self.0.clone()
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        IN_USE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|path| *path != self.0);
+    }
+}
+
+/// `dir` as an absolute path with symlinks resolved as far as it exists, so every spelling
+/// of a directory compares the same whether or not it was created yet.
+fn resolve_dir(dir: &Path) -> PathBuf {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+
+    let mut missing = vec![];
+    let mut existing = dir.as_path();
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return missing
+                .into_iter()
+                .rev()
+                .fold(resolved, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return dir,
+        }
+    }
+}
+
+/// The log files could not be set up within
+/// [`setup_timeout`](crate::LoggingBuilder::setup_timeout).
+#[derive(Debug)]
+pub(crate) struct SetupTimeout(Duration);
+
+impl fmt::Display for SetupTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Setting up the log files took longer than {:?}", self.0)
+    }
+}
+
+impl Error for SetupTimeout {}
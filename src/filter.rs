@@ -0,0 +1,157 @@
+//! Conditions on records that can be combined, for
+//! [`LoggingBuilder::filter`](crate::LoggingBuilder::filter).
+//!
+//! ```
+//! use logging::filter::{all_of, level, message_contains, target_prefix};
+//! use log::LevelFilter;
+//!
+//! // Trace from `db` and its submodules, but only slow queries.
+//! let slow_queries = all_of(vec![
+//!     level(LevelFilter::Trace),
+//!     target_prefix("db"),
+//!     message_contains("slow query"),
+//! ]);
+//! ```
+
+use std::fmt::Write;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Decides whether a record is logged.
+///
+/// Implemented for closures taking a [`Record`], so one-off conditions need no type of their
+/// own.
+pub trait Filter: Send + Sync {
+    fn matches(&self, record: &Record) -> bool;
+}
+
+impl<F: Fn(&Record) -> bool + Send + Sync> Filter for F {
+    fn matches(&self, record: &Record) -> bool {
+        self(record)
+    }
+}
+
+/// Matches records every one of `filters` matches. Matches everything if empty.
+pub fn all_of(filters: Vec<Box<dyn Filter>>) -> Box<dyn Filter> {
+    Box::new(move |record: &Record| filters.iter().all(|f| f.matches(record)))
+}
+
+/// Matches records any of `filters` matches. Matches nothing if empty.
+pub fn any_of(filters: Vec<Box<dyn Filter>>) -> Box<dyn Filter> {
+    Box::new(move |record: &Record| filters.iter().any(|f| f.matches(record)))
+}
+
+/// Matches records `filter` does not match.
+pub fn not(filter: Box<dyn Filter>) -> Box<dyn Filter> {
+    Box::new(move |record: &Record| !filter.matches(record))
+}
+
+/// Matches records at `level` or more severe.
+pub fn level(level: LevelFilter) -> Box<dyn Filter> {
+    Box::new(move |record: &Record| record.level() <= level)
+}
+
+/// Matches records whose target is `prefix` or one of its submodules, e.g. `db` matches `db`
+/// and `db::pool` but not `dbus`. A trailing `::` is ignored.
+pub fn target_prefix(prefix: impl Into<String>) -> Box<dyn Filter> {
+    let prefix = prefix.into();
+    let prefix = prefix.strip_suffix("::").unwrap_or(&prefix).to_string();
+
+    Box::new(move |record: &Record| {
+        record
+            .target()
+            .strip_prefix(prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
+
+/// Matches records whose message contains `text`.
+pub fn message_contains(text: impl Into<String>) -> Box<dyn Filter> {
+    let text = text.into();
+
+    Box::new(move |record: &Record| match record.args().as_str() {
+        Some(message) => message.contains(&text),
+        None => {
+            let mut message = String::new();
+            let _ = write!(message, "{}", record.args());
+            message.contains(&text)
+        }
+    })
+}
+
+/// Passes the records all of `filters` match to `inner`.
+pub(crate) struct Filtered {
+    filters: Vec<Box<dyn Filter>>,
+    inner: Box<dyn Log>,
+}
+
+impl Filtered {
+    pub(crate) fn new(filters: Vec<Box<dyn Filter>>, inner: Box<dyn Log>) -> Self {
+        Self { filters, inner }
+    }
+}
+
+impl Log for Filtered {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filters.iter().all(|f| f.matches(record)) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter, Record};
+
+    use super::{all_of, any_of, level, message_contains, not, target_prefix, Filter};
+
+    fn matches(filter: &dyn Filter, level: Level, target: &str, message: &str) -> bool {
+        filter.matches(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn combinators() {
+        let slow_queries = all_of(vec![
+            level(LevelFilter::Debug),
+            target_prefix("db::"),
+            message_contains("slow query"),
+        ]);
+        assert!(matches(
+            &*slow_queries,
+            Level::Debug,
+            "db",
+            "slow query: 3s"
+        ));
+        assert!(matches(
+            &*slow_queries,
+            Level::Warn,
+            "db::pool",
+            "a slow query"
+        ));
+        assert!(!matches(&*slow_queries, Level::Trace, "db", "slow query"));
+        assert!(!matches(&*slow_queries, Level::Debug, "dbus", "slow query"));
+        assert!(!matches(&*slow_queries, Level::Debug, "db", "fast query"));
+
+        let either = any_of(vec![target_prefix("net"), not(level(LevelFilter::Info))]);
+        assert!(matches(&*either, Level::Error, "net::tcp", "hello"));
+        assert!(matches(&*either, Level::Debug, "app", "hello"));
+        assert!(!matches(&*either, Level::Info, "app", "hello"));
+
+        assert!(matches(&*all_of(vec![]), Level::Info, "app", "hello"));
+        assert!(!matches(&*any_of(vec![]), Level::Info, "app", "hello"));
+    }
+}
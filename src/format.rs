@@ -0,0 +1,1253 @@
+//! Line formats and the support types for the [`log_format!`](crate::log_format) macro.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt::{self, Write},
+    time::Instant,
+};
+
+use chrono::FixedOffset;
+use log::{Level, Record};
+
+use crate::{
+    clock,
+    color::{Palette, RESET},
+    columns::{ColumnLayout, Fitted},
+    context,
+    levels::module_parents,
+    query::json_string,
+    sinks, CHRONO_FORMAT,
+};
+
+const JSON_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+/// How each record is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines, e.g. `[INFO] myapp - message`.
+    #[default]
+    Text,
+    /// One JSON object per line (NDJSON) with `timestamp`, `level`, `target` and `message`
+    /// keys, plus `file`, `line` and `thread` when known.
+    Json,
+    /// Compact length-prefixed records in the log file, read back with
+    /// [`decode_binary_log`](crate::decode_binary_log). Skips text formatting, for very high
+    /// log rates.
+    ///
+    /// The log file, its history, the [`per_level_files`](crate::LoggingBuilder::per_level_files)
+    /// and the [`split_by_target_segment`](crate::LoggingBuilder::split_by_target_segment)
+    /// files are binary. The terminal uses the text format and the TCP and Unix socket
+    /// outputs, ring buffer and audit file use the text file format.
+    Binary,
+    /// One line of space-separated `key=value` pairs per record, e.g.
+    /// `time=... level=info target=app msg="hello world"`, with the same keys as
+    /// [`Json`](Self::Json) and `msg` for the message. Values with spaces, `=`, quotes or
+    /// control characters are quoted and escaped.
+    Logfmt,
+    /// One [GELF 1.1](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+    /// object per line for Graylog, with the level as a syslog severity and the target,
+    /// file, line, thread and extra fields as `_`-prefixed additional fields.
+    ///
+    /// The first line of the message is the `short_message`; multi-line messages are also
+    /// sent whole as `full_message`. Over [`tcp_output`](crate::LoggingBuilder::tcp_output)
+    /// and [`unix_socket_output`](crate::LoggingBuilder::unix_socket_output), each object
+    /// ends with a null byte instead, as GELF TCP inputs expect.
+    Gelf,
+    /// The Apache Common Log Format for the records of the
+    /// [`access_log_target`](crate::LoggingBuilder::access_log_target), e.g.
+    /// `127.0.0.1 - bob [10/Oct/2024:13:55:36 +0200] "GET /index.html HTTP/1.1" 200 2326`,
+    /// for tools like GoAccess and AWStats. Other records use [`Text`](Self::Text).
+    ///
+    /// The fields are read from `key=value` pairs in the message, quoted like
+    /// [`Logfmt`](Self::Logfmt) values if they contain spaces: `remote_addr`, `ident`,
+    /// `user`, `method`, `path`, `protocol`, `status` and `bytes`. Missing fields are written
+    /// as `-`, except the protocol, which defaults to `HTTP/1.1`. The time is when the
+    /// record was logged.
+    CommonLog,
+    /// [`CommonLog`](Self::CommonLog) with the `referer` and `user_agent` fields added, as
+    /// in the Apache Combined Log Format.
+    CombinedLog,
+}
+
+/// Rewrites record targets for display. Filtering always uses the real target.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TargetDisplay {
+    /// Shown instead of empty targets and targets inside `root`.
+    pub(crate) default_target: Option<String>,
+    /// The app's crate root, i.e. the app name as a module path.
+    pub(crate) root: String,
+    /// Show targets inside `root` without the `root::` prefix.
+    pub(crate) strip_root: bool,
+    /// Versions shown for targets inside each module.
+    pub(crate) versions: HashMap<String, String>,
+}
+
+impl TargetDisplay {
+    pub(crate) fn new(default_target: Option<String>, app_name: &str) -> Self {
+        Self {
+            default_target,
+            root: app_name.replace('-', "_"),
+            strip_root: false,
+            versions: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn with_strip_root(mut self, strip: bool) -> Self {
+        self.strip_root = strip;
+
+        self
+    }
+
+    pub(crate) fn with_versions(mut self, versions: HashMap<String, String>) -> Self {
+        self.versions = versions;
+
+        self
+    }
+
+    /// The version of the most specific module in `versions` that `target` is inside.
+    pub(crate) fn version(&self, target: &str) -> Option<&str> {
+        if self.versions.is_empty() {
+            return None;
+        }
+
+        module_parents(target).find_map(|m| self.versions.get(m).map(String::as_str))
+    }
+
+    pub(crate) fn apply<'a>(&'a self, target: &'a str) -> &'a str {
+        if let Some(name) = &self.default_target {
+            let in_root = target
+                .strip_prefix(self.root.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"));
+
+            if target.is_empty() || (!self.root.is_empty() && in_root) {
+                return name;
+            }
+        }
+        if self.strip_root && !self.root.is_empty() {
+            if let Some(rest) = target
+                .strip_prefix(self.root.as_str())
+                .and_then(|rest| rest.strip_prefix("::"))
+            {
+                return rest;
+            }
+        }
+
+        target
+    }
+}
+
+/// The label written for each level.
+#[derive(Debug, Clone)]
+pub(crate) struct LevelNames([String; 5]);
+
+impl Default for LevelNames {
+    fn default() -> Self {
+        Self(
+            [
+                Level::Error,
+                Level::Warn,
+                Level::Info,
+                Level::Debug,
+                Level::Trace,
+            ]
+            .map(|l| l.as_str().to_string()),
+        )
+    }
+}
+
+impl LevelNames {
+    pub(crate) fn get(&self, level: Level) -> &str {
+        &self.0[level as usize - 1]
+    }
+
+    pub(crate) fn set(&mut self, level: Level, name: String) {
+        self.0[level as usize - 1] = name;
+    }
+}
+
+/// Settings for the built-in text format.
+#[derive(Debug, Clone)]
+pub(crate) struct TextStyle {
+    pub(crate) show_level: bool,
+    pub(crate) show_time: bool,
+    /// When set, lines include the time elapsed since this instant, e.g. `+1.234s`.
+    pub(crate) started: Option<Instant>,
+    pub(crate) show_target: bool,
+    pub(crate) targets: TargetDisplay,
+    pub(crate) level_names: LevelNames,
+    /// Append the source location and thread to warnings and errors.
+    pub(crate) verbose_errors: bool,
+    /// Colors for the level tag.
+    pub(crate) colors: Option<Palette>,
+    /// Start lines with the record's sequence number, e.g. `#42`.
+    pub(crate) show_sequence: bool,
+    /// Write times in this offset instead of the local time zone.
+    pub(crate) offset: Option<FixedOffset>,
+    /// Fixed widths for the time, level and target.
+    pub(crate) columns: ColumnLayout,
+    /// Written in place of an empty message. Without it, the ` - ` after the target is left
+    /// out instead.
+    pub(crate) empty_message: Option<String>,
+}
+
+/// A record rendered as `[LEVEL] time +uptime target - message`, leaving out the parts
+/// disabled by the [`TextStyle`]. Verbose warnings and errors end with
+/// ` (at file:line, thread name)`.
+pub(crate) struct TextLine<'a> {
+    pub(crate) style: &'a TextStyle,
+    pub(crate) record: &'a Record<'a>,
+    pub(crate) message: &'a fmt::Arguments<'a>,
+}
+
+impl fmt::Display for TextLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let style = self.style;
+
+        if let Some(sequence) = sinks::sequence().filter(|_| style.show_sequence) {
+            write!(f, "#{} ", sequence)?;
+        }
+        if style.show_level {
+            let level = self.record.level();
+            let tag = Fitted(
+                style.columns.level,
+                format_args!("[{}]", style.level_names.get(level)),
+            );
+            match &style.colors {
+                Some(colors) => write!(f, "{}{}{} ", colors.start(level), tag, RESET)?,
+                None => write!(f, "{} ", tag)?,
+            }
+        }
+        if style.show_time {
+            let time = clock::now(style.offset).naive_local();
+            write!(
+                f,
+                "{} ",
+                Fitted(style.columns.time, time.format(CHRONO_FORMAT))
+            )?;
+        }
+        if let Some(started) = style.started {
+            write!(f, "+{:.3}s ", started.elapsed().as_secs_f64())?;
+        }
+        let empty = is_empty(self.message);
+        if style.show_target {
+            write!(
+                f,
+                "{}",
+                Fitted(
+                    style.columns.target,
+                    Versioned(
+                        style.targets.apply(self.record.target()),
+                        style.targets.version(self.record.target())
+                    )
+                )
+            )?;
+            if !empty || style.empty_message.is_some() {
+                f.write_str(" - ")?;
+            }
+        }
+
+        match &style.empty_message {
+            Some(placeholder) if empty => f.write_str(placeholder)?,
+            _ => write!(f, "{}", self.message)?,
+        }
+
+        if !context::is_empty() {
+            f.write_str(" [")?;
+            let mut result = Ok(());
+            let mut first = true;
+            context::for_each(|key, value| {
+                let separator = if std::mem::take(&mut first) { "" } else { " " };
+                result = result.and_then(|_| write!(f, "{}{}={}", separator, key, value));
+            });
+            result?;
+            f.write_char(']')?;
+        }
+
+        if style.verbose_errors && self.record.level() <= Level::Warn {
+            f.write_str(" (")?;
+            if let (Some(file), Some(line)) = (self.record.file(), self.record.line()) {
+                write!(f, "at {}:{}, ", file, line)?;
+            }
+            let thread = std::thread::current();
+            write!(f, "thread {})", thread.name().unwrap_or("<unnamed>"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `message` renders as nothing, stopping at its first non-empty piece.
+fn is_empty(message: &fmt::Arguments) -> bool {
+    if let Some(message) = message.as_str() {
+        return message.is_empty();
+    }
+
+    struct Empty;
+
+    impl Write for Empty {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if s.is_empty() {
+                Ok(())
+            } else {
+                Err(fmt::Error)
+            }
+        }
+    }
+
+    fmt::write(&mut Empty, *message).is_ok()
+}
+
+/// A displayed target followed by `@version` if it has one.
+struct Versioned<'a>(&'a str, Option<&'a str>);
+
+impl fmt::Display for Versioned<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)?;
+        match self.1 {
+            Some(version) => write!(f, "@{}", version),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Settings for [`LogFormat::Json`] and [`LogFormat::Logfmt`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct JsonStyle {
+    /// Always emit every key, in the same order, using `null` for unknown values.
+    pub(crate) stable: bool,
+    pub(crate) targets: TargetDisplay,
+    pub(crate) level_names: LevelNames,
+    /// Fixed string fields added after the built-in keys.
+    pub(crate) fields: Vec<(String, String)>,
+    /// Add the record's sequence number as `seq`.
+    pub(crate) show_sequence: bool,
+    /// Write times in this offset instead of the local time zone.
+    pub(crate) offset: Option<FixedOffset>,
+}
+
+/// The keys [`JsonLine`] writes itself.
+pub(crate) const JSON_RESERVED_KEYS: [&str; 10] = [
+    "timestamp",
+    "level",
+    "target",
+    "version",
+    "message",
+    "file",
+    "line",
+    "thread",
+    "seq",
+    "context",
+];
+
+/// A record rendered as a single-line JSON object.
+pub(crate) struct JsonLine<'a> {
+    pub(crate) style: &'a JsonStyle,
+    pub(crate) record: &'a Record<'a>,
+    pub(crate) message: &'a fmt::Arguments<'a>,
+}
+
+impl fmt::Display for JsonLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let style = self.style;
+        let record = self.record;
+        let thread = std::thread::current();
+
+        write!(
+            f,
+            "{{\"timestamp\":\"{}\",\"level\":",
+            clock::now(style.offset).format(JSON_TIME_FORMAT)
+        )?;
+        write_json_str(f, format_args!("{}", style.level_names.get(record.level())))?;
+        f.write_str(",\"target\":")?;
+        write_json_str(f, format_args!("{}", style.targets.apply(record.target())))?;
+        match style.targets.version(record.target()) {
+            Some(version) => {
+                f.write_str(",\"version\":")?;
+                write_json_str(f, format_args!("{}", version))?;
+            }
+            None if style.stable && !style.targets.versions.is_empty() => {
+                f.write_str(",\"version\":null")?
+            }
+            None => {}
+        }
+        f.write_str(",\"message\":")?;
+        write_json_str(f, *self.message)?;
+
+        match record.file() {
+            Some(file) => {
+                f.write_str(",\"file\":")?;
+                write_json_str(f, format_args!("{}", file))?;
+            }
+            None if style.stable => f.write_str(",\"file\":null")?,
+            None => {}
+        }
+        match record.line() {
+            Some(line) => write!(f, ",\"line\":{}", line)?,
+            None if style.stable => f.write_str(",\"line\":null")?,
+            None => {}
+        }
+        match thread.name() {
+            Some(name) => {
+                f.write_str(",\"thread\":")?;
+                write_json_str(f, format_args!("{}", name))?;
+            }
+            None if style.stable => f.write_str(",\"thread\":null")?,
+            None => {}
+        }
+        if style.show_sequence {
+            match sinks::sequence() {
+                Some(sequence) => write!(f, ",\"seq\":{}", sequence)?,
+                None if style.stable => f.write_str(",\"seq\":null")?,
+                None => {}
+            }
+        }
+
+        for (key, value) in &style.fields {
+            f.write_char(',')?;
+            write_json_str(f, format_args!("{}", key))?;
+            f.write_char(':')?;
+            write_json_str(f, format_args!("{}", value))?;
+        }
+
+        if !context::is_empty() {
+            f.write_str(",\"context\":{")?;
+            let mut result = Ok(());
+            let mut first = true;
+            context::for_each(|key, value| {
+                result = result.and_then(|_| {
+                    if !std::mem::take(&mut first) {
+                        f.write_char(',')?;
+                    }
+                    write_json_str(f, format_args!("{}", key))?;
+                    f.write_char(':')?;
+                    write_json_str(f, format_args!("{}", value))
+                });
+            });
+            result?;
+            f.write_char('}')?;
+        }
+
+        f.write_char('}')
+    }
+}
+
+/// The keys [`LogfmtLine`] writes itself.
+pub(crate) const LOGFMT_RESERVED_KEYS: [&str; 9] = [
+    "time", "level", "target", "version", "msg", "file", "line", "thread", "seq",
+];
+
+/// A record rendered as logfmt `key=value` pairs. Level names are lowercase.
+pub(crate) struct LogfmtLine<'a> {
+    pub(crate) style: &'a JsonStyle,
+    pub(crate) record: &'a Record<'a>,
+    pub(crate) message: &'a fmt::Arguments<'a>,
+}
+
+impl fmt::Display for LogfmtLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let style = self.style;
+        let record = self.record;
+
+        write!(
+            f,
+            "time={}",
+            clock::now(style.offset).format(JSON_TIME_FORMAT)
+        )?;
+        let level = style.level_names.get(record.level()).to_lowercase();
+        write_logfmt_pair(f, "level", format_args!("{}", level))?;
+        write_logfmt_pair(
+            f,
+            "target",
+            format_args!("{}", style.targets.apply(record.target())),
+        )?;
+        if let Some(version) = style.targets.version(record.target()) {
+            write_logfmt_pair(f, "version", format_args!("{}", version))?;
+        }
+        write_logfmt_pair(f, "msg", *self.message)?;
+
+        if let Some(file) = record.file() {
+            write_logfmt_pair(f, "file", format_args!("{}", file))?;
+        }
+        if let Some(line) = record.line() {
+            write!(f, " line={}", line)?;
+        }
+        if let Some(name) = std::thread::current().name() {
+            write_logfmt_pair(f, "thread", format_args!("{}", name))?;
+        }
+        if let Some(sequence) = sinks::sequence().filter(|_| style.show_sequence) {
+            write!(f, " seq={}", sequence)?;
+        }
+
+        for (key, value) in &style.fields {
+            write_logfmt_pair(f, key, format_args!("{}", value))?;
+        }
+
+        let mut result = Ok(());
+        context::for_each(|key, value| {
+            result = result.and_then(|_| write_logfmt_pair(f, key, format_args!("{}", value)));
+        });
+
+        result
+    }
+}
+
+/// The additional fields [`GelfLine`] writes itself, without their `_`.
+pub(crate) const GELF_RESERVED_KEYS: [&str; 7] =
+    ["id", "target", "version", "file", "line", "thread", "seq"];
+
+/// A record rendered as a GELF 1.1 object, reusing the [`JsonStyle`] without its level names.
+pub(crate) struct GelfLine<'a> {
+    pub(crate) style: &'a JsonStyle,
+    /// The `host` field, the name of this machine.
+    pub(crate) host: &'a str,
+    pub(crate) record: &'a Record<'a>,
+    pub(crate) message: &'a fmt::Arguments<'a>,
+}
+
+impl fmt::Display for GelfLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let style = self.style;
+        let record = self.record;
+        let message = self.message.to_string();
+
+        f.write_str("{\"version\":\"1.1\",\"host\":")?;
+        write_json_str(f, format_args!("{}", self.host))?;
+        f.write_str(",\"short_message\":")?;
+        write_json_str(f, format_args!("{}", message.lines().next().unwrap_or("")))?;
+        if message.contains('\n') {
+            f.write_str(",\"full_message\":")?;
+            write_json_str(f, format_args!("{}", message))?;
+        }
+        let millis = clock::now(style.offset).timestamp_millis();
+        write!(
+            f,
+            ",\"timestamp\":{}.{:03},\"level\":{}",
+            millis.div_euclid(1000),
+            millis.rem_euclid(1000),
+            syslog_severity(record.level())
+        )?;
+
+        f.write_str(",\"_target\":")?;
+        write_json_str(f, format_args!("{}", style.targets.apply(record.target())))?;
+        if let Some(version) = style.targets.version(record.target()) {
+            f.write_str(",\"_version\":")?;
+            write_json_str(f, format_args!("{}", version))?;
+        }
+        if let Some(file) = record.file() {
+            f.write_str(",\"_file\":")?;
+            write_json_str(f, format_args!("{}", file))?;
+        }
+        if let Some(line) = record.line() {
+            write!(f, ",\"_line\":{}", line)?;
+        }
+        if let Some(name) = std::thread::current().name() {
+            f.write_str(",\"_thread\":")?;
+            write_json_str(f, format_args!("{}", name))?;
+        }
+        if let Some(sequence) = sinks::sequence().filter(|_| style.show_sequence) {
+            write!(f, ",\"_seq\":{}", sequence)?;
+        }
+
+        for (key, value) in &style.fields {
+            write_gelf_field(f, key, value)?;
+        }
+        let mut result = Ok(());
+        context::for_each(|key, value| {
+            result = result.and_then(|_| write_gelf_field(f, key, value));
+        });
+        result?;
+
+        f.write_char('}')
+    }
+}
+
+/// An access record rendered in the Common or Combined Log Format, from the `key=value`
+/// pairs of its message.
+pub(crate) struct AccessLine<'a> {
+    pub(crate) combined: bool,
+    pub(crate) offset: Option<FixedOffset>,
+    pub(crate) message: &'a fmt::Arguments<'a>,
+}
+
+impl fmt::Display for AccessLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = self.message.to_string();
+        let pairs = logfmt_pairs(&message);
+        let get = |key: &str| {
+            pairs
+                .iter()
+                .find(|(k, v)| *k == key && !v.is_empty())
+                .map(|(_, v)| v.as_str())
+        };
+        let field = |key: &str| get(key).unwrap_or("-").replace(char::is_whitespace, "_");
+
+        write!(
+            f,
+            "{} {} {} [{}] ",
+            field("remote_addr"),
+            field("ident"),
+            field("user"),
+            clock::now(self.offset).format("%d/%b/%Y:%H:%M:%S %z")
+        )?;
+        let request = match (get("method"), get("path")) {
+            (Some(method), Some(path)) => format!(
+                "{} {} {}",
+                method,
+                path,
+                get("protocol").unwrap_or("HTTP/1.1")
+            ),
+            _ => "-".to_string(),
+        };
+        write_clf_quoted(f, &request)?;
+        write!(f, " {} {}", field("status"), field("bytes"))?;
+
+        if self.combined {
+            for key in ["referer", "user_agent"] {
+                f.write_char(' ')?;
+                write_clf_quoted(f, get(key).unwrap_or("-"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `value` in double quotes, escaping quotes and backslashes as Apache does.
+fn write_clf_quoted(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{}", c)?,
+            c if c.is_control() => write!(f, "\\x{:02x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// The `key=value` pairs in `text`, with quoted values unescaped as written by
+/// [`LogFormat::Logfmt`]. Words without `=` are skipped, and a value whose quotes are not
+/// closed ends the pairs.
+pub(crate) fn logfmt_pairs(text: &str) -> Vec<(&str, String)> {
+    let mut pairs = vec![];
+    let mut rest = text;
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return pairs;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let (key, after) = rest.split_at(end);
+        let value = match after.strip_prefix('=') {
+            Some(value) => value,
+            None => {
+                rest = after;
+                continue;
+            }
+        };
+
+        if let Some(quoted) = value.strip_prefix('"') {
+            let mut chars = quoted.chars();
+            match json_string(&mut chars) {
+                Some(value) => pairs.push((key, value)),
+                None => return pairs,
+            }
+            rest = chars.as_str();
+        } else {
+            let end = value.find(char::is_whitespace).unwrap_or(value.len());
+            pairs.push((key, value[..end].to_string()));
+            rest = &value[end..];
+        }
+    }
+}
+
+/// The syslog severity GELF uses for `level`. `Trace` has none of its own.
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Whether `key` is allowed as a GELF additional field name, before its `_`.
+pub(crate) fn is_gelf_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(is_gelf_char)
+}
+
+fn is_gelf_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')
+}
+
+/// Writes `key` as an additional field, adding its `_` prefix and replacing characters GELF
+/// does not allow with `_`.
+fn write_gelf_field(f: &mut fmt::Formatter<'_>, key: &str, value: &str) -> fmt::Result {
+    let key = key.strip_prefix('_').unwrap_or(key);
+    f.write_str(",\"_")?;
+    for c in key.chars() {
+        f.write_char(if is_gelf_char(c) { c } else { '_' })?;
+    }
+    f.write_str("\":")?;
+    write_json_str(f, format_args!("{}", value))
+}
+
+/// The name of this machine for the GELF `host`, or `localhost` if it is unknown.
+pub(crate) fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: the buffer is valid for its length; the name is NUL-terminated if it fits.
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            if let Ok(name) = std::str::from_utf8(&buf[..len]) {
+                if !name.is_empty() {
+                    return name.to_string();
+                }
+            }
+        }
+    }
+
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// Whether `key` can be written as a logfmt key without quoting.
+pub(crate) fn is_logfmt_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && c != '=' && c != '"')
+}
+
+/// Writes ` key=value`, quoting and escaping `value` if it would not read back as one value.
+pub(crate) fn write_logfmt_pair(
+    f: &mut fmt::Formatter<'_>,
+    key: &str,
+    value: fmt::Arguments,
+) -> fmt::Result {
+    let mut buf = String::new();
+    let _ = buf.write_fmt(value);
+
+    write!(f, " {}=", key)?;
+    if !buf.is_empty() && is_logfmt_key(&buf) && !buf.contains('\\') {
+        f.write_str(&buf)
+    } else {
+        write!(f, "{}", JsonStr(&buf))
+    }
+}
+
+/// Displays a string as a quoted JSON string.
+pub(crate) struct JsonStr<'a>(pub(crate) &'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_json_str(f, format_args!("{}", self.0))
+    }
+}
+
+/// Writes `value` as a quoted JSON string, escaping it as it is formatted.
+fn write_json_str(f: &mut fmt::Formatter<'_>, value: fmt::Arguments) -> fmt::Result {
+    f.write_char('"')?;
+    JsonEscaper(f).write_fmt(value)?;
+    f.write_char('"')
+}
+
+struct JsonEscaper<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl Write for JsonEscaper<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut start = 0;
+
+        for (i, c) in s.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"",
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\r' => "\\r",
+                '\t' => "\\t",
+                c if (c as u32) < 0x20 => "",
+                _ => continue,
+            };
+
+            self.0.write_str(&s[start..i])?;
+            if escaped.is_empty() {
+                write!(self.0, "\\u{:04x}", c as u32)?;
+            } else {
+                self.0.write_str(escaped)?;
+            }
+            start = i + c.len_utf8();
+        }
+
+        self.0.write_str(&s[start..])
+    }
+}
+
+/// A template value.
+///
+/// Renders nothing when formatted with a precision of `0`, which lets the macro reference
+/// every placeholder without printing the ones the template doesn't use.
+pub struct Field<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Field<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.precision() == Some(0) {
+            return Ok(());
+        }
+
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// An optional record field that renders as an empty string when missing.
+pub struct Optional<T>(pub Option<T>);
+
+impl<T: fmt::Display> fmt::Display for Optional<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(v) => fmt::Display::fmt(v, f),
+            None => f.pad(""),
+        }
+    }
+}
+
+thread_local! {
+    /// The [`fixed_offset`](crate::LoggingBuilder::fixed_offset) of the custom format
+    /// running on this thread, for [`Now`].
+    static OFFSET: Cell<Option<FixedOffset>> = const { Cell::new(None) };
+}
+
+/// Runs the custom format `f` with [`Now`] rendering in `offset`.
+pub(crate) fn with_offset<R>(offset: Option<FixedOffset>, f: impl FnOnce() -> R) -> R {
+    let outer = OFFSET.with(|cell| cell.replace(offset));
+    let result = f();
+    OFFSET.with(|cell| cell.set(outer));
+
+    result
+}
+
+/// The current time, only read from the clock when rendered, in the offset the built-in
+/// formats use.
+pub struct Now;
+
+impl fmt::Display for Now {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = clock::now(OFFSET.with(Cell::get)).naive_local();
+        fmt::Display::fmt(&time.format(CHRONO_FORMAT), f)
+    }
+}
+
+/// Expands a template into a format closure for
+/// [`LoggingBuilder::custom_format`](crate::LoggingBuilder::custom_format).
+///
+/// Supported placeholders are `{time}`, `{level}`, `{target}`, `{msg}`, `{module}`, `{file}`
+/// and `{line}`. They accept the usual width and alignment specifiers, e.g. `{level:>5}`.
+/// The template is checked by `format_args!`, so an unknown placeholder is a compile error.
+///
+/// ```
+/// use logging::{log_format, LoggingBuilder};
+///
+/// let builder = LoggingBuilder::new().custom_format(log_format!("{time} {level:>5} {target} {msg}"));
+/// ```
+///
+/// ```compile_fail
+/// let format = logging::log_format!("{time} {levle} {msg}");
+/// ```
+#[macro_export]
+macro_rules! log_format {
+    ($template:literal) => {
+        |out: $crate::__private::FormatCallback,
+         message: &::std::fmt::Arguments,
+         record: &$crate::__private::Record| {
+            out.finish(format_args!(
+                concat!(
+                    $template,
+                    "{time:.0}{level:.0}{target:.0}{msg:.0}{module:.0}{file:.0}{line:.0}"
+                ),
+                time = $crate::__private::Field($crate::__private::Now),
+                level = $crate::__private::Field(record.level()),
+                target = $crate::__private::Field(record.target()),
+                msg = $crate::__private::Field(message),
+                module =
+                    $crate::__private::Field($crate::__private::Optional(record.module_path())),
+                file = $crate::__private::Field($crate::__private::Optional(record.file())),
+                line = $crate::__private::Field($crate::__private::Optional(record.line())),
+            ))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use log::{Level, Record};
+
+    use super::{
+        context, AccessLine, ColumnLayout, GelfLine, JsonLine, JsonStyle, LevelNames, LogfmtLine,
+        TargetDisplay, TextLine, TextStyle,
+    };
+
+    #[test]
+    fn level_names() {
+        let mut names = LevelNames::default();
+        names.set(Level::Warn, "WARNING".to_string());
+
+        assert_eq!(names.get(Level::Error), "ERROR");
+        assert_eq!(names.get(Level::Warn), "WARNING");
+        assert_eq!(names.get(Level::Info), "INFO");
+        assert_eq!(names.get(Level::Debug), "DEBUG");
+        assert_eq!(names.get(Level::Trace), "TRACE");
+    }
+
+    #[test]
+    fn default_target() {
+        let display = TargetDisplay::new(Some("My App".to_string()), "my-app");
+
+        assert_eq!(display.apply(""), "My App");
+        assert_eq!(display.apply("my_app"), "My App");
+        assert_eq!(display.apply("my_app::internal::thing"), "My App");
+        assert_eq!(display.apply("my_apples"), "my_apples");
+        assert_eq!(display.apply("hyper::client"), "hyper::client");
+
+        let display = TargetDisplay::new(None, "my-app");
+        assert_eq!(display.apply("my_app::internal"), "my_app::internal");
+    }
+
+    #[test]
+    fn strip_root() {
+        let display = TargetDisplay::new(None, "my-app").with_strip_root(true);
+
+        assert_eq!(display.apply("my_app::net::client"), "net::client");
+        assert_eq!(display.apply("my_app"), "my_app");
+        assert_eq!(display.apply("my_apples::net"), "my_apples::net");
+        assert_eq!(display.apply("hyper::client"), "hyper::client");
+
+        let display =
+            TargetDisplay::new(Some("My App".to_string()), "my-app").with_strip_root(true);
+        assert_eq!(display.apply("my_app::net"), "My App");
+    }
+
+    #[test]
+    fn gelf_fields() {
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("app::db")
+            .file(Some("src/db.rs"))
+            .line(Some(7))
+            .args(format_args!("slow query\n  select 1"))
+            .build();
+        let style = JsonStyle {
+            fields: vec![
+                ("service".to_string(), "checkout".to_string()),
+                ("_logger_version".to_string(), "1.0.0".to_string()),
+            ],
+            ..Default::default()
+        };
+        let gelf = |record: &Record| {
+            GelfLine {
+                style: &style,
+                host: "box-1",
+                record,
+                message: record.args(),
+            }
+            .to_string()
+        };
+
+        let line = gelf(&record);
+        let (start, rest) = line.split_once(",\"timestamp\":").unwrap();
+        assert_eq!(
+            start,
+            r#"{"version":"1.1","host":"box-1","short_message":"slow query","full_message":"slow query\n  select 1""#
+        );
+        let (timestamp, rest) = rest.split_once(',').unwrap();
+        let (seconds, millis) = timestamp.split_once('.').unwrap();
+        assert!(seconds.parse::<i64>().unwrap() > 1_600_000_000);
+        assert_eq!(millis.len(), 3);
+        assert!(rest.starts_with(r#""level":4,"_target":"app::db","_file":"src/db.rs","_line":7,"#));
+        assert!(rest.ends_with(r#","_service":"checkout","_logger_version":"1.0.0"}"#));
+
+        let _context = context::push("req id".to_string(), "42".to_string());
+        let record = Record::builder()
+            .level(Level::Trace)
+            .target("app")
+            .args(format_args!("hello"))
+            .build();
+        let line = gelf(&record);
+        assert!(line.contains(r#""short_message":"hello","timestamp":"#));
+        assert!(!line.contains("full_message"));
+        assert!(line.contains(r#""level":7,"#));
+        assert!(line.ends_with(r#","_req_id":"42"}"#));
+    }
+
+    /// Targets and messages only.
+    fn text_style() -> TextStyle {
+        TextStyle {
+            show_level: false,
+            show_time: false,
+            started: None,
+            show_target: true,
+            targets: TargetDisplay::default(),
+            level_names: LevelNames::default(),
+            verbose_errors: false,
+            colors: None,
+            show_sequence: false,
+            offset: None,
+            columns: ColumnLayout::default(),
+            empty_message: None,
+        }
+    }
+
+    #[test]
+    fn empty_messages() {
+        fn text(style: &TextStyle, args: std::fmt::Arguments) -> String {
+            let record = Record::builder().target("app").args(args).build();
+            TextLine {
+                style,
+                record: &record,
+                message: record.args(),
+            }
+            .to_string()
+        }
+
+        let style = text_style();
+        assert_eq!(text(&style, format_args!("{}", "")), "app");
+        assert_eq!(text(&style, format_args!("")), "app");
+        assert_eq!(text(&style, format_args!("hello")), "app - hello");
+
+        let style = TextStyle {
+            empty_message: Some("(empty)".to_string()),
+            ..text_style()
+        };
+        assert_eq!(text(&style, format_args!("{}", "")), "app - (empty)");
+        assert_eq!(text(&style, format_args!("hello")), "app - hello");
+    }
+
+    #[test]
+    fn context_fields() {
+        let record = Record::builder()
+            .target("app")
+            .args(format_args!("hello"))
+            .build();
+        let text = |style: &TextStyle| {
+            TextLine {
+                style,
+                record: &record,
+                message: record.args(),
+            }
+            .to_string()
+        };
+        let style = text_style();
+
+        assert_eq!(text(&style), "app - hello");
+        assert!(!json(&record, false).contains(r#""context":"#));
+
+        let _request = context::push("req_id".to_string(), "42".to_string());
+        let _user = context::push("user".to_string(), "bob ross".to_string());
+        assert_eq!(text(&style), "app - hello [req_id=42 user=bob ross]");
+        assert!(json(&record, false).ends_with(r#","context":{"req_id":"42","user":"bob ross"}}"#));
+        let logfmt = LogfmtLine {
+            style: &JsonStyle::default(),
+            record: &record,
+            message: record.args(),
+        }
+        .to_string();
+        assert!(logfmt.ends_with(r#" req_id=42 user="bob ross""#));
+    }
+
+    #[test]
+    fn target_versions() {
+        let display = TargetDisplay::new(None, "host").with_versions(HashMap::from([
+            ("plugin_a".to_string(), "1.2.0".to_string()),
+            ("plugin_a::vendored".to_string(), "0.3.1".to_string()),
+        ]));
+
+        assert_eq!(display.version("plugin_a"), Some("1.2.0"));
+        assert_eq!(display.version("plugin_a::net"), Some("1.2.0"));
+        assert_eq!(display.version("plugin_a::vendored::io"), Some("0.3.1"));
+        assert_eq!(display.version("plugin_ab"), None);
+        assert_eq!(display.version("host"), None);
+
+        let style = TextStyle {
+            targets: display,
+            ..text_style()
+        };
+        let record = Record::builder()
+            .target("plugin_a::net")
+            .args(format_args!("hello"))
+            .build();
+        let line = TextLine {
+            style: &style,
+            record: &record,
+            message: record.args(),
+        };
+        assert_eq!(line.to_string(), "plugin_a::net@1.2.0 - hello");
+    }
+
+    fn json(record: &Record, stable: bool) -> String {
+        JsonLine {
+            style: &JsonStyle {
+                stable,
+                ..Default::default()
+            },
+            record,
+            message: record.args(),
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn json_escapes_fields() {
+        let line = json(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("app")
+                .args(format_args!("say \"hi\"\\\n\u{1}"))
+                .build(),
+            false,
+        );
+
+        let message = line.split_once(",\"message\":").unwrap().1;
+        assert!(message.starts_with(r#""say \"hi\"\\\n\u0001","thread":"#));
+    }
+
+    #[test]
+    fn logfmt_quotes_values() {
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("app")
+            .line(Some(7))
+            .args(format_args!("say \"hi\" a=b\\\n"))
+            .build();
+        let line = LogfmtLine {
+            style: &JsonStyle {
+                fields: vec![
+                    ("service".to_string(), "checkout".to_string()),
+                    ("empty".to_string(), String::new()),
+                    ("path".to_string(), "C:\\logs".to_string()),
+                ],
+                ..Default::default()
+            },
+            record: &record,
+            message: record.args(),
+        }
+        .to_string();
+
+        let (time, rest) = line.strip_prefix("time=").unwrap().split_once(' ').unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(time).is_ok());
+        assert_eq!(
+            rest.split_once(" thread=").unwrap().0,
+            r#"level=warn target=app msg="say \"hi\" a=b\\\n" line=7"#
+        );
+        assert!(line.ends_with(r#" service=checkout empty="" path="C:\\logs""#));
+    }
+
+    #[test]
+    fn access_lines() {
+        let offset = chrono::FixedOffset::east_opt(2 * 3600);
+        fn line(combined: bool, offset: Option<chrono::FixedOffset>, message: &str) -> String {
+            AccessLine {
+                combined,
+                offset,
+                message: &format_args!("{}", message),
+            }
+            .to_string()
+        }
+
+        let common = line(
+            false,
+            offset,
+            r#"served remote_addr=127.0.0.1 user="bob smith" method=GET path=/index.html status=200 bytes=2326"#,
+        );
+        let (start, rest) = common.split_once(" [").unwrap();
+        assert_eq!(start, "127.0.0.1 - bob_smith");
+        let (time, rest) = rest.split_once("] ").unwrap();
+        assert!(
+            chrono::DateTime::parse_from_str(time, "%d/%b/%Y:%H:%M:%S %z").is_ok(),
+            "{}",
+            time
+        );
+        assert!(time.ends_with(" +0200"), "{}", time);
+        assert_eq!(rest, r#""GET /index.html HTTP/1.1" 200 2326"#);
+
+        let combined = line(
+            true,
+            offset,
+            r#"method=POST path=/api protocol=HTTP/2 status=201 referer=https://example.com user_agent="curl/8.0 \"x\"""#,
+        );
+        assert!(combined.starts_with("- - - ["), "{}", combined);
+        assert!(
+            combined
+                .ends_with(r#"] "POST /api HTTP/2" 201 - "https://example.com" "curl/8.0 \"x\"""#),
+            "{}",
+            combined
+        );
+
+        let other = line(true, offset, "not an access record");
+        assert!(other.ends_with(r#"] "-" - - "-" "-""#), "{}", other);
+    }
+
+    #[test]
+    fn json_static_fields() {
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("app")
+            .args(format_args!("hello"))
+            .build();
+        let line = JsonLine {
+            style: &JsonStyle {
+                fields: vec![
+                    ("service".to_string(), "checkout".to_string()),
+                    ("env".to_string(), "prod \"eu\"".to_string()),
+                ],
+                ..Default::default()
+            },
+            record: &record,
+            message: record.args(),
+        }
+        .to_string();
+
+        assert!(line.ends_with(r#","service":"checkout","env":"prod \"eu\""}"#));
+    }
+
+    #[test]
+    fn json_stable_keys() {
+        // Unnamed thread, so `thread` is unknown too.
+        let stable = std::thread::spawn(|| {
+            json(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("app")
+                    .args(format_args!("hello"))
+                    .build(),
+                true,
+            )
+        })
+        .join()
+        .unwrap();
+        assert!(stable.starts_with(r#"{"timestamp":""#));
+        assert!(stable.ends_with(
+            r#","level":"INFO","target":"app","message":"hello","file":null,"line":null,"thread":null}"#
+        ));
+
+        let sparse = std::thread::spawn(|| {
+            json(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("app")
+                    .args(format_args!("hello"))
+                    .line(Some(3))
+                    .build(),
+                false,
+            )
+        })
+        .join()
+        .unwrap();
+        assert!(sparse.ends_with(r#","message":"hello","line":3}"#));
+    }
+}
@@ -0,0 +1,80 @@
+//! One shared copy of each target, for the records kept in memory.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+
+/// How many distinct targets are shared. Beyond it, e.g. when targets are built at runtime,
+/// new targets are copied for each record instead of growing the set without bound.
+const MAX_TARGETS: usize = 4096;
+
+/// Hands out the records' targets, sharing the copies of repeated ones unless disabled.
+pub(crate) struct Targets(Option<RwLock<BTreeSet<Arc<str>>>>);
+
+impl Targets {
+    pub(crate) const fn new(intern: bool) -> Self {
+        if intern {
+            Self(Some(RwLock::new(BTreeSet::new())))
+        } else {
+            Self(None)
+        }
+    }
+
+    /// `target`, shared with the earlier records with the same target.
+    pub(crate) fn get(&self, target: &str) -> Arc<str> {
+        let Some(targets) = &self.0 else {
+            return Arc::from(target);
+        };
+        if let Some(shared) = targets
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target)
+        {
+            return shared.clone();
+        }
+
+        let mut targets = targets.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(shared) = targets.get(target) {
+            return shared.clone();
+        }
+        let shared = Arc::<str>::from(target);
+        if targets.len() < MAX_TARGETS {
+            targets.insert(shared.clone());
+        }
+
+        shared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Targets, MAX_TARGETS};
+
+    #[test]
+    fn shares_repeated_targets() {
+        let targets = Targets::new(true);
+        assert!(Arc::ptr_eq(
+            &targets.get("app::db"),
+            &targets.get("app::db")
+        ));
+        assert!(!Arc::ptr_eq(&targets.get("app::db"), &targets.get("app")));
+
+        let copies = Targets::new(false);
+        assert!(!Arc::ptr_eq(&copies.get("app::db"), &copies.get("app::db")));
+    }
+
+    #[test]
+    fn bounded() {
+        let targets = Targets::new(true);
+        for i in 0..MAX_TARGETS {
+            targets.get(&i.to_string());
+        }
+
+        assert!(Arc::ptr_eq(&targets.get("0"), &targets.get("0")));
+        assert_eq!(&*targets.get("app"), "app");
+        assert!(!Arc::ptr_eq(&targets.get("app"), &targets.get("app")));
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use log::{LevelFilter, Metadata};
+
+/// Which outputs a [`level_for_scoped`](crate::LoggingBuilder::level_for_scoped) override
+/// applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LevelScope {
+    /// The module's level on every output unless a [`Terminal`](Self::Terminal) or
+    /// [`File`](Self::File) override says otherwise.
+    Root,
+    /// Only the terminal.
+    Terminal,
+    /// Only the log file.
+    File,
+    /// Every output, replacing any earlier `Terminal` and `File` overrides for the module.
+    All,
+}
+
+/// Crates quieted to `Warn` by
+/// [`quiet_dependencies`](crate::LoggingBuilder::quiet_dependencies).
+pub(crate) const QUIET_DEPENDENCIES: &[&str] =
+    &["hyper", "mio", "want", "tokio", "reqwest", "rustls"];
+
+/// An output with its own module overrides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sink {
+    Terminal,
+    File,
+}
+
+/// The level configuration consulted for every record, shared with the
+/// [`LoggingHandle`](crate::LoggingHandle) so it can be changed at runtime.
+#[derive(Clone)]
+pub(crate) struct Levels {
+    global: LevelFilter,
+    modules: HashMap<String, LevelFilter>,
+    terminal: HashMap<String, LevelFilter>,
+    file: HashMap<String, LevelFilter>,
+    /// Consulted for targets no module entry matches, before falling back to `global`.
+    resolver: Option<Arc<LevelResolver>>,
+    /// Targets raised to `Trace` by debug scopes, with the number of live scopes for each.
+    elevated: HashMap<String, usize>,
+    /// Whether records no output wants still reach the logger, for
+    /// [`dump_on_error`](crate::LoggingBuilder::dump_on_error).
+    keep_suppressed: bool,
+    /// Whether a [`start_capture`](crate::LoggingHandle::start_capture) session wants
+    /// every record.
+    capturing: bool,
+}
+
+pub(crate) type SharedLevels = Arc<RwLock<Levels>>;
+
+pub(crate) type LevelResolver = dyn Fn(&str) -> Option<LevelFilter> + Send + Sync;
+
+/// The level configuration at one point in time, from [`LoggingHandle::snapshot`], to be
+/// put back with [`LoggingHandle::restore`].
+///
+/// A snapshot can also be changed before restoring it, to apply a whole new configuration
+/// at once.
+///
+/// [`LoggingHandle::snapshot`]: crate::LoggingHandle::snapshot
+/// [`LoggingHandle::restore`]: crate::LoggingHandle::restore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelSnapshot {
+    global: LevelFilter,
+    modules: HashMap<String, LevelFilter>,
+    terminal: HashMap<String, LevelFilter>,
+    file: HashMap<String, LevelFilter>,
+}
+
+impl LevelSnapshot {
+    /// Sets the level for targets without a module entry.
+    pub fn global_level(mut self, level: LevelFilter) -> Self {
+        self.global = level;
+
+        self
+    }
+
+    /// Sets the level for `module` and its submodules on every output, replacing its terminal
+    /// and file entries, like [`LevelScope::All`].
+    pub fn level_for(mut self, module: impl Into<String>, level: LevelFilter) -> Self {
+        let module = module.into();
+        self.terminal.remove(&module);
+        self.file.remove(&module);
+        self.modules.insert(module, level);
+
+        self
+    }
+}
+
+impl Levels {
+    pub(crate) fn new(global: LevelFilter, modules: HashMap<String, LevelFilter>) -> Self {
+        Self {
+            global,
+            modules,
+            terminal: HashMap::new(),
+            file: HashMap::new(),
+            resolver: None,
+            elevated: HashMap::new(),
+            keep_suppressed: false,
+            capturing: false,
+        }
+    }
+
+    /// Asks `resolver` for the level of targets without a module entry.
+    pub(crate) fn with_resolver(mut self, resolver: Option<Arc<LevelResolver>>) -> Self {
+        self.resolver = resolver;
+
+        self
+    }
+
+    /// Lets every record reach the logger, including those no output wants.
+    pub(crate) fn with_suppressed(mut self, keep: bool) -> Self {
+        self.keep_suppressed = keep;
+
+        self
+    }
+
+    /// Adds module overrides that only apply to `sink`.
+    pub(crate) fn with_sink(mut self, sink: Sink, modules: HashMap<String, LevelFilter>) -> Self {
+        match sink {
+            Sink::Terminal => self.terminal = modules,
+            Sink::File => self.file = modules,
+        }
+
+        self
+    }
+
+    /// The level for `target`, using the most specific module entry that matches it.
+    ///
+    /// Targets inside an elevated scope are always `Trace`.
+    pub(crate) fn level_for(&self, target: &str) -> LevelFilter {
+        if !self.elevated.is_empty()
+            && module_parents(target).any(|m| self.elevated.contains_key(m))
+        {
+            return LevelFilter::Trace;
+        }
+
+        module_parents(target)
+            .find_map(|m| self.modules.get(m).copied())
+            .or_else(|| self.resolve(target))
+            .unwrap_or(self.global)
+    }
+
+    /// The level for `target` on `sink`, using the most specific module entry that matches
+    /// it. At the same module, the sink's own entry wins.
+    pub(crate) fn sink_level_for(&self, sink: Sink, target: &str) -> LevelFilter {
+        let overrides = match sink {
+            Sink::Terminal => &self.terminal,
+            Sink::File => &self.file,
+        };
+        if overrides.is_empty() {
+            return self.level_for(target);
+        }
+
+        if !self.elevated.is_empty()
+            && module_parents(target).any(|m| self.elevated.contains_key(m))
+        {
+            return LevelFilter::Trace;
+        }
+
+        module_parents(target)
+            .find_map(|m| overrides.get(m).or_else(|| self.modules.get(m)).copied())
+            .or_else(|| self.resolve(target))
+            .unwrap_or(self.global)
+    }
+
+    fn resolve(&self, target: &str) -> Option<LevelFilter> {
+        self.resolver.as_ref().and_then(|resolver| resolver(target))
+    }
+
+    /// Whether `metadata` is enabled for outputs without their own overrides.
+    pub(crate) fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    pub(crate) fn sink_enabled(&self, sink: Sink, metadata: &Metadata) -> bool {
+        metadata.level() <= self.sink_level_for(sink, metadata.target())
+    }
+
+    /// Whether any output wants `metadata`.
+    pub(crate) fn enabled_anywhere(&self, metadata: &Metadata) -> bool {
+        self.enabled(metadata)
+            || self.sink_enabled(Sink::Terminal, metadata)
+            || self.sink_enabled(Sink::File, metadata)
+    }
+
+    /// The most verbose level any target can currently log at. With a resolver, which may
+    /// pick any level, that is always `Trace`.
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        if !self.elevated.is_empty()
+            || self.keep_suppressed
+            || self.capturing
+            || self.resolver.is_some()
+        {
+            return LevelFilter::Trace;
+        }
+
+        self.modules
+            .values()
+            .chain(self.terminal.values())
+            .chain(self.file.values())
+            .copied()
+            .fold(self.global, Ord::max)
+    }
+
+    pub(crate) fn global(&self) -> LevelFilter {
+        self.global
+    }
+
+    /// The module entries as `module=level` directives, sorted by module, with the terminal
+    /// and file overrides prefixed by their output, e.g. `hyper=warn,file:app=trace`.
+    pub(crate) fn directives(&self) -> String {
+        let mut directives = vec![];
+        for (prefix, modules) in [
+            ("", &self.modules),
+            ("terminal:", &self.terminal),
+            ("file:", &self.file),
+        ] {
+            let mut entries = modules.iter().collect::<Vec<_>>();
+            entries.sort();
+            for (module, level) in entries {
+                let level = level.as_str().to_ascii_lowercase();
+                directives.push(format!("{}{}={}", prefix, module, level));
+            }
+        }
+
+        directives.join(",")
+    }
+
+    pub(crate) fn snapshot(&self) -> LevelSnapshot {
+        LevelSnapshot {
+            global: self.global,
+            modules: self.modules.clone(),
+            terminal: self.terminal.clone(),
+            file: self.file.clone(),
+        }
+    }
+
+    pub(crate) fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+    }
+
+    /// Replaces the levels with `snapshot`, keeping the live debug scopes.
+    pub(crate) fn restore(&mut self, snapshot: LevelSnapshot) {
+        self.global = snapshot.global;
+        self.modules = snapshot.modules;
+        self.terminal = snapshot.terminal;
+        self.file = snapshot.file;
+    }
+
+    pub(crate) fn elevate(&mut self, target: &str) {
+        *self.elevated.entry(target.to_string()).or_default() += 1;
+    }
+
+    pub(crate) fn unelevate(&mut self, target: &str) {
+        if let Some(count) = self.elevated.get_mut(target) {
+            *count -= 1;
+            if *count == 0 {
+                self.elevated.remove(target);
+            }
+        }
+    }
+
+    /// Takes over `old`'s debug scopes, which outlive a reconfigure.
+    pub(crate) fn keep_scopes(&mut self, old: &Levels) {
+        self.elevated = old.elevated.clone();
+    }
+}
+
+/// Levels parsed from a directive string like `info,mycrate=debug,hyper=warn`.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Directives {
+    pub(crate) global: Option<LevelFilter>,
+    pub(crate) modules: Vec<(String, LevelFilter)>,
+}
+
+/// Parses comma separated `level`, `module=level` and `module` directives, the same syntax
+/// as `env_logger`. A bare module is enabled at `Trace`; later directives win.
+pub(crate) fn parse_directives(directives: &str) -> anyhow::Result<Directives> {
+    let mut parsed = Directives::default();
+
+    for directive in directives.split(',').map(str::trim) {
+        if directive.is_empty() {
+            continue;
+        }
+
+        let (module, level) = match directive.split_once('=') {
+            Some((module, level)) => (Some(module.trim()), Some(level.trim())),
+            None if directive.parse::<LevelFilter>().is_ok() => (None, Some(directive)),
+            None => (Some(directive), None),
+        };
+
+        let level = match level {
+            Some(level) => level.parse().map_err(|_| {
+                anyhow::anyhow!("Invalid level {:?} in directive {:?}", level, directive)
+            })?,
+            None => LevelFilter::Trace,
+        };
+
+        match module {
+            Some("") => anyhow::bail!("Missing module in directive {:?}", directive),
+            Some(module) => parsed.modules.push((module.to_string(), level)),
+            None => parsed.global = Some(level),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// `target` followed by each of its parent modules, e.g. `a::b::c`, `a::b`, `a`.
+pub(crate) fn module_parents(target: &str) -> impl Iterator<Item = &str> {
+    let mut next = Some(target);
+
+    std::iter::from_fn(move || {
+        let current = next?;
+        next = current.rfind("::").map(|i| &current[..i]);
+
+        Some(current)
+    })
+}
+
+/// A fern filter answering `enabled` with the current `levels`.
+pub(crate) fn filter<F>(levels: &SharedLevels, enabled: F) -> impl Fn(&Metadata) -> bool
+where
+    F: Fn(&Levels, &Metadata) -> bool + Send + Sync + 'static,
+{
+    let levels = levels.clone();
+
+    move |metadata| enabled(&levels.read().unwrap_or_else(|e| e.into_inner()), metadata)
+}
+
+/// Updates `log`'s global max level so records enabled by `levels` aren't filtered out
+/// before they reach the logger, and records that never are don't reach it at all.
+pub(crate) fn apply_max_level(levels: &Levels) {
+    log::set_max_level(levels.max_level());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use log::LevelFilter;
+
+    use super::{module_parents, parse_directives, Directives, Levels, Sink};
+
+    #[test]
+    fn most_specific_module_wins() {
+        let levels = Levels::new(
+            LevelFilter::Info,
+            HashMap::from([
+                ("hyper".to_string(), LevelFilter::Warn),
+                ("hyper::client".to_string(), LevelFilter::Debug),
+            ]),
+        );
+
+        assert_eq!(levels.level_for("app"), LevelFilter::Info);
+        assert_eq!(levels.level_for("hyper::proto"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("hyper::client::pool"), LevelFilter::Debug);
+        assert_eq!(levels.level_for("hyperx"), LevelFilter::Info);
+        assert_eq!(levels.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn resolver() {
+        let levels = Levels::new(
+            LevelFilter::Info,
+            HashMap::from([("app::db::tests".to_string(), LevelFilter::Warn)]),
+        )
+        .with_sink(
+            Sink::File,
+            HashMap::from([("app::ui".to_string(), LevelFilter::Error)]),
+        )
+        .with_resolver(Some(Arc::new(|target: &str| {
+            target
+                .split("::")
+                .any(|part| part == "tests")
+                .then_some(LevelFilter::Trace)
+        })));
+
+        assert_eq!(levels.level_for("app::net::tests"), LevelFilter::Trace);
+        assert_eq!(levels.level_for("app::tests::helpers"), LevelFilter::Trace);
+        assert_eq!(levels.level_for("app::net"), LevelFilter::Info);
+        assert_eq!(levels.level_for("app::db::tests"), LevelFilter::Warn);
+        assert_eq!(
+            levels.sink_level_for(Sink::File, "app::ui::tests"),
+            LevelFilter::Error
+        );
+        assert_eq!(
+            levels.sink_level_for(Sink::File, "app::net::tests"),
+            LevelFilter::Trace
+        );
+        assert_eq!(levels.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn nested_elevation() {
+        let mut levels = Levels::new(
+            LevelFilter::Info,
+            HashMap::from([("checkout::db".to_string(), LevelFilter::Warn)]),
+        );
+
+        levels.elevate("checkout");
+        levels.elevate("checkout");
+        assert_eq!(levels.level_for("checkout::db"), LevelFilter::Trace);
+        assert_eq!(levels.max_level(), LevelFilter::Trace);
+
+        levels.unelevate("checkout");
+        assert_eq!(levels.level_for("checkout"), LevelFilter::Trace);
+
+        levels.unelevate("checkout");
+        assert_eq!(levels.level_for("checkout"), LevelFilter::Info);
+        assert_eq!(levels.level_for("checkout::db"), LevelFilter::Warn);
+        assert_eq!(levels.max_level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn sink_overrides() {
+        let levels = Levels::new(
+            LevelFilter::Info,
+            HashMap::from([("hyper::client".to_string(), LevelFilter::Debug)]),
+        )
+        .with_sink(
+            Sink::Terminal,
+            HashMap::from([
+                ("hyper".to_string(), LevelFilter::Off),
+                ("hyper::client".to_string(), LevelFilter::Warn),
+            ]),
+        );
+
+        assert_eq!(
+            levels.sink_level_for(Sink::Terminal, "hyper::proto"),
+            LevelFilter::Off
+        );
+        assert_eq!(
+            levels.sink_level_for(Sink::Terminal, "hyper::client"),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            levels.sink_level_for(Sink::File, "hyper::proto"),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            levels.sink_level_for(Sink::File, "hyper::client"),
+            LevelFilter::Debug
+        );
+        assert_eq!(levels.level_for("hyper::proto"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn parents() {
+        assert_eq!(
+            module_parents("a::b::c").collect::<Vec<_>>(),
+            ["a::b::c", "a::b", "a"]
+        );
+    }
+
+    #[test]
+    fn directives() {
+        assert_eq!(
+            parse_directives(" info, mycrate=debug ,hyper=WARN,,noisy").unwrap(),
+            Directives {
+                global: Some(LevelFilter::Info),
+                modules: vec![
+                    ("mycrate".to_string(), LevelFilter::Debug),
+                    ("hyper".to_string(), LevelFilter::Warn),
+                    ("noisy".to_string(), LevelFilter::Trace),
+                ],
+            }
+        );
+        assert_eq!(parse_directives("").unwrap(), Directives::default());
+
+        let err = parse_directives("info,mycrate=loud").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid level "loud" in directive "mycrate=loud""#
+        );
+        let err = parse_directives("=debug").unwrap_err();
+        assert_eq!(err.to_string(), r#"Missing module in directive "=debug""#);
+    }
+}
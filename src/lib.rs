@@ -1,16 +1,150 @@
 use std::{
     collections::HashMap,
-    fs::{create_dir_all, read_dir, remove_file},
-    path::Path,
+    fmt,
+    fs::File,
+    io::{self, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{channel, sync_channel, Receiver, SyncSender},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
-use chrono::{Local, NaiveDateTime};
+use anyhow::Context;
 use directories::ProjectDirs;
-use fern::Dispatch;
-use log::LevelFilter;
+use fern::{Dispatch, FormatCallback};
+use log::{Level, LevelFilter, Log, Record};
+
+mod binary;
+mod build_info;
+mod clock;
+mod color;
+mod columns;
+mod config;
+mod context;
+mod desktop;
+mod early;
+mod error;
+mod event;
+mod files;
+pub mod filter;
+mod format;
+mod intern;
+mod levels;
+#[cfg(feature = "android")]
+mod logcat;
+mod manifest;
+mod memory;
+mod non_blocking;
+mod panic;
+mod periodic;
+mod query;
+mod resources;
+mod rotation;
+mod settings;
+#[cfg(unix)]
+mod signal;
+mod sinks;
+mod socket;
+mod stdout;
+mod summary;
+#[cfg(test)]
+mod test_util;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+mod wrap;
+
+pub use binary::decode_binary_log;
+pub use build_info::BuildInfo;
+pub use color::ColorScheme;
+pub use columns::{Column, ColumnLayout};
+pub use context::ContextGuard;
+pub use error::{ErrorChain, LoggingError};
+pub use event::LogEvent;
+pub use files::FileShare;
+pub use filter::Filter;
+pub use format::LogFormat;
+pub use levels::{LevelScope, LevelSnapshot};
+pub use non_blocking::{DropBehavior, Overflow};
+pub use query::{query_logs, QueryFilter};
+pub use rotation::{FileMode, RotateWhen, RotationOrder};
+pub use settings::Settings;
+#[cfg(any(test, feature = "testing"))]
+pub use testing::LogCapture;
+
+use binary::BinaryWriter;
+use clock::{Clock, OffsetClock, SystemClock};
+use color::Palette;
+use config::{Config, Effective, LineFormat};
+use files::{
+    Closable, CrashFiles, DirLock, FileSetup, Finalize, LogFiles, Preallocated, Reopen,
+    RetentionThread, RetryingWriter, SegmentFiles, SetupTimeout, ShutdownRotation, WriterFactory,
+};
+use filter::Filtered;
+use format::{
+    AccessLine, GelfLine, JsonLine, JsonStyle, LevelNames, LogfmtLine, TargetDisplay, TextLine,
+    TextStyle,
+};
+use intern::Targets;
+use levels::{LevelResolver, Levels, SharedLevels, Sink};
+use manifest::{Lines, Manifest};
+use memory::MemoryBudget;
+use non_blocking::{EncodeRecord, Framing, NonBlocking, Worker};
+use periodic::Periodic;
+use rotation::RotateCallback;
+use sinks::{
+    AppendBacktrace, Broadcast, Capture, Channel, Counter, Counts, ErrorCooldown, ErrorDump,
+    LineWriter, MessageTransform, Ordered, RateLimited, Reloadable, RingBuffer, Sequenced,
+    TransformMessage,
+};
+use socket::{Endpoint, SocketWriter};
+use stdout::StdoutTee;
+use summary::Summary;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::error::{with_backtrace, AnyhowErrorChain, StdErrorChain};
+    pub use crate::format::{Field, Now, Optional};
+    pub use fern::FormatCallback;
+    pub use log;
+    pub use log::Record;
+}
+
+/// The version of this crate, for telling which version wrote a log when formats change.
+pub const LOGGING_CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The [`LogFormat::Json`] key [`LoggingBuilder::show_logger_version`] adds.
+const LOGGER_VERSION_KEY: &str = "_logger_version";
+
+/// The field [`LoggingBuilder::session_label`] adds to JSON records.
+const SESSION_KEY: &str = "session";
+
+/// The version of the JSON, logfmt and GELF record layouts written by this crate, for
+/// [`LoggingBuilder::schema_version`]. Changes whenever a built-in key is added, renamed or
+/// removed.
+pub const LOG_SCHEMA_VERSION: &str = "1";
+
+/// The field [`LoggingBuilder::schema_version`] adds to JSON records.
+const SCHEMA_KEY: &str = "schema";
 
 const CHRONO_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
-const MAX_LOG_FILES: u8 = 5;
+const DEFAULT_MAX_LOG_FILES: usize = 5;
+const DEFAULT_MAX_CRASH_FILES: usize = 20;
+const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+const CHANNEL_CAPACITY: usize = 1024;
+/// The events each [`LoggingHandle::subscribe`] receiver holds before missing new ones.
+const SUBSCRIBER_CAPACITY: usize = 1024;
+/// How long [`DropBehavior::FlushAndJoin`] waits for the writer threads.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+type FormatFn = dyn Fn(FormatCallback, &fmt::Arguments, &Record) + Send + Sync;
+type DiagnosticsFn = dyn Fn(&str) + Send + Sync;
+#[cfg(feature = "error-reporter")]
+type ReportFn = dyn Fn(&LogEvent) + Send + Sync;
 
 pub struct LoggingBuilder {
     app_name: String,
@@ -21,6 +155,141 @@ pub struct LoggingBuilder {
     organization: String,
 
     level_for: HashMap<String, LevelFilter>,
+    terminal_level_for: HashMap<String, LevelFilter>,
+    file_level_for: HashMap<String, LevelFilter>,
+    level_resolver: Option<Arc<LevelResolver>>,
+    quiet_dependencies: bool,
+    quiet_targets: Vec<String>,
+    filters: Vec<Box<dyn Filter>>,
+    terminal_filters: Vec<Box<dyn Filter>>,
+    file_filters: Vec<Box<dyn Filter>>,
+    /// The first malformed [`levels`](Self::levels) directive string, reported by `finish`.
+    directives_error: Option<anyhow::Error>,
+
+    terminal_show_target: bool,
+    terminal_timestamp: bool,
+    terminal_show_level: bool,
+    columns: Option<ColumnLayout>,
+    split_streams: bool,
+    /// For [`terminal_width`](Self::terminal_width): `Some(None)` uses the terminal's width.
+    terminal_width: Option<Option<usize>>,
+    color_scheme: Option<ColorScheme>,
+    custom_format: Option<Arc<FormatFn>>,
+    transform_message: Option<Arc<MessageTransform>>,
+    format: LogFormat,
+    terminal_format: Option<LogFormat>,
+    file_format: Option<LogFormat>,
+    access_log_target: String,
+    json_stable: bool,
+    json_fields: Vec<(String, String)>,
+    default_target: Option<String>,
+    strip_app_prefix: bool,
+    target_versions: HashMap<String, String>,
+    show_uptime: bool,
+    show_sequence: bool,
+    show_logger_version: bool,
+    build_info: Option<BuildInfo>,
+    log_effective_config: bool,
+    log_panics: bool,
+    capture_backtrace: bool,
+    heartbeat: Option<Duration>,
+    heartbeat_level: Level,
+    heartbeat_message: String,
+    resource_stats: Option<Duration>,
+    verbose_errors: bool,
+    empty_message_placeholder: Option<String>,
+    level_names: LevelNames,
+    /// When logging started, for [`show_uptime`](Self::show_uptime). Reset by `finish`.
+    started: Instant,
+    clock: Arc<dyn Clock>,
+    /// `(hours, minutes)` for [`fixed_offset`](Self::fixed_offset), checked by `finish`.
+    fixed_offset: Option<(i32, i32)>,
+
+    max_log_files: usize,
+    on_rotate: Option<Arc<RotateCallback>>,
+    diagnostics: Option<Arc<DiagnosticsFn>>,
+    writer_factory: Option<Arc<WriterFactory>>,
+    file_handle: Option<File>,
+    max_log_age: Option<Duration>,
+    clock_skew: Duration,
+    background_rotation: Option<Duration>,
+    reopen_on_sighup: bool,
+    restrict_permissions: bool,
+    filename_format: String,
+    filename_microseconds: bool,
+    sanitize_filenames: bool,
+    session_label: Option<String>,
+    schema_version: Option<String>,
+    file_mode: Option<FileMode>,
+    history_max_bytes: Option<u64>,
+    per_level_files: bool,
+    per_level_files_cumulative: bool,
+    split_by_target_segment: bool,
+    rotation_order: RotationOrder,
+    rotate_when: RotateWhen,
+    file_share: FileShare,
+    /// The audit log's file name and target.
+    audit: Option<(String, String)>,
+    audit_in_log_file: bool,
+    crash_files: bool,
+    max_crash_files: usize,
+    finalize_on_exit: bool,
+    preallocate: Option<u64>,
+    daily_rollover: bool,
+    max_lines: Option<u64>,
+    write_manifest: bool,
+    capture_stdout: bool,
+    file_mode_env: Option<String>,
+    env_override: bool,
+
+    log_dir: Option<PathBuf>,
+    portable: bool,
+    /// Finds the executable for [`portable`](Self::portable); replaced in tests.
+    current_exe: fn() -> io::Result<PathBuf>,
+    /// Finds the platform cache directory for the qualifier, organization and app name;
+    /// replaced in tests.
+    cache_dir: fn(&str, &str, &str) -> Option<PathBuf>,
+    project_dirs_fallback: Option<PathBuf>,
+    require_existing_dir: bool,
+    fallback_dirs: Vec<PathBuf>,
+    setup_timeout: Option<Duration>,
+    fallback_to_terminal: bool,
+    /// `(retries, first pause)` for [`write_retries`](Self::write_retries).
+    write_retries: Option<(u32, Duration)>,
+
+    non_blocking: bool,
+    line_buffered: bool,
+    queue_capacity: usize,
+    overflow: Overflow,
+    drop_behavior: DropBehavior,
+
+    ordered: bool,
+    rate_limit: Option<u32>,
+    rate_limit_exempt_errors: bool,
+    error_cooldown: Option<Duration>,
+    shutdown_summary: bool,
+    ring_buffer: usize,
+    dump_on_error: usize,
+    max_memory: Option<usize>,
+    intern_targets: bool,
+    channel: Option<SyncSender<LogEvent>>,
+    #[cfg(feature = "error-reporter")]
+    error_reporter: Option<Arc<ReportFn>>,
+    tcp_output: Option<(io::Result<Vec<SocketAddr>>, LevelFilter)>,
+    unix_socket_output: Option<(PathBuf, LevelFilter)>,
+    #[cfg(feature = "android")]
+    logcat_output: Option<LevelFilter>,
+    /// Why [`capture_early`](Self::capture_early) could not install its logger.
+    early_error: Option<anyhow::Error>,
+    /// The levels of the handle being reconfigured, which its debug scopes share. They are
+    /// only replaced once the new configuration is built.
+    reconfiguring: Option<SharedLevels>,
+}
+
+impl Default for LoggingBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LoggingBuilder {
@@ -34,11 +303,134 @@ impl LoggingBuilder {
             organization: "".to_string(),
 
             level_for: HashMap::new(),
+            terminal_level_for: HashMap::new(),
+            file_level_for: HashMap::new(),
+            level_resolver: None,
+            quiet_dependencies: false,
+            quiet_targets: levels::QUIET_DEPENDENCIES
+                .iter()
+                .map(|target| target.to_string())
+                .collect(),
+            filters: vec![],
+            terminal_filters: vec![],
+            file_filters: vec![],
+            directives_error: None,
+
+            terminal_show_target: true,
+            terminal_timestamp: false,
+            terminal_show_level: true,
+            split_streams: false,
+            columns: None,
+            terminal_width: None,
+            color_scheme: None,
+            custom_format: None,
+            transform_message: None,
+            format: LogFormat::Text,
+            terminal_format: None,
+            file_format: None,
+            access_log_target: "access".to_string(),
+            json_stable: false,
+            json_fields: Vec::new(),
+            default_target: None,
+            strip_app_prefix: false,
+            target_versions: HashMap::new(),
+            show_uptime: false,
+            show_sequence: false,
+            show_logger_version: false,
+            build_info: None,
+            log_effective_config: false,
+            log_panics: false,
+            capture_backtrace: false,
+            heartbeat: None,
+            heartbeat_level: Level::Debug,
+            heartbeat_message: "heartbeat".to_string(),
+            resource_stats: None,
+            verbose_errors: false,
+            empty_message_placeholder: None,
+            level_names: LevelNames::default(),
+            started: Instant::now(),
+            clock: Arc::new(SystemClock),
+            fixed_offset: None,
+
+            max_log_files: DEFAULT_MAX_LOG_FILES,
+            on_rotate: None,
+            diagnostics: None,
+            writer_factory: None,
+            file_handle: None,
+            max_log_age: None,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            background_rotation: None,
+            reopen_on_sighup: false,
+            restrict_permissions: false,
+            filename_format: CHRONO_FORMAT.to_string(),
+            filename_microseconds: false,
+            sanitize_filenames: false,
+            session_label: None,
+            schema_version: None,
+            file_mode: None,
+            history_max_bytes: None,
+            per_level_files: false,
+            per_level_files_cumulative: false,
+            split_by_target_segment: false,
+            rotation_order: RotationOrder::ByFilename,
+            rotate_when: RotateWhen::Startup,
+            file_share: FileShare::All,
+            audit: None,
+            audit_in_log_file: false,
+            crash_files: false,
+            max_crash_files: DEFAULT_MAX_CRASH_FILES,
+            finalize_on_exit: false,
+            preallocate: None,
+            daily_rollover: false,
+            max_lines: None,
+            write_manifest: false,
+            capture_stdout: false,
+            file_mode_env: None,
+            env_override: false,
+
+            log_dir: None,
+            portable: false,
+            current_exe: std::env::current_exe,
+            cache_dir: |qualifier, organization, app_name| {
+                ProjectDirs::from(qualifier, organization, app_name)
+                    .map(|dirs| dirs.cache_dir().to_path_buf())
+            },
+            project_dirs_fallback: None,
+            require_existing_dir: false,
+            fallback_dirs: Vec::new(),
+            setup_timeout: None,
+            fallback_to_terminal: false,
+            write_retries: None,
+
+            non_blocking: false,
+            line_buffered: true,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow: Overflow::Block,
+            drop_behavior: DropBehavior::FlushAndJoin,
+
+            ordered: false,
+            rate_limit: None,
+            rate_limit_exempt_errors: false,
+            error_cooldown: None,
+            shutdown_summary: false,
+            ring_buffer: 0,
+            dump_on_error: 0,
+            max_memory: None,
+            intern_targets: true,
+            channel: None,
+            #[cfg(feature = "error-reporter")]
+            error_reporter: None,
+            tcp_output: None,
+            unix_socket_output: None,
+            #[cfg(feature = "android")]
+            logcat_output: None,
+            early_error: None,
+            reconfiguring: None,
         }
     }
 
-    pub fn app_name(mut self, app_name: impl ToString) -> Self {
-        self.app_name = app_name.to_string();
+    pub fn app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.app_name = app_name.into();
 
         self
     }
@@ -49,183 +441,5162 @@ impl LoggingBuilder {
         self
     }
 
-    pub fn qualifier(mut self, qualifier: impl ToString) -> Self {
-        self.qualifier = qualifier.to_string();
+    /// Sets the [`global_level`](Self::global_level) from a [`log::Level`], which maps to the
+    /// [`LevelFilter`] of the same name: `Error` to `LevelFilter::Error` and so on through
+    /// `Trace`. Use `global_level` for `LevelFilter::Off`.
+    pub fn global_level_from(self, level: log::Level) -> Self {
+        self.global_level(level.to_level_filter())
+    }
+
+    pub fn qualifier(mut self, qualifier: impl Into<String>) -> Self {
+        self.qualifier = qualifier.into();
+
+        self
+    }
+
+    pub fn organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = organization.into();
 
         self
     }
 
-    pub fn organization(mut self, organization: impl ToString) -> Self {
-        self.organization = organization.to_string();
+    /// Sets the global level and module levels from a directive string with the same syntax
+    /// as `env_logger`, e.g. `info,mycrate=debug,hyper=warn`.
+    ///
+    /// Each comma separated directive is a level, which sets the
+    /// [`global_level`](Self::global_level), `module=level`, which works like
+    /// [`level_for`](Self::level_for), or a bare module, which enables it at `Trace`.
+    /// [`finish`](Self::finish) fails if a directive is malformed, naming the directive.
+    pub fn levels(mut self, directives: impl AsRef<str>) -> Self {
+        match levels::parse_directives(directives.as_ref()) {
+            Ok(directives) => {
+                if let Some(level) = directives.global {
+                    self.global_level = level;
+                }
+                for (module, level) in directives.modules {
+                    self = self.level_for(module, level);
+                }
+            }
+            Err(e) => {
+                self.directives_error.get_or_insert(e);
+            }
+        }
 
         self
     }
 
-    pub fn level_for(mut self, module: impl ToString, level: LevelFilter) -> Self {
-        self.level_for.insert(module.to_string(), level);
+    /// Sets the level for `module` and its submodules on every output. Same as
+    /// [`level_for_scoped`](Self::level_for_scoped) with [`LevelScope::All`].
+    pub fn level_for(self, module: impl Into<String>, level: LevelFilter) -> Self {
+        self.level_for_scoped(module, level, LevelScope::All)
+    }
+
+    /// Sets the [`level_for`](Self::level_for) `module` from a [`log::Level`], mapped to the
+    /// [`LevelFilter`] of the same name as with [`global_level_from`](Self::global_level_from).
+    pub fn level_for_from(self, module: impl Into<String>, level: log::Level) -> Self {
+        self.level_for(module, level.to_level_filter())
+    }
+
+    /// Decides the level of targets that no [`level_for`](Self::level_for) entry matches.
+    /// Returning `None` leaves them at the [`global_level`](Self::global_level).
+    ///
+    /// Static entries for the target or any of its parent modules take precedence, on every
+    /// output. The resolver is called with the full target of every such record, including
+    /// those it ends up filtering out, so it should be cheap. As it can enable any level, the
+    /// `log` macros no longer skip disabled records before reaching the logger.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use log::LevelFilter;
+    ///
+    /// // Everything in a `tests` module at `Trace`.
+    /// let builder = logging::LoggingBuilder::new().level_resolver(Arc::new(|target: &str| {
+    ///     target
+    ///         .split("::")
+    ///         .any(|part| part == "tests")
+    ///         .then_some(LevelFilter::Trace)
+    /// }));
+    /// ```
+    pub fn level_resolver(mut self, resolver: Arc<LevelResolver>) -> Self {
+        self.level_resolver = Some(resolver);
 
         self
     }
 
-    pub fn finish(self) -> anyhow::Result<()> {
-        if self.app_name.is_empty() || self.qualifier.is_empty() || self.organization.is_empty() {
-            anyhow::bail!("Missing required fields")
+    /// Sets the level for `module` and its submodules on the outputs picked by `scope`, e.g.
+    /// silencing `hyper` on the terminal while keeping it in the file.
+    ///
+    /// Terminal and file overrides take precedence over the [`LevelScope::Root`] entry for the
+    /// same module. The channel, ring buffer and TCP outputs only use root entries.
+    pub fn level_for_scoped(
+        mut self,
+        module: impl Into<String>,
+        level: LevelFilter,
+        scope: LevelScope,
+    ) -> Self {
+        let module = module.into();
+        match scope {
+            LevelScope::Root => {
+                self.level_for.insert(module, level);
+            }
+            LevelScope::Terminal => {
+                self.terminal_level_for.insert(module, level);
+            }
+            LevelScope::File => {
+                self.file_level_for.insert(module, level);
+            }
+            LevelScope::All => {
+                self.terminal_level_for.remove(&module);
+                self.file_level_for.remove(&module);
+                self.level_for.insert(module, level);
+            }
         }
 
-        let term = Dispatch::new()
-            .format(|out, message, record| {
-                out.finish(format_args!(
-                    "[{}] {} - {}",
-                    record.level(),
-                    record.target(),
-                    message
-                ))
-            })
-            .level(LevelFilter::Debug)
-            .chain(std::io::stdout());
+        self
+    }
 
-        let project_dir = if let Some(d) =
-            ProjectDirs::from(&self.qualifier, &self.organization, &self.app_name)
-        {
-            d
-        } else {
-            anyhow::bail!("Unable to get project directories");
-        };
-        let mut log_dir = project_dir.cache_dir().to_path_buf();
-        log_dir.push("logs");
+    /// Logs common noisy dependencies, `hyper`, `mio`, `want`, `tokio`, `reqwest` and
+    /// `rustls`, at `Warn` at most. Defaults to `false`.
+    ///
+    /// A [`level_for`](Self::level_for) entry for one of them, or anything added with
+    /// [`quiet_dependency`](Self::quiet_dependency), always takes precedence, whichever is
+    /// set first. Quieting never raises a crate above the
+    /// [`global_level`](Self::global_level).
+    pub fn quiet_dependencies(mut self, quiet: bool) -> Self {
+        self.quiet_dependencies = quiet;
 
-        rotate_logs(&log_dir)?;
+        self
+    }
 
-        let time = Local::now();
+    /// Adds `target` to the crates quieted by
+    /// [`quiet_dependencies`](Self::quiet_dependencies).
+    pub fn quiet_dependency(mut self, target: impl Into<String>) -> Self {
+        self.quiet_targets.push(target.into());
 
-        let mut log_file_path = log_dir;
-        log_file_path.push(format!("{}.log", time.format(CHRONO_FORMAT)));
+        self
+    }
 
-        let file = Dispatch::new()
-            .format(|out, message, record| {
-                out.finish(format_args!(
-                    "[{}] {} {} - {}",
-                    record.level(),
-                    Local::now().naive_local().format(CHRONO_FORMAT),
-                    record.target(),
-                    message
-                ))
-            })
-            .level(LevelFilter::Debug)
-            .chain(fern::log_file(log_file_path)?);
+    /// Only logs records `filter` matches, on every output. Can be called more than once, in
+    /// which case records must match all of the filters. See [`filter`](mod@filter) for the
+    /// built-in filters and combinators.
+    ///
+    /// Filters see records after the level checks, so they can narrow what is logged but not
+    /// enable more.
+    pub fn filter(mut self, filter: Box<dyn Filter>) -> Self {
+        self.filters.push(filter);
 
-        let mut root = Dispatch::new().level(self.global_level);
-        for (mod_name, level) in self.level_for.iter() {
-            root = root.level_for(mod_name.clone(), level.clone());
-        }
+        self
+    }
 
-        root.chain(term).chain(file).apply()?;
+    /// Like [`filter`](Self::filter), but only for the terminal.
+    pub fn terminal_filter(mut self, filter: Box<dyn Filter>) -> Self {
+        self.terminal_filters.push(filter);
 
-        Ok(())
+        self
     }
-}
 
-/// Rotates all logs found in the `log_dir`.
-fn rotate_logs<P: AsRef<Path>>(log_dir: P) -> anyhow::Result<()> {
-    let mut logs = get_all_logs(log_dir)?;
+    /// Like [`filter`](Self::filter), but only for the log file.
+    pub fn file_filter(mut self, filter: Box<dyn Filter>) -> Self {
+        self.file_filters.push(filter);
+
+        self
+    }
 
-    while logs.len() >= MAX_LOG_FILES.into() {
-        let path = logs.pop().unwrap();
+    /// Whether terminal lines include the record's target. Defaults to `true`.
+    ///
+    /// The file format always includes the target.
+    pub fn terminal_show_target(mut self, show: bool) -> Self {
+        self.terminal_show_target = show;
 
-        remove_file(path)?;
+        self
     }
 
-    Ok(())
-}
+    /// Whether terminal lines include the time, after the level. Defaults to `false` to keep
+    /// the console uncluttered.
+    ///
+    /// The file format always includes the time.
+    pub fn terminal_timestamp(mut self, show: bool) -> Self {
+        self.terminal_timestamp = show;
 
-/// Gets all log files from the `log_dir` sorted by date.
-///
-/// **WARNING**: Any log file that cannot be parsed is deleted.
-fn get_all_logs<P: AsRef<Path>>(log_dir: P) -> anyhow::Result<Vec<String>> {
-    let log_dir = log_dir.as_ref();
+        self
+    }
 
-    if !log_dir.exists() {
-        create_dir_all(&log_dir)?;
+    /// Whether terminal lines include the `[LEVEL]` prefix. Defaults to `true`.
+    ///
+    /// The file format always includes the level.
+    pub fn terminal_show_level(mut self, show: bool) -> Self {
+        self.terminal_show_level = show;
+
+        self
     }
 
-    let mut log_files = vec![];
+    /// Wraps terminal lines longer than `width` characters, breaking them at spaces where
+    /// possible, or at the terminal's own width if `None`. Off by default.
+    ///
+    /// Lines are not wrapped when stdout is not a terminal, e.g. when piped to a file, or
+    /// when its width can't be found. The log file always has the full lines.
+    pub fn terminal_width(mut self, width: Option<usize>) -> Self {
+        self.terminal_width = Some(width);
 
-    let paths = read_dir(&log_dir)?;
-    for path in paths {
-        let path = path?.path();
-        let file_path = path.display().to_string();
-        let file_name = if let Some(n) = path.file_stem() {
-            n.to_str().unwrap_or_default()
-        } else {
-            continue;
-        };
+        self
+    }
 
-        let time = if let Ok(v) = NaiveDateTime::parse_from_str(file_name, CHRONO_FORMAT) {
-            v
-        } else {
-            std::fs::remove_file(path)?;
-            continue;
-        };
+    /// Lines up the time, level and target of the text log file in fixed-width columns, for
+    /// reading with fixed-width fonts or column-based tools.
+    ///
+    /// Fields wider than their column are cut short, ending in `~`, so the file stays
+    /// aligned. The terminal is not affected.
+    pub fn columns(mut self, layout: ColumnLayout) -> Self {
+        self.columns = Some(layout);
 
-        log_files.push((file_path, time));
+        self
     }
 
-    sort_log_files(&mut log_files);
+    /// Writes `Warn` and `Error` terminal lines to stderr and the rest to stdout, so shells
+    /// can separate them, e.g. `app 2>errors.txt`. Defaults to `false`, writing every line
+    /// to stdout.
+    ///
+    /// Each line goes to exactly one of the two streams.
+    pub fn split_streams(mut self, split: bool) -> Self {
+        self.split_streams = split;
 
-    Ok(log_files.iter().map(|(path, _)| path.to_string()).collect())
-}
+        self
+    }
 
-/// Intentionally split out to make it easier to test.
-#[inline]
-fn sort_log_files(logs: &mut Vec<(String, NaiveDateTime)>) {
-    logs.sort_by(|(_, a), (_, b)| b.cmp(a));
-}
+    /// Colors the `[LEVEL]` prefix on the terminal with `scheme`. Off by default.
+    ///
+    /// Uses truecolor when `COLORTERM` is `truecolor` or `24bit`, 256 colors when `TERM`
+    /// mentions `256color` and the 16 basic colors otherwise. Colors are left out when
+    /// stdout is not a terminal, `TERM` is `dumb`, `CLICOLOR` is `0` or `NO_COLOR` is set,
+    /// and shown even when not writing to a terminal if `CLICOLOR_FORCE` is set to anything
+    /// but `0`. `NO_COLOR` always wins. Log files are never colored.
+    pub fn color_scheme(mut self, scheme: ColorScheme) -> Self {
+        self.color_scheme = Some(scheme);
 
-#[cfg(test)]
-mod tests {
-    use chrono::{Duration, Local, NaiveDateTime};
+        self
+    }
 
-    use crate::CHRONO_FORMAT;
+    /// How records are rendered on the terminal and in the log file. Defaults to
+    /// [`LogFormat::Text`].
+    ///
+    /// [`terminal_format`](Self::terminal_format) and [`file_format`](Self::file_format)
+    /// override it for one of them.
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
 
-    #[test]
-    fn sort_log_files() {
-        let mut test_logs = vec![];
+        self
+    }
 
-        for file in vec![
-            Local::now()
-                .naive_local()
-                .checked_add_signed(Duration::seconds(60))
-                .unwrap()
-                .format(CHRONO_FORMAT),
-            Local::now()
-                .naive_local()
-                .checked_add_signed(Duration::seconds(160))
-                .unwrap()
-                .format(CHRONO_FORMAT),
-            Local::now()
-                .naive_local()
-                .checked_add_signed(Duration::seconds(260))
-                .unwrap()
-                .format(CHRONO_FORMAT),
-        ] {
-            test_logs.push(file.to_string());
+    /// How records are rendered on the terminal, e.g. colored text while the log file gets
+    /// JSON. Defaults to the [`format`](Self::format).
+    ///
+    /// [`LogFormat::Binary`] is shown as text.
+    pub fn terminal_format(mut self, format: LogFormat) -> Self {
+        self.terminal_format = Some(format);
+
+        self
+    }
+
+    /// How records are rendered in the log file and anything mirroring it, like the history
+    /// log. Defaults to the [`format`](Self::format).
+    pub fn file_format(mut self, format: LogFormat) -> Self {
+        self.file_format = Some(format);
+
+        self
+    }
+
+    /// The target whose records, and those of its submodules, [`LogFormat::CommonLog`] and
+    /// [`LogFormat::CombinedLog`] render as access log lines. Defaults to `access`.
+    pub fn access_log_target(mut self, target: impl Into<String>) -> Self {
+        self.access_log_target = target.into();
+
+        self
+    }
+
+    /// The [`terminal_format`](Self::terminal_format), or the [`format`](Self::format).
+    fn terminal_log_format(&self) -> LogFormat {
+        self.terminal_format.unwrap_or(self.format)
+    }
+
+    /// The [`file_format`](Self::file_format), or the [`format`](Self::format).
+    fn file_log_format(&self) -> LogFormat {
+        self.file_format.unwrap_or(self.format)
+    }
+
+    /// Whether [`LogFormat::Json`] objects always contain the same keys in the same order.
+    /// Defaults to `false`.
+    ///
+    /// When enabled, `file`, `line` and `thread`, and `seq` with
+    /// [`show_sequence`](Self::show_sequence), are written as `null` instead of being left
+    /// out, which keeps the schema fixed for columnar stores.
+    pub fn json_stable(mut self, stable: bool) -> Self {
+        self.json_stable = stable;
+
+        self
+    }
+
+    /// Adds a fixed `key` with a string `value` to every [`LogFormat::Json`] object, e.g.
+    /// `service`, `env` or `region`. Can be called multiple times; setting a key again
+    /// replaces its value.
+    ///
+    /// The fields are written after the built-in keys, in the order they were first added.
+    /// [`finish`](Self::finish) fails if `key` is one of the built-in keys: `timestamp`,
+    /// `level`, `target`, `message`, `file`, `line`, `thread` or `seq`.
+    ///
+    /// [`LogFormat::Logfmt`] lines get the fields as extra pairs. There, `time` and `msg` are
+    /// built-in keys too, and keys may not contain spaces, `=` or quotes.
+    /// [`LogFormat::Gelf`] objects get them as additional fields with a `_` prefix, so keys
+    /// may only contain letters, digits, `_`, `.` and `-`.
+    pub fn json_field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+
+        match self.json_fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.json_fields.push((key, value)),
         }
 
-        let mut logs = vec![];
+        self
+    }
+
+    /// A friendly name shown instead of empty targets and targets inside the app's own
+    /// crate, e.g. `myapp` instead of `myapp::internal::thing`.
+    ///
+    /// The app's crate is the [`app_name`](Self::app_name) with `-` replaced by `_`. This
+    /// only changes how targets are displayed; [`level_for`](Self::level_for) still matches
+    /// the real target. Custom formats see the real target.
+    pub fn default_target(mut self, name: impl Into<String>) -> Self {
+        self.default_target = Some(name.into());
 
-        for file in test_logs {
-            let time = if let Ok(v) = NaiveDateTime::parse_from_str(file.as_str(), CHRONO_FORMAT) {
-                v
-            } else {
-                continue;
-            };
+        self
+    }
+
+    /// Whether targets inside the app's own crate are shown without its name, e.g.
+    /// `net::client` instead of `myapp::net::client`. Defaults to `false`.
+    ///
+    /// The crate root itself and other crates' targets are shown as usual, and a
+    /// [`default_target`](Self::default_target) takes precedence. Like it, this only changes
+    /// how targets are displayed.
+    pub fn strip_app_prefix(mut self, strip: bool) -> Self {
+        self.strip_app_prefix = strip;
+
+        self
+    }
+
+    /// Tags records whose target is `module` or one of its submodules with `version`, e.g.
+    /// the version of the plugin crate they come from. The most specific module wins.
+    ///
+    /// Text lines show it after the target, as in `plugin_a::net@1.2.0`. JSON and logfmt
+    /// lines get a `version` key after the target instead. Only display is affected.
+    pub fn version_for(mut self, module: impl Into<String>, version: impl Into<String>) -> Self {
+        self.target_versions.insert(module.into(), version.into());
+
+        self
+    }
+
+    /// Writes times in a fixed offset from UTC instead of the local time zone, e.g.
+    /// `fixed_offset(9, 0)` for `+09:00` or `fixed_offset(-3, 30)` for `-03:30`.
+    ///
+    /// `minutes` take the sign of `hours`, except with `hours` of 0 where they may be
+    /// negative. Log file names use the offset too. [`finish`](Self::finish) fails if the
+    /// offset is not a valid time zone offset.
+    pub fn fixed_offset(mut self, hours: i32, minutes: i32) -> Self {
+        self.fixed_offset = Some((hours, minutes));
+
+        self
+    }
+
+    /// Whether text lines include the time elapsed since [`finish`](Self::finish), e.g.
+    /// `+1.234s`. Defaults to `false`.
+    ///
+    /// In the file it is written after the regular timestamp.
+    pub fn show_uptime(mut self, show: bool) -> Self {
+        self.show_uptime = show;
+
+        self
+    }
+
+    /// Whether every record is numbered, starting at 1, with text lines starting with the
+    /// number, e.g. `#42 [INFO] ...`, and [`LogFormat::Json`] objects having a `seq` key.
+    /// Defaults to `false`.
+    ///
+    /// The number is shared by all outputs of the logger and is unique across threads, so
+    /// gaps show where lines were lost, e.g. by a dropping [`Overflow`] policy. Lines from
+    /// different threads may reach a file out of order unless [`ordered`](Self::ordered) is
+    /// enabled. Custom formats do not include it.
+    pub fn show_sequence(mut self, show: bool) -> Self {
+        self.show_sequence = show;
+
+        self
+    }
+
+    /// Whether [`finish`](Self::finish) logs the [`LOGGING_CRATE_VERSION`] and
+    /// [`LogFormat::Json`] objects include it as `_logger_version`. Defaults to `false`.
+    pub fn show_logger_version(mut self, show: bool) -> Self {
+        self.show_logger_version = show;
+
+        self
+    }
+
+    /// Adds a `schema` field with `version` to every JSON, logfmt and GELF record, so
+    /// consumers can tell layouts apart while a format changes. By default no field is added.
+    ///
+    /// Pass [`LOG_SCHEMA_VERSION`] to follow the built-in layouts, or a version of your own
+    /// when the records also depend on your [`json_field`](Self::json_field)s.
+    /// [`finish`](Self::finish) fails if a `json_field` uses the `schema` key as well.
+    pub fn schema_version(mut self, version: impl Into<String>) -> Self {
+        self.schema_version = Some(version.into());
+
+        self
+    }
+
+    /// Logs the application's git commit and build time when [`finish`](Self::finish) runs,
+    /// and adds them to [`LogFormat::Json`] objects as `git_sha` and `build_timestamp`.
+    ///
+    /// Missing fields are left out. The keys are added like [`json_field`](Self::json_field)
+    /// values, so a later `json_field` call can replace them.
+    pub fn build_info(mut self, info: BuildInfo) -> Self {
+        for (key, value) in info.fields() {
+            self = self.json_field(key, value);
+        }
+        self.build_info = Some(info);
+
+        self
+    }
+
+    /// Whether [`finish`](Self::finish) and [`LoggingHandle::reconfigure`] log how logging
+    /// was set up, so the log itself shows it. Defaults to `false`.
+    ///
+    /// The Info record lists the resolved settings as logfmt pairs: `log_dir`, `file_mode`,
+    /// `global_level`, the module `levels`, `terminal_format`, `file_format`,
+    /// `max_log_files`, `max_log_age_secs` and the active `sinks`, e.g.
+    /// `sinks=terminal,file,tcp`. Missing values are `none`.
+    pub fn log_effective_config(mut self, log: bool) -> Self {
+        self.log_effective_config = log;
+
+        self
+    }
+
+    /// Text written in place of empty messages, e.g. `(empty)`. Defaults to `None`, which
+    /// ends lines with an empty message at the target instead of a dangling ` - `.
+    ///
+    /// Only the built-in text format is affected.
+    pub fn empty_message_placeholder(mut self, placeholder: Option<String>) -> Self {
+        self.empty_message_placeholder = placeholder;
 
-            logs.push((file, time));
+        self
+    }
+
+    /// Whether text lines for warnings and errors end with their source location and thread,
+    /// e.g. ` (at src/db.rs:42, thread main)`. Defaults to `false`.
+    ///
+    /// Lines at other levels are unchanged.
+    pub fn verbose_errors(mut self, verbose: bool) -> Self {
+        self.verbose_errors = verbose;
+
+        self
+    }
+
+    /// Writes `name` instead of the standard name for `level`, e.g. `WARNING` for
+    /// [`LevelFilter::Warn`].
+    ///
+    /// Levels without an override keep their standard names. [`LevelFilter::Off`] has no
+    /// records and is ignored. Only display is affected, not filtering.
+    pub fn level_name(mut self, level: LevelFilter, name: impl Into<String>) -> Self {
+        if let Some(level) = level.to_level() {
+            self.level_names.set(level, name.into());
         }
 
-        crate::sort_log_files(&mut logs);
+        self
+    }
+
+    /// Replaces both the terminal and file text formats with `format`.
+    ///
+    /// [`log_format!`](crate::log_format) builds the closure from a template for the common
+    /// cases. A custom format takes precedence over the terminal display options, but is
+    /// not used for [`LogFormat::Json`].
+    pub fn custom_format<F>(mut self, format: F) -> Self
+    where
+        F: Fn(FormatCallback, &fmt::Arguments, &Record) + Send + Sync + 'static,
+    {
+        self.custom_format = Some(Arc::new(format));
+
+        self
+    }
+
+    /// Rewrites the message of every record before any output formats it, e.g. to collapse
+    /// whitespace or strip ANSI codes from messages of other crates.
+    ///
+    /// It runs once per record that some output takes, with the message formatted, so it
+    /// should be cheap. Returning [`Cow::Borrowed`](std::borrow::Cow::Borrowed) keeps the
+    /// record as it was, without copying the message again.
+    ///
+    /// ```
+    /// use std::{borrow::Cow, sync::Arc};
+    ///
+    /// let builder = logging::LoggingBuilder::new().transform_message(Arc::new(|message: &str| {
+    ///     if message.contains('\t') {
+    ///         Cow::Owned(message.replace('\t', " "))
+    ///     } else {
+    ///         Cow::Borrowed(message)
+    ///     }
+    /// }));
+    /// ```
+    pub fn transform_message(mut self, transform: Arc<MessageTransform>) -> Self {
+        self.transform_message = Some(transform);
+
+        self
+    }
+
+    /// The maximum number of log files kept in the log directory, **including** the file
+    /// created by [`finish`](Self::finish). Defaults to `5`.
+    ///
+    /// After `finish` returns, at most `max` log files exist. Must be at least `1`.
+    pub fn max_log_files(mut self, max: usize) -> Self {
+        self.max_log_files = max;
+
+        self
+    }
+
+    /// Which log files rotation deletes first. Defaults to [`RotationOrder::ByFilename`].
+    ///
+    /// With [`FileMode::Append`] or [`daily_rollover`](Self::daily_rollover), a file named
+    /// after an old date may still be the one written to recently;
+    /// [`RotationOrder::ByModifiedTime`] keeps it over files written to longer ago, and makes
+    /// `Append` continue the most recently written file.
+    /// [`max_log_age`](Self::max_log_age) still goes by the names.
+    pub fn rotation_order(mut self, order: RotationOrder) -> Self {
+        self.rotation_order = order;
+
+        self
+    }
+
+    /// When old log files are deleted by [`max_log_files`](Self::max_log_files) and
+    /// [`max_log_age`](Self::max_log_age). Defaults to [`RotateWhen::Startup`].
+    ///
+    /// [`RotateWhen::Shutdown`] leaves the logs alone in `finish` and deletes them when the
+    /// [`LoggingHandle`] is dropped instead, keeping the newest `max_log_files` including the
+    /// one just written, so the directory is tidy when the process exits cleanly. Until then
+    /// it may hold one more file than the limit. [`FileMode::Numbered`] always shifts its
+    /// files at startup, and [`FileMode::Single`] has nothing to delete.
+    pub fn rotate_when(mut self, when: RotateWhen) -> Self {
+        self.rotate_when = when;
+
+        self
+    }
+
+    /// Called with the path of each old log file right before rotation deletes it.
+    ///
+    /// The file still exists when the callback runs and is removed once it returns, so the
+    /// callback can copy or upload it elsewhere. Callbacks run synchronously during
+    /// [`finish`](Self::finish), oldest file first.
+    pub fn on_rotate(mut self, cb: Arc<dyn Fn(&Path) + Send + Sync>) -> Self {
+        self.on_rotate = Some(cb);
+
+        self
+    }
+
+    /// Called with notes on the decisions this crate makes while setting up, for finding out
+    /// why logs are not where they were expected: the log directory used and the ones
+    /// skipped, the log file picked and each old log deleted.
+    ///
+    /// The notes are plain sentences meant for people, not for parsing. The hook is called
+    /// directly rather than through the logger, so it also works when setting up fails or
+    /// logging is never installed, and it must not log itself. Later rotations, e.g. by
+    /// [`daily_rollover`](Self::daily_rollover), call it too.
+    pub fn diagnostics(mut self, hook: Arc<dyn Fn(&str) + Send + Sync>) -> Self {
+        self.diagnostics = Some(hook);
+
+        self
+    }
+
+    /// Opens log files with `factory` instead of appending to them with a plain `File`, e.g.
+    /// to open them with custom flags, buffering or storage.
+    ///
+    /// The factory is called with the path of each file to write, which it should create if
+    /// missing and append to. [`FileMode::Single`] empties its file before calling it.
+    /// Rotation still works on the paths as usual. Each record is written as one line and
+    /// flushed, so the writer does its own buffering if any.
+    pub fn file_writer_factory(mut self, factory: Arc<WriterFactory>) -> Self {
+        self.writer_factory = Some(factory);
+
+        self
+    }
+
+    /// Logs to `file`, an already open file, instead of picking one in the log directory,
+    /// e.g. one inherited from a parent process or opened in a sandbox.
+    ///
+    /// The file is written as it is: the log directory is not resolved or locked, and
+    /// there is no rotation, so the [`FileMode`], file names, history log, audit log,
+    /// per-level files and the other options about log files have no effect. Open it for
+    /// appending if it should not be written over from the start.
+    pub fn file_handle(mut self, file: File) -> Self {
+        self.file_handle = Some(file);
+
+        self
+    }
+
+    /// Also deletes log files created longer than `max_age` ago, by the time in their
+    /// names, when rotating. Applies on top of [`max_log_files`](Self::max_log_files).
+    pub fn max_log_age(mut self, max_age: Duration) -> Self {
+        self.max_log_age = Some(max_age);
+
+        self
+    }
+
+    /// How far in the future a log file may be dated before [`max_log_age`](Self::max_log_age)
+    /// assumes the system clock was set back. Defaults to 5 minutes.
+    ///
+    /// Such files are kept rather than deleted, and a warning naming them is logged once
+    /// logging is installed. Files dated within the grace count as just created.
+    pub fn clock_skew_grace(mut self, grace: Duration) -> Self {
+        self.clock_skew = grace;
+
+        self
+    }
+
+    /// Also applies [`max_log_files`](Self::max_log_files) and
+    /// [`max_log_age`](Self::max_log_age) every `interval` while logging, on a thread the
+    /// [`LoggingHandle`] stops when dropped, for processes that run long enough to need it.
+    ///
+    /// The log file being written is never deleted. Has no effect with
+    /// [`FileMode::Single`] and [`FileMode::Numbered`], which keep no dated logs.
+    pub fn background_rotation(mut self, interval: Duration) -> Self {
+        self.background_rotation = Some(interval);
+
+        self
+    }
+
+    /// Reopens the log file at its path when the process receives `SIGHUP`, for rotating it
+    /// with external tools like logrotate: they rename the file and send the signal, and
+    /// logging continues in a new file with the old name. Defaults to `false`.
+    ///
+    /// Only supported on Unix; [`finish`](Self::finish) fails elsewhere. The handler stays
+    /// installed for the rest of the process and only wakes a helper thread that does the
+    /// reopening. Has no effect with [`daily_rollover`](Self::daily_rollover),
+    /// [`max_lines`](Self::max_lines) or [`finalize_on_exit`](Self::finalize_on_exit), which
+    /// switch files themselves.
+    pub fn reopen_on_sighup(mut self, reopen: bool) -> Self {
+        self.reopen_on_sighup = reopen;
+
+        self
+    }
+
+    /// The `chrono` format log file names are generated from. Defaults to
+    /// `%Y-%m-%d_%H-%M-%S`.
+    ///
+    /// Old log files are found by parsing their names with the same format, so it must
+    /// contain the full date and time. [`finish`](Self::finish) fails if the names would
+    /// contain characters that are not allowed on this platform, e.g. `:` from `%H:%M` or
+    /// `%T` on Windows.
+    ///
+    /// Files whose names do not match the format are never rotated or deleted, so after
+    /// changing it the logs written with the previous format stay until removed by hand.
+    pub fn filename_format(mut self, format: impl Into<String>) -> Self {
+        self.filename_format = format.into();
+
+        self
+    }
+
+    /// Adds the microseconds to log file names, e.g. `2024-01-02_03-04-05-123456.log`, by
+    /// appending `-%6f` to the [`filename_format`](Self::filename_format). Defaults to
+    /// `false`.
+    ///
+    /// Processes restarting within the same second then get names that sort by when they
+    /// started, instead of `_1`, `_2`, ... suffixes. Logs named without the microseconds, e.g.
+    /// from before this was enabled, are still found and rotated. Disabling it again leaves
+    /// the logs with microseconds alone, as they no longer match the format.
+    pub fn filename_microseconds(mut self, microseconds: bool) -> Self {
+        self.filename_microseconds = microseconds;
+
+        self
+    }
+
+    /// Whether the [`filename_format`](Self::filename_format) is rewritten to be a legal file
+    /// name instead of being rejected. Defaults to `false`.
+    ///
+    /// Specifiers like `%T` are replaced with `-` separated ones and illegal characters with
+    /// `-`.
+    pub fn sanitize_filenames(mut self, sanitize: bool) -> Self {
+        self.sanitize_filenames = sanitize;
+
+        self
+    }
+
+    /// Starts the names of new log files with `label`, e.g.
+    /// `experiment-baseline_2024-01-02_03-04-05.log`, and adds it to JSON, logfmt and GELF
+    /// records as a `session` field.
+    ///
+    /// Characters that are not allowed in file names on some platform and whitespace are
+    /// replaced with `-`; a label with nothing left is ignored. Logs of every label count
+    /// towards [`max_log_files`](Self::max_log_files) and [`max_log_age`](Self::max_log_age)
+    /// together. Has no effect on the names of [`FileMode::Single`] and
+    /// [`FileMode::Numbered`].
+    pub fn session_label(mut self, label: impl Into<String>) -> Self {
+        self.session_label = rotation::sanitize_label(&label.into());
+
+        self
+    }
+
+    /// How the log file for this run is picked. Defaults to [`FileMode::Rotate`].
+    ///
+    /// The mode can also be set when deploying, see [`file_mode_env`](Self::file_mode_env).
+    ///
+    /// The `<app_name>.log` file of [`FileMode::Single`] and [`FileMode::Numbered`] does not
+    /// match the [`filename_format`](Self::filename_format), so switching to `Rotate` or
+    /// `Append` leaves it alone.
+    pub fn file_mode(mut self, mode: FileMode) -> Self {
+        self.file_mode = Some(mode);
+
+        self
+    }
+
+    /// Writes to one `%Y-%m-%d.log` file per day, e.g. `2024-01-02.log`, moving to the next
+    /// day's file at the first line logged after midnight, for long-running services.
+    /// Defaults to `false`.
+    ///
+    /// Replaces the [`FileMode`] and [`filename_format`](Self::filename_format): a restart
+    /// appends to the day's file, and each new day's file deletes the oldest beyond
+    /// [`max_log_files`](Self::max_log_files). A line is never split across two files.
+    /// [`finalize_on_exit`](Self::finalize_on_exit) has no effect.
+    ///
+    /// Per-run log files do not match the daily names, so enabling this leaves them alone
+    /// instead of rotating them, and disabling it does the same with the daily files.
+    pub fn daily_rollover(mut self, daily: bool) -> Self {
+        self.daily_rollover = daily;
+
+        self
+    }
+
+    /// Moves to a new log file once `count` lines were written to the current one, rotating
+    /// old logs like a new run would. By default a run writes to a single file.
+    ///
+    /// Lines are counted by their line breaks, so a file never holds more than `count` lines
+    /// even when a message spans several. With [`FileMode::Append`], the lines already in the
+    /// file count too. Has no effect with [`FileMode::Single`], [`FileMode::Numbered`],
+    /// [`daily_rollover`](Self::daily_rollover) and
+    /// [`finalize_on_exit`](Self::finalize_on_exit), and
+    /// [`reopen_on_sighup`](Self::reopen_on_sighup) has no effect with it.
+    /// [`finish`](Self::finish) fails if `count` is zero.
+    pub fn max_lines(mut self, count: u64) -> Self {
+        self.max_lines = Some(count);
+
+        self
+    }
+
+    /// Also appends every line to a persistent `history.log` in the log directory, next to
+    /// the per-run files, e.g. a fresh file to attach to bug reports plus a continuous
+    /// history.
+    ///
+    /// The history has its own retention: when `finish` finds it larger than `max_bytes`, it
+    /// is moved to `history.log.1`, replacing the previous one, and a new history is started.
+    /// [`max_log_files`](Self::max_log_files) and the [`FileMode`] only apply to the per-run
+    /// files, and rotation never deletes the history files.
+    pub fn history_log(mut self, max_bytes: u64) -> Self {
+        self.history_max_bytes = Some(max_bytes);
+
+        self
+    }
+
+    /// Also writes each level's lines to a file of its own next to the log file, named after
+    /// it, e.g. `2024-01-02_03-04-05.error.log` and `2024-01-02_03-04-05.info.log`, while the
+    /// log file keeps every line. Defaults to `false`.
+    ///
+    /// A level's file is only created once a line is written to it. The files follow their
+    /// log file: rotation deletes them along with it without counting them towards
+    /// [`max_log_files`](Self::max_log_files), and with [`FileMode::Single`] and
+    /// [`FileMode::Numbered`] they are emptied or shifted with it. With
+    /// [`daily_rollover`](Self::daily_rollover) and [`max_lines`](Self::max_lines) they stay
+    /// with the run's first log file.
+    pub fn per_level_files(mut self, enabled: bool) -> Self {
+        self.per_level_files = enabled;
+
+        self
+    }
+
+    /// Whether each [`per_level_files`](Self::per_level_files) file also gets the more severe
+    /// levels, e.g. `warn.log` both warnings and errors, instead of only its own level.
+    /// Defaults to `false`.
+    pub fn per_level_files_cumulative(mut self, cumulative: bool) -> Self {
+        self.per_level_files_cumulative = cumulative;
+
+        self
+    }
+
+    /// Writes records to a subdirectory of the log directory named after the first segment
+    /// of their target, e.g. those of a plugin logging as `weather::fetch` to
+    /// `weather/2024-01-02_03-04-05.log`, instead of the log file. Defaults to `false`.
+    ///
+    /// Targets without `::`, like crate roots and the lines logged by this crate, and those
+    /// inside the app's own crate, e.g. `my_app::net` for an [`app_name`](Self::app_name) of
+    /// `my-app`, stay in the log file. Each subdirectory is set up when its first record
+    /// arrives and rotated on its own, with the same [`FileMode`] and retention as the log
+    /// directory, but without a history, audit or per-level file. Its lines are written
+    /// directly even with [`non_blocking`](Self::non_blocking). Has no effect with a
+    /// [`file_handle`](Self::file_handle).
+    pub fn split_by_target_segment(mut self, split: bool) -> Self {
+        self.split_by_target_segment = split;
+
+        self
+    }
+
+    /// Writes records for `target` and its submodules, e.g. `audit` and `audit::login`, to
+    /// `name` in the log directory, an append-only audit trail synced to disk after every
+    /// line.
+    ///
+    /// The audit log is never rotated or deleted, whatever the [`FileMode`], and is written
+    /// synchronously even with [`non_blocking`](Self::non_blocking). Audit records still need
+    /// to pass the level filters and are left out of the log file unless
+    /// [`audit_in_log_file`](Self::audit_in_log_file) is enabled.
+    ///
+    /// ```no_run
+    /// let _handle = logging::LoggingBuilder::new()
+    ///     .app_name("app")
+    ///     .qualifier("com")
+    ///     .organization("org")
+    ///     .audit_file("audit.log", "audit")
+    ///     .finish()?;
+    ///
+    /// log::info!(target: "audit::login", "user alice logged in");
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn audit_file(mut self, name: impl Into<String>, target: impl Into<String>) -> Self {
+        self.audit = Some((name.into(), target.into()));
+
+        self
+    }
+
+    /// Whether [`audit_file`](Self::audit_file) records also go to the log file and history.
+    /// Defaults to `false`.
+    pub fn audit_in_log_file(mut self, enabled: bool) -> Self {
+        self.audit_in_log_file = enabled;
+
+        self
+    }
+
+    /// Also writes each error to a file of its own in the `crashes` directory inside the log
+    /// directory, named `<time>-<n>.txt`, e.g. `2024-01-02_03-04-05-1.txt`, for a watchdog
+    /// to pick up and upload individually. Defaults to `false`.
+    ///
+    /// The files hold the error's line in the built-in text format with all parts shown,
+    /// including its context fields and, with [`capture_backtrace`](Self::capture_backtrace),
+    /// its backtrace. They are written as the error is logged, even with
+    /// [`non_blocking`](Self::non_blocking), and only the newest
+    /// [`max_crash_files`](Self::max_crash_files) are kept, apart from the log files'
+    /// rotation.
+    pub fn crash_files(mut self, enabled: bool) -> Self {
+        self.crash_files = enabled;
+
+        self
+    }
+
+    /// How many [`crash_files`](Self::crash_files) are kept, deleting the oldest. Defaults to
+    /// `20`. Must be at least `1`.
+    pub fn max_crash_files(mut self, max: usize) -> Self {
+        self.max_crash_files = max;
+
+        self
+    }
+
+    /// Writes the log file as `<name>.log.tmp` and renames it to `<name>.log` when the
+    /// [`LoggingHandle`] is dropped, so tools reading the log after a run only see complete
+    /// files, and a leftover `.tmp` file marks a run that crashed or was killed.
+    /// Defaults to `false`.
+    ///
+    /// Rotation ignores `.tmp` files, so they are never counted or deleted. Has no effect
+    /// with [`FileMode::Append`], whose file already exists. Lines logged after the handle
+    /// is dropped are discarded.
+    pub fn finalize_on_exit(mut self, finalize: bool) -> Self {
+        self.finalize_on_exit = finalize;
+
+        self
+    }
+
+    /// Whether the log directory and files are only accessible to the current user, for
+    /// logs that may contain personal data. Defaults to `false`.
+    ///
+    /// On Unix, directories created for the logs get mode `0700`, and the log, history and
+    /// audit files are created as, or changed to, `0600`. An existing log directory is left
+    /// alone. Elsewhere this is not enforced and the files get the platform's default
+    /// access, so keep the log directory inside the user's profile. A
+    /// [`file_writer_factory`](Self::file_writer_factory) creates its own files.
+    pub fn restrict_permissions(mut self, restrict: bool) -> Self {
+        self.restrict_permissions = restrict;
+
+        self
+    }
+
+    /// What other processes may do with the log files while they are open, on Windows.
+    /// Defaults to [`FileShare::All`], so logs can be followed, copied and cleaned up while
+    /// the app runs.
+    ///
+    /// [`FileShare::Read`] still lets viewers read the log but keeps other processes from
+    /// writing, renaming or deleting it. Other platforms do not lock open files, so this has
+    /// no effect there. A [`file_writer_factory`](Self::file_writer_factory) opens its own
+    /// files.
+    pub fn file_share(mut self, share: FileShare) -> Self {
+        self.file_share = share;
+
+        self
+    }
+
+    /// Reserves `bytes` of disk space for the log file when it is opened, so appending to it
+    /// does not fragment it, and frees whatever was not used when the [`LoggingHandle`] is
+    /// dropped. Off by default.
+    ///
+    /// Only Linux supports this, using `fallocate` without changing the file's size; it is a
+    /// no-op on other platforms and with a [`file_writer_factory`](Self::file_writer_factory).
+    /// If the filesystem cannot reserve the space, `finish` logs a warning and carries on.
+    /// After a crash the reserved space stays allocated until the file is deleted.
+    pub fn preallocate(mut self, bytes: u64) -> Self {
+        self.preallocate = Some(bytes);
+
+        self
+    }
+
+    /// Keeps a `manifest.json` in the log directory listing each file with its size, the
+    /// times of its first and last records and a count of records per level, so tools can
+    /// find logs without parsing file names.
+    ///
+    /// [`finish`](Self::finish) writes the manifest after rotating, and it is rewritten each
+    /// time logging moves to a new file or [`background_rotation`](Self::background_rotation)
+    /// deletes old ones. Dropping the [`LoggingHandle`] rewrites it with the final sizes; in
+    /// between, sizes and counts are those of the last rewrite. Times and levels are read back from the files and are `null` with a
+    /// [`custom_format`](Self::custom_format).
+    pub fn write_manifest(mut self, enabled: bool) -> Self {
+        self.write_manifest = enabled;
+
+        self
+    }
+
+    /// Copies everything the program writes to stdout, e.g. with `println!` or from a
+    /// library, into the log file verbatim, while still showing it on the terminal.
+    /// Defaults to `false`.
+    ///
+    /// Only supported on Unix, where [`finish`](Self::finish) redirects the stdout file
+    /// descriptor through a pipe; elsewhere `finish` fails. Only output written after
+    /// `finish` is copied, a whole line at a time, so a line is never split by a log record
+    /// but may land before records logged earlier. Dropping the [`LoggingHandle`] restores
+    /// stdout. Has no effect while file logging is disabled.
+    pub fn capture_stdout(mut self, capture: bool) -> Self {
+        self.capture_stdout = capture;
+
+        self
+    }
+
+    /// The environment variable [`finish`](Self::finish) reads the [`FileMode`] from,
+    /// e.g. `MYAPP_LOG_MODE=append`. Defaults to the [`app_name`](Self::app_name) in upper
+    /// case with `-` replaced by `_`, followed by `_LOG_MODE`.
+    ///
+    /// The variable accepts `rotate`, `append`, `single` or `numbered`; any other value makes
+    /// `finish` fail. It is only used when [`file_mode`](Self::file_mode) was not called,
+    /// unless [`env_override`](Self::env_override) is enabled.
+    pub fn file_mode_env(mut self, name: impl Into<String>) -> Self {
+        self.file_mode_env = Some(name.into());
+
+        self
+    }
+
+    /// Whether the [`file_mode_env`](Self::file_mode_env) variable wins over an explicit
+    /// [`file_mode`](Self::file_mode). Defaults to `false`.
+    pub fn env_override(mut self, env_override: bool) -> Self {
+        self.env_override = env_override;
+
+        self
+    }
+
+    /// Overrides the directory log files are written to.
+    ///
+    /// Defaults to a `logs` directory inside the platform cache directory for the
+    /// qualifier/organization/app name.
+    pub fn log_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.log_dir = Some(dir.into());
+
+        self
+    }
+
+    /// The log directory used when the platform cache directory cannot be determined, e.g.
+    /// in containers or CI without `HOME`, instead of file logging failing. Not used by
+    /// default.
+    ///
+    /// Unlike the cache directory, no `logs` directory is added to `dir`. A temporary
+    /// directory such as `std::env::temp_dir().join("my-app")` keeps the app running anywhere.
+    /// [`log_dir`](Self::log_dir) and [`portable`](Self::portable) take precedence.
+    pub fn project_dirs_fallback(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.project_dirs_fallback = Some(dir.into());
+
+        self
+    }
+
+    /// Writes logs to a `logs` directory next to the executable instead of the platform cache
+    /// directory, for portable apps run from e.g. a USB stick. Defaults to `false`.
+    ///
+    /// An explicit [`log_dir`](Self::log_dir) still takes precedence. File logging fails, as
+    /// with any unusable directory, if the executable's path cannot be determined.
+    pub fn portable(mut self, portable: bool) -> Self {
+        self.portable = portable;
+
+        self
+    }
+
+    /// Whether the log directory must already exist. Defaults to `false`, creating it and
+    /// any missing parents.
+    ///
+    /// When enabled, [`finish`](Self::finish) fails if the directory is missing, for
+    /// deployments that manage it themselves and want a missing directory to be noticed.
+    pub fn require_existing_dir(mut self, require: bool) -> Self {
+        self.require_existing_dir = require;
+
+        self
+    }
+
+    /// Directories to try in order when files cannot be created in the log directory, e.g.
+    /// on locked-down systems: the first usable one is used instead.
+    ///
+    /// [`finish`](Self::finish) logs a warning for each directory it skips, and
+    /// [`LoggingHandle::log_dir`] returns the one chosen. If none is usable, setting up the log
+    /// directory fails as it would without fallbacks.
+    ///
+    /// ```
+    /// let builder = logging::LoggingBuilder::new()
+    ///     .fallback_dirs(vec![std::env::temp_dir().join("my-app-logs")]);
+    /// ```
+    pub fn fallback_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.fallback_dirs = dirs;
+
+        self
+    }
+
+    /// How long [`finish`](Self::finish) may wait for the log directory and files to be set
+    /// up, e.g. on a hung network filesystem. Unbounded by default.
+    ///
+    /// The setup then runs on a helper thread. If it takes longer, logging continues on the
+    /// terminal only, with a warning, as with [`fallback_to_terminal`](Self::fallback_to_terminal).
+    /// Only the setup is bounded, not writing the lines afterwards.
+    pub fn setup_timeout(mut self, timeout: Duration) -> Self {
+        self.setup_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Whether to keep logging to the terminal when the log file cannot be set up.
+    /// Defaults to `false`, in which case [`finish`](Self::finish) returns the error.
+    ///
+    /// Use [`LoggingHandle::file_logging_active`] to check whether the fallback was taken.
+    pub fn fallback_to_terminal(mut self, fallback: bool) -> Self {
+        self.fallback_to_terminal = fallback;
+
+        self
+    }
+
+    /// Retries writes to the log file that fail with a transient error, such as
+    /// [`io::ErrorKind::Interrupted`], [`WouldBlock`](io::ErrorKind::WouldBlock) or
+    /// [`TimedOut`](io::ErrorKind::TimedOut), up to `retries` times, pausing `backoff` before
+    /// the first retry and twice as long before each one after. Off by default, in which
+    /// case a failed write loses its lines and the next one tries again.
+    ///
+    /// A write failing with another error, like a full disk or a missing permission, or
+    /// still failing after the last retry, is reported on stderr, and the log file is given
+    /// up on: later lines only go to the terminal and the other outputs, and
+    /// [`LoggingHandle::file_logging_active`] returns `false`. Pauses block the logging
+    /// thread, or the writer thread with [`non_blocking`](Self::non_blocking).
+    pub fn write_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.write_retries = Some((retries, backoff));
+
+        self
+    }
+
+    /// Whether each line, or [`LogFormat::Binary`] record, is written to the log file, and
+    /// flushed, as soon as it is logged, so `tail -f` shows it right away. Defaults to `true`.
+    ///
+    /// With `false`, lines are collected in an 8 KiB buffer and written when it fills or the
+    /// logger is flushed, e.g. when the [`LoggingHandle`] is dropped. That saves a write per
+    /// line for chatty applications, at the cost of lines showing up late, and being lost if
+    /// the process exits without flushing. Lines are never synced to disk either way.
+    /// [`non_blocking`](Self::non_blocking) writes are batched regardless.
+    pub fn line_buffered(mut self, line_buffered: bool) -> Self {
+        self.line_buffered = line_buffered;
+
+        self
+    }
+
+    /// Whether log file writes happen on a background thread. Defaults to `false`.
+    ///
+    /// Formatted lines are queued and written by a dedicated thread, so logging calls don't
+    /// wait on disk I/O. See [`queue_capacity`](Self::queue_capacity) and
+    /// [`overflow`](Self::overflow) for what happens when the writer falls behind.
+    pub fn non_blocking(mut self, non_blocking: bool) -> Self {
+        self.non_blocking = non_blocking;
+
+        self
+    }
+
+    /// The number of lines the non-blocking queue holds before the
+    /// [`overflow`](Self::overflow) policy applies. Defaults to `1024`.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+
+        self
+    }
+
+    /// What to do with new lines when the non-blocking queue is full. Defaults to
+    /// [`Overflow::Block`].
+    ///
+    /// Dropped lines are counted by [`LoggingHandle::dropped_messages`] and reported with a
+    /// warning record, in the file's format, once the writer catches up. Each file reports
+    /// only the lines it lost.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+
+        self
+    }
+
+    /// What dropping the [`LoggingHandle`] does with lines the writer threads have not
+    /// written yet. Defaults to [`DropBehavior::FlushAndJoin`], so the last lines reach the
+    /// disk when the handle goes out of scope.
+    pub fn drop_behavior(mut self, behavior: DropBehavior) -> Self {
+        self.drop_behavior = behavior;
+
+        self
+    }
+
+    /// Logs at most `lines_per_sec` records per second across every output, dropping the
+    /// rest, to protect the disk and log collectors from a runaway loop. Unlimited by default.
+    ///
+    /// Up to a second's worth of records may be logged in a burst. While records are being
+    /// dropped, a warning with the number dropped is logged at most once a second. The
+    /// [`shutdown_summary`](Self::shutdown_summary) still counts dropped records.
+    pub fn rate_limit(mut self, lines_per_sec: u32) -> Self {
+        self.rate_limit = Some(lines_per_sec);
+
+        self
+    }
+
+    /// Whether `Error` records bypass the [`rate_limit`](Self::rate_limit). Defaults to
+    /// `false`.
+    pub fn rate_limit_exempt_errors(mut self, exempt: bool) -> Self {
+        self.rate_limit_exempt_errors = exempt;
+
+        self
+    }
+
+    /// After an `Error` record is logged, suppresses errors with the same message until
+    /// `cooldown` has passed, so an outage repeating one error does not flood the logs. Off
+    /// by default.
+    ///
+    /// The first error after the cooldown is logged with the number suppressed, e.g.
+    /// `db down (12 identical errors suppressed)`. Other levels are never suppressed, and
+    /// suppressed errors do not count against the [`rate_limit`](Self::rate_limit).
+    pub fn error_cooldown(mut self, cooldown: Duration) -> Self {
+        self.error_cooldown = Some(cooldown);
+
+        self
+    }
+
+    /// Whether records are handed to the sinks one at a time. Defaults to `false`.
+    ///
+    /// The terminal and file normally write independently, so under concurrency lines from
+    /// different threads can appear in a different order in each. Ordered mode funnels every
+    /// record through a single lock so all sinks see the same order, at the cost of
+    /// serializing formatting and writing across threads.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+
+        self
+    }
+
+    /// Whether dropping the [`LoggingHandle`] logs a final line like
+    /// `=== myapp stopped after 2m 3s, 1 errors, 4 warnings ===`. Defaults to `false`.
+    ///
+    /// Keep the handle returned by [`finish`](Self::finish) alive until the app exits
+    /// normally. If the process is killed instead, the line is missing from the log. Only
+    /// records that passed the level filters are counted. The line itself is logged at
+    /// `Info`, so levels filtering out `Info` also hide it.
+    pub fn shutdown_summary(mut self, summary: bool) -> Self {
+        self.shutdown_summary = summary;
+
+        self
+    }
+
+    /// Keeps up to `lines` of the records the level filters suppress, e.g. `Debug` records
+    /// while logging at `Info`, and writes them to the log file, oldest first, just before
+    /// the next `Error`, for context around failures. Defaults to `0`, which disables it.
+    ///
+    /// Every record is then formatted, so this costs the time to format all suppressed
+    /// records and the memory for `lines` lines in the file format. Each error only dumps the
+    /// lines suppressed since the previous dump. Has no effect while file logging is
+    /// disabled.
+    pub fn dump_on_error(mut self, lines: usize) -> Self {
+        self.dump_on_error = lines;
+
+        self
+    }
+
+    /// Caps the combined size of the lines held in memory at `bytes`, for constrained
+    /// devices. Defaults to no limit.
+    ///
+    /// Only the text of each line is counted. When a new line does not fit:
+    ///
+    /// - The [`ring_buffer`](Self::ring_buffer) and [`dump_on_error`](Self::dump_on_error)
+    ///   buffers evict their oldest lines until it does, counted by
+    ///   [`LoggingHandle::evicted_lines`].
+    /// - The [`non_blocking`](Self::non_blocking) queues drop the new line, or their oldest
+    ///   lines with [`Overflow::DropOldest`], counted by
+    ///   [`LoggingHandle::dropped_messages`]. They never wait for memory, even with
+    ///   [`Overflow::Block`].
+    /// - The [`tcp_output`](Self::tcp_output) and
+    ///   [`unix_socket_output`](Self::unix_socket_output) backlogs drop their oldest lines,
+    ///   with a warning once the endpoint is reachable again.
+    /// - [`capture_early`](Self::capture_early) drops its oldest records, with a warning on
+    ///   replay, whether this is called before or after it. Its records are gone by the time
+    ///   the other buffers fill, so they get the whole cap too.
+    ///
+    /// Lines bigger than the cap are dropped.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        early::limit(bytes);
+
+        self
+    }
+
+    /// Whether the [`LogEvent`]s handed to [`channel_output`](Self::channel_output),
+    /// [`LoggingHandle::subscribe`] and the `error_reporter` share one copy of each target.
+    /// On by default, so logging from the same targets over and over allocates less. Up to
+    /// 4096 distinct targets are shared, and the others copied for each event.
+    pub fn intern_targets(mut self, intern: bool) -> Self {
+        self.intern_targets = intern;
+
+        self
+    }
+
+    /// Logs panics as errors with the `panic` target, then runs the previously installed
+    /// panic hook. Defaults to `false`.
+    ///
+    /// The hook flushes every output and waits for the [`non_blocking`](Self::non_blocking)
+    /// writers before returning, so the panic is in the log file even if the process aborts
+    /// right after, e.g. with `panic = "abort"`, where the [`LoggingHandle`] is never dropped.
+    pub fn log_panics(mut self, log_panics: bool) -> Self {
+        self.log_panics = log_panics;
+
+        self
+    }
+
+    /// Whether [`log_error_with_backtrace!`](crate::log_error_with_backtrace) appends a
+    /// backtrace to the error's entry in the log file. Defaults to `false`.
+    ///
+    /// Even then, backtraces are only captured when `RUST_LIB_BACKTRACE` or `RUST_BACKTRACE`
+    /// enable them, following the standard library's conventions, e.g. `RUST_BACKTRACE=1`.
+    /// The terminal never shows them.
+    pub fn capture_backtrace(mut self, capture: bool) -> Self {
+        self.capture_backtrace = capture;
+
+        self
+    }
+
+    /// Logs `heartbeat uptime=12.000s` with the `heartbeat` target every `interval`, on a
+    /// thread the [`LoggingHandle`] stops when dropped, so a log that stops getting them
+    /// shows when the process hung or died.
+    ///
+    /// The uptime counts from [`finish`](Self::finish). The lines are filtered like any
+    /// other, so the [`heartbeat_level`](Self::heartbeat_level) must be enabled for them.
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+
+        self
+    }
+
+    /// The level of the [`heartbeat`](Self::heartbeat) lines. Defaults to [`Level::Debug`].
+    pub fn heartbeat_level(mut self, level: Level) -> Self {
+        self.heartbeat_level = level;
+
+        self
+    }
+
+    /// The message of the [`heartbeat`](Self::heartbeat) lines, before the uptime. Defaults
+    /// to `heartbeat`.
+    pub fn heartbeat_message(mut self, message: impl Into<String>) -> Self {
+        self.heartbeat_message = message.into();
+
+        self
+    }
+
+    /// Logs the process's memory and CPU usage at [`Level::Info`] with the `resources` target
+    /// every `interval`, on a thread the [`LoggingHandle`] stops when dropped, for spotting
+    /// leaks from the logs alone.
+    ///
+    /// The message is `key=value` pairs: `rss_bytes` on Linux, `max_rss_bytes`,
+    /// `cpu_user_secs` and `cpu_system_secs` on Unix. Elsewhere it is `unavailable`.
+    pub fn resource_stats(mut self, interval: Duration) -> Self {
+        self.resource_stats = Some(interval);
+
+        self
+    }
+
+    /// Keeps the last `lines` log lines in memory, available from
+    /// [`LoggingHandle::recent`]. Defaults to `0`, which disables the buffer.
+    ///
+    /// Lines use the file format and pass the same level filters as the other sinks. The
+    /// buffer is filled even when file logging is unavailable, which makes it handy for
+    /// attaching recent logs to in-app bug reports.
+    pub fn ring_buffer(mut self, lines: usize) -> Self {
+        self.ring_buffer = lines;
+
+        self
+    }
+
+    /// Also sends every record that passes the level filters to the returned receiver, for
+    /// applications that poll logs from their own event loop.
+    ///
+    /// The channel holds up to 1024 events. Logging never blocks on it: when the receiver
+    /// falls behind, new events are dropped and counted by
+    /// [`LoggingHandle::dropped_events`].
+    pub fn channel_output(mut self) -> (Self, Receiver<LogEvent>) {
+        let (tx, rx) = sync_channel(CHANNEL_CAPACITY);
+        self.channel = Some(tx);
+
+        (self, rx)
+    }
+
+    /// Also passes every warning and error that passes the level filters to `report`, for
+    /// error-tracking services like Sentry. Requires the `error-reporter` feature.
+    ///
+    /// The events carry the level, target, message and the thread's
+    /// [`push_context`](LoggingHandle::push_context) fields. `report` runs on a thread of its
+    /// own, so logging never waits for it: up to 1024 events wait to be reported, and when it
+    /// falls behind new ones are dropped and counted by [`LoggingHandle::dropped_events`].
+    /// Events still waiting when the process exits are lost.
+    ///
+    /// An adapter for the `sentry` crate:
+    ///
+    /// ```no_run
+    /// # mod sentry {
+    /// #     pub enum Level { Warning, Error }
+    /// #     pub struct Scope;
+    /// #     impl Scope {
+    /// #         pub fn set_tag(&mut self, _: &str, _: &str) {}
+    /// #     }
+    /// #     pub fn with_scope(_: impl FnOnce(&mut Scope), f: impl FnOnce()) { f() }
+    /// #     pub fn capture_message(_: &str, _: Level) {}
+    /// # }
+    /// use std::sync::Arc;
+    ///
+    /// let _handle = logging::LoggingBuilder::new()
+    ///     .app_name("app")
+    ///     .qualifier("com")
+    ///     .organization("org")
+    ///     .error_reporter(Arc::new(|event: &logging::LogEvent| {
+    ///         let level = match event.level {
+    ///             log::Level::Error => sentry::Level::Error,
+    ///             _ => sentry::Level::Warning,
+    ///         };
+    ///         sentry::with_scope(
+    ///             |scope| {
+    ///                 scope.set_tag("target", &event.target);
+    ///                 for (key, value) in &event.context {
+    ///                     scope.set_tag(key, value);
+    ///                 }
+    ///             },
+    ///             || sentry::capture_message(&event.message, level),
+    ///         );
+    ///     }))
+    ///     .finish()?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    #[cfg(feature = "error-reporter")]
+    pub fn error_reporter(mut self, report: Arc<dyn Fn(&LogEvent) + Send + Sync>) -> Self {
+        self.error_reporter = Some(report);
+
+        self
+    }
+
+    /// Also sends records at `level` or more severe to a TCP endpoint, such as a logstash or
+    /// vector listener, one line per record in the file format.
+    ///
+    /// Lines are written by a background thread using the [`queue_capacity`](Self::queue_capacity)
+    /// and [`overflow`](Self::overflow) settings, so a slow endpoint never holds up logging
+    /// unless the policy is [`Overflow::Block`]. [`finish`](Self::finish) fails if `addr`
+    /// cannot be resolved, but not if the endpoint is down: connecting is retried every few
+    /// seconds, and up to `queue_capacity` lines are kept meanwhile, dropping the oldest. How
+    /// many were dropped is sent once the endpoint is back, as a warning record in the file
+    /// format.
+    ///
+    /// With [`LogFormat::Gelf`], records end with a null byte instead of a newline, as
+    /// Graylog's GELF TCP input expects.
+    pub fn tcp_output(mut self, addr: impl ToSocketAddrs, level: LevelFilter) -> Self {
+        let addrs = addr.to_socket_addrs().map(|addrs| addrs.collect());
+        self.tcp_output = Some((addrs, level));
+
+        self
+    }
+
+    /// Also sends records at `level` or more severe to the Unix domain socket at `path`, such
+    /// as a local collector's, one line per record in the file format. Unix only:
+    /// [`finish`](Self::finish) fails elsewhere.
+    ///
+    /// Lines are written like those of [`tcp_output`](Self::tcp_output). The socket does not
+    /// need to exist yet: until a listener is bound at `path`, connecting is retried every
+    /// few seconds and up to [`queue_capacity`](Self::queue_capacity) lines are kept, dropping
+    /// the oldest.
+    pub fn unix_socket_output(mut self, path: impl Into<PathBuf>, level: LevelFilter) -> Self {
+        self.unix_socket_output = Some((path.into(), level));
+
+        self
+    }
+
+    /// Also sends records at `level` or more severe to Android's logcat, tagged with the app
+    /// name and at the matching priority, from `ERROR` down to `VERBOSE` for
+    /// [`Level::Trace`](log::Level::Trace). Requires the `android` feature and only supported
+    /// on Android; [`finish`](Self::finish) fails elsewhere.
+    ///
+    /// The messages are `target - message`, as logcat shows the time and level itself. Where
+    /// apps cannot write files, this can be the main output next to an unusable log file with
+    /// [`fallback_to_terminal`](Self::fallback_to_terminal).
+    #[cfg(feature = "android")]
+    pub fn logcat_output(mut self, level: LevelFilter) -> Self {
+        self.logcat_output = Some(level);
+
+        self
+    }
+
+    /// Replaces the clock used for file names and rotation.
+    #[cfg(test)]
+    fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+
+        self
+    }
+
+    #[cfg(test)]
+    fn current_exe(mut self, current_exe: fn() -> io::Result<PathBuf>) -> Self {
+        self.current_exe = current_exe;
+
+        self
+    }
+
+    #[cfg(test)]
+    fn cache_dir(mut self, cache_dir: fn(&str, &str, &str) -> Option<PathBuf>) -> Self {
+        self.cache_dir = cache_dir;
+
+        self
+    }
+
+    /// Installs a global logger right away that keeps records until
+    /// [`finish`](Self::finish) replays them into the configured outputs, so logs from
+    /// setup code running in between, e.g. while loading the settings the builder is
+    /// configured from, are not lost.
+    ///
+    /// Call it first, before anything that may log. Up to 1024 records are kept, each with
+    /// a copy of its target and message, dropping the oldest and warning about them on
+    /// replay. Records are filtered by the levels only when replayed, and their lines show
+    /// the time of the replay. `finish` fails if another global logger was installed first.
+    pub fn capture_early(mut self) -> Self {
+        if let Err(e) = early::install(self.max_memory) {
+            self.early_error = Some(e.into());
+        }
+
+        self
+    }
+
+    /// The settings so far, for checking how the builder was configured.
+    pub fn settings(&self) -> Settings<'_> {
+        Settings(self)
+    }
+
+    pub fn finish(self) -> anyhow::Result<LoggingHandle> {
+        let show_logger_version = self.show_logger_version;
+        let build_info = self
+            .build_info
+            .filter(|info| info.fields().next().is_some());
+        let log_panics = self.log_panics;
+        let capture_backtrace = self.capture_backtrace;
+        let timers = self.timers();
+        let (root, mut handle) = self.build()?;
+
+        let (_, root) = root.into_log();
+        let logger = Reloadable::new(root);
+        if early::installed() {
+            early::forward(Box::new(logger.clone()))?;
+        } else {
+            log::set_boxed_logger(Box::new(logger.clone()))?;
+        }
+        handle.logger = Some(logger);
+        levels::apply_max_level(&handle.levels.read().unwrap());
+        if log_panics {
+            panic::install();
+        }
+        error::set_capture_backtraces(capture_backtrace);
+        handle.start_timers(timers, Instant::now())?;
+
+        if let Some(e) = handle.file_error() {
+            log::warn!("Logging to file disabled: {:#}", e);
+        }
+        for warning in handle.warnings.drain(..) {
+            log::warn!("{}", warning);
+        }
+        if show_logger_version {
+            log::info!("Logging with logging {}", LOGGING_CRATE_VERSION);
+        }
+        if let Some(info) = build_info {
+            log::info!("Build {}", info);
+        }
+        if let Some(effective) = handle.effective_config.take() {
+            log::info!("Logging configured{}", effective);
+        }
+
+        Ok(handle)
+    }
+
+    /// The lines to log periodically once logging is installed.
+    fn timers(&self) -> Timers {
+        Timers {
+            heartbeat: self.heartbeat.map(|interval| {
+                (
+                    interval,
+                    self.heartbeat_level,
+                    self.heartbeat_message.clone(),
+                )
+            }),
+            resource_stats: self.resource_stats,
+        }
+    }
+
+    /// Builds the root dispatch without installing it as the global logger.
+    fn build(mut self) -> anyhow::Result<(Dispatch, LoggingHandle)> {
+        let config = config::resolve(&self)?;
+
+        self.started = Instant::now();
+
+        let levels: SharedLevels = match &self.reconfiguring {
+            Some(levels) => levels.clone(),
+            None => Arc::new(RwLock::new(config.levels.clone())),
+        };
+
+        let dropped = Arc::new(AtomicU64::new(0));
+        let file_failed = Arc::new(AtomicBool::new(false));
+        let memory = MemoryBudget::new(self.max_memory);
+
+        let mut warnings = vec![];
+        // Settled first, so every output uses the same directory.
+        if !self.fallback_dirs.is_empty() {
+            if let Some(dir) = self.usable_log_dir(&mut warnings) {
+                self.log_dir = Some(dir);
+            }
+        }
+
+        // Before the files are picked, so a second logger fails without rotating them.
+        let dir_lock = match self.resolve_log_dir() {
+            _ if self.file_handle.is_some() => None,
+            Ok(dir) => {
+                self.diagnose(format_args!("Using the log directory {}", dir.display()));
+                Some(DirLock::acquire(&dir)?)
+            }
+            Err(e) => {
+                self.diagnose(format_args!("No log directory: {:#}", e));
+                None
+            }
+        };
+
+        let mut workers = vec![];
+        let (
+            file,
+            finalize,
+            shared_file,
+            preallocated,
+            retention,
+            shutdown_rotation,
+            reopen,
+            file_error,
+        ) = match self.file_dispatch(
+            &config,
+            &dropped,
+            &memory,
+            &file_failed,
+            &mut warnings,
+            &mut workers,
+        ) {
+            Ok(sink) => (
+                Some(filtered(
+                    sink.dispatch.filter(levels::filter(&levels, |l, m| {
+                        l.sink_enabled(Sink::File, m)
+                    })),
+                    std::mem::take(&mut self.file_filters),
+                )),
+                sink.finalize,
+                sink.shared_file,
+                sink.preallocated,
+                sink.retention,
+                sink.shutdown_rotation,
+                sink.reopen,
+                None,
+            ),
+            Err(e) if self.fallback_to_terminal || e.is::<SetupTimeout>() => {
+                self.diagnose(format_args!(
+                    "Logging to the terminal only, as the log file is unusable: {:#}",
+                    e
+                ));
+                (None, None, None, None, None, None, None, Some(e))
+            }
+            Err(e) => return Err(e),
+        };
+        let file = file.map(|file| {
+            if self.capture_backtrace {
+                Dispatch::new().chain(Box::new(AppendBacktrace(file.into_log().1)) as Box<dyn Log>)
+            } else {
+                file
+            }
+        });
+
+        // Before capturing stdout, which would make it a pipe.
+        let wrap_width = self.terminal_width.and_then(wrap::resolve_width);
+
+        // Log lines go to the original stdout so they are not copied into the file twice.
+        let (stdout_tee, terminal): (_, fern::Output) = match &shared_file {
+            Some(file) if self.capture_stdout => {
+                let (tee, terminal) = StdoutTee::start(file.clone())
+                    .map_err(|e| anyhow::anyhow!("Unable to capture stdout: {}", e))?;
+                (
+                    Some(tee),
+                    (Box::new(terminal) as Box<dyn Write + Send>).into(),
+                )
+            }
+            _ => (None, std::io::stdout().into()),
+        };
+        let terminal = if self.split_streams {
+            split_streams(terminal, std::io::stderr().into()).into()
+        } else {
+            terminal
+        };
+        let terminal = match wrap_width {
+            Some(width) => Dispatch::new()
+                .format(move |out, message, _| {
+                    out.finish(format_args!("{}", wrap::wrap(&message.to_string(), width)))
+                })
+                .chain(terminal)
+                .into(),
+            None => terminal,
+        };
+        let term = filtered(
+            self.line_dispatch(&config.terminal)
+                .filter(levels::filter(&levels, |l, m| {
+                    l.sink_enabled(Sink::Terminal, m)
+                }))
+                .chain(terminal),
+            std::mem::take(&mut self.terminal_filters),
+        );
+
+        let mut root = Dispatch::new()
+            .level(LevelFilter::Trace)
+            .filter(levels::filter(&levels, Levels::enabled_anywhere));
+
+        let mut active = vec!["terminal"];
+        let mut sinks = Dispatch::new().chain(term);
+        if let Some(file) = file {
+            active.push("file");
+            sinks = sinks.chain(file);
+        }
+
+        // Outputs without their own module overrides.
+        let mut others = Dispatch::new().filter(levels::filter(&levels, Levels::enabled));
+
+        if let (true, Ok(dir)) = (self.crash_files, self.resolve_log_dir()) {
+            active.push("crash_files");
+            let crashes = CrashFiles::new(
+                dir.join("crashes"),
+                self.max_crash_files,
+                self.file_clock(),
+                self.restrict_permissions,
+            );
+            let dispatch = self
+                .text_dispatch(true, true, true, None, ColumnLayout::default())
+                .level(LevelFilter::Error)
+                .chain(Box::new(crashes) as Box<dyn Write + Send>);
+            others = others.chain(if self.capture_backtrace {
+                Dispatch::new()
+                    .chain(Box::new(AppendBacktrace(dispatch.into_log().1)) as Box<dyn Log>)
+            } else {
+                dispatch
+            });
+        }
+
+        let mut endpoints = vec![];
+        if let Some((addrs, level)) = config.tcp.clone() {
+            active.push("tcp");
+            endpoints.push((Endpoint::Tcp(addrs), level));
+        }
+        #[cfg(unix)]
+        if let Some((path, level)) = self.unix_socket_output.clone() {
+            active.push("unix_socket");
+            endpoints.push((Endpoint::Unix(path), level));
+        }
+        #[cfg(all(feature = "android", target_os = "android"))]
+        if let Some(level) = self.logcat_output {
+            active.push("logcat");
+            let logcat = logcat::Logcat::new(&self.app_name);
+            others = others.chain(
+                self.text_dispatch(false, false, true, None, ColumnLayout::default())
+                    .level(level)
+                    .chain(Box::new(logcat) as Box<dyn log::Log>),
+            );
+        }
+        // Graylog's GELF TCP inputs split records on null bytes rather than newlines.
+        let framing = match config.file {
+            LineFormat::Gelf => Framing::Nul,
+            _ => Framing::Lines,
+        };
+        for (endpoint, level) in endpoints {
+            let sink = NonBlocking::new(
+                Box::new(SocketWriter::new(
+                    endpoint,
+                    self.queue_capacity,
+                    memory.clone(),
+                    self.encode_record(&config.file, framing),
+                )),
+                self.queue_capacity,
+                self.overflow,
+                framing,
+                self.encode_record(&config.file, framing),
+                dropped.clone(),
+                memory.clone(),
+            )?;
+            workers.push(sink.worker());
+            others = others.chain(
+                self.line_dispatch(&config.file)
+                    .level(level)
+                    .chain(Box::new(sink) as Box<dyn log::Log>),
+            );
+        }
+
+        let dropped_events = Arc::new(AtomicU64::new(0));
+        let targets = Arc::new(Targets::new(self.intern_targets));
+        if let Some(tx) = self.channel.take() {
+            active.push("channel");
+            let channel = Channel::new(tx, targets.clone(), dropped_events.clone());
+            others = others.chain(Box::new(channel) as Box<dyn log::Log>);
+        }
+        #[cfg(feature = "error-reporter")]
+        if let Some(report) = &self.error_reporter {
+            active.push("error_reporter");
+            let reporter = sinks::ErrorReporter::new(
+                report.clone(),
+                CHANNEL_CAPACITY,
+                targets.clone(),
+                dropped_events.clone(),
+            )?;
+            others = others.chain(Box::new(reporter) as Box<dyn log::Log>);
+        }
+
+        let broadcast = Broadcast::new(SUBSCRIBER_CAPACITY, targets, dropped_events.clone());
+        others = others.chain(Box::new(broadcast.clone()) as Box<dyn log::Log>);
+
+        let ring_buffer = RingBuffer::new(self.ring_buffer, memory.clone());
+        if self.ring_buffer > 0 {
+            active.push("ring_buffer");
+            others = others.chain(
+                self.line_dispatch(&config.file)
+                    .chain(Box::new(ring_buffer.clone()) as Box<dyn log::Log>),
+            );
+        }
+        sinks = sinks.chain(others);
+
+        if self.show_sequence {
+            let (_, inner) = sinks.into_log();
+            sinks = Dispatch::new().chain(Box::new(Sequenced::new(inner)) as Box<dyn log::Log>);
+        }
+
+        if let Some(rate) = self.rate_limit {
+            let (_, inner) = sinks.into_log();
+            let limited = RateLimited::new(rate, self.rate_limit_exempt_errors, inner);
+            sinks = Dispatch::new().chain(Box::new(limited) as Box<dyn log::Log>);
+        }
+
+        if let Some(cooldown) = self.error_cooldown {
+            let (_, inner) = sinks.into_log();
+            let cooldown = ErrorCooldown::new(cooldown, self.clock.clone(), inner);
+            sinks = Dispatch::new().chain(Box::new(cooldown) as Box<dyn log::Log>);
+        }
+
+        // Outside the sequence numbers and rate limit, so filtered records use up neither.
+        sinks = filtered(sinks, std::mem::take(&mut self.filters));
+
+        if self.ordered {
+            let (_, sinks) = sinks.into_log();
+            root = root.chain(Box::new(Ordered::new(sinks)) as Box<dyn log::Log>);
+        } else {
+            root = root.chain(sinks);
+        }
+
+        let manifest = file_error
+            .is_none()
+            .then(|| self.manifest(&config))
+            .flatten();
+        if let Some(Err(e)) = manifest.as_ref().map(Manifest::write) {
+            warnings.push(format!("Unable to write the log manifest: {}", e));
+        }
+
+        let summary = self.shutdown_summary.then(|| Summary {
+            app_name: self.app_name.clone(),
+            started: self.started,
+            counts: Arc::new(Counts::default()),
+        });
+        if let Some(summary) = &summary {
+            root = root.chain(Box::new(Counter(summary.counts.clone())) as Box<dyn log::Log>);
+        }
+
+        if let (Some(file), true) = (shared_file, self.dump_on_error > 0) {
+            let buffer = RingBuffer::new(self.dump_on_error, memory.clone());
+            let (_, suppressed) = self
+                .line_dispatch(&config.file)
+                .chain(Box::new(buffer.clone()) as Box<dyn log::Log>)
+                .into_log();
+            let dump = ErrorDump {
+                levels: levels.clone(),
+                suppressed,
+                buffer,
+                file,
+            };
+            root = Dispatch::new()
+                .chain(Box::new(dump) as Box<dyn log::Log>)
+                .chain(root);
+        }
+
+        // Outside every filter, as captures want all records.
+        let capture = Capture::new(self.line_dispatch(&config.file));
+        root = Dispatch::new()
+            .chain(Box::new(capture.clone()) as Box<dyn log::Log>)
+            .chain(root);
+
+        if let Some(transform) = &self.transform_message {
+            let transform = TransformMessage {
+                transform: transform.clone(),
+                inner: root.into_log().1,
+            };
+            root = Dispatch::new().chain(Box::new(transform) as Box<dyn log::Log>);
+        }
+
+        let log_dir = self.resolve_log_dir().ok();
+        let effective_config = self.log_effective_config.then(|| {
+            Effective {
+                config: &config,
+                log_dir: log_dir.as_deref(),
+                max_log_files: self.max_log_files,
+                max_log_age: self.max_log_age,
+                sinks: &active,
+            }
+            .to_string()
+        });
+
+        if let Some(shared) = &self.reconfiguring {
+            let mut shared = shared.write().unwrap_or_else(|e| e.into_inner());
+            let mut levels = config.levels.clone();
+            levels.keep_scopes(&shared);
+            *shared = levels;
+        }
+
+        Ok((
+            root,
+            LoggingHandle {
+                file_error,
+                file_failed,
+                dropped,
+                levels,
+                ring_buffer,
+                memory,
+                capture,
+                logger: None,
+                dropped_events,
+                broadcast,
+                log_dir,
+                summary,
+                stdout_tee,
+                drop_behavior: self.drop_behavior,
+                workers,
+                preallocated,
+                finalize,
+                manifest,
+                retention,
+                shutdown_rotation,
+                reopen,
+                heartbeat: None,
+                resource_stats: None,
+                dir_lock,
+                warnings,
+                effective_config,
+            },
+        ))
+    }
+
+    /// The first of the log directory and the [`fallback_dirs`](Self::fallback_dirs) that log
+    /// files can be created in, warning about those skipped.
+    fn usable_log_dir(&self, warnings: &mut Vec<String>) -> Option<PathBuf> {
+        let preferred = self.resolve_log_dir().ok();
+
+        for dir in preferred.iter().chain(&self.fallback_dirs) {
+            match files::probe_dir(dir, self.require_existing_dir, self.restrict_permissions) {
+                Ok(()) => return Some(dir.clone()),
+                Err(e) => {
+                    let warning =
+                        format!("Unable to use the log directory {}: {}", dir.display(), e);
+                    self.diagnose(format_args!("{}", warning));
+                    warnings.push(warning);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Passes `note` to the [`diagnostics`](Self::diagnostics) hook, if any.
+    fn diagnose(&self, note: fmt::Arguments) {
+        if let Some(hook) = &self.diagnostics {
+            hook(&note.to_string());
+        }
+    }
+
+    /// Resolves the directory log files are written to.
+    fn resolve_log_dir(&self) -> anyhow::Result<PathBuf> {
+        if let Some(dir) = &self.log_dir {
+            return Ok(dir.clone());
+        }
+
+        if self.portable {
+            let exe = (self.current_exe)().context("Unable to find the executable")?;
+            let exe_dir = match exe.parent() {
+                Some(dir) => dir,
+                None => anyhow::bail!("The executable {} has no parent directory", exe.display()),
+            };
+
+            return Ok(exe_dir.join("logs"));
+        }
+
+        let cache_dir = if let Some(d) =
+            (self.cache_dir)(&self.qualifier, &self.organization, &self.app_name)
+        {
+            d
+        } else if let Some(dir) = &self.project_dirs_fallback {
+            return Ok(dir.clone());
+        } else {
+            anyhow::bail!("Unable to get project directories");
+        };
+        let mut log_dir = cache_dir;
+        log_dir.push("logs");
+
+        Ok(log_dir)
+    }
+
+    /// The manifest of [`write_manifest`](Self::write_manifest), if enabled.
+    fn manifest(&self, config: &Config) -> Option<Manifest> {
+        if !self.write_manifest {
+            return None;
+        }
+
+        Some(Manifest {
+            log_dir: self.resolve_log_dir().ok()?,
+            lines: match config.file {
+                LineFormat::Text { .. } => Lines::Text,
+                LineFormat::Json => Lines::Json,
+                LineFormat::Logfmt
+                | LineFormat::Gelf
+                | LineFormat::Binary
+                | LineFormat::Access { .. }
+                | LineFormat::Custom(_) => Lines::Custom,
+            },
+            level_names: self.level_names.clone(),
+        })
+    }
+
+    /// Sets up the log files and creates their dispatch, along with the rename for
+    /// [`finalize_on_exit`](Self::finalize_on_exit) and the log file writer for
+    /// [`capture_stdout`](Self::capture_stdout).
+    ///
+    /// `dropped` counts lines lost by the non-blocking queue, which shares `memory` with the
+    /// other buffers, and `file_failed` is set when
+    /// [`write_retries`](Self::write_retries) gives up on the log file. Rotation warnings are
+    /// added to `warnings` and writer threads to `workers`.
+    fn file_dispatch(
+        &self,
+        config: &Config,
+        dropped: &Arc<AtomicU64>,
+        memory: &MemoryBudget,
+        file_failed: &Arc<AtomicBool>,
+        warnings: &mut Vec<String>,
+        workers: &mut Vec<Worker>,
+    ) -> anyhow::Result<FileSink> {
+        let mut segments = None;
+        let mut files = match &self.file_handle {
+            Some(file) => {
+                self.diagnose(format_args!("Logging to the given file handle"));
+                LogFiles::handle(file.try_clone().context("Unable to use the file handle")?)
+            }
+            None => {
+                let to_chrono = |d| {
+                    chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::max_value())
+                };
+                let setup = FileSetup {
+                    log_dir: self.resolve_log_dir()?,
+                    require_existing_dir: self.require_existing_dir,
+                    file_mode: config.file_mode,
+                    app_name: self.app_name.clone(),
+                    filename_format: config.filename_format.clone(),
+                    max_files: self.max_log_files,
+                    on_rotate: match (&self.diagnostics, &self.on_rotate) {
+                        (Some(hook), on_rotate) => {
+                            let (hook, on_rotate) = (hook.clone(), on_rotate.clone());
+                            Some(Arc::new(move |path: &Path| {
+                                hook(&format!("Deleting the old log file {}", path.display()));
+                                if let Some(cb) = &on_rotate {
+                                    cb(path);
+                                }
+                            }) as Arc<RotateCallback>)
+                        }
+                        (None, on_rotate) => on_rotate.clone(),
+                    },
+                    clock: self.file_clock(),
+                    max_age: self.max_log_age.map(to_chrono),
+                    clock_skew: to_chrono(self.clock_skew),
+                    history_max_bytes: self.history_max_bytes,
+                    finalize_on_exit: self.finalize_on_exit,
+                    writer_factory: self.writer_factory.clone(),
+                    audit_name: self.audit.as_ref().map(|(name, _)| name.clone()),
+                    preallocate: self.preallocate,
+                    daily_rollover: self.daily_rollover,
+                    max_lines: self.max_lines,
+                    background_rotation: self.background_rotation,
+                    restrict_permissions: self.restrict_permissions,
+                    session_label: self.session_label.clone(),
+                    reopen_on_sighup: self.reopen_on_sighup,
+                    per_level_files: self.per_level_files,
+                    rotation_order: self.rotation_order,
+                    rotate_when: self.rotate_when,
+                    file_share: self.file_share,
+                    manifest: self.manifest(config),
+                };
+
+                if self.split_by_target_segment {
+                    let framing = match config.file {
+                        LineFormat::Binary => Framing::Binary,
+                        _ => Framing::Lines,
+                    };
+                    segments = Some(SegmentFiles::new(&setup, framing, self.line_buffered));
+                }
+
+                let files = match self.setup_timeout {
+                    Some(timeout) => setup.open_within(timeout)?,
+                    None => setup.open()?,
+                };
+                self.diagnose(format_args!(
+                    "Logging to {} in {:?} mode",
+                    files.path.display(),
+                    config.file_mode
+                ));
+
+                files
+            }
+        };
+        warnings.append(&mut files.warnings);
+        if let Some((retries, backoff)) = self.write_retries {
+            files.log_file = Box::new(RetryingWriter::new(
+                files.log_file,
+                retries,
+                backoff,
+                file_failed.clone(),
+            ));
+        }
+        #[cfg(unix)]
+        if let Some(reopen) = &files.reopen {
+            signal::watch(reopen).context("Unable to handle SIGHUP")?;
+        }
+
+        let (log_file, shared_file) = if self.capture_stdout || self.dump_on_error > 0 {
+            let shared = Closable::new(files.log_file);
+            (
+                Box::new(shared.clone()) as Box<dyn Write + Send>,
+                Some(shared),
+            )
+        } else {
+            (files.log_file, None)
+        };
+
+        // Binary records are encoded by the output, from the unformatted record.
+        let (mut dispatch, framing) = match config.file {
+            LineFormat::Binary => (Dispatch::new(), Framing::Binary),
+            _ => (self.line_dispatch(&config.file), Framing::Lines),
+        };
+        let format = &config.file;
+        dispatch =
+            dispatch.chain(self.file_output(log_file, format, framing, dropped, memory, workers)?);
+        if let Some(history) = files.history {
+            dispatch = dispatch
+                .chain(self.file_output(history, format, framing, dropped, memory, workers)?);
+        }
+        for (level, file) in files.per_level {
+            let cumulative = self.per_level_files_cumulative;
+            let level_dispatch = match config.file {
+                LineFormat::Binary => Dispatch::new(),
+                _ => self.line_dispatch(&config.file),
+            };
+            dispatch = dispatch.chain(
+                level_dispatch
+                    .filter(move |m| m.level() == level || (cumulative && m.level() < level))
+                    .chain(self.file_output(file, format, framing, dropped, memory, workers)?),
+            );
+        }
+
+        if let Some(segments) = segments {
+            let root = self.app_name.replace('-', "_");
+            let segment_dispatch = match config.file {
+                LineFormat::Binary => Dispatch::new(),
+                _ => self.line_dispatch(&config.file),
+            };
+            dispatch = Dispatch::new()
+                .chain(dispatch.filter(move |m| files::target_segment(m.target(), &root).is_none()))
+                .chain(segment_dispatch.chain(Box::new(segments) as Box<dyn Log>));
+        }
+
+        if let (Some((_, target)), Some(audit)) = (&self.audit, files.audit) {
+            let is_audit = {
+                let target = target.clone();
+                move |m: &log::Metadata| levels::module_parents(m.target()).any(|t| t == target)
+            };
+            if !self.audit_in_log_file {
+                let is_audit = is_audit.clone();
+                dispatch = dispatch.filter(move |m| !is_audit(m));
+            }
+            dispatch = Dispatch::new().chain(dispatch).chain(
+                self.line_dispatch(&config.file)
+                    .filter(is_audit)
+                    .chain(Box::new(audit) as Box<dyn Write + Send>),
+            );
+        }
+
+        Ok(FileSink {
+            dispatch,
+            finalize: files.finalize,
+            shared_file,
+            preallocated: files.preallocated,
+            retention: files.retention,
+            shutdown_rotation: files.shutdown_rotation,
+            reopen: files.reopen,
+        })
+    }
+
+    /// Wraps `file` in a non-blocking sink if enabled, adding its writer thread to `workers`.
+    /// The sink's notices are written in `format`.
+    fn file_output(
+        &self,
+        file: Box<dyn Write + Send>,
+        format: &LineFormat,
+        framing: Framing,
+        dropped: &Arc<AtomicU64>,
+        memory: &MemoryBudget,
+        workers: &mut Vec<Worker>,
+    ) -> anyhow::Result<fern::Output> {
+        if self.non_blocking {
+            let notice: Box<EncodeRecord> = match framing {
+                Framing::Lines | Framing::Nul => self.encode_record(format, framing),
+                Framing::Binary => Box::new(binary::encode),
+            };
+            let sink = NonBlocking::new(
+                file,
+                self.queue_capacity,
+                self.overflow,
+                framing,
+                notice,
+                dropped.clone(),
+                memory.clone(),
+            )?;
+            workers.push(sink.worker());
+
+            Ok((Box::new(sink) as Box<dyn log::Log>).into())
+        } else if framing == Framing::Binary {
+            let writer = BinaryWriter::new(file, self.line_buffered);
+            Ok((Box::new(writer) as Box<dyn log::Log>).into())
+        } else {
+            let writer = LineWriter::new(file, self.line_buffered);
+            Ok((Box::new(writer) as Box<dyn log::Log>).into())
+        }
+    }
+
+    /// Renders a record into a line in `format`, for the notices outputs write themselves.
+    fn encode_record(&self, format: &LineFormat, framing: Framing) -> Box<EncodeRecord> {
+        let (tx, rx) = channel::<String>();
+        let line_sep = if framing == Framing::Nul { "\0" } else { "\n" };
+        let (_, formatted) = self
+            .line_dispatch(format)
+            .chain(fern::Output::sender(tx, line_sep))
+            .into_log();
+        let rx = Mutex::new(rx);
+
+        Box::new(move |record| {
+            formatted.log(record);
+            let rx = rx.lock().unwrap_or_else(|e| e.into_inner());
+            rx.try_iter().collect::<String>().into_bytes()
+        })
+    }
+
+    /// Creates a dispatch rendering records with `format`, without an output attached.
+    fn line_dispatch(&self, format: &LineFormat) -> Dispatch {
+        match format {
+            LineFormat::Text {
+                show_level,
+                show_time,
+                show_target,
+                colors,
+                columns,
+            } => self.text_dispatch(
+                *show_level,
+                *show_time,
+                *show_target,
+                colors.clone(),
+                *columns,
+            ),
+            LineFormat::Json => self.json_dispatch(),
+            LineFormat::Logfmt => self.logfmt_dispatch(),
+            LineFormat::Gelf => self.gelf_dispatch(),
+            LineFormat::Access { combined, text } => self.access_dispatch(*combined, text),
+            LineFormat::Binary => {
+                self.text_dispatch(true, true, true, None, self.columns.unwrap_or_default())
+            }
+            LineFormat::Custom(format) => {
+                let (format, offset) = (format.clone(), self.offset());
+                Dispatch::new().format(move |out, message, record| {
+                    crate::format::with_offset(offset, || format(out, message, record))
+                })
+            }
+        }
+    }
+
+    /// Creates a dispatch using the built-in text format with the given parts enabled.
+    fn text_dispatch(
+        &self,
+        show_level: bool,
+        show_time: bool,
+        show_target: bool,
+        colors: Option<Palette>,
+        columns: ColumnLayout,
+    ) -> Dispatch {
+        let style = self.text_style(show_level, show_time, show_target, colors, columns);
+
+        Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "{}",
+                TextLine {
+                    style: &style,
+                    record,
+                    message,
+                }
+            ))
+        })
+    }
+
+    /// The settings for the built-in text format with the given parts enabled.
+    fn text_style(
+        &self,
+        show_level: bool,
+        show_time: bool,
+        show_target: bool,
+        colors: Option<Palette>,
+        columns: ColumnLayout,
+    ) -> TextStyle {
+        TextStyle {
+            show_level,
+            show_time,
+            started: self.show_uptime.then_some(self.started),
+            show_target,
+            targets: self.target_display(),
+            level_names: self.level_names.clone(),
+            verbose_errors: self.verbose_errors,
+            colors,
+            show_sequence: self.show_sequence,
+            offset: self.offset(),
+            columns,
+            empty_message: self.empty_message_placeholder.clone(),
+        }
+    }
+
+    /// Creates a dispatch formatting the records of the
+    /// [`access_log_target`](Self::access_log_target) in the Common or Combined Log Format,
+    /// and the others with `text`.
+    fn access_dispatch(&self, combined: bool, text: &LineFormat) -> Dispatch {
+        let style = match text {
+            LineFormat::Text {
+                show_level,
+                show_time,
+                show_target,
+                colors,
+                columns,
+            } => self.text_style(
+                *show_level,
+                *show_time,
+                *show_target,
+                colors.clone(),
+                *columns,
+            ),
+            _ => self.text_style(true, true, true, None, ColumnLayout::default()),
+        };
+        let target = self.access_log_target.clone();
+        let offset = self.offset();
+
+        Dispatch::new().format(move |out, message, record| {
+            if levels::module_parents(record.target()).any(|t| t == target) {
+                out.finish(format_args!(
+                    "{}",
+                    AccessLine {
+                        combined,
+                        offset,
+                        message,
+                    }
+                ))
+            } else {
+                out.finish(format_args!(
+                    "{}",
+                    TextLine {
+                        style: &style,
+                        record,
+                        message,
+                    }
+                ))
+            }
+        })
+    }
+
+    /// The [`fixed_offset`](Self::fixed_offset), if set and valid.
+    fn offset(&self) -> Option<chrono::FixedOffset> {
+        let (hours, minutes) = self.fixed_offset?;
+        if !(-59..60).contains(&minutes) || (minutes < 0 && hours != 0) {
+            return None;
+        }
+        let minutes = if hours < 0 { -minutes } else { minutes };
+
+        chrono::FixedOffset::east_opt(hours.checked_mul(3600)?.checked_add(minutes * 60)?)
+    }
+
+    /// The clock for file names, in the [`fixed_offset`](Self::fixed_offset) if set.
+    fn file_clock(&self) -> Arc<dyn Clock> {
+        match self.offset() {
+            Some(offset) => Arc::new(OffsetClock {
+                inner: self.clock.clone(),
+                offset,
+            }),
+            None => self.clock.clone(),
+        }
+    }
+
+    fn target_display(&self) -> TargetDisplay {
+        TargetDisplay::new(self.default_target.clone(), &self.app_name)
+            .with_strip_root(self.strip_app_prefix)
+            .with_versions(self.target_versions.clone())
+    }
+
+    /// The settings for the JSON and logfmt formats.
+    fn json_style(&self) -> JsonStyle {
+        JsonStyle {
+            stable: self.json_stable,
+            targets: self.target_display(),
+            level_names: self.level_names.clone(),
+            fields: self
+                .json_fields
+                .iter()
+                .cloned()
+                .chain(self.show_logger_version.then(|| {
+                    (
+                        LOGGER_VERSION_KEY.to_string(),
+                        LOGGING_CRATE_VERSION.to_string(),
+                    )
+                }))
+                .chain(
+                    self.session_label
+                        .clone()
+                        .map(|label| (SESSION_KEY.to_string(), label)),
+                )
+                .chain(
+                    self.schema_version
+                        .clone()
+                        .map(|version| (SCHEMA_KEY.to_string(), version)),
+                )
+                .collect(),
+            show_sequence: self.show_sequence,
+            offset: self.offset(),
+        }
+    }
+
+    /// Creates a dispatch formatting records as JSON.
+    fn json_dispatch(&self) -> Dispatch {
+        let style = self.json_style();
+
+        Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "{}",
+                JsonLine {
+                    style: &style,
+                    record,
+                    message,
+                }
+            ))
+        })
+    }
+
+    /// Creates a dispatch formatting records as logfmt.
+    fn logfmt_dispatch(&self) -> Dispatch {
+        let style = self.json_style();
+
+        Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "{}",
+                LogfmtLine {
+                    style: &style,
+                    record,
+                    message,
+                }
+            ))
+        })
+    }
+
+    /// Creates a dispatch formatting records as GELF.
+    fn gelf_dispatch(&self) -> Dispatch {
+        let style = self.json_style();
+        let host = format::hostname();
+
+        Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "{}",
+                GelfLine {
+                    style: &style,
+                    host: &host,
+                    record,
+                    message,
+                }
+            ))
+        })
+    }
+}
+
+/// Wraps `dispatch` so it only gets the records all of `filters` match.
+fn filtered(dispatch: Dispatch, filters: Vec<Box<dyn Filter>>) -> Dispatch {
+    if filters.is_empty() {
+        return dispatch;
+    }
+
+    let (_, inner) = dispatch.into_log();
+    Dispatch::new().chain(Box::new(Filtered::new(filters, inner)) as Box<dyn log::Log>)
+}
+
+/// Sends `Warn` and `Error` records to `stderr` and the rest to `stdout`, for
+/// [`LoggingBuilder::split_streams`].
+fn split_streams(stdout: fern::Output, stderr: fern::Output) -> Dispatch {
+    Dispatch::new()
+        .chain(
+            Dispatch::new()
+                .filter(|m| m.level() > log::Level::Warn)
+                .chain(stdout),
+        )
+        .chain(
+            Dispatch::new()
+                .filter(|m| m.level() <= log::Level::Warn)
+                .chain(stderr),
+        )
+}
+
+/// The log file dispatch and what else [`LoggingBuilder::build`] needs from the file setup.
+struct FileSink {
+    dispatch: Dispatch,
+    finalize: Option<Finalize>,
+    /// The log file, shared with the stdout tee and the error dump.
+    shared_file: Option<Closable>,
+    preallocated: Option<Preallocated>,
+    retention: Option<RetentionThread>,
+    shutdown_rotation: Option<ShutdownRotation>,
+    reopen: Option<Arc<Reopen>>,
+}
+
+/// The [`heartbeat`](LoggingBuilder::heartbeat) and
+/// [`resource_stats`](LoggingBuilder::resource_stats) settings, started once logging is
+/// installed.
+struct Timers {
+    heartbeat: Option<(Duration, Level, String)>,
+    resource_stats: Option<Duration>,
+}
+
+/// Returned by [`LoggingBuilder::finish`] once logging is installed.
+pub struct LoggingHandle {
+    file_error: Option<anyhow::Error>,
+    /// Set when [`LoggingBuilder::write_retries`] gives up on the log file.
+    file_failed: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    levels: SharedLevels,
+    ring_buffer: RingBuffer,
+    memory: MemoryBudget,
+    capture: Capture,
+    /// The installed logger, set by `finish`.
+    logger: Option<Reloadable>,
+    dropped_events: Arc<AtomicU64>,
+    broadcast: Broadcast,
+    log_dir: Option<PathBuf>,
+    summary: Option<Summary>,
+    stdout_tee: Option<StdoutTee>,
+    drop_behavior: DropBehavior,
+    /// The non-blocking writer threads.
+    workers: Vec<Worker>,
+    preallocated: Option<Preallocated>,
+    finalize: Option<Finalize>,
+    manifest: Option<Manifest>,
+    retention: Option<RetentionThread>,
+    shutdown_rotation: Option<ShutdownRotation>,
+    /// Keeps the log file registered for `SIGHUP`.
+    reopen: Option<Arc<Reopen>>,
+    /// Started by `finish`, as it logs through the installed logger.
+    heartbeat: Option<Periodic>,
+    resource_stats: Option<Periodic>,
+    dir_lock: Option<DirLock>,
+    /// Logged by `finish` once logging is installed.
+    warnings: Vec<String>,
+    /// The settings for `log_effective_config`, also logged by `finish`.
+    effective_config: Option<String>,
+}
+
+impl LoggingHandle {
+    /// Whether log lines are being written to a log file.
+    ///
+    /// This is only `false` when [`LoggingBuilder::fallback_to_terminal`] was enabled and
+    /// the log file could not be set up, or when [`LoggingBuilder::write_retries`] gave up
+    /// on it.
+    pub fn file_logging_active(&self) -> bool {
+        self.file_error.is_none() && !self.file_failed.load(Ordering::Relaxed)
+    }
+
+    /// The version of this crate, the same as [`LOGGING_CRATE_VERSION`].
+    pub fn logger_version(&self) -> &'static str {
+        LOGGING_CRATE_VERSION
+    }
+
+    /// Why file logging is disabled, if it is.
+    pub fn file_error(&self) -> Option<&anyhow::Error> {
+        self.file_error.as_ref()
+    }
+
+    /// The directory log files are written to, if it could be determined.
+    pub fn log_dir(&self) -> Option<&Path> {
+        self.log_dir.as_deref()
+    }
+
+    /// Opens the log directory in the platform's file manager, e.g. for a "Show logs" button.
+    ///
+    /// Runs `explorer` on Windows, `open` on macOS and `xdg-open` elsewhere. Fails if the
+    /// directory does not exist, the program cannot be run or, outside Windows, it reports
+    /// that nothing could open the directory.
+    pub fn open_log_dir(&self) -> anyhow::Result<()> {
+        let dir = self
+            .log_dir()
+            .ok_or_else(|| anyhow::anyhow!("Unable to get project directories"))?;
+
+        desktop::open_dir(dir)
+    }
+
+    /// The number of log lines dropped because the non-blocking queue was full.
+    ///
+    /// Always `0` unless [`LoggingBuilder::non_blocking`] is enabled with a dropping
+    /// [`Overflow`] policy or [`LoggingBuilder::max_memory`].
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The number of events dropped because the
+    /// [`channel_output`](LoggingBuilder::channel_output) receiver, a
+    /// [`subscribe`](Self::subscribe) receiver or the `error_reporter` fell behind.
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// A new receiver for every record logged from now on that passes the level filters,
+    /// for components that each want all events, e.g. a log panel and a remote debugger.
+    ///
+    /// Every subscriber gets its own copy of each event. Each receiver holds up to 1024
+    /// events; logging never blocks on it, so a subscriber that falls behind misses the new
+    /// events until it catches up, which [`dropped_events`](Self::dropped_events) counts.
+    /// Dropping the receiver unsubscribes it.
+    pub fn subscribe(&self) -> Receiver<LogEvent> {
+        self.broadcast.subscribe()
+    }
+
+    /// The most recent log lines, oldest first, when
+    /// [`LoggingBuilder::ring_buffer`] is enabled.
+    pub fn recent(&self) -> Vec<String> {
+        self.ring_buffer.lines()
+    }
+
+    /// The number of lines the [`ring_buffer`](LoggingBuilder::ring_buffer) and
+    /// [`dump_on_error`](LoggingBuilder::dump_on_error) buffers evicted or dropped to stay
+    /// under [`LoggingBuilder::max_memory`].
+    pub fn evicted_lines(&self) -> u64 {
+        self.memory.dropped()
+    }
+
+    /// Logs everything from `target` and its submodules at `Trace` until the returned guard
+    /// is dropped, then restores the configured levels.
+    ///
+    /// Scopes can nest and overlap: a target stays at `Trace` while any scope for it is
+    /// alive, regardless of the order the guards are dropped in. A scope also wins over
+    /// more specific [`LoggingBuilder::level_for`] entries below its target.
+    pub fn debug_scope(&self, target: impl Into<String>) -> DebugScope {
+        let target = target.into();
+
+        let mut levels = self.levels.write().unwrap_or_else(|e| e.into_inner());
+        levels.elevate(&target);
+        levels::apply_max_level(&levels);
+
+        DebugScope {
+            levels: self.levels.clone(),
+            target,
+        }
+    }
+
+    /// Also writes every record to a new file at `path` until
+    /// [`stop_capture`](Self::stop_capture), at `Trace` whatever the configured levels, e.g.
+    /// to hand a full log of reproducing a bug to support.
+    ///
+    /// The lines are in the log file's format. The other outputs and their levels are not
+    /// affected. Starting a capture while one is running ends the running one.
+    pub fn start_capture(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        if let Some(mut previous) = self.capture.start(Box::new(file)) {
+            previous.flush()?;
+        }
+
+        let mut levels = self.levels.write().unwrap_or_else(|e| e.into_inner());
+        levels.set_capturing(true);
+        levels::apply_max_level(&levels);
+
+        Ok(())
+    }
+
+    /// Ends the [`start_capture`](Self::start_capture) session and closes its file. Does
+    /// nothing if no capture is running.
+    pub fn stop_capture(&self) -> std::io::Result<()> {
+        {
+            let mut levels = self.levels.write().unwrap_or_else(|e| e.into_inner());
+            levels.set_capturing(false);
+            levels::apply_max_level(&levels);
+        }
+
+        match self.capture.stop() {
+            Some(mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the whole configuration with `builder`'s, e.g. after the user changed the
+    /// logging settings, without restarting. Records logged meanwhile go to either the old
+    /// or the new outputs, and none are lost.
+    ///
+    /// The new outputs are set up first, so on error nothing changes. Then the old ones are
+    /// flushed and closed as if this handle was dropped: their non-blocking writers finish,
+    /// [`finalize_on_exit`](LoggingBuilder::finalize_on_exit) files are renamed and a
+    /// running [`start_capture`](Self::start_capture) session ends. Open
+    /// [`debug_scope`](Self::debug_scope)s keep applying until dropped.
+    ///
+    /// Levels, formats, outputs and file settings can all change. The log files are picked
+    /// again, so [`FileMode::Rotate`] starts a new file while [`FileMode::Append`] continues
+    /// the newest. Stdout capture, if enabled before, is stopped first and started again if
+    /// `builder` enables it. Only [`log_panics`](LoggingBuilder::log_panics) stays as it
+    /// was, and the [`shutdown_summary`](LoggingBuilder::shutdown_summary), if still
+    /// enabled, counts from here on.
+    ///
+    /// Fails if this handle was not returned by [`LoggingBuilder::finish`].
+    pub fn reconfigure(&mut self, mut builder: LoggingBuilder) -> anyhow::Result<()> {
+        let logger = match &self.logger {
+            Some(logger) => logger.clone(),
+            None => {
+                anyhow::bail!("Only handles returned by LoggingBuilder::finish can reconfigure")
+            }
+        };
+        // So a new tee captures the real stdout rather than the old pipe.
+        if let Some(tee) = &mut self.stdout_tee {
+            tee.stop();
+        }
+        let capture_backtrace = builder.capture_backtrace;
+        let timers = builder.timers();
+        let started = self
+            .heartbeat
+            .as_ref()
+            .map_or_else(Instant::now, |heartbeat| heartbeat.started);
+
+        // So live debug scopes keep applying, and end in the new configuration.
+        builder.reconfiguring = Some(self.levels.clone());
+        // Released first, as the new outputs may use the same directory.
+        let released = self.dir_lock.take().map(DirLock::release);
+        let (root, mut new) = match builder.build() {
+            Ok(built) => built,
+            Err(mut e) => {
+                // Put back what was given up for the new configuration, so nothing changes.
+                if let Some(dir) = released {
+                    match DirLock::acquire(&dir) {
+                        Ok(lock) => self.dir_lock = Some(lock),
+                        Err(lost) => {
+                            e = e.context(format!("Also lost the log directory: {}", lost))
+                        }
+                    }
+                }
+                if let Some(tee) = &mut self.stdout_tee {
+                    if let Err(lost) = tee.resume() {
+                        e = e.context(format!("Also stopped capturing stdout: {}", lost));
+                    }
+                }
+
+                return Err(e);
+            }
+        };
+        let (_, root) = root.into_log();
+        new.logger = Some(logger.clone());
+
+        let old_root = logger.replace(root);
+        levels::apply_max_level(&new.levels.read().unwrap_or_else(|e| e.into_inner()));
+        old_root.flush();
+        // Dropping the old sinks lets their writer threads finish.
+        drop(old_root);
+        error::set_capture_backtraces(capture_backtrace);
+
+        let mut old = std::mem::replace(self, new);
+        old.summary = None;
+        drop(old);
+        self.start_timers(timers, started)?;
+
+        if let Some(e) = self.file_error() {
+            log::warn!("Logging to file disabled: {:#}", e);
+        }
+        for warning in self.warnings.drain(..) {
+            log::warn!("{}", warning);
+        }
+        if let Some(effective) = self.effective_config.take() {
+            log::info!("Logging configured{}", effective);
+        }
+
+        Ok(())
+    }
+
+    /// Starts the threads `timers` ask for, counting the uptime from `started`.
+    fn start_timers(&mut self, timers: Timers, started: Instant) -> io::Result<()> {
+        let logger = match &self.logger {
+            Some(logger) => logger,
+            None => return Ok(()),
+        };
+
+        if let Some((interval, level, message)) = timers.heartbeat {
+            self.heartbeat = Some(Periodic::spawn(
+                "logging-heartbeat",
+                interval,
+                level,
+                "heartbeat",
+                Box::new(logger.clone()),
+                started,
+                move |started| periodic::heartbeat(&message, started),
+            )?);
+        }
+        if let Some(interval) = timers.resource_stats {
+            self.resource_stats = Some(Periodic::spawn(
+                "logging-resources",
+                interval,
+                Level::Info,
+                "resources",
+                Box::new(logger.clone()),
+                started,
+                |_| resources::probe().to_string(),
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Adds `key=value` to every record logged on the current thread until the returned
+    /// guard is dropped, e.g. a request id for correlating the lines of one request.
+    ///
+    /// Contexts nest: records carry every field pushed and not yet dropped, oldest first.
+    /// Threads spawned meanwhile start without them. Text lines end with
+    /// `[req_id=42 user=bob]`, JSON objects get a `context` object and logfmt lines the
+    /// pairs themselves. Custom formats do not show them.
+    pub fn push_context(&self, key: impl Into<String>, value: impl fmt::Display) -> ContextGuard {
+        context::push(key.into(), value.to_string())
+    }
+
+    /// The current global level and module entries, including the terminal and file
+    /// overrides, for putting back later with [`restore`](Self::restore).
+    pub fn snapshot(&self) -> LevelSnapshot {
+        self.levels
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .snapshot()
+    }
+
+    /// Replaces the whole level configuration with `snapshot` at once.
+    ///
+    /// Live [`debug_scope`](Self::debug_scope)s are not part of snapshots and stay in effect
+    /// until their guards are dropped.
+    pub fn restore(&self, snapshot: LevelSnapshot) {
+        let mut levels = self.levels.write().unwrap_or_else(|e| e.into_inner());
+        levels.restore(snapshot);
+        levels::apply_max_level(&levels);
+    }
+}
+
+impl Drop for LoggingHandle {
+    fn drop(&mut self) {
+        // Before the writers stop, so its warnings are still written.
+        self.retention.take();
+        self.reopen.take();
+        self.heartbeat.take();
+        self.resource_stats.take();
+        if let Some(summary) = &self.summary {
+            log::info!("{}", summary.line());
+            log::logger().flush();
+        }
+        if let Some(tee) = &mut self.stdout_tee {
+            tee.stop();
+        }
+        match self.drop_behavior {
+            DropBehavior::FlushAndJoin => {
+                log::logger().flush();
+                let deadline = Instant::now() + JOIN_TIMEOUT;
+                for worker in &self.workers {
+                    worker.join(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
+            DropBehavior::FlushOnly => {
+                log::logger().flush();
+                for worker in &self.workers {
+                    worker.flush();
+                }
+            }
+            DropBehavior::Detach => {}
+        }
+        if let Some(preallocated) = &self.preallocated {
+            let _ = preallocated.release();
+        }
+        if let Some(finalize) = &self.finalize {
+            log::logger().flush();
+            let _ = finalize.run();
+        }
+        if let Some(rotation) = &self.shutdown_rotation {
+            let _ = rotation.run();
+        }
+        if let Some(manifest) = &self.manifest {
+            log::logger().flush();
+            let _ = manifest.write();
+        }
+    }
+}
+
+/// Restores the levels raised by [`LoggingHandle::debug_scope`] when dropped.
+#[must_use = "the scope ends as soon as the guard is dropped"]
+pub struct DebugScope {
+    levels: SharedLevels,
+    target: String,
+}
+
+impl Drop for DebugScope {
+    fn drop(&mut self) {
+        let mut levels = self.levels.write().unwrap_or_else(|e| e.into_inner());
+        levels.unelevate(&self.target);
+        levels::apply_max_level(&levels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read_dir, remove_dir_all, File},
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use chrono::{Duration, NaiveDateTime};
+    use log::{Level, LevelFilter, Log, Record};
+
+    use fern::Dispatch;
+
+    use crate::{
+        color::{ColorDepth, Palette},
+        config::{self, LineFormat},
+        error,
+        levels::Sink,
+        sinks::Reloadable,
+        test_util::{self, builder, create_log, format_line, log_line, temp_dir, ManualClock},
+        ColorScheme, Column, ColumnLayout, FileMode, FileShare, LevelScope, LogFormat,
+        LoggingBuilder, LoggingError, LoggingHandle, Overflow, RotateWhen, CHRONO_FORMAT,
+        LOGGING_CRATE_VERSION, LOG_SCHEMA_VERSION,
+    };
+
+    fn terminal(builder: &LoggingBuilder) -> Dispatch {
+        builder.line_dispatch(&config::terminal_format(builder))
+    }
+
+    fn file(builder: &LoggingBuilder) -> Dispatch {
+        builder.line_dispatch(&config::file_format(builder))
+    }
+
+    #[test]
+    fn split_streams() {
+        let (stdout, stdout_rx) = std::sync::mpsc::channel();
+        let (stderr, stderr_rx) = std::sync::mpsc::channel();
+        let (_, split) = super::split_streams(stdout.into(), stderr.into()).into_log();
+
+        for level in [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ] {
+            split.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", level))
+                    .build(),
+            );
+        }
+
+        assert_eq!(
+            stdout_rx.try_iter().collect::<Vec<_>>(),
+            ["INFO\n", "DEBUG\n", "TRACE\n"]
+        );
+        assert_eq!(
+            stderr_rx.try_iter().collect::<Vec<_>>(),
+            ["ERROR\n", "WARN\n"]
+        );
+    }
+
+    #[test]
+    fn terminal_show_target() {
+        let line = |builder: LoggingBuilder| {
+            format_line(
+                terminal(&builder),
+                Level::Info,
+                "myapp::commands::run",
+                "done",
+            )
+        };
+
+        assert_eq!(
+            line(LoggingBuilder::new()),
+            "[INFO] myapp::commands::run - done\n"
+        );
+        assert_eq!(
+            line(LoggingBuilder::new().terminal_show_target(false)),
+            "[INFO] done\n"
+        );
+        assert_eq!(
+            line(
+                LoggingBuilder::new()
+                    .terminal_show_target(false)
+                    .terminal_show_level(false)
+            ),
+            "done\n"
+        );
+    }
+
+    #[test]
+    fn terminal_timestamp() {
+        let has_time = |line: &str| {
+            let time = line
+                .strip_prefix("[INFO] ")
+                .unwrap()
+                .get(..19)
+                .unwrap_or_default();
+            NaiveDateTime::parse_from_str(time, CHRONO_FORMAT).is_ok()
+        };
+        let line = |dispatch| format_line(dispatch, Level::Info, "app", "done");
+
+        let builder = LoggingBuilder::new();
+        assert_eq!(line(terminal(&builder)), "[INFO] app - done\n");
+        assert!(has_time(&line(file(&builder))));
+
+        let builder = LoggingBuilder::new().terminal_timestamp(true);
+        let terminal = line(terminal(&builder));
+        assert!(has_time(&terminal), "{}", terminal);
+        assert!(terminal.ends_with(" app - done\n"));
+        assert!(has_time(&line(file(&builder))));
+    }
+
+    #[test]
+    fn fallback_to_terminal() {
+        let dir = temp_dir("fallback-to-terminal");
+        let blocker = dir.join("not-a-dir");
+        File::create(&blocker).unwrap();
+
+        let builder = || test_util::builder(blocker.join("logs"));
+
+        assert!(builder().build().is_err());
+
+        let (_, handle) = builder().fallback_to_terminal(true).build().unwrap();
+        assert!(!handle.file_logging_active());
+        assert!(handle.file_error().is_some());
+
+        let (_, handle) = builder()
+            .log_dir(dir.join("logs"))
+            .fallback_to_terminal(true)
+            .build()
+            .unwrap();
+        assert!(handle.file_logging_active());
+        assert_eq!(read_dir(dir.join("logs")).unwrap().count(), 1);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_retries() {
+        /// Fails with the queued errors before writing.
+        #[derive(Clone, Default)]
+        struct Flaky {
+            errors: Arc<std::sync::Mutex<Vec<std::io::ErrorKind>>>,
+            written: Arc<std::sync::Mutex<Vec<u8>>>,
+        }
+
+        impl std::io::Write for Flaky {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if let Some(kind) = self.errors.lock().unwrap().pop() {
+                    return Err(kind.into());
+                }
+                self.written.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = temp_dir("write-retries");
+        let build = |flaky: &Flaky| {
+            let flaky = flaky.clone();
+            builder(&dir)
+                .file_mode(FileMode::Single)
+                .file_writer_factory(Arc::new(move |_: &Path| {
+                    Ok(Box::new(flaky.clone()) as Box<dyn std::io::Write + Send>)
+                }))
+                .write_retries(3, std::time::Duration::from_millis(1))
+                .build()
+                .unwrap()
+        };
+        fn log(root: &dyn Log, message: &str) {
+            log_line(&root, Level::Info, "app", message);
+        }
+        let lines = |flaky: &Flaky| {
+            let written = String::from_utf8(flaky.written.lock().unwrap().clone()).unwrap();
+            written
+                .lines()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let flaky = Flaky::default();
+        let (root, handle) = build(&flaky);
+        let (_, root) = root.into_log();
+        *flaky.errors.lock().unwrap() = vec![
+            std::io::ErrorKind::TimedOut,
+            std::io::ErrorKind::WouldBlock,
+            std::io::ErrorKind::Interrupted,
+        ];
+        log(&*root, "one");
+        assert_eq!(lines(&flaky).len(), 1);
+        assert!(lines(&flaky)[0].ends_with(" app - one"));
+        assert!(handle.file_logging_active());
+        drop(handle);
+
+        // One more transient error than retries, then a permanent one.
+        for errors in [
+            vec![std::io::ErrorKind::WouldBlock; 4],
+            vec![std::io::ErrorKind::PermissionDenied],
+        ] {
+            let flaky = Flaky::default();
+            let (root, handle) = build(&flaky);
+            let (_, root) = root.into_log();
+            *flaky.errors.lock().unwrap() = errors;
+            log(&*root, "lost");
+            log(&*root, "discarded");
+            assert!(lines(&flaky).is_empty());
+            assert!(flaky.errors.lock().unwrap().is_empty());
+            assert!(!handle.file_logging_active());
+        }
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filename_format() {
+        let dir = temp_dir("filename-format");
+        let builder = || {
+            test_util::builder(&dir)
+                .fallback_to_terminal(true)
+                .filename_format("%Y/%m/%d %T")
+        };
+
+        assert!(builder().build().is_err());
+
+        let (_, handle) = builder().sanitize_filenames(true).build().unwrap();
+        assert!(handle.file_logging_active());
+        let name = read_dir(&dir).unwrap().next().unwrap().unwrap().file_name();
+        assert!(NaiveDateTime::parse_from_str(
+            name.to_str().unwrap().trim_end_matches(".log"),
+            "%Y-%m-%d %H-%M-%S"
+        )
+        .is_ok());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_names_follow_clock() {
+        let dir = temp_dir("file-names-follow-clock");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let names = || {
+            let mut names = read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        for _ in 0..3 {
+            let _ = builder(&dir)
+                .max_log_files(2)
+                .clock(clock.clone())
+                .build()
+                .unwrap();
+            clock.advance(Duration::minutes(1));
+        }
+
+        assert_eq!(
+            names(),
+            ["2024-01-02_03-05-05.log", "2024-01-02_03-06-05.log"]
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn history_log() {
+        let dir = temp_dir("history-log");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let run = |message: &str, max_bytes| {
+            let (root, _) = builder(&dir)
+                .max_log_files(1)
+                .history_log(max_bytes)
+                .clock(clock.clone())
+                .build()
+                .unwrap();
+            let (_, root) = root.into_log();
+            log_line(&root, Level::Info, "app", message);
+            root.flush();
+            clock.advance(Duration::minutes(1));
+        };
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+
+        run("one", 1024);
+        run("two", 1024);
+
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+        assert!(read("2024-01-02_03-05-05.log").ends_with(" app - two\n"));
+        let history = read("history.log");
+        assert_eq!(history.lines().count(), 2);
+        assert!(history.contains(" app - one\n") && history.ends_with(" app - two\n"));
+
+        run("three", 10);
+
+        assert!(read("history.log").ends_with(" app - three\n"));
+        assert_eq!(read("history.log.1"), history);
+        assert_eq!(read_dir(&dir).unwrap().count(), 3);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn capture() {
+        let dir = temp_dir("capture");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let (root, handle) = builder(&dir)
+            .global_level(LevelFilter::Info)
+            .clock(clock)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        let log = |level, message: &str| log_line(&root, level, "app", message);
+        let capture = dir.join("capture.txt");
+
+        log(Level::Info, "before");
+        handle.start_capture(&capture).unwrap();
+        log(Level::Trace, "detail");
+        log(Level::Info, "during");
+        handle.stop_capture().unwrap();
+        log(Level::Info, "after");
+
+        let captured = std::fs::read_to_string(&capture).unwrap();
+        let lines = captured.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{}", captured);
+        assert!(lines[0].starts_with("[TRACE] ") && lines[0].ends_with(" app - detail"));
+        assert!(lines[1].ends_with(" app - during"));
+
+        let log = std::fs::read_to_string(dir.join("2024-01-02_03-04-05.log")).unwrap();
+        assert!(!log.contains("detail"));
+        assert!(log.contains(" app - before\n") && log.contains(" app - during\n"));
+        assert!(log.contains(" app - after\n"));
+
+        handle.stop_capture().unwrap();
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn capture_backtrace() {
+        let dir = temp_dir("capture-backtrace");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let (root, handle) = builder(&dir)
+            .capture_backtrace(true)
+            .clock(clock)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        let log = |message: &str| log_line(&root, Level::Error, "app", message);
+
+        error::pending(std::backtrace::Backtrace::force_capture(), || {
+            log("disk on fire")
+        });
+        log("no backtrace");
+        drop(root);
+        drop(handle);
+
+        let log = std::fs::read_to_string(dir.join("2024-01-02_03-04-05.log")).unwrap();
+        assert!(log.contains(" app - disk on fire\nBacktrace:\n"), "{}", log);
+        assert!(log.contains("capture_backtrace"), "{}", log);
+        assert_eq!(log.matches("Backtrace:").count(), 1, "{}", log);
+        assert!(log.ends_with(" app - no backtrace\n"), "{}", log);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reconfigure() {
+        let dir = temp_dir("reconfigure");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let builder = |format| test_util::builder(&dir).format(format).clock(clock.clone());
+        let (root, mut handle) = builder(LogFormat::Text).build().unwrap();
+        let logger = Reloadable::new(root.into_log().1);
+        handle.logger = Some(logger.clone());
+        let log = |message: &str| log_line(&logger, Level::Info, "app", message);
+
+        log("text");
+        assert!(handle
+            .reconfigure(builder(LogFormat::Json).max_log_files(0))
+            .is_err());
+        log("still text");
+        clock.advance(Duration::seconds(1));
+        handle.reconfigure(builder(LogFormat::Json)).unwrap();
+        log("json");
+
+        let old = std::fs::read_to_string(dir.join("2024-01-02_03-04-05.log")).unwrap();
+        assert!(old.contains(" app - text\n") && old.ends_with(" app - still text\n"));
+        let new = std::fs::read_to_string(dir.join("2024-01-02_03-04-06.log")).unwrap();
+        assert_eq!(new.lines().count(), 1);
+        assert!(new.contains(r#""message":"json""#));
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reconfigure_keeps_debug_scopes() {
+        let dir = temp_dir("reconfigure-scopes");
+        let (root, mut handle) = builder(&dir)
+            .global_level(LevelFilter::Warn)
+            .build()
+            .unwrap();
+        handle.logger = Some(Reloadable::new(root.into_log().1));
+        // What is handed to `log::set_max_level`, which other tests also set.
+        let max_level = |handle: &LoggingHandle| handle.levels.read().unwrap().max_level();
+
+        let scope = handle.debug_scope("app::db");
+        handle
+            .reconfigure(builder(&dir).global_level(LevelFilter::Info))
+            .unwrap();
+        assert_eq!(max_level(&handle), LevelFilter::Trace);
+        assert_eq!(
+            handle.levels.read().unwrap().level_for("app::db"),
+            LevelFilter::Trace
+        );
+
+        drop(scope);
+        assert_eq!(max_level(&handle), LevelFilter::Info);
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn daily_rollover() {
+        let dir = temp_dir("daily-rollover");
+        File::create(dir.join("2023-12-30.log")).unwrap();
+        let clock = Arc::new(ManualClock::new("2024-01-01 23:59:59"));
+        let (root, _) = builder(&dir)
+            .max_log_files(2)
+            .daily_rollover(true)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        let log = |message: &str| log_line(&root, Level::Info, "app", message);
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+
+        log("before midnight");
+        clock.advance(Duration::seconds(1));
+        log("after midnight");
+
+        assert!(read("2024-01-01.log").ends_with(" app - before midnight\n"));
+        assert!(read("2024-01-02.log").ends_with(" app - after midnight\n"));
+        assert!(!dir.join("2023-12-30.log").exists());
+
+        clock.advance(Duration::days(1));
+        log("next day");
+
+        let mut names = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["2024-01-02.log", "2024-01-03.log"]);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_lines() {
+        let dir = temp_dir("max-lines");
+        let (root, _) = builder(&dir)
+            .max_log_files(10)
+            .max_lines(2)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        for message in ["one", "two", "three", "four\nfive\nsix", "seven"] {
+            log_line(&root, Level::Info, "app", message);
+        }
+
+        let mut names = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names.len(), 4, "{:?}", names);
+        let contents = names
+            .iter()
+            .map(|name| std::fs::read_to_string(dir.join(name)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(contents.iter().all(|c| c.lines().count() <= 2));
+        assert!(contents[1].ends_with(" app - four\n"), "{:?}", contents);
+        assert_eq!(contents[2], "five\nsix\n");
+        assert!(contents[3].ends_with(" app - seven\n"), "{:?}", contents);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diagnostics() {
+        let dir = temp_dir("diagnostics");
+        let old = create_log(&dir, -60);
+        let unusable = dir.join("file");
+        File::create(&unusable).unwrap();
+        let notes = Arc::new(std::sync::Mutex::new(vec![]));
+        let (_, handle) = builder(&unusable)
+            .fallback_dirs(vec![dir.clone()])
+            .max_log_files(1)
+            .diagnostics({
+                let notes = notes.clone();
+                Arc::new(move |note: &str| notes.lock().unwrap().push(note.to_string()))
+            })
+            .build()
+            .unwrap();
+
+        let notes = notes.lock().unwrap().clone();
+        assert_eq!(notes.len(), 4, "{:?}", notes);
+        assert!(notes[0].starts_with(&format!(
+            "Unable to use the log directory {}: ",
+            unusable.display()
+        )));
+        assert_eq!(
+            notes[1],
+            format!("Using the log directory {}", dir.display())
+        );
+        assert_eq!(
+            notes[2],
+            format!("Deleting the old log file {}", old.display())
+        );
+        assert!(notes[3].starts_with(&format!("Logging to {}", dir.display())));
+        assert!(notes[3].ends_with(".log in Rotate mode"), "{}", notes[3]);
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn crash_files() {
+        let dir = temp_dir("crash-files");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let (root, _handle) = builder(&dir)
+            .crash_files(true)
+            .max_crash_files(2)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        let log = |level, message: &str| log_line(&root, level, "app", message);
+        let crashes = || {
+            let mut names = read_dir(dir.join("crashes"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        log(Level::Info, "fine");
+        assert!(!dir.join("crashes").exists());
+
+        log(Level::Error, "disk on fire");
+        assert_eq!(crashes(), ["2024-01-02_03-04-05-1.txt"]);
+        let crash = std::fs::read_to_string(dir.join("crashes").join(&crashes()[0])).unwrap();
+        assert!(crash.starts_with("[ERROR] "), "{}", crash);
+        assert!(crash.ends_with(" app - disk on fire\n"), "{}", crash);
+
+        log(Level::Error, "still on fire");
+        clock.advance(Duration::seconds(1));
+        log(Level::Error, "ashes");
+        assert_eq!(
+            crashes(),
+            ["2024-01-02_03-04-05-2.txt", "2024-01-02_03-04-06-3.txt"]
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn per_level_files() {
+        let dir = temp_dir("per-level-files");
+        let run = |cumulative| {
+            let (root, _) = builder(&dir)
+                .file_mode(FileMode::Single)
+                .per_level_files(true)
+                .per_level_files_cumulative(cumulative)
+                .build()
+                .unwrap();
+            let (_, root) = root.into_log();
+            for (level, message) in [(Level::Info, "started"), (Level::Error, "failed")] {
+                log_line(&root, level, "app", message);
+            }
+        };
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+
+        run(false);
+        assert_eq!(read("app.log").lines().count(), 2);
+        let info = read("app.info.log");
+        assert!(info.starts_with("[INFO] ") && info.ends_with(" app - started\n"));
+        assert_eq!(read("app.error.log").lines().count(), 1);
+        assert!(!dir.join("app.warn.log").exists());
+        assert!(!dir.join("app.trace.log").exists());
+
+        run(true);
+        let info = read("app.info.log");
+        assert_eq!(info.lines().count(), 2, "{}", info);
+        assert!(info.ends_with(" app - failed\n"));
+        assert_eq!(read("app.error.log").lines().count(), 1);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_by_target_segment() {
+        let dir = temp_dir("split-by-target-segment");
+        let (root, _) = LoggingBuilder::new()
+            .app_name("my-app")
+            .qualifier("com")
+            .organization("org")
+            .log_dir(&dir)
+            .file_mode(FileMode::Single)
+            .split_by_target_segment(true)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        for (target, message) in [
+            ("weather::fetch", "sunny"),
+            ("news::feed::rss", "headline"),
+            ("weather::cache", "cached"),
+            ("my_app::net", "connected"),
+            ("weather", "loaded"),
+        ] {
+            log_line(&root, Level::Info, target, message);
+        }
+        let read = |path: &str| {
+            let text = std::fs::read_to_string(dir.join(path)).unwrap();
+            text.lines()
+                .map(|line| line.split_once("] ").unwrap().1.split_once(' ').unwrap().1)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            read("weather/my-app.log"),
+            ["weather::fetch - sunny", "weather::cache - cached"]
+        );
+        assert_eq!(read("news/my-app.log"), ["news::feed::rss - headline"]);
+        assert_eq!(
+            read("my-app.log"),
+            ["my_app::net - connected", "weather - loaded"]
+        );
+        assert!(!dir.join("my_app").exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn audit_file() {
+        let dir = temp_dir("audit-file");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let run = |in_log_file| {
+            let (root, _) = builder(&dir)
+                .max_log_files(1)
+                .audit_file("audit.log", "audit")
+                .audit_in_log_file(in_log_file)
+                .clock(clock.clone())
+                .build()
+                .unwrap();
+            let (_, root) = root.into_log();
+            for (target, message) in [
+                ("audit::login", "alice"),
+                ("auditor", "ignored"),
+                ("app", "hello"),
+            ] {
+                log_line(&root, Level::Info, target, message);
+            }
+            root.flush();
+            clock.advance(Duration::minutes(1));
+        };
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+
+        run(false);
+        let log = read("2024-01-02_03-04-05.log");
+        assert!(!log.contains("alice"));
+        assert!(log.contains(" auditor - ignored\n") && log.contains(" app - hello\n"));
+
+        run(true);
+        assert!(read("2024-01-02_03-05-05.log").contains(" audit::login - alice\n"));
+
+        let audit = read("audit.log");
+        assert_eq!(audit.lines().count(), 2);
+        assert!(audit
+            .lines()
+            .all(|line| line.ends_with(" audit::login - alice")));
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_follows_rotation() {
+        let dir = temp_dir("manifest-rotation");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        for _ in 0..3 {
+            let (_, handle) = builder(&dir)
+                .max_log_files(2)
+                .write_manifest(true)
+                .clock(clock.clone())
+                .build()
+                .unwrap();
+            drop(handle);
+            clock.advance(Duration::minutes(1));
+        }
+
+        let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+        let names = manifest
+            .lines()
+            .filter_map(|line| line.strip_prefix("{\"name\":\""))
+            .map(|line| line.split('"').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["2024-01-02_03-05-05.log", "2024-01-02_03-06-05.log"]
+        );
+        assert_eq!(read_dir(&dir).unwrap().count(), 3);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_follows_max_lines() {
+        let dir = temp_dir("manifest-max-lines");
+        let (root, handle) = builder(&dir)
+            .max_lines(1)
+            .write_manifest(true)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        for message in ["one", "two"] {
+            log_line(&root, Level::Info, "app", message);
+        }
+
+        // Rewritten on moving to the second file, before the handle is dropped.
+        let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+        assert_eq!(manifest.matches("{\"name\":").count(), 2, "{}", manifest);
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn portable() {
+        let builder = || {
+            LoggingBuilder::new()
+                .app_name("app")
+                .qualifier("com")
+                .organization("org")
+                .portable(true)
+        };
+
+        let found = builder().current_exe(|| Ok(PathBuf::from("/media/usb/app/app.exe")));
+        assert_eq!(
+            found.resolve_log_dir().unwrap(),
+            Path::new("/media/usb/app/logs")
+        );
+        assert_eq!(
+            found.log_dir("/var/log/app").resolve_log_dir().unwrap(),
+            Path::new("/var/log/app")
+        );
+
+        let missing = builder().current_exe(|| Err(std::io::ErrorKind::NotFound.into()));
+        let err = missing.resolve_log_dir().unwrap_err();
+        assert_eq!(err.to_string(), "Unable to find the executable");
+    }
+
+    #[test]
+    fn project_dirs_fallback() {
+        let builder = || {
+            LoggingBuilder::new()
+                .app_name("app")
+                .qualifier("com")
+                .organization("org")
+                .cache_dir(|_, _, _| None)
+        };
+
+        let err = builder().resolve_log_dir().unwrap_err();
+        assert_eq!(err.to_string(), "Unable to get project directories");
+
+        let dir = temp_dir("project-dirs-fallback");
+        let fallback = builder().project_dirs_fallback(&dir);
+        assert_eq!(fallback.resolve_log_dir().unwrap(), dir);
+        let (root, handle) = fallback.build().unwrap();
+        assert_eq!(handle.log_dir(), Some(dir.as_path()));
+        let (_, root) = root.into_log();
+        log_line(&root, Level::Info, "app", "in the fallback");
+        assert_eq!(read_dir(&dir).unwrap().count(), 1);
+
+        let found = builder()
+            .cache_dir(|_, _, app_name| Some(PathBuf::from("/cache").join(app_name)))
+            .project_dirs_fallback(&dir);
+        assert_eq!(
+            found.resolve_log_dir().unwrap(),
+            Path::new("/cache/app/logs")
+        );
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn require_existing_dir() {
+        let dir = temp_dir("require-existing-dir");
+        let build = |log_dir: &Path| builder(log_dir).require_existing_dir(true).build();
+
+        let missing = dir.join("logs");
+        let err = build(&missing).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("Log directory {} does not exist", missing.display())
+        );
+        assert!(!missing.exists());
+
+        let _ = build(&dir).unwrap();
+        assert_eq!(read_dir(&dir).unwrap().count(), 1);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fallback_dirs() {
+        let dir = temp_dir("fallback-dirs");
+        let blocker = dir.join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let fallback = dir.join("fallback");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+
+        let (_, handle) = builder(blocker.join("logs"))
+            .fallback_dirs(vec![blocker.join("other"), fallback.clone()])
+            .clock(clock)
+            .build()
+            .unwrap();
+
+        assert_eq!(handle.log_dir(), Some(fallback.as_path()));
+        assert!(handle.file_logging_active());
+        assert_eq!(handle.warnings.len(), 2, "{:?}", handle.warnings);
+        assert!(handle.warnings[0].starts_with(&format!(
+            "Unable to use the log directory {}: ",
+            blocker.join("logs").display()
+        )));
+        let names = read_dir(&fallback)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["2024-01-02_03-04-05.log"]);
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn background_rotation() {
+        let dir = temp_dir("background-rotation");
+        let old = dir.join("2024-01-02_02-00-00.log");
+        std::fs::write(&old, "").unwrap();
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+
+        let (_, handle) = builder(&dir)
+            .max_log_age(std::time::Duration::from_secs(2 * 60 * 60))
+            .background_rotation(std::time::Duration::from_millis(10))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(old.exists());
+
+        clock.advance(chrono::Duration::hours(3));
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while old.exists() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!old.exists());
+        assert!(dir.join("2024-01-02_03-04-05.log").exists());
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_when() {
+        let dir = temp_dir("rotate-when");
+        let logs = || {
+            let mut names = read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let run = |when| {
+            for name in logs() {
+                std::fs::remove_file(dir.join(name)).unwrap();
+            }
+            for second in 1..=3 {
+                std::fs::write(dir.join(format!("2024-01-01_00-00-0{}.log", second)), "").unwrap();
+            }
+
+            let (_, handle) = builder(&dir)
+                .max_log_files(2)
+                .rotate_when(when)
+                .clock(Arc::new(ManualClock::new("2024-01-02 03:04:05")))
+                .build()
+                .unwrap();
+            let started = logs();
+            drop(handle);
+
+            (started, logs())
+        };
+        let kept = vec![
+            "2024-01-01_00-00-03.log".to_string(),
+            "2024-01-02_03-04-05.log".to_string(),
+        ];
+
+        let (started, stopped) = run(RotateWhen::Startup);
+        assert_eq!((started, stopped), (kept.clone(), kept.clone()));
+
+        let (started, stopped) = run(RotateWhen::Shutdown);
+        assert_eq!(started.len(), 4, "{:?}", started);
+        assert_eq!(stopped, kept);
+
+        let (started, stopped) = run(RotateWhen::Both);
+        assert_eq!((started, stopped), (kept.clone(), kept.clone()));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_in_use() {
+        let dir = temp_dir("directory-in-use");
+        let log_dir = dir.join("logs");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let build = |log_dir: &Path| {
+            builder(log_dir)
+                .fallback_to_terminal(true)
+                .clock(clock.clone())
+                .build()
+        };
+
+        let first = build(&log_dir).unwrap();
+        std::fs::create_dir(dir.join("other")).unwrap();
+        let err = build(&dir.join("other/../logs")).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoggingError>(),
+            Some(&LoggingError::DirectoryInUse {
+                path: log_dir.canonicalize().unwrap()
+            })
+        );
+        assert!(err.to_string().contains("already used by another logger"));
+        assert_eq!(read_dir(&log_dir).unwrap().count(), 1);
+
+        drop(first);
+        drop(build(&log_dir).unwrap());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reopen_on_sighup() {
+        let dir = temp_dir("reopen-on-sighup");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let (root, handle) = builder(&dir)
+            .reopen_on_sighup(true)
+            .clock(clock)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        let log = |message: &str| {
+            log_line(&root, Level::Info, "app", message);
+            root.flush();
+        };
+        let path = dir.join("2024-01-02_03-04-05.log");
+        let rotated = dir.join("2024-01-02_03-04-05.log.1");
+
+        log("before");
+        std::fs::rename(&path, &rotated).unwrap();
+        log("moved");
+        assert_eq!(unsafe { libc::raise(libc::SIGHUP) }, 0);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !path.exists() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // The file is created just before the writer switches to it.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        log("after");
+        drop(root);
+        drop(handle);
+
+        let old = std::fs::read_to_string(&rotated).unwrap();
+        assert!(old.contains(" app - before\n") && old.contains(" app - moved\n"));
+        let new = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            new.ends_with(" app - after\n") && !new.contains("moved"),
+            "{}",
+            new
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restrict_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("restrict-permissions");
+        let log_dir = dir.join("logs");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+
+        let mode = |path: &Path| path.metadata().unwrap().permissions().mode() & 0o777;
+        let build = |restrict| {
+            builder(&log_dir)
+                .history_log(1024)
+                .restrict_permissions(restrict)
+                .clock(clock.clone())
+                .build()
+                .unwrap()
+        };
+
+        drop(build(false));
+        let history = log_dir.join("history.log");
+        std::fs::set_permissions(&history, std::fs::Permissions::from_mode(0o644)).unwrap();
+        drop(build(true));
+        assert_eq!(mode(&history), 0o600);
+        assert_eq!(mode(&log_dir.join("2024-01-02_03-04-05_1.log")), 0o600);
+
+        remove_dir_all(&log_dir).unwrap();
+        drop(build(true));
+        assert_eq!(mode(&log_dir), 0o700);
+        assert_eq!(mode(&log_dir.join("2024-01-02_03-04-05.log")), 0o600);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_share() {
+        let dir = temp_dir("file-share");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let path = dir.join("2024-01-02_03-04-05.log");
+
+        for share in [FileShare::All, FileShare::Read] {
+            let (root, handle) = builder(&dir)
+                .max_log_files(1)
+                .file_share(share)
+                .clock(clock.clone())
+                .build()
+                .unwrap();
+            let (_, root) = root.into_log();
+            log_line(&root, Level::Info, "app", "live");
+            root.flush();
+
+            let lines = std::fs::read_to_string(&path).unwrap();
+            assert!(lines.ends_with(" app - live\n"), "{:?}", lines);
+            let writable = std::fs::OpenOptions::new().append(true).open(&path);
+            assert_eq!(
+                writable.is_ok(),
+                cfg!(not(windows)) || share == FileShare::All
+            );
+
+            drop(root);
+            drop(handle);
+        }
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn setup_timeout() {
+        let dir = temp_dir("setup-timeout");
+        let old = create_log(&dir, -100);
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+        let wait = std::sync::Mutex::new(wait);
+        // Only the setup thread holds on to the callback, so this is gone once it finishes.
+        let setup = Arc::new(());
+        let setup_running = Arc::downgrade(&setup);
+
+        let (_, handle) = builder(&dir)
+            .max_log_files(1)
+            .on_rotate(Arc::new(move |_: &Path| {
+                let _ = &setup;
+                let _ = wait.lock().unwrap().recv();
+            }))
+            .clock(clock)
+            .setup_timeout(std::time::Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        assert!(!handle.file_logging_active());
+        assert_eq!(
+            handle.file_error().unwrap().to_string(),
+            "Setting up the log files took longer than 50ms"
+        );
+        assert!(old.exists());
+
+        release.send(()).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while setup_running.strong_count() > 0 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(setup_running.strong_count(), 0);
+        assert!(!old.exists());
+        assert!(dir.join("2024-01-02_03-04-05.log").exists());
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finalize_on_exit() {
+        let dir = temp_dir("finalize-on-exit");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let (root, handle) = builder(&dir)
+            .finalize_on_exit(true)
+            .clock(clock)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        log_line(&root, Level::Info, "app", "hello");
+
+        let temp = dir.join("2024-01-02_03-04-05.log.tmp");
+        let path = dir.join("2024-01-02_03-04-05.log");
+        assert!(temp.exists() && !path.exists());
+
+        drop(handle);
+        assert!(!temp.exists());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with(" app - hello\n"));
+
+        log_line(&root, Level::Info, "app", "late");
+        assert!(!temp.exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn capture_stdout() {
+        use std::io::Write;
+
+        let dir = temp_dir("capture-stdout");
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let builder = || {
+            test_util::builder(&dir)
+                .capture_stdout(true)
+                .clock(clock.clone())
+        };
+        let (root, mut handle) = builder().build().unwrap();
+        let root = Reloadable::new(root.into_log().1);
+        handle.logger = Some(root.clone());
+
+        // `println!` is captured by the test harness, so write to the descriptor directly.
+        let mut stdout = std::io::stdout();
+        stdout.write_all(b"from stdout\n").unwrap();
+        stdout.flush().unwrap();
+
+        // A failed reconfigure keeps capturing and keeps the directory.
+        assert!(handle.reconfigure(builder().max_log_files(0)).is_err());
+        assert!(matches!(
+            builder()
+                .build()
+                .err()
+                .unwrap()
+                .downcast_ref::<LoggingError>(),
+            Some(LoggingError::DirectoryInUse { .. })
+        ));
+        stdout.write_all(b"after failure\n").unwrap();
+        stdout.flush().unwrap();
+        log_line(&root, Level::Info, "app", "hello");
+        drop(handle);
+
+        let log = std::fs::read_to_string(dir.join("2024-01-02_03-04-05.log")).unwrap();
+        assert!(log.lines().any(|line| line == "from stdout"));
+        assert!(log.lines().any(|line| line == "after failure"));
+        assert!(log.lines().any(|line| line.ends_with(" app - hello")));
+        assert_eq!(log.matches("from stdout").count(), 1);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocate() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = temp_dir("preallocate");
+        let (root, handle) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .preallocate(1 << 20)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        log_line(&root, Level::Info, "app", "hello");
+
+        let path = dir.join("app.log");
+        let allocated = |path: &Path| path.metadata().unwrap().blocks() * 512;
+        let len = path.metadata().unwrap().len();
+        assert!(len < 100);
+        // Not every filesystem the temp dir may be on honors the reservation.
+        if allocated(&path) >= 1 << 20 {
+            drop(handle);
+            assert!(allocated(&path) < 1 << 20);
+            assert_eq!(path.metadata().unwrap().len(), len);
+        }
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn binary_format() {
+        let dir = temp_dir("binary-format");
+        let (root, handle) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .format(LogFormat::Binary)
+            .ring_buffer(1)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        log_line(&root, Level::Warn, "app::net", "hello");
+
+        let events = crate::decode_binary_log(dir.join("app.log")).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].level, Level::Warn);
+        assert_eq!(&*events[0].target, "app::net");
+        assert_eq!(events[0].message, "hello");
+
+        let recent = &handle.recent()[0];
+        assert!(recent.starts_with("[WARN] ") && recent.ends_with(" app::net - hello"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_panic_is_flushed() {
+        /// Only keeps what was written once flushed.
+        #[derive(Clone, Default)]
+        struct Buffered {
+            pending: Vec<u8>,
+            flushed: Arc<std::sync::Mutex<Vec<u8>>>,
+        }
+
+        impl std::io::Write for Buffered {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flushed.lock().unwrap().append(&mut self.pending);
+                Ok(())
+            }
+        }
+
+        let dir = temp_dir("log-panic");
+        let writer = Buffered::default();
+        let flushed = writer.flushed.clone();
+        let (root, _handle) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .non_blocking(true)
+            .file_writer_factory(Arc::new(move |_: &Path| {
+                Ok(Box::new(writer.clone()) as Box<dyn std::io::Write + Send>)
+            }))
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| crate::panic::log_panic(&*root, info)));
+        let result = std::panic::catch_unwind(|| panic!("disk on fire"));
+        drop(std::panic::take_hook());
+        std::panic::set_hook(previous);
+        assert!(result.is_err());
+
+        let written = String::from_utf8(flushed.lock().unwrap().clone()).unwrap();
+        assert!(written.starts_with("[ERROR] "), "{}", written);
+        assert!(written.contains(" panic - thread 'tests::log_panic_is_flushed' panicked at src"));
+        assert!(written.ends_with(": disk on fire\n"), "{}", written);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn line_buffered() {
+        for line_buffered in [true, false] {
+            let dir = temp_dir("line-buffered");
+            let (root, _) = builder(&dir)
+                .file_mode(FileMode::Single)
+                .line_buffered(line_buffered)
+                .build()
+                .unwrap();
+            let (_, root) = root.into_log();
+            log_line(&root, Level::Info, "app", "hello");
+
+            let log = std::fs::read_to_string(dir.join("app.log")).unwrap();
+            assert_eq!(log.ends_with(" app - hello\n"), line_buffered, "{}", log);
+            root.flush();
+            let log = std::fs::read_to_string(dir.join("app.log")).unwrap();
+            assert!(log.ends_with(" app - hello\n"), "{}", log);
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn line_buffered_binary() {
+        for line_buffered in [true, false] {
+            let dir = temp_dir("line-buffered-binary");
+            let (root, _) = builder(&dir)
+                .file_mode(FileMode::Single)
+                .format(LogFormat::Binary)
+                .line_buffered(line_buffered)
+                .build()
+                .unwrap();
+            let (_, root) = root.into_log();
+            log_line(&root, Level::Info, "app", "hello");
+
+            let events = crate::decode_binary_log(dir.join("app.log")).unwrap();
+            assert_eq!(events.len(), usize::from(line_buffered));
+            root.flush();
+            let events = crate::decode_binary_log(dir.join("app.log")).unwrap();
+            assert_eq!(events[0].message, "hello");
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn file_writer_factory() {
+        #[derive(Clone, Default)]
+        struct Memory(Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Memory {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = temp_dir("file-writer-factory");
+        let memory = Memory::default();
+        let opened = Arc::new(std::sync::Mutex::new(vec![]));
+        let factory = {
+            let (memory, opened) = (memory.clone(), opened.clone());
+            Arc::new(move |path: &Path| {
+                opened.lock().unwrap().push(path.to_path_buf());
+                Ok(Box::new(memory.clone()) as Box<dyn std::io::Write + Send>)
+            })
+        };
+
+        let (root, _) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .file_writer_factory(factory)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        log_line(&root, Level::Info, "app", "hello");
+
+        assert_eq!(*opened.lock().unwrap(), [dir.join("app.log")]);
+        let written = String::from_utf8(memory.0.lock().unwrap().clone()).unwrap();
+        assert!(written.ends_with(" app - hello\n"), "{}", written);
+        assert_eq!(std::fs::read_to_string(dir.join("app.log")).unwrap(), "");
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_lines_notice_is_a_record() {
+        struct Slow(File);
+
+        impl std::io::Write for Slow {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.0.flush()
+            }
+        }
+
+        let dir = temp_dir("dropped-notice");
+        let factory = Arc::new(|path: &Path| {
+            let file = File::options().create(true).append(true).open(path)?;
+            Ok(Box::new(Slow(file)) as Box<dyn std::io::Write + Send>)
+        });
+        let (root, handle) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .format(LogFormat::Json)
+            .file_writer_factory(factory)
+            .non_blocking(true)
+            .queue_capacity(1)
+            .overflow(Overflow::DropNewest)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        for i in 0..100 {
+            log_line(&root, Level::Info, "app", &format!("line {}", i));
+        }
+        root.flush();
+        assert!(handle.dropped_messages() > 0);
+        drop(handle);
+
+        let log = std::fs::read_to_string(dir.join("app.log")).unwrap();
+        let events = crate::query_logs(&dir, &crate::QueryFilter::new()).unwrap();
+        assert_eq!(events.len(), log.lines().count(), "{}", log);
+        assert!(events
+            .iter()
+            .any(|event| event.level == Level::Warn && event.message.starts_with("Dropped ")));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_handle() {
+        let dir = temp_dir("file-handle");
+        let path = dir.join("inherited.txt");
+        std::fs::write(&path, "earlier\n").unwrap();
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+
+        let (root, _) = builder(&dir)
+            .file_mode(FileMode::Rotate)
+            .file_handle(file)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        log_line(&root, Level::Info, "app", "hello");
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("earlier\n[INFO] "), "{}", written);
+        assert!(written.ends_with(" app - hello\n"), "{}", written);
+        let names = read_dir(&dir).unwrap().count();
+        assert_eq!(names, 1, "only the given file is written");
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_behavior_flush_and_join() {
+        /// Takes a while to write, like a slow disk.
+        #[derive(Clone, Default)]
+        struct Slow(Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Slow {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = temp_dir("drop-behavior");
+        let slow = Slow::default();
+        let factory = {
+            let slow = slow.clone();
+            Arc::new(move |_: &Path| Ok(Box::new(slow.clone()) as Box<dyn std::io::Write + Send>))
+        };
+
+        let (root, handle) = builder(&dir)
+            .non_blocking(true)
+            .file_writer_factory(factory)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        for message in ["one", "last"] {
+            log_line(&root, Level::Info, "app", message);
+        }
+        drop(handle);
+
+        let written = String::from_utf8(slow.0.lock().unwrap().clone()).unwrap();
+        assert!(written.ends_with(" app - last\n"), "{}", written);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn single_file_mode() {
+        let dir = temp_dir("single-file-mode");
+        create_log(&dir, -100);
+
+        for _ in 0..2 {
+            let _ = builder(&dir)
+                .max_log_files(1)
+                .file_mode(FileMode::Single)
+                .build()
+                .unwrap();
+        }
+
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+        assert!(dir.join("app.log").exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_format_template() {
+        let line = format_line(
+            terminal(
+                &LoggingBuilder::new()
+                    .custom_format(crate::log_format!("{level:>5}|{target}|{msg}|{line}")),
+            ),
+            Level::Info,
+            "app",
+            "hello",
+        );
+        assert_eq!(line, " INFO|app|hello|\n");
+
+        let line = format_line(
+            terminal(&LoggingBuilder::new().custom_format(crate::log_format!("{time} {msg}"))),
+            Level::Info,
+            "app",
+            "hello",
+        );
+        let (time, msg) = line.trim_end().split_once(' ').unwrap();
+        assert!(NaiveDateTime::parse_from_str(time, CHRONO_FORMAT).is_ok());
+        assert_eq!(msg, "hello");
+
+        // `{time}` follows the fixed offset like the built-in formats.
+        let time = |hours| {
+            let builder = LoggingBuilder::new()
+                .custom_format(crate::log_format!("{time}"))
+                .fixed_offset(hours, 0);
+            let line = format_line(terminal(&builder), Level::Info, "app", "hello");
+            NaiveDateTime::parse_from_str(line.trim_end(), CHRONO_FORMAT).unwrap()
+        };
+        let apart = time(12) - time(-12);
+        assert!(
+            (apart - Duration::hours(24)).num_seconds().abs() < 5,
+            "{}",
+            apart
+        );
+    }
+
+    #[test]
+    fn debug_scope() {
+        let dir = temp_dir("debug-scope");
+        let (_, handle) = builder(&dir)
+            .global_level(LevelFilter::Info)
+            .build()
+            .unwrap();
+        let level = |target: &str| handle.levels.read().unwrap().level_for(target);
+
+        let outer = handle.debug_scope("checkout");
+        let inner = handle.debug_scope("checkout");
+        assert_eq!(level("checkout::cart"), LevelFilter::Trace);
+        assert_eq!(level("billing"), LevelFilter::Info);
+
+        drop(outer);
+        assert_eq!(level("checkout::cart"), LevelFilter::Trace);
+
+        drop(inner);
+        assert_eq!(level("checkout::cart"), LevelFilter::Info);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let dir = temp_dir("snapshot-restore");
+        let (_, handle) = builder(&dir)
+            .global_level(LevelFilter::Info)
+            .level_for("db", LevelFilter::Warn)
+            .level_for_scoped("hyper", LevelFilter::Off, LevelScope::Terminal)
+            .build()
+            .unwrap();
+        let level = |sink, target: &str| handle.levels.read().unwrap().sink_level_for(sink, target);
+
+        let before = handle.snapshot();
+        let _scope = handle.debug_scope("billing");
+        handle.restore(
+            before
+                .clone()
+                .global_level(LevelFilter::Trace)
+                .level_for("hyper", LevelFilter::Debug)
+                .level_for("net", LevelFilter::Error),
+        );
+        assert_eq!(level(Sink::Terminal, "app"), LevelFilter::Trace);
+        assert_eq!(level(Sink::Terminal, "hyper"), LevelFilter::Debug);
+        assert_eq!(level(Sink::File, "db::pool"), LevelFilter::Warn);
+        assert_eq!(level(Sink::File, "net"), LevelFilter::Error);
+
+        handle.restore(before.clone());
+        assert_eq!(handle.snapshot(), before);
+        assert_eq!(level(Sink::Terminal, "app"), LevelFilter::Info);
+        assert_eq!(level(Sink::Terminal, "hyper"), LevelFilter::Off);
+        assert_eq!(level(Sink::File, "hyper"), LevelFilter::Info);
+        assert_eq!(level(Sink::File, "net"), LevelFilter::Info);
+        assert_eq!(level(Sink::File, "billing"), LevelFilter::Trace);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_format_applies_to_both_sinks() {
+        let builder = LoggingBuilder::new().format(crate::LogFormat::Json);
+
+        for dispatch in [terminal(&builder), file(&builder)] {
+            let line = format_line(dispatch, Level::Info, "app", "hello");
+            assert!(line.starts_with(r#"{"timestamp":"#));
+            assert!(line.contains(r#""level":"INFO","target":"app","message":"hello""#));
+        }
+    }
+
+    #[test]
+    fn per_sink_formats() {
+        let builder = LoggingBuilder::new().file_format(LogFormat::Json);
+
+        let line = format_line(file(&builder), Level::Info, "app", "hello");
+        assert!(line.starts_with(r#"{"timestamp":"#), "{}", line);
+        assert!(line.contains(r#""level":"INFO","target":"app","message":"hello""#));
+        let line = format_line(terminal(&builder), Level::Info, "app", "hello");
+        assert!(line.ends_with("app - hello\n"), "{}", line);
+        assert!(matches!(
+            config::terminal_format(&builder),
+            LineFormat::Text { .. }
+        ));
+
+        let builder = LoggingBuilder::new()
+            .format(LogFormat::Logfmt)
+            .terminal_format(LogFormat::Text);
+        let line = format_line(terminal(&builder), Level::Info, "app", "hello");
+        assert!(line.ends_with("app - hello\n"), "{}", line);
+        let line = format_line(file(&builder), Level::Info, "app", "hello");
+        assert!(line.contains(" msg=hello"), "{}", line);
+    }
+
+    #[test]
+    fn fixed_offset() {
+        let builder = LoggingBuilder::new()
+            .format(LogFormat::Json)
+            .fixed_offset(-3, 30);
+        let line = format_line(file(&builder), Level::Info, "app", "hello");
+        let time = line.split('"').nth(3).unwrap();
+        let time = chrono::DateTime::parse_from_rfc3339(time).unwrap();
+        assert_eq!(time.offset().local_minus_utc(), -(3 * 3600 + 30 * 60));
+        assert!((chrono::Utc::now() - time.with_timezone(&chrono::Utc)) < Duration::seconds(5));
+
+        let dir = temp_dir("fixed-offset");
+        let clock = Arc::new(ManualClock::utc("2024-01-01 20:30:00"));
+        for (hours, minutes) in [(9, 0), (0, -30)] {
+            let _ = test_util::builder(&dir)
+                .clock(clock.clone())
+                .fixed_offset(hours, minutes)
+                .build()
+                .unwrap();
+        }
+        let mut names = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            ["2024-01-01_20-00-00.log", "2024-01-02_05-30-00.log"]
+        );
+        remove_dir_all(&dir).unwrap();
+
+        for (hours, minutes) in [(24, 0), (5, 60), (5, -30)] {
+            let e = LoggingBuilder::new()
+                .app_name("app")
+                .qualifier("com")
+                .organization("org")
+                .fixed_offset(hours, minutes)
+                .build()
+                .err()
+                .unwrap();
+            assert!(e.to_string().contains("is not a valid offset"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn show_uptime() {
+        let builder = LoggingBuilder::new().show_uptime(true);
+
+        let line = format_line(terminal(&builder), Level::Info, "app", "hello");
+        assert!(line.starts_with("[INFO] +0.0"));
+        assert!(line.ends_with("s app - hello\n"));
+
+        let line = format_line(file(&builder), Level::Info, "app", "hello");
+        let parts = line.splitn(4, ' ').collect::<Vec<_>>();
+        assert!(NaiveDateTime::parse_from_str(parts[1], CHRONO_FORMAT).is_ok());
+        assert!(parts[2].starts_with('+') && parts[2].ends_with('s'));
+        assert_eq!(parts[3], "app - hello\n");
+    }
+
+    #[test]
+    fn subscribers_honor_levels() {
+        let dir = temp_dir("subscribers");
+        let (root, handle) = builder(&dir)
+            .global_level(LevelFilter::Info)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        let (a, b) = (handle.subscribe(), handle.subscribe());
+
+        for (level, message) in [(Level::Debug, "quiet"), (Level::Info, "loud")] {
+            log_line(&root, level, "app", message);
+        }
+
+        for rx in [a, b] {
+            let messages = rx.try_iter().map(|e| e.message).collect::<Vec<_>>();
+            assert_eq!(messages, ["loud"]);
+        }
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn intern_targets() {
+        let dir = temp_dir("intern-targets");
+        for intern in [true, false] {
+            let (builder, rx) = builder(&dir).intern_targets(intern).channel_output();
+            let (root, handle) = builder.build().unwrap();
+            let (_, root) = root.into_log();
+            let subscriber = handle.subscribe();
+
+            log_line(&root, Level::Info, "app::db", "one");
+            log_line(&root, Level::Info, "app::db", "two");
+
+            let [one, two] = [rx.try_recv().unwrap(), rx.try_recv().unwrap()];
+            assert_eq!(Arc::ptr_eq(&one.target, &two.target), intern);
+            assert_eq!(
+                Arc::ptr_eq(&one.target, &subscriber.try_recv().unwrap().target),
+                intern
+            );
+        }
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dump_on_error() {
+        let dir = temp_dir("dump-on-error");
+        let (root, _) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .global_level(LevelFilter::Info)
+            .dump_on_error(2)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for (level, message) in [
+            (Level::Debug, "dropped"),
+            (Level::Trace, "one"),
+            (Level::Info, "two"),
+            (Level::Debug, "three"),
+            (Level::Error, "boom"),
+            (Level::Error, "again"),
+        ] {
+            log_line(&root, level, "app", message);
+        }
+
+        let log = std::fs::read_to_string(dir.join("app.log")).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5, "{}", log);
+        assert!(lines[0].starts_with("[INFO] ") && lines[0].ends_with(" app - two"));
+        assert!(lines[1].starts_with("[TRACE] ") && lines[1].ends_with(" app - one"));
+        assert!(lines[2].starts_with("[DEBUG] ") && lines[2].ends_with(" app - three"));
+        assert!(lines[3].ends_with(" app - boom") && lines[4].ends_with(" app - again"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filters() {
+        use crate::filter::{level, not, target_prefix};
+
+        let dir = temp_dir("filters");
+        let (root, handle) = builder(&dir)
+            .file_mode(FileMode::Single)
+            .ring_buffer(10)
+            .filter(not(target_prefix("noisy")))
+            .file_filter(level(LevelFilter::Warn))
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for (level, target, message) in [
+            (Level::Info, "app", "one"),
+            (Level::Warn, "app", "two"),
+            (Level::Error, "noisy::db", "three"),
+        ] {
+            log_line(&root, level, target, message);
+        }
+
+        let recent = handle.recent();
+        assert_eq!(recent.len(), 2, "{:?}", recent);
+        assert!(recent[0].ends_with(" app - one") && recent[1].ends_with(" app - two"));
+
+        let log = std::fs::read_to_string(dir.join("app.log")).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1, "{}", log);
+        assert!(lines[0].starts_with("[WARN] ") && lines[0].ends_with(" app - two"));
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ring_buffer_honors_levels() {
+        let dir = temp_dir("ring-buffer");
+        let (root, handle) = builder(&dir)
+            .global_level(LevelFilter::Info)
+            .ring_buffer(2)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for (level, message) in [
+            (Level::Info, "one"),
+            (Level::Debug, "hidden"),
+            (Level::Info, "two"),
+            (Level::Warn, "three"),
+        ] {
+            log_line(&root, level, "app", message);
+        }
+
+        let recent = handle.recent();
+        assert_eq!(recent.len(), 2);
+        assert!(recent[0].starts_with("[INFO] ") && recent[0].ends_with(" app - two"));
+        assert!(recent[1].starts_with("[WARN] ") && recent[1].ends_with(" app - three"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_memory() {
+        let dir = temp_dir("max-memory");
+        let builder = || test_util::builder(&dir).global_level(LevelFilter::Info);
+        assert!(builder().max_memory(0).build().is_err());
+
+        let (root, handle) = builder()
+            .ring_buffer(100)
+            .dump_on_error(100)
+            .max_memory(200)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+        for i in 0..50 {
+            let level = if i % 2 == 0 {
+                Level::Info
+            } else {
+                Level::Debug
+            };
+            log_line(&root, level, "app", &format!("line {}", i));
+        }
+
+        let recent = handle.recent();
+        assert!(recent.iter().map(String::len).sum::<usize>() <= 200);
+        assert!(recent.len() < 25, "{:?}", recent);
+        assert!(recent.last().unwrap().ends_with(" app - line 48"));
+        assert!(handle.evicted_lines() >= 25);
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "error-reporter")]
+    #[test]
+    fn error_reporter() {
+        let dir = temp_dir("error-reporter");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (root, handle) = builder(&dir)
+            .global_level(LevelFilter::Trace)
+            .error_reporter(Arc::new(move |event: &crate::LogEvent| {
+                tx.send(event.clone()).unwrap();
+            }))
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        let _request = handle.push_context("req_id", 42);
+        for level in [Level::Trace, Level::Info, Level::Warn, Level::Error] {
+            log_line(&root, level, "app::db", &format!("query {}", level));
+        }
+
+        let timeout = std::time::Duration::from_secs(5);
+        let events = [
+            rx.recv_timeout(timeout).unwrap(),
+            rx.recv_timeout(timeout).unwrap(),
+        ];
+        assert_eq!(
+            events.each_ref().map(|e| e.level),
+            [Level::Warn, Level::Error]
+        );
+        for event in &events {
+            assert_eq!(&*event.target, "app::db");
+            assert_eq!(event.message, format!("query {}", event.level));
+            assert_eq!(event.context, [("req_id".to_string(), "42".to_string())]);
+        }
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(50))
+            .is_err());
+
+        drop(root);
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tcp_output() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dir = temp_dir("tcp-output");
+        let (root, _handle) = builder(&dir)
+            .tcp_output(listener.local_addr().unwrap(), LevelFilter::Warn)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for (level, message) in [(Level::Info, "quiet"), (Level::Warn, "loud")] {
+            log_line(&root, level, "app", message);
+        }
+        root.flush();
+
+        let (stream, _) = listener.accept().unwrap();
+        let line = BufReader::new(stream).lines().next().unwrap().unwrap();
+        assert!(line.starts_with("[WARN] ") && line.ends_with(" app - loud"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gelf_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dir = temp_dir("gelf-over-tcp");
+        let (root, _handle) = builder(&dir)
+            .format(LogFormat::Gelf)
+            .tcp_output(listener.local_addr().unwrap(), LevelFilter::Warn)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        log_line(&root, Level::Warn, "app", "one");
+        log_line(&root, Level::Warn, "app", "two");
+        root.flush();
+
+        let (stream, _) = listener.accept().unwrap();
+        let records = BufReader::new(stream)
+            .split(b'\0')
+            .take(2)
+            .map(|record| String::from_utf8(record.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert!(records[0].starts_with('{') && records[0].ends_with('}'));
+        assert!(records[0].contains(r#""short_message":"one""#));
+        assert!(records[1].contains(r#""short_message":"two""#));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_output() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = temp_dir("unix-socket-output");
+        let path = dir.join("collector.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let (root, _handle) = builder(&dir)
+            .format(LogFormat::Json)
+            .unix_socket_output(&path, LevelFilter::Warn)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for (level, message) in [(Level::Info, "quiet"), (Level::Warn, "loud")] {
+            log_line(&root, level, "app", message);
+        }
+        root.flush();
+
+        let (stream, _) = listener.accept().unwrap();
+        let line = BufReader::new(stream).lines().next().unwrap().unwrap();
+        assert!(line.starts_with('{'), "{}", line);
+        assert!(line.contains(r#""level":"WARN""#), "{}", line);
+        assert!(line.contains(r#""message":"loud""#), "{}", line);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn level_scopes() {
+        let dir = temp_dir("level-scopes");
+        let (root, handle) = builder(&dir)
+            .global_level(LevelFilter::Info)
+            .ring_buffer(10)
+            .level_for_scoped("hyper", LevelFilter::Off, LevelScope::Terminal)
+            .level_for_scoped("db", LevelFilter::Debug, LevelScope::File)
+            .level_for_scoped("net", LevelFilter::Warn, LevelScope::Root)
+            .level_for_scoped("net", LevelFilter::Trace, LevelScope::Terminal)
+            .level_for_scoped("cache", LevelFilter::Trace, LevelScope::File)
+            .level_for("cache", LevelFilter::Error)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        let levels = handle.levels.read().unwrap();
+        let level = |sink, target| levels.sink_level_for(sink, target);
+        assert_eq!(level(Sink::Terminal, "hyper"), LevelFilter::Off);
+        assert_eq!(level(Sink::File, "hyper"), LevelFilter::Info);
+        assert_eq!(level(Sink::Terminal, "db"), LevelFilter::Info);
+        assert_eq!(level(Sink::File, "db"), LevelFilter::Debug);
+        assert_eq!(level(Sink::Terminal, "net"), LevelFilter::Trace);
+        assert_eq!(level(Sink::File, "net"), LevelFilter::Warn);
+        assert_eq!(level(Sink::File, "cache"), LevelFilter::Error);
+        drop(levels);
+
+        for (level, target) in [
+            (Level::Info, "hyper"),
+            (Level::Debug, "db"),
+            (Level::Info, "net"),
+            (Level::Warn, "net"),
+        ] {
+            log_line(&root, level, target, target);
+        }
+        root.flush();
+
+        let file = read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let lines = std::fs::read_to_string(file).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("[INFO] ") && lines[0].ends_with(" hyper - hyper"));
+        assert!(lines[1].starts_with("[DEBUG] ") && lines[1].ends_with(" db - db"));
+        assert!(lines[2].starts_with("[WARN] ") && lines[2].ends_with(" net - net"));
+
+        let recent = handle.recent();
+        assert_eq!(recent.len(), 2);
+        assert!(recent[0].ends_with(" hyper - hyper"));
+        assert!(recent[1].ends_with(" net - net"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shutdown_summary() {
+        let dir = temp_dir("shutdown-summary");
+        let (root, handle) = LoggingBuilder::new()
+            .app_name("myapp")
+            .qualifier("com")
+            .organization("org")
+            .log_dir(&dir)
+            .global_level(LevelFilter::Info)
+            .level_for("noisy", LevelFilter::Error)
+            .shutdown_summary(true)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for (level, target) in [
+            (Level::Error, "app"),
+            (Level::Warn, "app"),
+            (Level::Warn, "app"),
+            (Level::Warn, "noisy"),
+            (Level::Info, "app"),
+        ] {
+            log_line(&root, level, target, target);
+        }
+
+        let line = handle.summary.as_ref().unwrap().line();
+        assert!(
+            line.starts_with("=== myapp stopped after 0.0s, "),
+            "{}",
+            line
+        );
+        assert!(line.ends_with(", 1 errors, 2 warnings ==="), "{}", line);
+
+        drop(handle);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn show_sequence() {
+        let dir = temp_dir("show-sequence");
+        let (root, handle) = builder(&dir)
+            .ring_buffer(10)
+            .show_sequence(true)
+            .build()
+            .unwrap();
+        let (_, root) = root.into_log();
+
+        for message in ["one", "two"] {
+            log_line(&root, Level::Info, "app", message);
+        }
+
+        let recent = handle.recent();
+        assert!(recent[0].starts_with("#1 [INFO] "), "{}", recent[0]);
+        assert!(recent[1].starts_with("#2 [INFO] "), "{}", recent[1]);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_info() {
+        let builder = LoggingBuilder::new()
+            .format(LogFormat::Json)
+            .build_info(crate::BuildInfo {
+                git_sha: Some("abc123"),
+                build_timestamp: None,
+            });
+
+        let line = format_line(file(&builder), Level::Info, "app", "hello");
+        assert!(line.ends_with(",\"git_sha\":\"abc123\"}\n"), "{}", line);
+    }
+
+    #[test]
+    fn show_logger_version() {
+        let builder = LoggingBuilder::new()
+            .format(LogFormat::Json)
+            .json_field("service", "app");
+        let line = format_line(file(&builder), Level::Info, "app", "hello");
+        assert!(line.ends_with(",\"service\":\"app\"}\n"), "{}", line);
+
+        let line = format_line(
+            file(&builder.show_logger_version(true)),
+            Level::Info,
+            "app",
+            "hello",
+        );
+        assert!(line.ends_with(&format!(
+            ",\"service\":\"app\",\"_logger_version\":\"{}\"}}\n",
+            LOGGING_CRATE_VERSION
+        )));
+    }
+
+    #[test]
+    fn log_effective_config() {
+        let dir = temp_dir("effective-config");
+        let builder = || {
+            test_util::builder(&dir)
+                .file_mode(FileMode::Rotate)
+                .global_level(LevelFilter::Warn)
+                .levels("app=debug,hyper=error")
+                .file_format(LogFormat::Json)
+                .max_log_files(3)
+                .ring_buffer(10)
+        };
+
+        let (_, handle) = builder().build().unwrap();
+        assert_eq!(handle.effective_config, None);
+        drop(handle);
+
+        let (_, handle) = builder().log_effective_config(true).build().unwrap();
+        let effective = handle.effective_config.clone().unwrap();
+        assert_eq!(
+            effective,
+            format!(
+                " log_dir={} file_mode=Rotate global_level=warn levels=\"app=debug,hyper=error\" \
+                 terminal_format=text file_format=json max_log_files=3 max_log_age_secs=none \
+                 sinks=terminal,file,ring_buffer",
+                dir.display()
+            )
+        );
+        drop(handle);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn schema_version() {
+        let builder = |format| {
+            LoggingBuilder::new()
+                .json_field("service", "app")
+                .schema_version(LOG_SCHEMA_VERSION)
+                .format(format)
+        };
+
+        let line = format_line(file(&builder(LogFormat::Json)), Level::Info, "app", "hello");
+        assert!(
+            line.ends_with(",\"service\":\"app\",\"schema\":\"1\"}\n"),
+            "{}",
+            line
+        );
+
+        let line = format_line(
+            file(&builder(LogFormat::Logfmt)),
+            Level::Info,
+            "app",
+            "hello",
+        );
+        assert!(line.ends_with(" service=app schema=1\n"), "{}", line);
+
+        let line = format_line(file(&builder(LogFormat::Gelf)), Level::Info, "app", "hello");
+        assert!(line.contains(",\"_schema\":\"1\""), "{}", line);
+
+        let line = format_line(
+            file(&builder(LogFormat::Json).schema_version("2024.1")),
+            Level::Info,
+            "app",
+            "hello",
+        );
+        assert!(line.ends_with(",\"schema\":\"2024.1\"}\n"), "{}", line);
+    }
+
+    #[test]
+    fn verbose_errors() {
+        let builder = LoggingBuilder::new().verbose_errors(true);
+        let line = |level| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let (_, log) = terminal(&builder).chain(tx).into_log();
+            log.log(
+                &Record::builder()
+                    .level(level)
+                    .target("app")
+                    .file(Some("src/db.rs"))
+                    .line(Some(42))
+                    .args(format_args!("hello"))
+                    .build(),
+            );
+            rx.recv().unwrap()
+        };
+
+        assert_eq!(
+            line(Level::Error),
+            "[ERROR] app - hello (at src/db.rs:42, thread tests::verbose_errors)\n"
+        );
+        assert!(
+            line(Level::Warn).ends_with("hello (at src/db.rs:42, thread tests::verbose_errors)\n")
+        );
+        assert_eq!(line(Level::Info), "[INFO] app - hello\n");
+        assert_eq!(
+            format_line(terminal(&builder), Level::Info, "app", "hello"),
+            format_line(
+                terminal(&LoggingBuilder::new()),
+                Level::Info,
+                "app",
+                "hello"
+            )
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let builder = LoggingBuilder::new().columns(
+            ColumnLayout::new()
+                .level(Column::right(7))
+                .target(Column::left(10)),
+        );
+
+        let line = format_line(file(&builder), Level::Info, "app::db", "hello");
+        let (level, rest) = line.split_once(' ').unwrap();
+        assert_eq!(level, "");
+        let (level, rest) = rest.split_once(' ').unwrap();
+        assert_eq!(level, "[INFO]");
+        assert_eq!(rest.split_once(' ').unwrap().1, "app::db    - hello\n");
+
+        let line = format_line(file(&builder), Level::Debug, "app::database", "hello");
+        assert!(line.starts_with("[DEBUG] "));
+        assert!(line.ends_with(" app::data~ - hello\n"), "{}", line);
+
+        let line = format_line(terminal(&builder), Level::Info, "app::database", "hello");
+        assert_eq!(line, "[INFO] app::database - hello\n");
+    }
+
+    #[test]
+    fn colored_levels() {
+        let builder = LoggingBuilder::new().color_scheme(ColorScheme::HighContrast);
+        let colored = LineFormat::Text {
+            show_level: true,
+            show_time: false,
+            show_target: true,
+            colors: Palette::new(ColorScheme::HighContrast, ColorDepth::Basic),
+            columns: ColumnLayout::default(),
+        };
+
+        assert_eq!(
+            format_line(builder.line_dispatch(&colored), Level::Warn, "app", "hello"),
+            "\x1b[1;93m[WARN]\x1b[0m app - hello\n"
+        );
+        assert_eq!(
+            format_line(file(&builder), Level::Warn, "app", "hello")
+                .split_once(' ')
+                .unwrap()
+                .0,
+            "[WARN]"
+        );
+    }
+
+    #[test]
+    fn level_name_override() {
+        let builder = LoggingBuilder::new().level_name(LevelFilter::Warn, "WARNING");
 
-        assert!(logs[0].1 > logs[1].1);
-        assert!(logs[1].1 > logs[2].1);
+        let line = format_line(terminal(&builder), Level::Warn, "app", "careful");
+        assert_eq!(line, "[WARNING] app - careful\n");
+        let line = format_line(terminal(&builder), Level::Info, "app", "fine");
+        assert_eq!(line, "[INFO] app - fine\n");
     }
 }
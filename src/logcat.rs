@@ -0,0 +1,86 @@
+//! Sending records to Android's logcat, for
+//! [`LoggingBuilder::logcat_output`](crate::LoggingBuilder::logcat_output). Enabled by the
+//! `android` feature.
+
+use log::Level;
+
+/// The `android_LogPriority` for `level`.
+#[cfg_attr(not(target_os = "android"), allow(dead_code))]
+pub(crate) fn priority(level: Level) -> i32 {
+    match level {
+        Level::Error => 6,
+        Level::Warn => 5,
+        Level::Info => 4,
+        Level::Debug => 3,
+        Level::Trace => 2,
+    }
+}
+
+/// `text` as a C string, with interior NUL bytes, which would cut it short, replaced.
+#[cfg_attr(not(target_os = "android"), allow(dead_code))]
+fn c_string(text: &str) -> std::ffi::CString {
+    std::ffi::CString::new(text.replace('\0', "\u{fffd}")).unwrap_or_default()
+}
+
+#[cfg(target_os = "android")]
+pub(crate) use sink::Logcat;
+
+#[cfg(target_os = "android")]
+mod sink {
+    use std::ffi::{c_char, c_int, CString};
+
+    use log::{Log, Metadata, Record};
+
+    #[link(name = "log")]
+    extern "C" {
+        fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int;
+    }
+
+    /// Writes each record's formatted message to logcat under the tag, at the record's
+    /// priority.
+    pub(crate) struct Logcat {
+        tag: CString,
+    }
+
+    impl Logcat {
+        pub(crate) fn new(tag: &str) -> Self {
+            Self {
+                tag: super::c_string(tag),
+            }
+        }
+    }
+
+    impl Log for Logcat {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let text = super::c_string(&record.args().to_string());
+            unsafe {
+                __android_log_write(
+                    super::priority(record.level()),
+                    self.tag.as_ptr(),
+                    text.as_ptr(),
+                );
+            }
+        }
+
+        fn flush(&self) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::{c_string, priority};
+
+    #[test]
+    fn maps_levels() {
+        let priorities = Level::iter().map(priority).collect::<Vec<_>>();
+        assert_eq!(priorities, [6, 5, 4, 3, 2]);
+
+        assert_eq!(c_string("a\0b").to_str().unwrap(), "a\u{fffd}b");
+    }
+}
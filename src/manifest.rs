@@ -0,0 +1,271 @@
+//! An index of the log directory for tooling that collects logs.
+
+use std::{
+    fmt::Write as _,
+    fs::{read_dir, rename, write, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDateTime;
+use log::Level;
+
+use crate::{
+    format::{JsonStr, LevelNames},
+    CHRONO_FORMAT,
+};
+
+/// The file name of the manifest in the log directory.
+pub(crate) const MANIFEST_NAME: &str = "manifest.json";
+/// Written first and renamed over the manifest, so readers never see half of it.
+const TEMP_NAME: &str = "manifest.json.tmp";
+
+const LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+/// Which built-in format the log lines are in, for reading their levels and times back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lines {
+    Text,
+    Json,
+    /// A custom format, which cannot be read back.
+    Custom,
+}
+
+/// Writes `manifest.json`, listing every file in the log directory with its size, the
+/// times of its first and last records and how many records it has at each level.
+///
+/// ```json
+/// {"files":[
+/// {"name":"2024-01-02_03-04-05.log","size":120,"first":"2024-01-02_03-04-05","last":"2024-01-02_03-04-09","levels":{"ERROR":0,"WARN":1,"INFO":2,"DEBUG":0,"TRACE":0}}
+/// ]}
+/// ```
+///
+/// Times are as written in the lines. They and the levels are `null` for custom formats.
+#[derive(Clone)]
+pub(crate) struct Manifest {
+    pub(crate) log_dir: PathBuf,
+    pub(crate) lines: Lines,
+    pub(crate) level_names: LevelNames,
+}
+
+impl Manifest {
+    /// Rewrites the manifest to match the files now in the log directory.
+    pub(crate) fn write(&self) -> io::Result<()> {
+        let mut names = vec![];
+        for entry in read_dir(&self.log_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Ok(name) = entry.file_name().into_string() {
+                if !is_manifest(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+
+        let mut json = "{\"files\":[".to_string();
+        for (i, name) in names.iter().enumerate() {
+            let path = self.log_dir.join(name);
+            let size = path.metadata()?.len();
+            let contents = self.read(&path)?;
+
+            let _ = write!(
+                json,
+                "{}\n{{\"name\":{},\"size\":{},\"first\":{},\"last\":{},\"levels\":",
+                if i == 0 { "" } else { "," },
+                JsonStr(name),
+                size,
+                OptionalStr(contents.first.as_deref()),
+                OptionalStr(contents.last.as_deref()),
+            );
+            match contents.counts {
+                Some(counts) => {
+                    json.push('{');
+                    for (i, (level, count)) in LEVELS.iter().zip(counts).enumerate() {
+                        let comma = if i == 0 { "" } else { "," };
+                        let _ = write!(json, "{}\"{}\":{}", comma, level, count);
+                    }
+                    json.push_str("}}");
+                }
+                None => json.push_str("null}"),
+            }
+        }
+        json.push_str("\n]}\n");
+
+        let temp = self.log_dir.join(TEMP_NAME);
+        write(&temp, json)?;
+        rename(temp, self.log_dir.join(MANIFEST_NAME))
+    }
+
+    /// Reads the levels and times back from the records in the file at `path`. Lines that
+    /// do not start a record, such as the rest of a multi-line message, are skipped.
+    fn read(&self, path: &Path) -> io::Result<Contents> {
+        let mut contents = Contents {
+            first: None,
+            last: None,
+            counts: (self.lines != Lines::Custom).then_some([0; 5]),
+        };
+        let counts = if let Some(counts) = &mut contents.counts {
+            counts
+        } else {
+            return Ok(contents);
+        };
+
+        for line in BufReader::new(File::open(path)?).split(b'\n') {
+            let line = line?;
+            let line = String::from_utf8_lossy(&line);
+            let parsed = match self.lines {
+                Lines::Text => self.parse_text(&line),
+                Lines::Json => self.parse_json(&line),
+                Lines::Custom => None,
+            };
+
+            if let Some((level, time)) = parsed {
+                counts[level as usize - 1] += 1;
+                if contents.first.is_none() {
+                    contents.first = Some(time.to_string());
+                }
+                contents.last = Some(time.to_string());
+            }
+        }
+
+        Ok(contents)
+    }
+
+    /// The level and time of a `[LEVEL] time ...` line, which may start with a sequence
+    /// number.
+    fn parse_text<'a>(&self, line: &'a str) -> Option<(Level, &'a str)> {
+        let line = match line.strip_prefix('#').and_then(|rest| rest.split_once(' ')) {
+            Some((sequence, rest)) if sequence.bytes().all(|b| b.is_ascii_digit()) => rest,
+            _ => line,
+        };
+        let (name, rest) = line.trim_start().strip_prefix('[')?.split_once("] ")?;
+        let level = self.level(name)?;
+        let time = rest.trim_start().split(' ').next()?;
+        NaiveDateTime::parse_from_str(time, CHRONO_FORMAT).ok()?;
+
+        Some((level, time))
+    }
+
+    /// The level and time of a `{"timestamp":"...","level":"...",...}` line.
+    fn parse_json<'a>(&self, line: &'a str) -> Option<(Level, &'a str)> {
+        let (time, rest) = line.strip_prefix("{\"timestamp\":\"")?.split_once('"')?;
+        let (name, _) = rest.strip_prefix(",\"level\":\"")?.split_once('"')?;
+
+        Some((self.level(name)?, time))
+    }
+
+    fn level(&self, name: &str) -> Option<Level> {
+        LEVELS
+            .into_iter()
+            .find(|level| self.level_names.get(*level) == name)
+    }
+}
+
+/// Whether `file_name` is the manifest or its temporary file.
+fn is_manifest(file_name: &str) -> bool {
+    file_name == MANIFEST_NAME || file_name == TEMP_NAME
+}
+
+struct Contents {
+    first: Option<String>,
+    last: Option<String>,
+    /// Records per level, most severe first.
+    counts: Option<[u64; 5]>,
+}
+
+/// Displays a JSON string, or `null`.
+struct OptionalStr<'a>(Option<&'a str>);
+
+impl std::fmt::Display for OptionalStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(s) => write!(f, "{}", JsonStr(s)),
+            None => f.write_str("null"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, remove_dir_all, write};
+
+    use super::{Lines, Manifest, MANIFEST_NAME};
+    use crate::{format::LevelNames, test_util::temp_dir};
+
+    #[test]
+    fn summarizes_files() {
+        let dir = temp_dir("manifest");
+        write(
+            dir.join("a.log"),
+            "[WARN] 2024-01-02_03-04-05 app - one\n\
+             second line of one\n\
+             [INFO] 2024-01-02_03-04-06 app - two\n\
+             [INFO] 2024-01-02_03-04-09 app - three\n",
+        )
+        .unwrap();
+        write(dir.join("b.log"), "").unwrap();
+        std::fs::create_dir(dir.join("nested")).unwrap();
+
+        let manifest = |lines| Manifest {
+            log_dir: dir.clone(),
+            lines,
+            level_names: LevelNames::default(),
+        };
+        manifest(Lines::Text).write().unwrap();
+        assert_eq!(
+            read_to_string(dir.join(MANIFEST_NAME)).unwrap(),
+            "{\"files\":[\n\
+             {\"name\":\"a.log\",\"size\":132,\"first\":\"2024-01-02_03-04-05\",\"last\":\"2024-01-02_03-04-09\",\"levels\":{\"ERROR\":0,\"WARN\":1,\"INFO\":2,\"DEBUG\":0,\"TRACE\":0}},\n\
+             {\"name\":\"b.log\",\"size\":0,\"first\":null,\"last\":null,\"levels\":{\"ERROR\":0,\"WARN\":0,\"INFO\":0,\"DEBUG\":0,\"TRACE\":0}}\n\
+             ]}\n"
+        );
+
+        write(
+            dir.join("a.log"),
+            "{\"timestamp\":\"2024-01-02T03:04:05.000+00:00\",\"level\":\"ERROR\",\"target\":\"app\",\"message\":\"one\"}\n",
+        )
+        .unwrap();
+        manifest(Lines::Json).write().unwrap();
+        let json = read_to_string(dir.join(MANIFEST_NAME)).unwrap();
+        assert!(json.contains("\"first\":\"2024-01-02T03:04:05.000+00:00\",\"last\":\"2024-01-02T03:04:05.000+00:00\",\"levels\":{\"ERROR\":1,"));
+
+        manifest(Lines::Custom).write().unwrap();
+        let json = read_to_string(dir.join(MANIFEST_NAME)).unwrap();
+        assert!(json.contains("\"first\":null,\"last\":null,\"levels\":null}"));
+        assert!(!json.contains(MANIFEST_NAME));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    // Quotes are not allowed in Windows file names.
+    #[cfg(unix)]
+    #[test]
+    fn escapes_names() {
+        let dir = temp_dir("manifest-escapes");
+        write(dir.join("b \"quoted\".log"), "").unwrap();
+
+        let manifest = Manifest {
+            log_dir: dir.clone(),
+            lines: Lines::Custom,
+            level_names: LevelNames::default(),
+        };
+        manifest.write().unwrap();
+        let json = read_to_string(dir.join(MANIFEST_NAME)).unwrap();
+        assert!(
+            json.contains("{\"name\":\"b \\\"quoted\\\".log\","),
+            "{}",
+            json
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! The combined limit of the in-memory buffers, for
+//! [`LoggingBuilder::max_memory`](crate::LoggingBuilder::max_memory).
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+struct Inner {
+    limit: usize,
+    used: AtomicUsize,
+    /// Lines the buffers evicted or dropped because the limit was reached.
+    dropped: AtomicU64,
+}
+
+/// The bytes held by the buffers sharing it. Each buffer reserves a line's bytes before
+/// keeping it and releases them once the line is gone, so their total stays under one limit.
+#[derive(Clone)]
+pub(crate) struct MemoryBudget(Arc<Inner>);
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes, or an unlimited one.
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self(Arc::new(Inner {
+            limit: limit.unwrap_or(usize::MAX),
+            used: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    /// Reserves `bytes`, unless that would go over the limit.
+    pub(crate) fn reserve(&self, bytes: usize) -> bool {
+        let limit = self.0.limit;
+
+        self.0
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|&total| total <= limit)
+            })
+            .is_ok()
+    }
+
+    /// Gives back bytes from [`reserve`](Self::reserve).
+    pub(crate) fn release(&self, bytes: usize) {
+        self.0.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    /// Counts a line evicted or dropped to stay under the limit.
+    pub(crate) fn count_dropped(&self) {
+        self.0.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn used(&self) -> usize {
+        self.0.used.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryBudget;
+
+    #[test]
+    fn reserves_within_limit() {
+        let budget = MemoryBudget::new(Some(10));
+        assert!(budget.reserve(6));
+        assert!(!budget.reserve(5));
+        assert!(budget.reserve(4));
+        assert_eq!(budget.used(), 10);
+
+        budget.release(6);
+        assert!(budget.reserve(5));
+        assert_eq!(budget.used(), 9);
+
+        assert!(MemoryBudget::new(None).reserve(usize::MAX));
+    }
+}
@@ -0,0 +1,541 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{Level, Log, Metadata, Record};
+
+use crate::{binary, memory::MemoryBudget};
+
+/// Encodes a record the way an output writes its lines, for the notices an output writes
+/// itself.
+pub(crate) type EncodeRecord = dyn Fn(&Record) -> Vec<u8> + Send + Sync;
+
+/// What a non-blocking sink does with a new line when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Wait for the writer thread to make room. No lines are lost.
+    #[default]
+    Block,
+    /// Drop the line being logged.
+    DropNewest,
+    /// Drop the oldest queued line to make room for the new one.
+    DropOldest,
+}
+
+/// What dropping the [`LoggingHandle`](crate::LoggingHandle) does with lines still queued
+/// for the writer threads of [`non_blocking`](crate::LoggingBuilder::non_blocking) and
+/// [`tcp_output`](crate::LoggingBuilder::tcp_output).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropBehavior {
+    /// Write the queued lines and stop the writer threads, waiting up to 5 seconds for them.
+    /// Lines logged afterwards are discarded.
+    #[default]
+    FlushAndJoin,
+    /// Wait until the queued lines are written, leaving the writer threads running.
+    FlushOnly,
+    /// Return immediately. Queued lines are lost if the process exits first.
+    Detach,
+}
+
+/// How a [`NonBlocking`] sink encodes records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// The formatted message followed by a newline.
+    Lines,
+    /// The formatted message followed by a null byte, as GELF over TCP is framed.
+    Nul,
+    /// The [`binary`] encoding.
+    Binary,
+}
+
+struct State {
+    lines: VecDeque<Vec<u8>>,
+    /// Whether the writer thread is currently writing lines it took off the queue.
+    writing: bool,
+    /// Set by [`Worker::join`]; the writer thread stops once the queue is empty.
+    closed: bool,
+    /// Whether the writer thread has stopped.
+    finished: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when lines are queued.
+    queued: Condvar,
+    /// Signalled when the writer thread takes lines off the queue or finishes writing them.
+    taken: Condvar,
+
+    capacity: usize,
+    overflow: Overflow,
+    framing: Framing,
+    /// Encodes the notice about dropped lines.
+    notice: Box<EncodeRecord>,
+    /// The lines this sink dropped, for the notice.
+    lost: AtomicU64,
+    /// The lines all sinks dropped.
+    dropped: Arc<AtomicU64>,
+    /// Reserved for each queued line until it is written.
+    budget: MemoryBudget,
+}
+
+/// A sink that hands formatted lines to a writer thread so logging never waits on I/O,
+/// except when the queue is full and the [`Overflow`] policy is [`Overflow::Block`].
+pub(crate) struct NonBlocking {
+    shared: Arc<Shared>,
+}
+
+impl NonBlocking {
+    /// Spawns the writer thread for `writer`, which reports the lines it lost in a record
+    /// encoded by `notice`.
+    ///
+    /// `dropped` is incremented for every line lost to the overflow policy or to `budget`.
+    /// When the budget runs out, [`Overflow::DropOldest`] drops queued lines until the new
+    /// one fits and the other policies drop the new line, as waiting for memory the other
+    /// buffers hold could block forever.
+    pub(crate) fn new(
+        writer: Box<dyn Write + Send>,
+        capacity: usize,
+        overflow: Overflow,
+        framing: Framing,
+        notice: Box<EncodeRecord>,
+        dropped: Arc<AtomicU64>,
+        budget: MemoryBudget,
+    ) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                lines: VecDeque::with_capacity(capacity),
+                writing: false,
+                closed: false,
+                finished: false,
+            }),
+            queued: Condvar::new(),
+            taken: Condvar::new(),
+
+            capacity,
+            overflow,
+            framing,
+            notice,
+            lost: AtomicU64::new(0),
+            dropped,
+            budget,
+        });
+
+        {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("logging-writer".to_string())
+                .spawn(move || write_lines(&shared, writer))?;
+        }
+
+        Ok(Self { shared })
+    }
+
+    /// A handle for shutting down the writer thread.
+    pub(crate) fn worker(&self) -> Worker {
+        Worker(self.shared.clone())
+    }
+
+    fn push(&self, line: Vec<u8>) {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.closed {
+            return;
+        }
+
+        while state.lines.len() >= shared.capacity {
+            match shared.overflow {
+                Overflow::Block => {
+                    state = shared.taken.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+                Overflow::DropNewest => {
+                    shared.count_dropped();
+                    return;
+                }
+                Overflow::DropOldest => {
+                    if let Some(oldest) = state.lines.pop_front() {
+                        shared.budget.release(oldest.len());
+                    }
+                    shared.count_dropped();
+                }
+            }
+        }
+        while !shared.budget.reserve(line.len()) {
+            shared.count_dropped();
+            let oldest = match shared.overflow {
+                Overflow::DropOldest => state.lines.pop_front(),
+                Overflow::Block | Overflow::DropNewest => None,
+            };
+            match oldest {
+                Some(oldest) => shared.budget.release(oldest.len()),
+                None => return,
+            }
+        }
+
+        state.lines.push_back(line);
+        shared.queued.notify_one();
+    }
+}
+
+impl Log for NonBlocking {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.push(match self.shared.framing {
+            Framing::Lines => format!("{}\n", record.args()).into_bytes(),
+            Framing::Nul => format!("{}\0", record.args()).into_bytes(),
+            Framing::Binary => binary::encode(record),
+        });
+    }
+
+    /// Blocks until every queued line has been written.
+    fn flush(&self) {
+        self.shared.flush();
+    }
+}
+
+impl Shared {
+    fn count_dropped(&self) {
+        self.lost.fetch_add(1, Ordering::Relaxed);
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        while !state.lines.is_empty() || state.writing {
+            state = self.taken.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// The writer thread of a [`NonBlocking`] sink, kept by the
+/// [`LoggingHandle`](crate::LoggingHandle) for its [`DropBehavior`].
+pub(crate) struct Worker(Arc<Shared>);
+
+impl Worker {
+    /// Blocks until every queued line has been written.
+    pub(crate) fn flush(&self) {
+        self.0.flush();
+    }
+
+    /// Stops the writer thread after it writes the queued lines, waiting at most `timeout`.
+    /// Returns whether the thread stopped in time.
+    pub(crate) fn join(&self, timeout: Duration) -> bool {
+        let shared = &self.0;
+        let deadline = Instant::now() + timeout;
+        let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.closed = true;
+        shared.queued.notify_all();
+
+        while !state.finished {
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return false,
+            };
+            state = shared
+                .taken
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        true
+    }
+}
+
+/// The writer thread. Writes queued lines in batches and reports dropped lines.
+fn write_lines(shared: &Shared, mut writer: Box<dyn Write + Send>) {
+    let mut reported = 0;
+
+    loop {
+        let lines = {
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.writing = false;
+            shared.taken.notify_all();
+
+            while state.lines.is_empty() {
+                if state.closed {
+                    state.finished = true;
+                    shared.taken.notify_all();
+                    return;
+                }
+                state = shared.queued.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+
+            state.writing = true;
+            let lines = std::mem::take(&mut state.lines);
+            shared.taken.notify_all();
+
+            lines
+        };
+        let bytes = lines.iter().map(Vec::len).sum();
+
+        let result = (|| {
+            for line in lines {
+                writer.write_all(&line)?;
+            }
+
+            let lost = shared.lost.load(Ordering::Relaxed);
+            if lost > reported {
+                let notice = (shared.notice)(
+                    &Record::builder()
+                        .level(Level::Warn)
+                        .target(module_path!())
+                        .args(format_args!(
+                            "Dropped {} log lines because the queue was full",
+                            lost - reported
+                        ))
+                        .build(),
+                );
+                writer.write_all(&notice)?;
+                reported = lost;
+            }
+
+            writer.flush()
+        })();
+        shared.budget.release(bytes);
+
+        if let Err(e) = result {
+            eprintln!("Error writing log lines: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            mpsc::{channel, Sender},
+            Arc, Condvar, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use log::{Log, Record};
+
+    use super::{Framing, NonBlocking, Overflow};
+    use crate::memory::MemoryBudget;
+
+    type Gate = Arc<(Mutex<bool>, Condvar)>;
+
+    /// Records written bytes. Writes block until the gate is opened.
+    struct GatedWriter {
+        gate: Gate,
+        entered: Sender<()>,
+        out: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for GatedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.entered.send(());
+
+            let (open, cvar) = &*self.gate;
+            let mut open = open.lock().unwrap();
+            while !*open {
+                open = cvar.wait(open).unwrap();
+            }
+
+            self.out.lock().unwrap().extend_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Saturated {
+        sink: Arc<NonBlocking>,
+        gate: Gate,
+        out: Arc<Mutex<Vec<u8>>>,
+        dropped: Arc<AtomicU64>,
+    }
+
+    /// Creates a sink with a queue of 2 whose writer thread is stuck writing `first`.
+    fn saturated(overflow: Overflow) -> Saturated {
+        saturated_within(overflow, MemoryBudget::new(None))
+    }
+
+    fn saturated_within(overflow: Overflow, budget: MemoryBudget) -> Saturated {
+        let gate: Gate = Arc::new((Mutex::new(false), Condvar::new()));
+        let (entered, entered_rx) = channel();
+        let out = Arc::new(Mutex::new(vec![]));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let writer = GatedWriter {
+            gate: gate.clone(),
+            entered,
+            out: out.clone(),
+        };
+        let sink = Arc::new(
+            NonBlocking::new(
+                Box::new(writer),
+                2,
+                overflow,
+                Framing::Lines,
+                Box::new(|record| format!("[WARN] {}\n", record.args()).into_bytes()),
+                dropped.clone(),
+                budget,
+            )
+            .unwrap(),
+        );
+
+        log_line(&sink, "first");
+        entered_rx.recv().unwrap();
+
+        Saturated {
+            sink,
+            gate,
+            out,
+            dropped,
+        }
+    }
+
+    impl Saturated {
+        /// Opens the gate and returns the written lines and the dropped count once the queue
+        /// has drained.
+        fn drain(&self) -> (Vec<String>, u64) {
+            let (open, cvar) = &*self.gate;
+            *open.lock().unwrap() = true;
+            cvar.notify_all();
+
+            self.sink.flush();
+
+            let out = String::from_utf8(self.out.lock().unwrap().clone()).unwrap();
+            let lines = out.lines().map(str::to_string).collect();
+
+            (lines, self.dropped.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn join_writes_queued_lines() {
+        let saturated = saturated(Overflow::Block);
+        log_line(&saturated.sink, "a");
+
+        let worker = saturated.sink.worker();
+        assert!(!worker.join(Duration::from_millis(50)));
+        log_line(&saturated.sink, "late");
+
+        let (open, cvar) = &*saturated.gate;
+        *open.lock().unwrap() = true;
+        cvar.notify_all();
+        assert!(worker.join(Duration::from_secs(5)));
+
+        let (lines, _) = saturated.drain();
+        assert_eq!(lines, ["first", "a"]);
+    }
+
+    fn log_line(sink: &NonBlocking, line: &str) {
+        sink.log(&Record::builder().args(format_args!("{}", line)).build());
+    }
+
+    /// The logged lines, without the dropped-lines warnings.
+    fn written(lines: &[String]) -> Vec<&str> {
+        lines
+            .iter()
+            .map(String::as_str)
+            .filter(|l| !l.starts_with("[WARN]"))
+            .collect()
+    }
+
+    #[test]
+    fn drop_newest() {
+        let saturated = saturated(Overflow::DropNewest);
+        for line in ["a", "b", "c", "d"] {
+            log_line(&saturated.sink, line);
+        }
+
+        let (lines, dropped) = saturated.drain();
+        assert_eq!(written(&lines), ["first", "a", "b"]);
+        assert!(lines
+            .iter()
+            .any(|l| l.ends_with("Dropped 2 log lines because the queue was full")));
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let saturated = saturated(Overflow::DropOldest);
+        for line in ["a", "b", "c", "d"] {
+            log_line(&saturated.sink, line);
+        }
+
+        let (lines, dropped) = saturated.drain();
+        assert_eq!(written(&lines), ["first", "c", "d"]);
+        assert!(lines
+            .iter()
+            .any(|l| l.ends_with("Dropped 2 log lines because the queue was full")));
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn block() {
+        let saturated = saturated(Overflow::Block);
+
+        let (done_tx, done) = channel();
+        let producer = {
+            let sink = saturated.sink.clone();
+            thread::spawn(move || {
+                for line in ["a", "b", "c", "d"] {
+                    log_line(&sink, line);
+                }
+                done_tx.send(()).unwrap();
+            })
+        };
+
+        // Only two lines fit in the queue, so the producer has to wait for the writer.
+        assert!(done.recv_timeout(Duration::from_millis(100)).is_err());
+
+        let (open, cvar) = &*saturated.gate;
+        *open.lock().unwrap() = true;
+        cvar.notify_all();
+        producer.join().unwrap();
+
+        let (lines, dropped) = saturated.drain();
+        assert_eq!(lines, ["first", "a", "b", "c", "d"]);
+        assert_eq!(dropped, 0);
+    }
+
+    #[test]
+    fn budget_drops_without_blocking() {
+        // `first\n` stays reserved until it is written.
+        let budget = MemoryBudget::new(Some(10));
+        let saturated = saturated_within(Overflow::Block, budget.clone());
+        for line in ["a", "bb", "c"] {
+            log_line(&saturated.sink, line);
+        }
+
+        let (lines, dropped) = saturated.drain();
+        assert_eq!(written(&lines), ["first", "a", "c"]);
+        assert_eq!(dropped, 1);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn budget_drops_oldest() {
+        let budget = MemoryBudget::new(Some(10));
+        let saturated = saturated_within(Overflow::DropOldest, budget.clone());
+        for line in ["a", "bb"] {
+            log_line(&saturated.sink, line);
+        }
+
+        let (lines, dropped) = saturated.drain();
+        assert_eq!(written(&lines), ["first", "bb"]);
+        assert_eq!(dropped, 1);
+        assert_eq!(budget.used(), 0);
+    }
+}
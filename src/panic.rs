@@ -0,0 +1,42 @@
+//! Logging panics, for [`LoggingBuilder::log_panics`](crate::LoggingBuilder::log_panics).
+
+use std::panic::PanicHookInfo;
+
+use log::{Level, Log, Record};
+
+/// Chains a panic hook that logs the panic to the global logger and flushes it before the
+/// previous hook runs, which may abort.
+pub(crate) fn install() {
+    let previous = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        log_panic(log::logger(), info);
+        previous(info);
+    }));
+}
+
+/// Logs `info` as an error and flushes `logger`, waiting for non-blocking writers, so the
+/// line is written even if the process aborts next.
+pub(crate) fn log_panic(logger: &dyn Log, info: &PanicHookInfo) {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    let thread = std::thread::current();
+    let thread = thread.name().unwrap_or("<unnamed>");
+
+    let args = match info.location() {
+        Some(location) => format!("thread '{}' panicked at {}: {}", thread, location, message),
+        None => format!("thread '{}' panicked: {}", thread, message),
+    };
+    logger.log(
+        &Record::builder()
+            .level(Level::Error)
+            .target("panic")
+            .args(format_args!("{}", args))
+            .build(),
+    );
+    logger.flush();
+}
@@ -0,0 +1,154 @@
+//! Lines logged on a timer, for [`LoggingBuilder::heartbeat`](crate::LoggingBuilder::heartbeat)
+//! and [`LoggingBuilder::resource_stats`](crate::LoggingBuilder::resource_stats).
+
+use std::{
+    sync::mpsc::{channel, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{Level, Log, Record};
+
+/// Logs the line built by a closure to `logger` every interval until dropped, which stops
+/// the thread and waits for it.
+pub(crate) struct Periodic {
+    /// What the uptime counts from, kept when logging is reconfigured.
+    pub(crate) started: Instant,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Periodic {
+    /// Starts the `name` thread, logging `line(started)` at `level` with `target`.
+    pub(crate) fn spawn(
+        name: &str,
+        interval: Duration,
+        level: Level,
+        target: &'static str,
+        logger: Box<dyn Log>,
+        started: Instant,
+        line: impl Fn(Instant) -> String + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let (stop, stopped) = channel::<()>();
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    logger.log(
+                        &Record::builder()
+                            .level(level)
+                            .target(target)
+                            .args(format_args!("{}", line(started)))
+                            .build(),
+                    );
+                }
+            })?;
+
+        Ok(Self {
+            started,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Periodic {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The line of a [`heartbeat`](crate::LoggingBuilder::heartbeat): `message` and the time
+/// since `started`.
+pub(crate) fn heartbeat(message: &str, started: Instant) -> String {
+    format!("{} uptime={:.3}s", message, started.elapsed().as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use log::{Level, Log, Metadata, Record};
+
+    use super::Periodic;
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<(Level, String, String)>>>);
+
+    impl Log for Lines {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push((
+                record.level(),
+                record.target().to_string(),
+                record.args().to_string(),
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn beats_until_dropped() {
+        let lines = Lines::default();
+        let heartbeat = Periodic::spawn(
+            "test-heartbeat",
+            Duration::from_millis(20),
+            Level::Info,
+            "heartbeat",
+            Box::new(lines.clone()),
+            Instant::now(),
+            |started| super::heartbeat("alive", started),
+        )
+        .unwrap();
+
+        sleep(Duration::from_millis(150));
+        drop(heartbeat);
+        let count = lines.0.lock().unwrap().len();
+        assert!((3..=8).contains(&count), "{} heartbeats", count);
+
+        let (level, target, message) = lines.0.lock().unwrap()[0].clone();
+        assert_eq!((level, target.as_str()), (Level::Info, "heartbeat"));
+        assert!(message.starts_with("alive uptime=0.0"), "{}", message);
+        assert!(message.ends_with('s'), "{}", message);
+
+        sleep(Duration::from_millis(60));
+        assert_eq!(lines.0.lock().unwrap().len(), count);
+    }
+
+    #[test]
+    fn resource_stats_until_dropped() {
+        let lines = Lines::default();
+        let stats = Periodic::spawn(
+            "test-resources",
+            Duration::from_millis(20),
+            Level::Info,
+            "resources",
+            Box::new(lines.clone()),
+            Instant::now(),
+            |_| crate::resources::probe().to_string(),
+        )
+        .unwrap();
+
+        sleep(Duration::from_millis(150));
+        drop(stats);
+        let count = lines.0.lock().unwrap().len();
+        assert!((3..=8).contains(&count), "{} lines", count);
+        let (_, target, message) = lines.0.lock().unwrap()[0].clone();
+        assert_eq!(target, "resources");
+        assert!(!message.is_empty());
+
+        sleep(Duration::from_millis(60));
+        assert_eq!(lines.0.lock().unwrap().len(), count);
+    }
+}
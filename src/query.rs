@@ -0,0 +1,325 @@
+//! Searching the log files written by the built-in text and JSON formats.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::Chars,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use log::Level;
+
+use crate::{rotation::is_history, LogEvent, CHRONO_FORMAT};
+
+/// Which records [`query_logs`] returns. Matches every record by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryFilter {
+    level: Option<Level>,
+    target_prefix: Option<String>,
+    since: Option<DateTime<Local>>,
+    until: Option<DateTime<Local>>,
+    contains: Option<String>,
+}
+
+impl QueryFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only records at `level` or more severe.
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+
+        self
+    }
+
+    /// Only records whose target is `prefix` or one of its submodules.
+    pub fn target_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.target_prefix = Some(prefix.into());
+
+        self
+    }
+
+    /// Only records logged at `time` or later.
+    pub fn since(mut self, time: DateTime<Local>) -> Self {
+        self.since = Some(time);
+
+        self
+    }
+
+    /// Only records logged before `time`.
+    pub fn until(mut self, time: DateTime<Local>) -> Self {
+        self.until = Some(time);
+
+        self
+    }
+
+    /// Only records whose message contains `text`.
+    pub fn contains(mut self, text: impl Into<String>) -> Self {
+        self.contains = Some(text.into());
+
+        self
+    }
+
+    fn matches(&self, event: &LogEvent) -> bool {
+        self.level.is_none_or(|level| event.level <= level)
+            && self.target_prefix.as_deref().is_none_or(|prefix| {
+                event
+                    .target
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            && self.since.is_none_or(|since| event.time >= since)
+            && self.until.is_none_or(|until| event.time < until)
+            && self
+                .contains
+                .as_deref()
+                .is_none_or(|text| event.message.contains(text))
+    }
+}
+
+/// Reads the records `filter` matches from every log file in `log_dir`, oldest first.
+///
+/// Lines in the built-in text and [`LogFormat::Json`](crate::LogFormat::Json) formats are
+/// understood, with their standard level names. Lines that cannot be parsed are skipped,
+/// except that text lines not starting like a record continue the message of the record
+/// before them, as multi-line messages do. The history log is left out, as its lines are
+/// copies of those in the other files.
+///
+/// The events have no module path. Times in text lines are read as local time.
+pub fn query_logs(log_dir: impl AsRef<Path>, filter: &QueryFilter) -> io::Result<Vec<LogEvent>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(log_dir.as_ref())? {
+        let entry = entry?;
+        if let Ok(name) = entry.file_name().into_string() {
+            if name.ends_with(".log") && !is_history(&name) && entry.file_type()?.is_file() {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+
+    let mut events = vec![];
+    for name in names {
+        let mut parsed = vec![];
+        for line in BufReader::new(File::open(log_dir.as_ref().join(name))?).split(b'\n') {
+            let line = line?;
+            let line = String::from_utf8_lossy(&line);
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+
+            match parse_line(line) {
+                Some(event) => parsed.push(event),
+                None if !line.starts_with(['[', '#', '{']) => {
+                    if let Some(event) = parsed.last_mut() {
+                        event.message.push('\n');
+                        event.message.push_str(line);
+                    }
+                }
+                None => {}
+            }
+        }
+        events.extend(parsed.into_iter().filter(|event| filter.matches(event)));
+    }
+    // Stable, so records logged within the same second keep their order.
+    events.sort_by_key(|event| event.time);
+
+    Ok(events)
+}
+
+fn parse_line(line: &str) -> Option<LogEvent> {
+    if line.starts_with('{') {
+        parse_json(line)
+    } else {
+        parse_text(line)
+    }
+}
+
+/// Parses `[LEVEL] time target - message`, which may start with a sequence number and have
+/// an uptime after the time.
+fn parse_text(line: &str) -> Option<LogEvent> {
+    let line = match line.strip_prefix('#').and_then(|rest| rest.split_once(' ')) {
+        Some((sequence, rest)) if sequence.bytes().all(|b| b.is_ascii_digit()) => rest,
+        _ => line,
+    };
+    let (level, rest) = line.trim_start().strip_prefix('[')?.split_once(']')?;
+    let level = level.parse().ok()?;
+
+    let (time, rest) = rest.trim_start().split_once(' ')?;
+    let time = NaiveDateTime::parse_from_str(time, CHRONO_FORMAT).ok()?;
+    let time = Local.from_local_datetime(&time).earliest()?;
+
+    let rest = match rest
+        .strip_prefix('+')
+        .and_then(|rest| rest.split_once("s "))
+    {
+        Some((uptime, rest)) if uptime.parse::<f64>().is_ok() => rest,
+        _ => rest,
+    };
+    let (target, message) = rest.split_once(" - ")?;
+    // Leave out the `@version` from `version_for`.
+    let target = target.split_once('@').map_or(target, |(target, _)| target);
+
+    Some(LogEvent {
+        time,
+        level,
+        target: target.trim_end().into(),
+        message: message.to_string(),
+        module_path: None,
+        file: None,
+        line: None,
+        context: vec![],
+    })
+}
+
+/// Parses a [`JsonLine`](crate::format::JsonLine) object.
+fn parse_json(line: &str) -> Option<LogEvent> {
+    let mut time = None;
+    let mut level = None;
+    let mut target = None;
+    let mut message = None;
+    let mut file = None;
+    let mut number = None;
+
+    let mut chars = line.strip_prefix('{')?.chars();
+    loop {
+        match chars.next()? {
+            '}' => break,
+            ',' => continue,
+            '"' => {}
+            _ => return None,
+        }
+        let key = json_string(&mut chars)?;
+        if chars.next()? != ':' {
+            return None;
+        }
+
+        let rest = chars.as_str();
+        if rest.starts_with('"') {
+            chars.next();
+            let value = json_string(&mut chars)?;
+            match key.as_str() {
+                "timestamp" => time = Some(value),
+                "level" => level = Some(value),
+                "target" => target = Some(value),
+                "message" => message = Some(value),
+                "file" => file = Some(value),
+                _ => {}
+            }
+        } else {
+            let end = rest.find([',', '}'])?;
+            if key == "line" {
+                number = rest[..end].parse().ok();
+            }
+            chars = rest[end..].chars();
+        }
+    }
+
+    Some(LogEvent {
+        time: DateTime::parse_from_rfc3339(&time?)
+            .ok()?
+            .with_timezone(&Local),
+        level: level?.parse().ok()?,
+        target: target?.into(),
+        message: message?,
+        module_path: None,
+        file,
+        line: number,
+        context: vec![],
+    })
+}
+
+/// Reads the rest of a JSON string whose opening quote was already read.
+pub(crate) fn json_string(chars: &mut Chars) -> Option<String> {
+    let mut value = String::new();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let hex = chars.by_ref().take(4).collect::<String>();
+                    let code = u32::from_str_radix(&hex, 16).ok()?;
+                    value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_dir_all, write};
+
+    use chrono::{Local, NaiveDateTime, TimeZone};
+    use log::Level;
+
+    use super::{query_logs, QueryFilter};
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn queries_across_files() {
+        let dir = temp_dir("query-logs");
+        write(
+            dir.join("2024-01-02_03-00-00.log"),
+            "[INFO] 2024-01-02_03-00-00 app - started\n\
+             [WARN] 2024-01-02_03-00-05 app::db - slow query\n\
+             Caused by:\n    0: timeout\n\
+             [garbage\n\
+             #7 [ERROR] 2024-01-02_03-00-09 +1.500s app::db - failed query\n",
+        )
+        .unwrap();
+        write(
+            dir.join("2024-01-02_04-00-00.log"),
+            concat!(
+                r#"{"timestamp":"2024-01-02T04:00:01.000+00:00","level":"DEBUG","target":"app::db","message":"query \"users\"\né","line":42}"#,
+                "\n{\"timestamp\":\"oops\"}\n",
+            ),
+        )
+        .unwrap();
+        write(
+            dir.join("history.log"),
+            "[ERROR] 2024-01-02_03-00-09 app::db - failed query\n",
+        )
+        .unwrap();
+
+        let all = query_logs(&dir, &QueryFilter::new()).unwrap();
+        let messages = all.iter().map(|e| e.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        assert!(messages.contains(&"slow query\nCaused by:\n    0: timeout"));
+        assert!(messages.contains(&"query \"users\"\né"));
+        assert!(all.windows(2).all(|w| w[0].time <= w[1].time));
+        let json = all.iter().find(|e| e.level == Level::Debug).unwrap();
+        assert_eq!(json.line, Some(42));
+
+        let time = |s| {
+            let time = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+            Local.from_local_datetime(&time).unwrap()
+        };
+        let db = query_logs(
+            &dir,
+            &QueryFilter::new()
+                .min_level(Level::Warn)
+                .target_prefix("app::db")
+                .since(time("2024-01-02 03:00:05"))
+                .until(time("2024-01-02 03:00:10"))
+                .contains("query"),
+        )
+        .unwrap();
+        let messages = db.iter().map(|e| e.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            ["slow query\nCaused by:\n    0: timeout", "failed query"]
+        );
+        assert_eq!(db[1].level, Level::Error);
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Reading the process's memory and CPU usage, for
+//! [`LoggingBuilder::resource_stats`](crate::LoggingBuilder::resource_stats).
+
+use std::{fmt, time::Duration};
+
+/// A snapshot of the process's resource usage. Fields the platform does not report are
+/// `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Stats {
+    /// The resident set size, Linux only.
+    pub(crate) rss_bytes: Option<u64>,
+    /// The largest resident set size so far, on Unix.
+    pub(crate) max_rss_bytes: Option<u64>,
+    pub(crate) user_cpu: Option<Duration>,
+    pub(crate) system_cpu: Option<Duration>,
+}
+
+/// Renders as `rss_bytes=... max_rss_bytes=... cpu_user_secs=... cpu_system_secs=...`,
+/// leaving out missing fields, or `unavailable` without any.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = [
+            ("rss_bytes", self.rss_bytes),
+            ("max_rss_bytes", self.max_rss_bytes),
+        ];
+        let secs = [
+            ("cpu_user_secs", self.user_cpu),
+            ("cpu_system_secs", self.system_cpu),
+        ];
+
+        let mut separator = "";
+        for (key, value) in bytes {
+            if let Some(value) = value {
+                write!(f, "{}{}={}", separator, key, value)?;
+                separator = " ";
+            }
+        }
+        for (key, value) in secs {
+            if let Some(value) = value {
+                write!(f, "{}{}={:.3}", separator, key, value.as_secs_f64())?;
+                separator = " ";
+            }
+        }
+
+        if separator.is_empty() {
+            f.write_str("unavailable")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The current resource usage of the process.
+pub(crate) fn probe() -> Stats {
+    #[allow(unused_mut)]
+    let mut stats = Stats::default();
+
+    #[cfg(unix)]
+    {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0 {
+            let time = |t: libc::timeval| {
+                Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+            };
+            stats.user_cpu = Some(time(usage.ru_utime));
+            stats.system_cpu = Some(time(usage.ru_stime));
+            // Kilobytes, except on Apple platforms.
+            let unit = if cfg!(target_vendor = "apple") {
+                1
+            } else {
+                1024
+            };
+            stats.max_rss_bytes = Some(usage.ru_maxrss as u64 * unit);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        stats.rss_bytes = std::fs::read_to_string("/proc/self/statm")
+            .ok()
+            .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .filter(|_| page_size > 0)
+            .map(|pages| pages * page_size as u64);
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{probe, Stats};
+
+    #[test]
+    fn renders_fields() {
+        let stats = Stats {
+            rss_bytes: Some(4096),
+            max_rss_bytes: None,
+            user_cpu: Some(Duration::from_millis(1500)),
+            system_cpu: Some(Duration::from_micros(250)),
+        };
+        assert_eq!(
+            stats.to_string(),
+            "rss_bytes=4096 cpu_user_secs=1.500 cpu_system_secs=0.000"
+        );
+        assert_eq!(Stats::default().to_string(), "unavailable");
+
+        #[cfg(target_os = "linux")]
+        {
+            let stats = probe();
+            assert!(stats.rss_bytes.is_some_and(|rss| rss > 0), "{:?}", stats);
+            assert!(stats.max_rss_bytes.is_some() && stats.user_cpu.is_some());
+        }
+        let _ = probe();
+    }
+}
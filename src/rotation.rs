@@ -0,0 +1,1208 @@
+//! Naming, finding and deleting old log files.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::{read_dir, remove_file, rename},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::bail;
+use chrono::{
+    format::{Item, StrftimeItems},
+    Duration, NaiveDate, NaiveDateTime,
+};
+
+use log::Level;
+
+use crate::clock::Clock;
+
+/// How the log file for a run is picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileMode {
+    /// Start a new timestamped file every run, deleting the oldest ones beyond
+    /// [`max_log_files`](crate::LoggingBuilder::max_log_files).
+    #[default]
+    Rotate,
+    /// Keep appending to the newest log file, starting a new one only if there is none.
+    Append,
+    /// Write to `<app_name>.log`, truncating it every run. Other files are left alone.
+    Single,
+    /// Write to `<app_name>.log`, first renaming the previous runs' files to
+    /// `<app_name>.log.1`, `<app_name>.log.2` and so on, and deleting those beyond
+    /// [`max_log_files`](crate::LoggingBuilder::max_log_files). Other files are left alone.
+    Numbered,
+}
+
+impl FromStr for FileMode {
+    type Err = anyhow::Error;
+
+    /// Parses `rotate`, `append`, `single` or `numbered`, ignoring case.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rotate" => Ok(Self::Rotate),
+            "append" => Ok(Self::Append),
+            "single" => Ok(Self::Single),
+            "numbered" => Ok(Self::Numbered),
+            _ => bail!(
+                "Unknown file mode {:?}, expected rotate, append, single or numbered",
+                s
+            ),
+        }
+    }
+}
+
+/// Which log files rotation treats as the oldest, for
+/// [`rotation_order`](crate::LoggingBuilder::rotation_order).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotationOrder {
+    /// By the time in their names, i.e. when they were created.
+    #[default]
+    ByFilename,
+    /// By when they were last written to, as the file system reports. Files without a
+    /// modification time count as the oldest.
+    ByModifiedTime,
+}
+
+/// When old log files are deleted, for [`rotate_when`](crate::LoggingBuilder::rotate_when).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotateWhen {
+    /// When logging starts, before the new log file is created.
+    #[default]
+    Startup,
+    /// When the [`LoggingHandle`](crate::LoggingHandle) is dropped, after the log file is
+    /// closed.
+    Shutdown,
+    Both,
+}
+
+impl RotateWhen {
+    pub(crate) fn at_startup(self) -> bool {
+        matches!(self, RotateWhen::Startup | RotateWhen::Both)
+    }
+
+    pub(crate) fn at_shutdown(self) -> bool {
+        matches!(self, RotateWhen::Shutdown | RotateWhen::Both)
+    }
+}
+
+/// The file name of [`history_log`](crate::LoggingBuilder::history_log), which rotation
+/// leaves alone along with its numbered predecessors.
+pub(crate) const HISTORY_NAME: &str = "history.log";
+
+/// The file name format of [`daily_rollover`](crate::LoggingBuilder::daily_rollover) logs.
+pub(crate) const DAILY_FORMAT: &str = "%Y-%m-%d";
+/// Appended to the file name format by
+/// [`filename_microseconds`](crate::LoggingBuilder::filename_microseconds).
+pub(crate) const MICROS_SUFFIX: &str = "-%6f";
+
+pub(crate) type RotateCallback = dyn Fn(&Path) + Send + Sync;
+
+/// How log files are named and how many of them are kept.
+pub(crate) struct Rotation<'a> {
+    /// The `chrono` format file names are generated from and parsed with.
+    pub(crate) filename_format: &'a str,
+    pub(crate) max_files: usize,
+    pub(crate) on_rotate: Option<&'a Arc<RotateCallback>>,
+    pub(crate) clock: &'a dyn Clock,
+    /// Logs created longer ago than this are deleted regardless of `max_files`.
+    pub(crate) max_age: Option<Duration>,
+    /// How far in the future a log may be dated before it is assumed the clock went
+    /// backward, rather than the log being just created by a slightly faster clock.
+    pub(crate) clock_skew: Duration,
+    /// Problems worth telling the user about once logging is installed.
+    pub(crate) warnings: RefCell<Vec<String>>,
+    /// The [`audit_file`](crate::LoggingBuilder::audit_file), which rotation leaves alone.
+    pub(crate) audit_name: Option<&'a str>,
+    /// The log file being written, which rotation leaves alone.
+    pub(crate) current: Option<&'a Path>,
+    /// The sanitized [`session_label`](crate::LoggingBuilder::session_label) new file names
+    /// start with.
+    pub(crate) label: Option<&'a str>,
+    pub(crate) order: RotationOrder,
+}
+
+impl Rotation<'_> {
+    /// The path of a log file created now in `log_dir`.
+    ///
+    /// If a file with that name already exists, e.g. after restarting within the same
+    /// second, a `_1`, `_2`, ... suffix is added to the name instead of overwriting it.
+    pub(crate) fn new_path(&self, log_dir: &Path) -> PathBuf {
+        let stem = self.labeled(self.clock.now().format(self.filename_format));
+
+        let mut path = log_dir.join(format!("{}.log", stem));
+        let mut suffix = 0;
+        while path.exists() {
+            suffix += 1;
+            path = log_dir.join(format!("{}_{}.log", stem, suffix));
+        }
+
+        path
+    }
+
+    /// The path of today's [`DAILY_FORMAT`] log in `log_dir`, after deleting the oldest logs
+    /// until at most `max_files` remain including it.
+    pub(crate) fn daily_path(&self, log_dir: &Path) -> anyhow::Result<PathBuf> {
+        let path = log_dir.join(format!(
+            "{}.log",
+            self.labeled(self.clock.now().format(DAILY_FORMAT))
+        ));
+
+        let logs = self.get_all_logs(log_dir)?;
+        let keep = if path.exists() {
+            self.max_files
+        } else {
+            self.max_files - 1
+        };
+        self.prune(logs, keep)?;
+
+        Ok(path)
+    }
+
+    /// `name` after the label, if any.
+    fn labeled(&self, name: impl fmt::Display) -> String {
+        match self.label {
+            Some(label) => format!("{}_{}", label, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Parses the stem of a log file named with a session label, whatever the label, by
+    /// trying the name after each `_`. Only names starting like the format's are tried.
+    fn parse_labeled(&self, stem: &str, prefix: &str) -> Option<(NaiveDateTime, u32)> {
+        stem.match_indices('_')
+            .map(|(i, _)| &stem[i + 1..])
+            .filter(|rest| rest.starts_with(prefix))
+            .find_map(|rest| self.parse_stem(rest))
+    }
+
+    /// The time a log file was created at and its collision suffix, parsed from its stem.
+    ///
+    /// Formats with only a date, like [`DAILY_FORMAT`], give the start of the day. Formats
+    /// ending in [`MICROS_SUFFIX`] also parse names without the microseconds, written before
+    /// they were enabled.
+    fn parse_stem(&self, stem: &str) -> Option<(NaiveDateTime, u32)> {
+        parse_stem(stem, self.filename_format).or_else(|| {
+            let format = self.filename_format.strip_suffix(MICROS_SUFFIX)?;
+            parse_stem(stem, format)
+        })
+    }
+
+    /// Rotates all logs found in the `log_dir`.
+    ///
+    /// Deletes the oldest logs, by the time in their names, until fewer than `max_files`
+    /// remain, leaving room for the log file that is about to be created. The `max_files - 1`
+    /// newest logs always survive, whatever order they were created or modified in.
+    /// `on_rotate` is called with each file before it is deleted, oldest first.
+    pub(crate) fn rotate_logs<P: AsRef<Path>>(&self, log_dir: P) -> anyhow::Result<()> {
+        let logs = self.get_all_logs(log_dir)?;
+
+        self.prune(logs, self.max_files - 1)
+    }
+
+    /// The newest log file in `log_dir`, after deleting the oldest logs until at most
+    /// `max_files` remain. Names a new log file if there are none.
+    pub(crate) fn append_path<P: AsRef<Path>>(&self, log_dir: P) -> anyhow::Result<PathBuf> {
+        let log_dir = log_dir.as_ref();
+        let logs = self.get_all_logs(log_dir)?;
+
+        let newest = if let Some(newest) = logs.first() {
+            PathBuf::from(newest)
+        } else {
+            return Ok(self.new_path(log_dir));
+        };
+        self.prune(logs, self.max_files)?;
+
+        Ok(newest)
+    }
+
+    /// Shifts `name`, `name.1`, `name.2`, ... in `log_dir` up by one number, deleting the
+    /// files that would end up beyond `max_files` including the new `name`. Returns the path
+    /// of `name`, which no longer exists.
+    pub(crate) fn shift_numbered(&self, log_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+        let path = |n: usize| match n {
+            0 => log_dir.join(name),
+            n => log_dir.join(format!("{}.{}", name, n)),
+        };
+
+        let mut numbers = vec![];
+        if log_dir.exists() {
+            for entry in read_dir(log_dir)? {
+                let file_name = entry?.file_name();
+                let file_name = file_name.to_str().unwrap_or_default();
+
+                if file_name == name {
+                    numbers.push(0);
+                } else if let Some(n) = file_name
+                    .strip_prefix(name)
+                    .and_then(|rest| rest.strip_prefix('.'))
+                    .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|n| *n > 0)
+                {
+                    numbers.push(n);
+                }
+            }
+        }
+        numbers.sort_unstable_by(|a, b| b.cmp(a));
+
+        for n in numbers {
+            if n + 1 >= self.max_files {
+                if let Some(cb) = self.on_rotate {
+                    cb(&path(n));
+                }
+
+                remove_file(path(n))?;
+            } else {
+                rename(path(n), path(n + 1))?;
+            }
+        }
+
+        Ok(path(0))
+    }
+
+    /// Deletes the oldest of the newest-first `logs` until `keep` remain.
+    pub(crate) fn prune(&self, mut logs: Vec<String>, keep: usize) -> anyhow::Result<()> {
+        while logs.len() > keep {
+            let path = logs.pop().unwrap();
+
+            if let Some(cb) = self.on_rotate {
+                cb(Path::new(&path));
+            }
+
+            remove_log(Path::new(&path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets all log files from the `log_dir`, newest first by the `order`, after deleting
+    /// those older than `max_age`, which always goes by the time in their names.
+    ///
+    /// Logs dated more than `clock_skew` in the future are never deleted for their age;
+    /// a warning is recorded instead, as the clock has likely been set back since they
+    /// were created.
+    ///
+    /// Only `.log` files whose names parse with the format, optionally after a session label,
+    /// are logs; every other file is left alone. Names that do not start like the format's
+    /// are only tried after a label, so most unrelated files are skipped without being
+    /// parsed. A missing `log_dir` has no logs; it is not created.
+    pub(crate) fn get_all_logs<P: AsRef<Path>>(&self, log_dir: P) -> anyhow::Result<Vec<String>> {
+        let log_dir = log_dir.as_ref();
+
+        if !log_dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let prefix = literal_prefix(self.filename_format);
+        let mut log_files = vec![];
+
+        let paths = read_dir(log_dir)?;
+        for entry in paths {
+            let entry = entry?;
+            let name = entry.file_name();
+            let stem = match name.to_str() {
+                Some(name) if !is_history(name) && Some(name) != self.audit_name => {
+                    name.strip_suffix(".log")
+                }
+                _ => None,
+            };
+            if self.current == Some(entry.path().as_path()) {
+                continue;
+            }
+            // Per-level files go with their log, whatever its age or number.
+            if let Some(log_stem) = stem.and_then(level_file_of) {
+                if self
+                    .parse_stem(log_stem)
+                    .or_else(|| self.parse_labeled(log_stem, &prefix))
+                    .is_some()
+                {
+                    continue;
+                }
+            }
+            let path = entry.path();
+            let file_path = path.display().to_string();
+
+            let created = match stem {
+                Some(stem) if stem.starts_with(&prefix) => {
+                    match self
+                        .parse_stem(stem)
+                        .or_else(|| self.parse_labeled(stem, &prefix))
+                    {
+                        Some(created) => created,
+                        None => continue,
+                    }
+                }
+                Some(stem) => match self.parse_labeled(stem, &prefix) {
+                    Some(created) => created,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            if self.expired(&file_path, created.0) {
+                if let Some(cb) = self.on_rotate {
+                    cb(&path);
+                }
+
+                remove_log(&path)?;
+                continue;
+            }
+
+            let modified = match self.order {
+                RotationOrder::ByFilename => None,
+                RotationOrder::ByModifiedTime => entry.metadata().and_then(|m| m.modified()).ok(),
+            };
+            log_files.push((file_path, (modified, created)));
+        }
+
+        sort_log_files(&mut log_files);
+
+        Ok(log_files.iter().map(|(path, _)| path.to_string()).collect())
+    }
+
+    /// Whether the log at `path`, created at `created`, is older than `max_age`.
+    fn expired(&self, path: &str, created: NaiveDateTime) -> bool {
+        let max_age = if let Some(max_age) = self.max_age {
+            max_age
+        } else {
+            return false;
+        };
+        let now = self.clock.now().naive_local();
+
+        if created - now > self.clock_skew {
+            self.warnings.borrow_mut().push(format!(
+                "Keeping log file {} dated {}, which is in the future. Was the system clock set back?",
+                path, created
+            ));
+            return false;
+        }
+
+        (now - created).max(Duration::zero()) > max_age
+    }
+}
+
+/// The text every name generated from `format` starts with, before its first specifier.
+fn literal_prefix(format: &str) -> String {
+    StrftimeItems::new(format)
+        .map_while(|item| match item {
+            Item::Literal(s) | Item::Space(s) => Some(s.to_string()),
+            Item::OwnedLiteral(s) | Item::OwnedSpace(s) => Some(s.into()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The time and collision suffix of a log file stem named with `format`.
+fn parse_stem(stem: &str, format: &str) -> Option<(NaiveDateTime, u32)> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(stem, format) {
+        return Some((time, 0));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(stem, format) {
+        return date.and_hms_opt(0, 0, 0).map(|time| (time, 0));
+    }
+
+    let (stem, suffix) = stem.rsplit_once('_')?;
+    if !suffix.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let suffix = suffix.parse().ok()?;
+    let time = NaiveDateTime::parse_from_str(stem, format).ok()?;
+
+    Some((time, suffix))
+}
+
+/// The [`per_level_files`](crate::LoggingBuilder::per_level_files) file for `level` next to
+/// the log at `path`, e.g. `2024-01-02_03-04-05.error.log`.
+pub(crate) fn level_path(path: &Path, level: Level) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.log", level.as_str().to_lowercase()));
+
+    path.with_file_name(name)
+}
+
+/// The stem of the log a per-level file stem like `<stem>.error` belongs to.
+fn level_file_of(stem: &str) -> Option<&str> {
+    let (log_stem, level) = stem.rsplit_once('.')?;
+
+    Level::iter()
+        .any(|l| l.as_str().eq_ignore_ascii_case(level))
+        .then_some(log_stem)
+}
+
+/// Deletes the log at `path` along with its per-level files.
+fn remove_log(path: &Path) -> std::io::Result<()> {
+    remove_file(path)?;
+    for level in Level::iter() {
+        match remove_file(level_path(path, level)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `file_name` is the history log or one of its numbered predecessors.
+pub(crate) fn is_history(file_name: &str) -> bool {
+    file_name
+        .strip_prefix(HISTORY_NAME)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Intentionally split out to make it easier to test.
+#[inline]
+fn sort_log_files<K: Ord>(logs: &mut [(String, K)]) {
+    logs.sort_by(|(_, a), (_, b)| b.cmp(a));
+}
+
+/// Checks that `format` produces file names that are legal on this platform and can be
+/// parsed back into the time they were created at. Returns the format to use.
+///
+/// With `sanitize`, specifiers that expand to separators like `:` and illegal literal
+/// characters are replaced with `-` first.
+pub(crate) fn filename_format(
+    format: &str,
+    sanitize: bool,
+    clock: &dyn Clock,
+) -> anyhow::Result<String> {
+    let format = if sanitize {
+        sanitize_format(format)
+    } else {
+        format.to_string()
+    };
+
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        bail!("Invalid filename format {:?}", format);
+    }
+
+    let name = clock.now().format(&format).to_string();
+
+    let mut illegal: Vec<char> = name.chars().filter(|c| is_illegal(*c)).collect();
+    illegal.sort_unstable();
+    illegal.dedup();
+    if !illegal.is_empty() {
+        bail!(
+            "Filename format {:?} produces characters that are not allowed in file names on \
+             this platform: {:?}. Enable `sanitize_filenames` to replace them",
+            format,
+            illegal
+        );
+    }
+
+    if NaiveDateTime::parse_from_str(&name, &format).is_err() {
+        bail!(
+            "Filename format {:?} must contain the full date and time so old log files can be \
+             found",
+            format
+        );
+    }
+
+    Ok(format)
+}
+
+/// `label` made safe to use in file names on every platform, with illegal characters and
+/// whitespace replaced by `-`. `None` if nothing is left of it.
+pub(crate) fn sanitize_label(label: &str) -> Option<String> {
+    let sanitized = label
+        .chars()
+        .map(|c| {
+            let illegal = c.is_control()
+                || c.is_whitespace()
+                || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*');
+            if illegal {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    // Windows drops trailing dots, and leading ones hide files elsewhere.
+    let sanitized = sanitized.trim_matches(|c| c == '.' || c == '-');
+
+    (!sanitized.is_empty()).then(|| sanitized.to_string())
+}
+
+/// Whether `c` is not allowed in file names on this platform.
+#[cfg(windows)]
+fn is_illegal(c: char) -> bool {
+    c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+}
+
+/// Whether `c` is not allowed in file names on this platform.
+#[cfg(not(windows))]
+fn is_illegal(c: char) -> bool {
+    matches!(c, '\0' | '/')
+}
+
+/// Rewrites `format` so it no longer produces `:` or `/`, and replaces any other literal
+/// characters that are not allowed in file names.
+fn sanitize_format(format: &str) -> String {
+    let mut sanitized = String::with_capacity(format.len());
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            sanitized.push(if is_illegal(c) { '-' } else { c });
+            continue;
+        }
+
+        // Flags, padding and the `%:z` family come before the specifier itself.
+        let mut spec = String::from('%');
+        for c in chars.by_ref() {
+            spec.push(c);
+            if !matches!(c, '-' | '_' | '0'..='9' | '.' | ':' | '#') {
+                break;
+            }
+        }
+
+        sanitized.push_str(match spec.as_str() {
+            "%T" | "%X" => "%H-%M-%S",
+            "%R" => "%H-%M",
+            "%r" => "%I-%M-%S %p",
+            "%D" | "%x" => "%m-%d-%y",
+            "%c" => "%a %b %e %H-%M-%S %Y",
+            "%+" => "%Y-%m-%dT%H-%M-%S%.f%z",
+            "%:z" | "%::z" | "%:::z" => "%z",
+            _ => &spec,
+        });
+    }
+
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read_dir, remove_dir_all},
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::{Duration, Local, NaiveDateTime};
+
+    use super::{FileMode, RotateCallback, Rotation, RotationOrder};
+    use crate::{
+        clock::SystemClock,
+        test_util::{create_log, temp_dir, ManualClock},
+        CHRONO_FORMAT,
+    };
+
+    fn filename_format(format: &str, sanitize: bool) -> anyhow::Result<String> {
+        super::filename_format(format, sanitize, &SystemClock)
+    }
+
+    fn rotation(max_files: usize) -> Rotation<'static> {
+        Rotation {
+            filename_format: CHRONO_FORMAT,
+            max_files,
+            on_rotate: None,
+            clock: &SystemClock,
+            max_age: None,
+            clock_skew: Duration::minutes(5),
+            warnings: Default::default(),
+            audit_name: None,
+            current: None,
+            label: None,
+            order: RotationOrder::ByFilename,
+        }
+    }
+
+    #[test]
+    fn sort_log_files() {
+        let mut test_logs = vec![];
+
+        for file in [
+            Local::now()
+                .naive_local()
+                .checked_add_signed(Duration::seconds(60))
+                .unwrap()
+                .format(CHRONO_FORMAT),
+            Local::now()
+                .naive_local()
+                .checked_add_signed(Duration::seconds(160))
+                .unwrap()
+                .format(CHRONO_FORMAT),
+            Local::now()
+                .naive_local()
+                .checked_add_signed(Duration::seconds(260))
+                .unwrap()
+                .format(CHRONO_FORMAT),
+        ] {
+            test_logs.push(file.to_string());
+        }
+
+        let mut logs = vec![];
+
+        for file in test_logs {
+            let time = if let Ok(v) = NaiveDateTime::parse_from_str(file.as_str(), CHRONO_FORMAT) {
+                v
+            } else {
+                continue;
+            };
+
+            logs.push((file, time));
+        }
+
+        super::sort_log_files(&mut logs);
+
+        assert!(logs[0].1 > logs[1].1);
+        assert!(logs[1].1 > logs[2].1);
+    }
+
+    #[test]
+    fn max_log_files_includes_new_file() {
+        for max in 1..=4 {
+            let dir = temp_dir(&format!("max-log-files-{}", max));
+            for i in 0..6 {
+                create_log(&dir, -100 - i);
+            }
+
+            rotation(max).rotate_logs(&dir).unwrap();
+            create_log(&dir, 0);
+
+            assert_eq!(read_dir(&dir).unwrap().count(), max);
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn on_rotate_runs_before_delete() {
+        let dir = temp_dir("on-rotate");
+        let oldest = create_log(&dir, -300);
+        let older = create_log(&dir, -200);
+        create_log(&dir, -100);
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let cb: Arc<RotateCallback> = {
+            let seen = seen.clone();
+            Arc::new(move |path: &Path| {
+                seen.lock()
+                    .unwrap()
+                    .push((path.to_path_buf(), path.exists()))
+            })
+        };
+        Rotation {
+            on_rotate: Some(&cb),
+            ..rotation(2)
+        }
+        .rotate_logs(&dir)
+        .unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(oldest.clone(), true), (older.clone(), true)]
+        );
+        assert!(!oldest.exists());
+        assert!(!older.exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scanning_missing_dir_creates_nothing() {
+        let dir = temp_dir("scan-missing-dir");
+        let missing = dir.join("logs");
+
+        assert!(rotation(1).get_all_logs(&missing).unwrap().is_empty());
+        rotation(1).rotate_logs(&missing).unwrap();
+        assert!(!missing.exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filename_format_validation() {
+        assert_eq!(
+            filename_format(CHRONO_FORMAT, false).unwrap(),
+            CHRONO_FORMAT
+        );
+
+        let err = filename_format("%Y/%m/%d_%H-%M-%S", false).unwrap_err();
+        assert!(err.to_string().contains("'/'"), "{}", err);
+        assert!(filename_format("%Y-%m-%d", false).is_err());
+        assert!(filename_format("%Y-%m-%d_%Q", false).is_err());
+
+        assert_eq!(
+            filename_format("%Y/%m/%d_%T", true).unwrap(),
+            "%Y-%m-%d_%H-%M-%S"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_illegal_characters() {
+        let err = filename_format("%Y-%m-%d_%H:%M:%S", false).unwrap_err();
+        assert!(err.to_string().contains("':'"), "{}", err);
+        assert!(filename_format("%Y-%m-%d_%H-%M-%S*", false).is_err());
+
+        assert_eq!(
+            filename_format("%Y-%m-%d_%H:%M:%S*", true).unwrap(),
+            "%Y-%m-%d_%H-%M-%S-"
+        );
+    }
+
+    #[test]
+    fn sanitized_format_round_trips() {
+        let dir = temp_dir("sanitized-format");
+        let format = filename_format("%Y/%m/%d_%T", true).unwrap();
+        let clock = ManualClock::new("2024-01-02 03:04:05");
+        let rotation = Rotation {
+            filename_format: &format,
+            clock: &clock,
+            ..rotation(2)
+        };
+
+        for _ in 0..3 {
+            std::fs::File::create(rotation.new_path(&dir)).unwrap();
+            clock.advance(Duration::seconds(100));
+        }
+
+        assert_eq!(rotation.get_all_logs(&dir).unwrap().len(), 3);
+        rotation.rotate_logs(&dir).unwrap();
+        let names = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["2024-01-02_03-07-25.log"]);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn append_path_picks_newest() {
+        let dir = temp_dir("append-path");
+        let clock = ManualClock::new("2024-01-02 03:04:05");
+        let rotation = Rotation {
+            clock: &clock,
+            ..rotation(2)
+        };
+
+        let first = rotation.append_path(&dir).unwrap();
+        assert_eq!(first, dir.join("2024-01-02_03-04-05.log"));
+
+        for offset in [-300, -200, -100] {
+            create_log(&dir, offset);
+        }
+        let newest = create_log(&dir, 0);
+
+        assert_eq!(rotation.append_path(&dir).unwrap(), newest);
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_file_mode() {
+        assert_eq!("Append".parse::<FileMode>().unwrap(), FileMode::Append);
+        assert_eq!("rotate".parse::<FileMode>().unwrap(), FileMode::Rotate);
+        assert_eq!("SINGLE".parse::<FileMode>().unwrap(), FileMode::Single);
+        assert_eq!("numbered".parse::<FileMode>().unwrap(), FileMode::Numbered);
+        assert!("sometimes".parse::<FileMode>().is_err());
+    }
+
+    #[test]
+    fn same_second_names_get_suffixes() {
+        let dir = temp_dir("same-second-names");
+        let clock = ManualClock::new("2024-01-02 03:04:05");
+        let rotation = Rotation {
+            clock: &clock,
+            ..rotation(3)
+        };
+
+        let mut paths = vec![];
+        for _ in 0..3 {
+            let path = rotation.new_path(&dir);
+            std::fs::File::create(&path).unwrap();
+            paths.push(path);
+        }
+        assert_eq!(
+            paths,
+            [
+                dir.join("2024-01-02_03-04-05.log"),
+                dir.join("2024-01-02_03-04-05_1.log"),
+                dir.join("2024-01-02_03-04-05_2.log"),
+            ]
+        );
+
+        let logs = rotation.get_all_logs(&dir).unwrap();
+        assert_eq!(logs.len(), 3);
+        assert_eq!(logs[0], paths[2].display().to_string());
+        assert_eq!(logs[2], paths[0].display().to_string());
+
+        rotation.rotate_logs(&dir).unwrap();
+        assert!(!paths[0].exists());
+        assert!(paths[1].exists() && paths[2].exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sanitize_label() {
+        let sanitize = |label| super::sanitize_label(label);
+
+        assert_eq!(sanitize("baseline").as_deref(), Some("baseline"));
+        assert_eq!(
+            sanitize("experiment: a/b test").as_deref(),
+            Some("experiment--a-b-test")
+        );
+        assert_eq!(sanitize("run_1*?").as_deref(), Some("run_1"));
+        assert_eq!(sanitize(" ..hidden. ").as_deref(), Some("hidden"));
+        assert_eq!(sanitize("tab\there\n").as_deref(), Some("tab-here"));
+        assert_eq!(sanitize("über").as_deref(), Some("über"));
+        assert_eq!(sanitize(""), None);
+        assert_eq!(sanitize(" / "), None);
+    }
+
+    #[test]
+    fn labeled_names_parse() {
+        let dir = temp_dir("labeled-names");
+        let clock = ManualClock::new("2024-01-02 03:04:05");
+        let labeled = |label| Rotation {
+            clock: &clock,
+            label: Some(label),
+            ..rotation(4)
+        };
+
+        let mut paths = vec![];
+        for label in ["experiment-baseline", "run_2", "run_2"] {
+            let path = labeled(label).new_path(&dir);
+            std::fs::File::create(&path).unwrap();
+            paths.push(path);
+            clock.advance(Duration::seconds(100));
+        }
+        assert_eq!(
+            paths,
+            [
+                dir.join("experiment-baseline_2024-01-02_03-04-05.log"),
+                dir.join("run_2_2024-01-02_03-05-45.log"),
+                dir.join("run_2_2024-01-02_03-07-25.log"),
+            ]
+        );
+        let unlabeled = Rotation {
+            clock: &clock,
+            ..rotation(3)
+        };
+        let newest = unlabeled.new_path(&dir);
+        std::fs::File::create(&newest).unwrap();
+        std::fs::write(dir.join("experiment_notes.log"), "").unwrap();
+
+        let logs = unlabeled.get_all_logs(&dir).unwrap();
+        assert_eq!(logs.len(), 4);
+        assert_eq!(logs[0], newest.display().to_string());
+        assert_eq!(logs[3], paths[0].display().to_string());
+
+        unlabeled.rotate_logs(&dir).unwrap();
+        assert!(!paths[0].exists() && !paths[1].exists());
+        assert!(paths[2].exists() && newest.exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn microsecond_names_round_trip() {
+        let dir = temp_dir("microsecond-names");
+        let format = format!("{}{}", CHRONO_FORMAT, super::MICROS_SUFFIX);
+        assert_eq!(filename_format(&format, false).unwrap(), format);
+
+        let clock = ManualClock::new("2024-01-02 03:04:05");
+        std::fs::File::create(
+            Rotation {
+                clock: &clock,
+                ..rotation(3)
+            }
+            .new_path(&dir),
+        )
+        .unwrap();
+        let micros = Rotation {
+            filename_format: &format,
+            clock: &clock,
+            ..rotation(3)
+        };
+        let mut paths = vec![];
+        for _ in 0..3 {
+            clock.advance(Duration::microseconds(250));
+            let path = micros.new_path(&dir);
+            std::fs::File::create(&path).unwrap();
+            paths.push(path);
+        }
+        assert_eq!(
+            paths,
+            [
+                dir.join("2024-01-02_03-04-05-000250.log"),
+                dir.join("2024-01-02_03-04-05-000500.log"),
+                dir.join("2024-01-02_03-04-05-000750.log"),
+            ]
+        );
+
+        let logs = micros.get_all_logs(&dir).unwrap();
+        assert_eq!(logs.len(), 4);
+        assert_eq!(logs[0], paths[2].display().to_string());
+        assert!(logs[3].ends_with("2024-01-02_03-04-05.log"), "{:?}", logs);
+
+        micros.rotate_logs(&dir).unwrap();
+        assert!(!dir.join("2024-01-02_03-04-05.log").exists() && !paths[0].exists());
+        assert!(paths[1].exists() && paths[2].exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbered_shift() {
+        let dir = temp_dir("numbered-shift");
+        let rotated = Arc::new(Mutex::new(vec![]));
+        let cb: Arc<RotateCallback> = {
+            let rotated = rotated.clone();
+            Arc::new(move |path: &Path| rotated.lock().unwrap().push(path.to_path_buf()))
+        };
+        let rotation = Rotation {
+            on_rotate: Some(&cb),
+            ..rotation(3)
+        };
+        let contents = || {
+            let mut files = read_dir(&dir)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let name = path.file_name().unwrap().to_str().unwrap().to_string();
+                    (name, std::fs::read_to_string(path).unwrap())
+                })
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        std::fs::write(dir.join("app.log.bak"), "other").unwrap();
+
+        for run in ["one", "two", "three", "four"] {
+            let path = rotation.shift_numbered(&dir, "app.log").unwrap();
+            assert_eq!(path, dir.join("app.log"));
+            assert!(!path.exists());
+            std::fs::write(path, run).unwrap();
+        }
+
+        assert_eq!(
+            contents(),
+            [
+                ("app.log".to_string(), "four".to_string()),
+                ("app.log.1".to_string(), "three".to_string()),
+                ("app.log.2".to_string(), "two".to_string()),
+                ("app.log.bak".to_string(), "other".to_string()),
+            ]
+        );
+        assert_eq!(*rotated.lock().unwrap(), [dir.join("app.log.2")]);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_keeps_newest() {
+        let names = [
+            "2024-01-02_03-04-05.log",
+            "2023-12-31_23-59-59.log",
+            "2024-01-02_03-04-05_1.log",
+            "2024-01-01_00-00-00.log",
+            "2024-03-01_12-00-00.log",
+        ];
+        let newest_first = [
+            "2024-03-01_12-00-00.log",
+            "2024-01-02_03-04-05_1.log",
+            "2024-01-02_03-04-05.log",
+            "2024-01-01_00-00-00.log",
+            "2023-12-31_23-59-59.log",
+        ];
+
+        for (case, max, survivors) in [
+            ("empty", 3, 0),
+            ("fewer", 10, 5),
+            ("exactly", 6, 5),
+            ("one-over", 5, 4),
+            ("some", 3, 2),
+            ("all", 1, 0),
+        ] {
+            let dir = temp_dir(&format!("rotation-keeps-newest-{}", case));
+            if case != "empty" {
+                for name in names {
+                    std::fs::File::create(dir.join(name)).unwrap();
+                }
+            }
+
+            rotation(max).rotate_logs(&dir).unwrap();
+
+            let mut left = read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            left.sort_by_key(|name| newest_first.iter().position(|n| n == name));
+            assert_eq!(left, newest_first[..survivors], "{}", case);
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn level_files_go_with_their_log() {
+        let dir = temp_dir("level-files-go-with-their-log");
+        for name in [
+            "2024-01-01_00-00-00.log",
+            "2024-01-01_00-00-00.error.log",
+            "2024-01-01_00-00-00.info.log",
+            "2024-01-02_00-00-00.log",
+            "2024-01-02_00-00-00.warn.log",
+        ] {
+            std::fs::File::create(dir.join(name)).unwrap();
+        }
+
+        let rotation = rotation(2);
+        let logs = rotation.get_all_logs(&dir).unwrap();
+        assert_eq!(logs.len(), 2, "{:?}", logs);
+        rotation.rotate_logs(&dir).unwrap();
+
+        let mut left = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            ["2024-01-02_00-00-00.log", "2024-01-02_00-00-00.warn.log"]
+        );
+        assert_eq!(
+            super::level_path(&dir.join("app.log"), log::Level::Error),
+            dir.join("app.error.log")
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_by_modified_time() {
+        let dir = temp_dir("rotation-by-modified-time");
+        let now = std::time::SystemTime::now();
+        for (name, age_secs) in [
+            ("2024-01-01_00-00-00.log", 10),
+            ("2024-01-02_00-00-00.log", 300),
+            ("2024-01-03_00-00-00.log", 200),
+        ] {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age_secs))
+                .unwrap();
+        }
+        let names = |logs: Vec<String>| {
+            logs.iter()
+                .map(|path| {
+                    Path::new(path)
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let by_name = rotation(3);
+        assert_eq!(
+            names(by_name.get_all_logs(&dir).unwrap()),
+            [
+                "2024-01-03_00-00-00.log",
+                "2024-01-02_00-00-00.log",
+                "2024-01-01_00-00-00.log"
+            ]
+        );
+
+        let by_mtime = Rotation {
+            order: RotationOrder::ByModifiedTime,
+            ..rotation(3)
+        };
+        assert_eq!(
+            names(by_mtime.get_all_logs(&dir).unwrap()),
+            [
+                "2024-01-01_00-00-00.log",
+                "2024-01-03_00-00-00.log",
+                "2024-01-02_00-00-00.log"
+            ]
+        );
+        by_mtime.rotate_logs(&dir).unwrap();
+        assert!(!dir.join("2024-01-02_00-00-00.log").exists());
+        assert!(dir.join("2024-01-01_00-00-00.log").exists());
+        assert_eq!(
+            by_mtime.append_path(&dir).unwrap(),
+            dir.join("2024-01-01_00-00-00.log")
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_age_tolerates_clock_skew() {
+        let dir = temp_dir("max-age-clock-skew");
+        let clock = ManualClock::new("2024-01-10 12:00:00");
+        let rotation = Rotation {
+            clock: &clock,
+            max_age: Some(Duration::days(7)),
+            ..rotation(10)
+        };
+        let names = [
+            "2024-01-01_12-00-00.log",
+            "2024-01-05_12-00-00.log",
+            "2024-01-10_12-03-00.log",
+            "2024-02-01_00-00-00.log",
+        ];
+        for name in names {
+            std::fs::File::create(dir.join(name)).unwrap();
+        }
+
+        let logs = rotation.get_all_logs(&dir).unwrap();
+        assert_eq!(
+            logs,
+            names[1..]
+                .iter()
+                .rev()
+                .map(|name| dir.join(name).display().to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(!dir.join(names[0]).exists());
+
+        let warnings = rotation.warnings.take();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(names[3]), "{}", warnings[0]);
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scan_skips_unrelated_files() {
+        let dir = temp_dir("scan-unrelated");
+        for i in 0..1000 {
+            std::fs::File::create(dir.join(format!("notes-{}.txt", i))).unwrap();
+        }
+        for name in ["app.log.1", "history.log", "data.log.bak", "README"] {
+            std::fs::File::create(dir.join(name)).unwrap();
+        }
+        for offset in [-300, -200, -100] {
+            create_log(&dir, offset);
+        }
+        for name in ["garbage.log", "notes.log", "app.log"] {
+            std::fs::File::create(dir.join(name)).unwrap();
+        }
+
+        rotation(2).rotate_logs(&dir).unwrap();
+        assert_eq!(read_dir(&dir).unwrap().count(), 1000 + 4 + 1 + 3);
+        for name in ["garbage.log", "notes.log", "app.log"] {
+            assert!(dir.join(name).exists(), "{}", name);
+        }
+
+        let prefixed = Rotation {
+            filename_format: "app-%Y-%m-%d_%H-%M-%S",
+            ..rotation(1)
+        };
+        prefixed.rotate_logs(&dir).unwrap();
+        assert_eq!(read_dir(&dir).unwrap().count(), 1000 + 4 + 1 + 3);
+        assert_eq!(super::literal_prefix("app %%-%Y"), "app %-");
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+//! Reading back what a [`LoggingBuilder`] was configured with.
+
+use std::{collections::HashMap, path::Path};
+
+use log::LevelFilter;
+
+use crate::{FileMode, LogFormat, LoggingBuilder, Overflow};
+
+/// A read-only view of a [`LoggingBuilder`]'s settings, from
+/// [`LoggingBuilder::settings`], for wrapper crates and tests that check how a builder was
+/// put together before calling [`finish`](LoggingBuilder::finish).
+///
+/// Values are as set on the builder, before `finish` applies defaults from the environment
+/// or the platform.
+#[derive(Clone, Copy)]
+pub struct Settings<'a>(pub(crate) &'a LoggingBuilder);
+
+impl<'a> Settings<'a> {
+    pub fn app_name(&self) -> &'a str {
+        &self.0.app_name
+    }
+
+    pub fn qualifier(&self) -> &'a str {
+        &self.0.qualifier
+    }
+
+    pub fn organization(&self) -> &'a str {
+        &self.0.organization
+    }
+
+    pub fn global_level(&self) -> LevelFilter {
+        self.0.global_level
+    }
+
+    /// The module levels for every output, from [`LoggingBuilder::level_for`],
+    /// [`levels`](LoggingBuilder::levels) and [`LevelScope::Root`](crate::LevelScope::Root)
+    /// overrides.
+    pub fn levels(&self) -> &'a HashMap<String, LevelFilter> {
+        &self.0.level_for
+    }
+
+    /// The explicit [`log_dir`](LoggingBuilder::log_dir), if any.
+    pub fn log_dir(&self) -> Option<&'a Path> {
+        self.0.log_dir.as_deref()
+    }
+
+    /// The explicit [`file_mode`](LoggingBuilder::file_mode), if any.
+    pub fn file_mode(&self) -> Option<FileMode> {
+        self.0.file_mode
+    }
+
+    pub fn max_log_files(&self) -> usize {
+        self.0.max_log_files
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.0.format
+    }
+
+    /// The explicit [`terminal_format`](LoggingBuilder::terminal_format), if any.
+    pub fn terminal_format(&self) -> Option<LogFormat> {
+        self.0.terminal_format
+    }
+
+    /// The explicit [`file_format`](LoggingBuilder::file_format), if any.
+    pub fn file_format(&self) -> Option<LogFormat> {
+        self.0.file_format
+    }
+
+    pub fn non_blocking(&self) -> bool {
+        self.0.non_blocking
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.0.queue_capacity
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.0.overflow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use log::LevelFilter;
+
+    use crate::{FileMode, LogFormat, LoggingBuilder, Overflow};
+
+    #[test]
+    fn reflects_builder() {
+        let builder = LoggingBuilder::new()
+            .app_name("app")
+            .qualifier("com")
+            .organization("org")
+            .levels("warn,hyper=error")
+            .log_dir("/var/log/app")
+            .file_mode(FileMode::Append)
+            .format(LogFormat::Json)
+            .terminal_format(LogFormat::Text);
+        let settings = builder.settings();
+
+        assert_eq!(settings.app_name(), "app");
+        assert_eq!(settings.qualifier(), "com");
+        assert_eq!(settings.organization(), "org");
+        assert_eq!(settings.global_level(), LevelFilter::Warn);
+        assert_eq!(settings.levels().get("hyper"), Some(&LevelFilter::Error));
+        assert_eq!(settings.log_dir(), Some(Path::new("/var/log/app")));
+        assert_eq!(settings.file_mode(), Some(FileMode::Append));
+        assert_eq!(settings.max_log_files(), 5);
+        assert_eq!(settings.format(), LogFormat::Json);
+        assert_eq!(settings.terminal_format(), Some(LogFormat::Text));
+        assert_eq!(settings.file_format(), None);
+        assert!(!settings.non_blocking());
+        assert_eq!(settings.queue_capacity(), 1024);
+        assert_eq!(settings.overflow(), Overflow::Block);
+    }
+}
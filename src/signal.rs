@@ -0,0 +1,151 @@
+//! Reopening the log files on `SIGHUP`, for
+//! [`LoggingBuilder::reopen_on_sighup`](crate::LoggingBuilder::reopen_on_sighup).
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    thread,
+};
+
+use crate::files::Reopen;
+
+/// The write end of the pipe the signal handler wakes the reopening thread through.
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+/// Set up once per process, with the error if that failed.
+static INSTALLED: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// The log files of the live loggers that want reopening.
+static FILES: Mutex<Vec<Weak<Reopen>>> = Mutex::new(Vec::new());
+
+/// Reopens `file` on every `SIGHUP` until it is dropped.
+///
+/// The first call installs the handler and starts the `logging-sighup` thread that does the
+/// reopening, as the handler itself may only do async-signal-safe work: it writes a byte to
+/// a pipe the thread waits on. Both stay for the rest of the process.
+pub(crate) fn watch(file: &Arc<Reopen>) -> io::Result<()> {
+    INSTALLED
+        .get_or_init(|| install().map_err(|e| e.to_string()))
+        .clone()
+        .map_err(io::Error::other)?;
+
+    let mut files = FILES.lock().unwrap_or_else(|e| e.into_inner());
+    files.retain(|file| file.strong_count() > 0);
+    files.push(Arc::downgrade(file));
+
+    Ok(())
+}
+
+fn install() -> io::Result<()> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors `pipe` writes.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read, write] = fds;
+    // So a burst of signals never blocks the handler, and children do not inherit the pipe.
+    set_flags(read)?;
+    set_flags(write)?;
+
+    thread::Builder::new()
+        .name("logging-sighup".to_string())
+        .spawn(move || {
+            while wait(read) {
+                reopen_all();
+            }
+        })?;
+    WAKE.store(write, Ordering::Relaxed);
+
+    // SAFETY: `sigaction` is plain data, for which all zeroes is a valid value.
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    // SAFETY: `action` is initialized and the handler only does async-signal-safe work.
+    if unsafe { libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Makes `fd` non-blocking and closed on `exec`.
+fn set_flags(fd: libc::c_int) -> io::Result<()> {
+    // SAFETY: `fd` is open, and these commands only read and set its flags.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1
+            || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1
+            || libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Waits for the handler to write to the pipe at `fd` and reads everything it wrote, so a
+/// burst of signals reopens the files once. Returns `false` if the pipe failed.
+fn wait(fd: libc::c_int) -> bool {
+    let mut poll = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let mut woken = false;
+    while !woken {
+        // SAFETY: `poll` is a single valid `pollfd`.
+        if unsafe { libc::poll(&mut poll, 1, -1) } < 0
+            && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted
+        {
+            return false;
+        }
+
+        let mut buf = [0u8; 64];
+        loop {
+            // SAFETY: `buf` is valid for writes of its length.
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+            if n > 0 {
+                woken = true;
+                continue;
+            }
+            if n == 0 {
+                return false;
+            }
+            match io::Error::last_os_error().kind() {
+                io::ErrorKind::Interrupted => continue,
+                io::ErrorKind::WouldBlock => break,
+                _ => return false,
+            }
+        }
+    }
+
+    true
+}
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    let fd = WAKE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // SAFETY: `fd` is the write end of the pipe, which is never closed, and `write` is
+        // async-signal-safe. A full pipe fails rather than blocks, as a wake is then pending.
+        unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+    }
+}
+
+fn reopen_all() {
+    let files = FILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect::<Vec<_>>();
+
+    for file in files {
+        if let Err(e) = file.reopen() {
+            log::warn!("Unable to reopen the log file: {}", e);
+        }
+    }
+}
@@ -0,0 +1,1142 @@
+//! `log::Log` adapters wrapped around the sinks built by
+//! [`LoggingBuilder`](crate::LoggingBuilder).
+
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    io::{BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, FixedOffset};
+use log::{Level, Log, Metadata, Record};
+
+use crate::{
+    clock::Clock, error, files::Closable, intern::Targets, levels::SharedLevels,
+    memory::MemoryBudget, LogEvent,
+};
+
+/// Passes records to `inner` one at a time, so every sink below it sees records in the same
+/// order.
+pub(crate) struct Ordered {
+    lock: Mutex<()>,
+    inner: Box<dyn Log>,
+}
+
+impl Ordered {
+    pub(crate) fn new(inner: Box<dyn Log>) -> Self {
+        Self {
+            lock: Mutex::new(()),
+            inner,
+        }
+    }
+}
+
+impl Log for Ordered {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+thread_local! {
+    /// The sequence number of the record [`Sequenced`] is passing on, on this thread.
+    static SEQUENCE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// The sequence number of the record being logged, while inside a [`Sequenced`] sink.
+pub(crate) fn sequence() -> Option<u64> {
+    SEQUENCE.with(Cell::get)
+}
+
+/// Numbers every record, starting at 1, before passing it to `inner`. Formats below it read
+/// the number with [`sequence`].
+pub(crate) struct Sequenced {
+    next: AtomicU64,
+    inner: Box<dyn Log>,
+}
+
+impl Sequenced {
+    pub(crate) fn new(inner: Box<dyn Log>) -> Self {
+        Self {
+            next: AtomicU64::new(1),
+            inner,
+        }
+    }
+}
+
+impl Log for Sequenced {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let sequence = self.next.fetch_add(1, Ordering::Relaxed);
+
+        let outer = SEQUENCE.with(|s| s.replace(Some(sequence)));
+        self.inner.log(record);
+        SEQUENCE.with(|s| s.set(outer));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Passes at most `rate` records per second to `inner`, allowing bursts of up to a second's
+/// worth, and drops the rest. At most once a second, the next record let through is preceded
+/// by a warning with the number dropped.
+pub(crate) struct RateLimited {
+    rate: f64,
+    exempt_errors: bool,
+    bucket: Mutex<Bucket>,
+    inner: Box<dyn Log>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    /// Records dropped since the last warning.
+    dropped: u64,
+    warned: Instant,
+}
+
+impl RateLimited {
+    pub(crate) fn new(rate: u32, exempt_errors: bool, inner: Box<dyn Log>) -> Self {
+        Self {
+            rate: rate.into(),
+            exempt_errors,
+            bucket: Mutex::new(Bucket {
+                tokens: rate.into(),
+                refilled: Instant::now(),
+                dropped: 0,
+                warned: Instant::now(),
+            }),
+            inner,
+        }
+    }
+
+    /// Takes a token for a record, returning the number of dropped records to warn about, or
+    /// `None` if this one is dropped too.
+    fn take(&self, exempt: bool) -> Option<u64> {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+        } else if !exempt {
+            bucket.dropped += 1;
+            return None;
+        }
+
+        if bucket.dropped > 0 && now.duration_since(bucket.warned) >= Duration::from_secs(1) {
+            bucket.warned = now;
+            Some(std::mem::take(&mut bucket.dropped))
+        } else {
+            Some(0)
+        }
+    }
+}
+
+impl Log for RateLimited {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let exempt = self.exempt_errors && record.level() == Level::Error;
+        let dropped = match self.take(exempt) {
+            Some(dropped) => dropped,
+            None => return,
+        };
+
+        if dropped > 0 {
+            self.inner.log(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .args(format_args!(
+                        "Dropped {} log lines over the rate limit of {} per second",
+                        dropped, self.rate
+                    ))
+                    .build(),
+            );
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Passes the first `Error` record with a given message to `inner`, then suppresses the
+/// same message until `cooldown` has passed since it was logged. The next one let through
+/// says how many were suppressed. Other levels are passed on as is.
+pub(crate) struct ErrorCooldown {
+    cooldown: chrono::Duration,
+    clock: Arc<dyn Clock>,
+    /// Keyed by a hash of the message.
+    seen: Mutex<HashMap<u64, Cooldown>>,
+    inner: Box<dyn Log>,
+}
+
+struct Cooldown {
+    logged: DateTime<FixedOffset>,
+    suppressed: u64,
+}
+
+impl ErrorCooldown {
+    pub(crate) fn new(cooldown: Duration, clock: Arc<dyn Clock>, inner: Box<dyn Log>) -> Self {
+        Self {
+            cooldown: chrono::Duration::from_std(cooldown)
+                .unwrap_or_else(|_| chrono::Duration::max_value()),
+            clock,
+            seen: Mutex::new(HashMap::new()),
+            inner,
+        }
+    }
+
+    /// Records an error with `message`, returning the number suppressed since it was last
+    /// logged, or `None` if this one is suppressed too.
+    fn check(&self, message: &str) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        message.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let now = self.clock.now();
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(entry) = seen.get_mut(&key) {
+            if now - entry.logged < self.cooldown {
+                entry.suppressed += 1;
+                return None;
+            }
+
+            entry.logged = now;
+            return Some(std::mem::take(&mut entry.suppressed));
+        }
+
+        // Forget messages whose cooldown is over, unless they still have a count to report.
+        seen.retain(|_, entry| entry.suppressed > 0 || now - entry.logged < self.cooldown);
+        seen.insert(
+            key,
+            Cooldown {
+                logged: now,
+                suppressed: 0,
+            },
+        );
+
+        Some(0)
+    }
+}
+
+impl Log for ErrorCooldown {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() != Level::Error {
+            return self.inner.log(record);
+        }
+
+        let message = record.args().to_string();
+        match self.check(&message) {
+            Some(0) => self.inner.log(record),
+            Some(suppressed) => self.inner.log(
+                &Record::builder()
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .args(format_args!(
+                        "{} ({} identical errors suppressed)",
+                        message, suppressed
+                    ))
+                    .build(),
+            ),
+            None => {}
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub(crate) type MessageTransform = dyn for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync;
+
+/// Rewrites the message of every record `inner` logs with `transform`. Records whose message
+/// comes back borrowed are passed on as they are.
+pub(crate) struct TransformMessage {
+    pub(crate) transform: Arc<MessageTransform>,
+    pub(crate) inner: Box<dyn Log>,
+}
+
+impl Log for TransformMessage {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return self.inner.log(record);
+        }
+
+        let formatted;
+        let message = match record.args().as_str() {
+            Some(message) => message,
+            None => {
+                formatted = record.args().to_string();
+                &formatted
+            }
+        };
+        match (self.transform)(message) {
+            Cow::Borrowed(_) => self.inner.log(record),
+            Cow::Owned(message) => self.inner.log(
+                &Record::builder()
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .args(format_args!("{}", message))
+                    .build(),
+            ),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Appends the backtrace of an error logged with
+/// [`log_error_with_backtrace!`](crate::log_error_with_backtrace) to its record before
+/// passing it to `inner`, the log file.
+pub(crate) struct AppendBacktrace(pub(crate) Box<dyn Log>);
+
+impl Log for AppendBacktrace {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match error::pending_backtrace() {
+            Some(backtrace) => self.0.log(
+                &Record::builder()
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .args(format_args!("{}\nBacktrace:\n{}", record.args(), backtrace))
+                    .build(),
+            ),
+            None => self.0.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Formats every record into the file of the session started by
+/// [`LoggingHandle::start_capture`](crate::LoggingHandle::start_capture), whatever the
+/// levels, and does nothing between sessions.
+#[derive(Clone)]
+pub(crate) struct Capture {
+    active: Arc<AtomicBool>,
+    file: Closable,
+    /// Formats records into `file`.
+    formatted: Arc<dyn Log>,
+}
+
+impl Capture {
+    /// Builds the capture around `format`, chaining it to the capture file.
+    pub(crate) fn new(format: fern::Dispatch) -> Self {
+        let file = Closable::closed();
+        let (_, formatted) = format
+            .chain(Box::new(file.clone()) as Box<dyn Write + Send>)
+            .into_log();
+
+        Self {
+            active: Arc::new(AtomicBool::new(false)),
+            file,
+            formatted: formatted.into(),
+        }
+    }
+
+    /// Starts writing to `writer`, returning the file of the session it replaces.
+    pub(crate) fn start(&self, writer: Box<dyn Write + Send>) -> Option<Box<dyn Write + Send>> {
+        let previous = self.file.replace(Some(writer));
+        self.active.store(true, Ordering::Release);
+
+        previous
+    }
+
+    /// Ends the session, returning its file.
+    pub(crate) fn stop(&self) -> Option<Box<dyn Write + Send>> {
+        self.active.store(false, Ordering::Release);
+
+        self.file.replace(None)
+    }
+}
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.active.load(Ordering::Acquire) {
+            self.formatted.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.formatted.flush();
+    }
+}
+
+/// The installed logger, whose sinks
+/// [`LoggingHandle::reconfigure`](crate::LoggingHandle::reconfigure) replaces.
+#[derive(Clone)]
+pub(crate) struct Reloadable(Arc<RwLock<Box<dyn Log>>>);
+
+impl Reloadable {
+    pub(crate) fn new(inner: Box<dyn Log>) -> Self {
+        Self(Arc::new(RwLock::new(inner)))
+    }
+
+    /// Passes all later records to `inner`, returning the logger they went to before. Waits
+    /// for records being logged to the old one.
+    pub(crate) fn replace(&self, inner: Box<dyn Log>) -> Box<dyn Log> {
+        std::mem::replace(
+            &mut *self.0.write().unwrap_or_else(|e| e.into_inner()),
+            inner,
+        )
+    }
+}
+
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).log(record);
+    }
+
+    fn flush(&self) {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).flush();
+    }
+}
+
+/// Writes each formatted record to `writer` as a line.
+///
+/// Line buffered, every line is written with a single write and flushed right away.
+/// Otherwise lines collect in a buffer, written when it fills or the logger is flushed, for
+/// [`line_buffered`](crate::LoggingBuilder::line_buffered).
+pub(crate) struct LineWriter {
+    writer: Mutex<BufWriter<Box<dyn Write + Send>>>,
+    line_buffered: bool,
+}
+
+impl LineWriter {
+    pub(crate) fn new(writer: Box<dyn Write + Send>, line_buffered: bool) -> Self {
+        Self {
+            writer: Mutex::new(BufWriter::new(writer)),
+            line_buffered,
+        }
+    }
+}
+
+impl Log for LineWriter {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut result = writeln!(writer, "{}", record.args());
+        if self.line_buffered {
+            result = result.and_then(|_| writer.flush());
+        }
+
+        if let Err(e) = result {
+            eprintln!("Error writing log lines: {}", e);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush();
+    }
+}
+
+/// Keeps the most recent formatted lines in memory.
+///
+/// When `budget` runs out, the oldest lines are evicted until the new one fits, and lines
+/// that do not fit even in an empty buffer are dropped; both are counted by the budget.
+#[derive(Clone)]
+pub(crate) struct RingBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+    budget: MemoryBudget,
+}
+
+impl RingBuffer {
+    pub(crate) fn new(capacity: usize, budget: MemoryBudget) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            budget,
+        }
+    }
+
+    /// The buffered lines, oldest first.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+
+        lines.iter().cloned().collect()
+    }
+
+    /// Removes and returns the buffered lines, oldest first.
+    pub(crate) fn take(&self) -> Vec<String> {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        let taken = lines.drain(..).collect::<Vec<_>>();
+        self.budget.release(taken.iter().map(String::len).sum());
+
+        taken
+    }
+}
+
+/// Keeps the records the level filters suppress in `buffer`, through `suppressed`, and writes
+/// them to `file` when an error is logged, for
+/// [`dump_on_error`](crate::LoggingBuilder::dump_on_error).
+///
+/// Must come before the file in the dispatch, so the dump lands before the error's line.
+pub(crate) struct ErrorDump {
+    pub(crate) levels: SharedLevels,
+    /// Formats records into `buffer`.
+    pub(crate) suppressed: Box<dyn Log>,
+    pub(crate) buffer: RingBuffer,
+    pub(crate) file: Closable,
+}
+
+impl Log for ErrorDump {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let enabled = self
+            .levels
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .enabled_anywhere(record.metadata());
+
+        if !enabled {
+            self.suppressed.log(record);
+        } else if record.level() == Level::Error {
+            let lines = self.buffer.take();
+            if lines.is_empty() {
+                return;
+            }
+
+            let mut file = self.file.clone();
+            let result = lines
+                .iter()
+                .try_for_each(|line| writeln!(file, "{}", line))
+                .and_then(|_| file.flush());
+            if let Err(e) = result {
+                eprintln!("Error writing suppressed log lines: {}", e);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl Log for RingBuffer {
+    fn enabled(&self, _: &Metadata) -> bool {
+        self.capacity > 0
+    }
+
+    fn log(&self, record: &Record) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let line = record.args().to_string();
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+
+        if lines.len() >= self.capacity {
+            if let Some(oldest) = lines.pop_front() {
+                self.budget.release(oldest.len());
+            }
+        }
+        while !self.budget.reserve(line.len()) {
+            self.budget.count_dropped();
+            match lines.pop_front() {
+                Some(oldest) => self.budget.release(oldest.len()),
+                None => return,
+            }
+        }
+        lines.push_back(line);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends each record to an application-owned channel without ever blocking.
+pub(crate) struct Channel {
+    tx: SyncSender<LogEvent>,
+    targets: Arc<Targets>,
+    /// Incremented for every event dropped because the channel was full.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Channel {
+    pub(crate) fn new(
+        tx: SyncSender<LogEvent>,
+        targets: Arc<Targets>,
+        dropped: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            tx,
+            targets,
+            dropped,
+        }
+    }
+}
+
+impl Log for Channel {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        match self
+            .tx
+            .try_send(LogEvent::from_record(record, &self.targets))
+        {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Hands warnings and errors to an [`error_reporter`](crate::LoggingBuilder::error_reporter)
+/// on a thread of its own, so a slow reporter never holds up logging.
+#[cfg(feature = "error-reporter")]
+pub(crate) struct ErrorReporter {
+    tx: SyncSender<LogEvent>,
+    targets: Arc<Targets>,
+    /// Incremented for every event dropped because the reporter fell behind.
+    dropped: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "error-reporter")]
+impl ErrorReporter {
+    /// Spawns the thread calling `report`, which stops once the sink is dropped. Up to
+    /// `capacity` events wait for it.
+    pub(crate) fn new(
+        report: Arc<crate::ReportFn>,
+        capacity: usize,
+        targets: Arc<Targets>,
+        dropped: Arc<AtomicU64>,
+    ) -> std::io::Result<Self> {
+        let (tx, rx) = sync_channel::<LogEvent>(capacity);
+        std::thread::Builder::new()
+            .name("logging-reporter".to_string())
+            .spawn(move || {
+                for event in rx {
+                    report(&event);
+                }
+            })?;
+
+        Ok(Self {
+            tx,
+            targets,
+            dropped,
+        })
+    }
+}
+
+#[cfg(feature = "error-reporter")]
+impl Log for ErrorReporter {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match self
+            .tx
+            .try_send(LogEvent::from_record(record, &self.targets))
+        {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends each record to every subscribed channel without ever blocking.
+///
+/// A subscriber whose channel is full misses the event, which `dropped` counts, and gets
+/// the next one that fits once it has received some. It stays subscribed until its receiver
+/// is dropped.
+#[derive(Clone)]
+pub(crate) struct Broadcast {
+    subscribers: Arc<Mutex<Vec<SyncSender<LogEvent>>>>,
+    capacity: usize,
+    targets: Arc<Targets>,
+    /// Incremented for every event a subscriber missed because its channel was full.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Broadcast {
+    pub(crate) fn new(capacity: usize, targets: Arc<Targets>, dropped: Arc<AtomicU64>) -> Self {
+        Self {
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            capacity,
+            targets,
+            dropped,
+        }
+    }
+
+    /// A new receiver for every record logged from now on, holding up to `capacity` events.
+    pub(crate) fn subscribe(&self) -> Receiver<LogEvent> {
+        let (tx, rx) = sync_channel(self.capacity);
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(tx);
+
+        rx
+    }
+}
+
+impl Log for Broadcast {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let event = LogEvent::from_record(record, &self.targets);
+        subscribers.retain(|tx| match tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Counts the errors and warnings that were logged.
+#[derive(Default)]
+pub(crate) struct Counts {
+    errors: AtomicU64,
+    warnings: AtomicU64,
+}
+
+impl Counts {
+    pub(crate) fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn warnings(&self) -> u64 {
+        self.warnings.load(Ordering::Relaxed)
+    }
+
+    fn count(&self, level: Level) {
+        match level {
+            Level::Error => self.errors.fetch_add(1, Ordering::Relaxed),
+            Level::Warn => self.warnings.fetch_add(1, Ordering::Relaxed),
+            _ => return,
+        };
+    }
+}
+
+/// Adds every record to a shared [`Counts`].
+pub(crate) struct Counter(pub(crate) Arc<Counts>);
+
+impl Log for Counter {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.count(record.level());
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            mpsc::{channel, sync_channel},
+            Arc,
+        },
+        thread,
+    };
+
+    use fern::Dispatch;
+    use log::{Level, Log, Record};
+
+    use super::{
+        Broadcast, Channel, Counts, ErrorCooldown, Ordered, RateLimited, RingBuffer, Sequenced,
+        TransformMessage,
+    };
+    use crate::{
+        intern::Targets,
+        memory::MemoryBudget,
+        test_util::{log_line, ManualClock},
+    };
+
+    #[test]
+    fn rate_limit_caps_throughput() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sink = RateLimited::new(100, true, Dispatch::new().chain(tx).into_log().1);
+        let log = |level| log_line(&sink, level, "app", "line");
+
+        let started = std::time::Instant::now();
+        while started.elapsed() < std::time::Duration::from_millis(300) {
+            log(Level::Info);
+        }
+        let passed = rx.try_iter().count();
+        // The first second's burst plus 30 lines refilled over 300ms.
+        assert!((100..=150).contains(&passed), "{}", passed);
+
+        for _ in 0..10 {
+            log(Level::Error);
+        }
+        assert_eq!(rx.try_iter().count(), 10);
+
+        std::thread::sleep(std::time::Duration::from_millis(700));
+        {
+            let mut bucket = sink.bucket.lock().unwrap();
+            bucket.tokens = 0.0;
+            bucket.refilled = std::time::Instant::now();
+        }
+        log(Level::Info);
+        log(Level::Error);
+        let lines = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("Dropped "));
+        assert!(lines[0].ends_with(" log lines over the rate limit of 100 per second\n"));
+    }
+
+    #[test]
+    fn transform_message() {
+        struct Seen(std::sync::Mutex<Vec<(String, bool)>>);
+
+        impl Log for Seen {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &Record) {
+                let message = record.args().to_string();
+                let unchanged = record.args().as_str().is_some();
+                self.0.lock().unwrap().push((message, unchanged));
+            }
+
+            fn flush(&self) {}
+        }
+
+        let seen = Arc::new(Seen(Default::default()));
+        let transform = |transform: Arc<super::MessageTransform>| TransformMessage {
+            transform,
+            inner: Box::new(seen.clone()),
+        };
+        let upper = transform(Arc::new(|message: &str| message.to_uppercase().into()));
+        let same = transform(Arc::new(|message: &str| message.into()));
+
+        let name = "db";
+        for sink in [&upper, &same] {
+            sink.log(&Record::builder().args(format_args!("static")).build());
+            sink.log(
+                &Record::builder()
+                    .args(format_args!("{} down", name))
+                    .build(),
+            );
+        }
+        assert_eq!(
+            *seen.0.lock().unwrap(),
+            [
+                ("STATIC".to_string(), false),
+                ("DB DOWN".to_string(), false),
+                ("static".to_string(), true),
+                ("db down".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_cooldown() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clock = Arc::new(ManualClock::new("2024-01-02 03:04:05"));
+        let sink = ErrorCooldown::new(
+            std::time::Duration::from_secs(60),
+            clock.clone(),
+            Dispatch::new().chain(tx).into_log().1,
+        );
+        let log = |level, message: &str| log_line(&sink, level, "app", message);
+
+        log(Level::Error, "db down");
+        for _ in 0..3 {
+            clock.advance(chrono::Duration::seconds(10));
+            log(Level::Error, "db down");
+        }
+        log(Level::Error, "disk full");
+        log(Level::Warn, "db down");
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            ["db down\n", "disk full\n", "db down\n"]
+        );
+
+        clock.advance(chrono::Duration::seconds(30));
+        log(Level::Error, "db down");
+        log(Level::Error, "db down");
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            ["db down (3 identical errors suppressed)\n"]
+        );
+
+        clock.advance(chrono::Duration::seconds(60));
+        log(Level::Error, "db down");
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            ["db down (1 identical errors suppressed)\n"]
+        );
+    }
+
+    #[test]
+    fn channel_drops_when_full() {
+        let (tx, rx) = sync_channel(2);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let sink = Channel::new(tx, Arc::new(Targets::new(true)), dropped.clone());
+
+        for i in 0..3 {
+            log_line(&sink, Level::Warn, "app", &i.to_string());
+        }
+
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.level, Level::Warn);
+        assert_eq!(&*event.target, "app");
+        assert_eq!(event.message, "0");
+        assert_eq!(rx.try_recv().unwrap().message, "1");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn broadcast_fans_out() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let sink = Broadcast::new(2, Arc::new(Targets::new(true)), dropped.clone());
+        let log = |i| {
+            sink.log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .args(format_args!("{}", i))
+                    .build(),
+            )
+        };
+
+        log(0);
+        let fast = sink.subscribe();
+        let slow = sink.subscribe();
+        let gone = sink.subscribe();
+        drop(gone);
+
+        for i in 1..4 {
+            log(i);
+            assert_eq!(fast.try_recv().unwrap().message, i.to_string());
+        }
+
+        let slow = slow.try_iter().map(|e| e.message).collect::<Vec<_>>();
+        assert_eq!(slow, ["1", "2"]);
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(sink.subscribers.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_newest() {
+        let buffer = RingBuffer::new(2, MemoryBudget::new(None));
+        for i in 0..5 {
+            buffer.log(&Record::builder().args(format_args!("{}", i)).build());
+        }
+
+        assert_eq!(buffer.lines(), ["3", "4"]);
+    }
+
+    #[test]
+    fn ring_buffer_stays_within_budget() {
+        let budget = MemoryBudget::new(Some(10));
+        let buffer = RingBuffer::new(100, budget.clone());
+        for line in ["aaaa", "bbbb", "cccc", "dd"] {
+            buffer.log(&Record::builder().args(format_args!("{}", line)).build());
+        }
+        assert_eq!(buffer.lines(), ["bbbb", "cccc", "dd"]);
+        assert_eq!((budget.used(), budget.dropped()), (10, 1));
+
+        buffer.log(
+            &Record::builder()
+                .args(format_args!("too long a line"))
+                .build(),
+        );
+        assert!(buffer.lines().is_empty());
+        assert_eq!((budget.used(), budget.dropped()), (0, 5));
+
+        buffer.log(&Record::builder().args(format_args!("ok")).build());
+        assert_eq!(buffer.take(), ["ok"]);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn ordered_sinks_agree() {
+        let (a_tx, a) = channel();
+        let (b_tx, b) = channel();
+        let (_, inner) = Dispatch::new().chain(a_tx).chain(b_tx).into_log();
+        let ordered = Arc::new(Ordered::new(inner));
+
+        let threads = (0..8)
+            .map(|t| {
+                let ordered = ordered.clone();
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        ordered.log(&Record::builder().args(format_args!("{}-{}", t, i)).build());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        drop(ordered);
+
+        let a = a.iter().collect::<Vec<String>>();
+        let b = b.iter().collect::<Vec<String>>();
+        assert_eq!(a.len(), 8 * 200);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn sequence_is_unique_and_increasing() {
+        let (tx, rx) = channel();
+        let (_, inner) = Dispatch::new()
+            .format(|out, message, _| {
+                out.finish(format_args!("{} {}", super::sequence().unwrap(), message))
+            })
+            .chain(tx)
+            .into_log();
+        let sequenced = Arc::new(Sequenced::new(inner));
+
+        let threads = (0..2)
+            .map(|t| {
+                let sequenced = sequenced.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        sequenced.log(&Record::builder().args(format_args!("{}-{}", t, i)).build());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        drop(sequenced);
+        assert_eq!(super::sequence(), None);
+
+        let mut per_thread = [vec![], vec![]];
+        let mut all = vec![];
+        for line in rx.iter() {
+            let (sequence, message) = line.split_once(' ').unwrap();
+            let sequence = sequence.parse::<u64>().unwrap();
+            per_thread[message.starts_with('1') as usize].push(sequence);
+            all.push(sequence);
+        }
+        for sequences in per_thread {
+            assert_eq!(sequences.len(), 500);
+            assert!(sequences.windows(2).all(|w| w[0] < w[1]));
+        }
+        all.sort_unstable();
+        assert_eq!(all, (1..=1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn counts_across_threads() {
+        let counts = Arc::new(Counts::default());
+
+        let threads = (0..4)
+            .map(|_| {
+                let counts = counts.clone();
+                thread::spawn(move || {
+                    for level in [Level::Error, Level::Warn, Level::Warn, Level::Info] {
+                        for _ in 0..100 {
+                            counts.count(level);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(counts.errors(), 400);
+        assert_eq!(counts.warnings(), 800);
+    }
+}
@@ -0,0 +1,271 @@
+//! Streaming lines to TCP and Unix socket endpoints.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::PathBuf};
+
+use log::{Level, Record};
+
+use crate::{memory::MemoryBudget, non_blocking::EncodeRecord};
+
+/// How long connecting to, or writing to, the endpoint may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait after a failed connection attempt before trying again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where a [`SocketWriter`] sends its lines.
+pub(crate) enum Endpoint {
+    /// The first reachable of the addresses.
+    Tcp(Vec<SocketAddr>),
+    /// The Unix domain socket at the path.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    fn connect(&self) -> Option<Box<dyn Write + Send>> {
+        match self {
+            Self::Tcp(addrs) => addrs
+                .iter()
+                .find_map(|addr| TcpStream::connect_timeout(addr, TIMEOUT).ok())
+                .filter(|stream| stream.set_write_timeout(Some(TIMEOUT)).is_ok())
+                .map(|stream| Box::new(stream) as Box<dyn Write + Send>),
+            #[cfg(unix)]
+            Self::Unix(path) => UnixStream::connect(path)
+                .ok()
+                .filter(|stream| stream.set_write_timeout(Some(TIMEOUT)).is_ok())
+                .map(|stream| Box::new(stream) as Box<dyn Write + Send>),
+        }
+    }
+}
+
+/// Writes lines to a socket endpoint, reconnecting after failures.
+///
+/// Lines are buffered until [`flush`](Write::flush). While the endpoint is unreachable they
+/// stay in a backlog of at most `capacity` lines, dropping the oldest, and are sent once a
+/// later flush reconnects. Once `budget` runs out, the oldest lines are dropped until the new
+/// one fits. The lines dropped are reported in a record encoded by `notice`, in the format of
+/// the other lines. Meant to run behind a [`NonBlocking`](crate::non_blocking::NonBlocking)
+/// sink so the timeouts never hold up logging.
+pub(crate) struct SocketWriter {
+    endpoint: Endpoint,
+    stream: Option<Box<dyn Write + Send>>,
+    /// When connecting last failed.
+    failed_at: Option<Instant>,
+
+    backlog: VecDeque<Vec<u8>>,
+    capacity: usize,
+    budget: MemoryBudget,
+    /// Lines dropped from the backlog since the last successful send.
+    lost: u64,
+    notice: Box<EncodeRecord>,
+}
+
+impl SocketWriter {
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        capacity: usize,
+        budget: MemoryBudget,
+        notice: Box<EncodeRecord>,
+    ) -> Self {
+        Self {
+            endpoint,
+            stream: None,
+            failed_at: None,
+
+            backlog: VecDeque::new(),
+            capacity,
+            budget,
+            lost: 0,
+            notice,
+        }
+    }
+
+    /// Connects to the endpoint, unless the last attempt failed recently.
+    fn connect(&mut self) {
+        if self.stream.is_none()
+            && self
+                .failed_at
+                .is_none_or(|at| at.elapsed() >= RETRY_INTERVAL)
+        {
+            self.stream = self.endpoint.connect();
+            self.failed_at = self.stream.is_none().then(Instant::now);
+        }
+    }
+
+    /// Sends the backlog, oldest line first.
+    fn send(&mut self) -> io::Result<()> {
+        self.connect();
+        let stream = if let Some(stream) = &mut self.stream {
+            stream
+        } else {
+            return Ok(());
+        };
+
+        if self.lost > 0 {
+            stream.write_all(&(self.notice)(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .args(format_args!(
+                        "Dropped {} log lines while the endpoint was unreachable",
+                        self.lost
+                    ))
+                    .build(),
+            ))?;
+            self.lost = 0;
+        }
+
+        while let Some(line) = self.backlog.front() {
+            stream.write_all(line)?;
+            if let Some(line) = self.backlog.pop_front() {
+                self.budget.release(line.len());
+            }
+        }
+
+        stream.flush()
+    }
+}
+
+impl Write for SocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.backlog.len() >= self.capacity {
+            if let Some(oldest) = self.backlog.pop_front() {
+                self.budget.release(oldest.len());
+            }
+            self.lost += 1;
+        }
+        while !self.budget.reserve(buf.len()) {
+            self.lost += 1;
+            match self.backlog.pop_front() {
+                Some(oldest) => self.budget.release(oldest.len()),
+                None => return Ok(buf.len()),
+            }
+        }
+        self.backlog.push_back(buf.to_vec());
+
+        Ok(buf.len())
+    }
+
+    /// Sends buffered lines. Connection failures are not errors; the lines are kept for the
+    /// next attempt.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.send().is_err() {
+            self.stream = None;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use log::Record;
+
+    use super::{Endpoint, SocketWriter};
+    use crate::memory::MemoryBudget;
+
+    fn notice(record: &Record) -> Vec<u8> {
+        format!("[WARN] {}\n", record.args()).into_bytes()
+    }
+
+    #[test]
+    fn backlog_is_sent_after_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut writer = SocketWriter::new(
+            Endpoint::Tcp(vec![addr]),
+            2,
+            MemoryBudget::new(None),
+            Box::new(notice),
+        );
+        for line in ["one\n", "two\n", "three\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+        assert!(writer.stream.is_none());
+        assert_eq!(writer.backlog.len(), 2);
+
+        let listener = TcpListener::bind(addr).unwrap();
+        writer.failed_at = None;
+        writer.flush().unwrap();
+        assert!(writer.backlog.is_empty());
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines = BufReader::new(stream)
+            .lines()
+            .take(3)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(lines[0].ends_with("Dropped 1 log lines while the endpoint was unreachable"));
+        assert_eq!(lines[1..], ["two", "three"]);
+    }
+
+    #[test]
+    fn backlog_stays_within_budget() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let budget = MemoryBudget::new(Some(10));
+        let mut writer = SocketWriter::new(
+            Endpoint::Tcp(vec![addr]),
+            8,
+            budget.clone(),
+            Box::new(notice),
+        );
+        for line in ["one\n", "two\n", "three\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(writer.backlog, [b"two\n".to_vec(), b"three\n".to_vec()]);
+        assert_eq!((writer.lost, budget.used()), (1, 10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_appears_later() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = crate::test_util::temp_dir("unix-socket-later");
+        let path = dir.join("collector.sock");
+
+        let mut writer = SocketWriter::new(
+            Endpoint::Unix(path.clone()),
+            8,
+            MemoryBudget::new(None),
+            Box::new(notice),
+        );
+        writer.write_all(b"early\n").unwrap();
+        writer.flush().unwrap();
+        assert!(writer.stream.is_none());
+        assert_eq!(writer.backlog.len(), 1);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        writer.failed_at = None;
+        writer.write_all(b"late\n").unwrap();
+        writer.flush().unwrap();
+        assert!(writer.backlog.is_empty());
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines = BufReader::new(stream)
+            .lines()
+            .take(2)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lines, ["early", "late"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Copying everything the process writes to stdout into the log file, for
+//! [`LoggingBuilder::capture_stdout`](crate::LoggingBuilder::capture_stdout).
+
+#[cfg(unix)]
+pub(crate) use unix::StdoutTee;
+
+#[cfg(not(unix))]
+pub(crate) struct StdoutTee;
+
+#[cfg(not(unix))]
+impl StdoutTee {
+    pub(crate) fn start(
+        _: impl std::io::Write + Send + 'static,
+    ) -> std::io::Result<(Self, std::fs::File)> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "capture_stdout is only supported on Unix",
+        ))
+    }
+
+    pub(crate) fn stop(&mut self) {}
+
+    pub(crate) fn resume(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::File,
+        io::{self, Read, Write},
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        thread::{self, JoinHandle},
+    };
+
+    type Sink = Box<dyn Write + Send>;
+
+    /// Keeps stdout redirected into a pipe whose contents a thread copies to the original
+    /// stdout and to the log file.
+    pub(crate) struct StdoutTee {
+        /// The original stdout, put back by [`stop`](Self::stop).
+        original: Option<OwnedFd>,
+        /// Hands the log file back once the copying finishes.
+        thread: Option<JoinHandle<Sink>>,
+        /// The log file while stopped, for [`resume`](Self::resume).
+        file: Option<Sink>,
+    }
+
+    impl StdoutTee {
+        /// Redirects stdout, copying everything written to it to `file` a line at a time.
+        /// Returns the original stdout, for output that must not be copied.
+        pub(crate) fn start(file: impl Write + Send + 'static) -> io::Result<(Self, File)> {
+            let mut tee = Self {
+                original: None,
+                thread: None,
+                file: Some(Box::new(file)),
+            };
+            let terminal = tee.redirect()?;
+
+            Ok((tee, terminal))
+        }
+
+        /// Starts copying to the same log file again after [`stop`](Self::stop).
+        pub(crate) fn resume(&mut self) -> io::Result<()> {
+            if self.original.is_some() {
+                return Ok(());
+            }
+
+            self.redirect().map(drop)
+        }
+
+        fn redirect(&mut self) -> io::Result<File> {
+            io::stdout().flush()?;
+
+            let original = dup(libc::STDOUT_FILENO)?;
+            let terminal = File::from(dup(original.as_raw_fd())?);
+            let (read, write) = pipe()?;
+            let copy = terminal.try_clone()?;
+            let file = self
+                .file
+                .take()
+                .ok_or_else(|| io::Error::other("The stdout copying thread panicked"))?;
+            // SAFETY: Both descriptors are open; the pipe becomes stdout.
+            if unsafe { libc::dup2(write.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+                self.file = Some(file);
+                return Err(io::Error::last_os_error());
+            }
+            drop(write);
+
+            let thread = thread::Builder::new()
+                .name("logging-stdout".to_string())
+                .spawn(move || tee(File::from(read), copy, file));
+            self.original = Some(original);
+            self.thread = Some(thread?);
+
+            Ok(terminal)
+        }
+
+        /// Puts the original stdout back and waits for the copying to finish.
+        pub(crate) fn stop(&mut self) {
+            let _ = io::stdout().flush();
+
+            if let Some(original) = self.original.take() {
+                // SAFETY: Both descriptors are open. Replacing stdout closes the last write
+                // end of the pipe, which ends the thread.
+                unsafe { libc::dup2(original.as_raw_fd(), libc::STDOUT_FILENO) };
+            }
+            if let Some(thread) = self.thread.take() {
+                self.file = thread.join().ok();
+            }
+        }
+    }
+
+    impl Drop for StdoutTee {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    /// Copies `pipe` to `terminal` as it arrives and to `file` in whole lines, so they do
+    /// not interleave with log records.
+    fn tee(mut pipe: File, mut terminal: File, mut file: Sink) -> Sink {
+        let mut buf = [0; 4096];
+        let mut line = vec![];
+
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+
+            let _ = terminal.write_all(&buf[..n]);
+            line.extend_from_slice(&buf[..n]);
+            if let Some(end) = line.iter().rposition(|b| *b == b'\n') {
+                let _ = file.write_all(&line[..=end]).and_then(|_| file.flush());
+                line.drain(..=end);
+            }
+        }
+
+        if !line.is_empty() {
+            let _ = file.write_all(&line).and_then(|_| file.flush());
+        }
+
+        file
+    }
+
+    fn dup(fd: i32) -> io::Result<OwnedFd> {
+        // SAFETY: `dup` does not touch memory; a valid result is a new descriptor we own.
+        match unsafe { libc::dup(fd) } {
+            -1 => Err(io::Error::last_os_error()),
+            fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        }
+    }
+
+    fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two descriptors, which we own on success.
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+}
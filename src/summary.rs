@@ -0,0 +1,49 @@
+use std::{sync::Arc, time::Duration, time::Instant};
+
+use crate::sinks::Counts;
+
+/// The line logged by [`LoggingHandle`](crate::LoggingHandle) when it is dropped, for
+/// [`LoggingBuilder::shutdown_summary`](crate::LoggingBuilder::shutdown_summary).
+pub(crate) struct Summary {
+    pub(crate) app_name: String,
+    pub(crate) started: Instant,
+    pub(crate) counts: Arc<Counts>,
+}
+
+impl Summary {
+    pub(crate) fn line(&self) -> String {
+        format!(
+            "=== {} stopped after {}, {} errors, {} warnings ===",
+            self.app_name,
+            uptime(self.started.elapsed()),
+            self.counts.errors(),
+            self.counts.warnings()
+        )
+    }
+}
+
+/// Renders `elapsed` like `1h 2m 3s`, `2m 3s` or `3.4s`.
+fn uptime(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+
+    match (secs / 3600, secs / 60 % 60) {
+        (0, 0) => format!("{:.1}s", elapsed.as_secs_f64()),
+        (0, minutes) => format!("{}m {}s", minutes, secs % 60),
+        (hours, minutes) => format!("{}h {}m {}s", hours, minutes, secs % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::uptime;
+
+    #[test]
+    fn uptime_units() {
+        assert_eq!(uptime(Duration::from_millis(3400)), "3.4s");
+        assert_eq!(uptime(Duration::from_secs(123)), "2m 3s");
+        assert_eq!(uptime(Duration::from_secs(3723)), "1h 2m 3s");
+        assert_eq!(uptime(Duration::from_secs(7200)), "2h 0m 0s");
+    }
+}
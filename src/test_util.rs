@@ -0,0 +1,95 @@
+//! Helpers shared by the unit tests.
+
+use std::{
+    fs::{create_dir_all, remove_dir_all, File},
+    path::{Path, PathBuf},
+    sync::{mpsc::channel, Mutex},
+};
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, TimeZone};
+use fern::Dispatch;
+use log::{Level, Log, Record};
+
+use crate::{clock::Clock, LoggingBuilder, CHRONO_FORMAT};
+
+/// Creates an empty, test-specific directory under the system temp dir.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("logging-test-{}-{}", name, std::process::id()));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// A builder for the `app` application, logging to `dir`.
+pub(crate) fn builder(dir: impl Into<PathBuf>) -> LoggingBuilder {
+    LoggingBuilder::new()
+        .app_name("app")
+        .qualifier("com")
+        .organization("org")
+        .log_dir(dir)
+}
+
+/// Logs `message` through `logger`.
+pub(crate) fn log_line(logger: &dyn Log, level: Level, target: &str, message: &str) {
+    logger.log(
+        &Record::builder()
+            .level(level)
+            .target(target)
+            .args(format_args!("{}", message))
+            .build(),
+    );
+}
+
+/// Creates an empty log file named after the current time offset by `offset_secs`.
+pub(crate) fn create_log(dir: &Path, offset_secs: i64) -> PathBuf {
+    let name = Local::now()
+        .naive_local()
+        .checked_add_signed(Duration::seconds(offset_secs))
+        .unwrap()
+        .format(CHRONO_FORMAT);
+    let path = dir.join(format!("{}.log", name));
+    File::create(&path).unwrap();
+
+    path
+}
+
+/// Logs a single record through `dispatch` and returns the formatted line.
+pub(crate) fn format_line(dispatch: Dispatch, level: Level, target: &str, message: &str) -> String {
+    let (tx, rx) = channel();
+    let (_, log) = dispatch.chain(tx).into_log();
+    log_line(&log, level, target, message);
+
+    rx.recv().unwrap()
+}
+
+/// A clock that only moves when told to.
+pub(crate) struct ManualClock(Mutex<DateTime<FixedOffset>>);
+
+impl ManualClock {
+    /// A clock reading `time`, in `%Y-%m-%d %H:%M:%S` local time.
+    pub(crate) fn new(time: &str) -> Self {
+        let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap();
+        let time = Local.from_local_datetime(&time).unwrap();
+
+        Self(Mutex::new(time.with_timezone(time.offset())))
+    }
+
+    /// A clock reading `time`, in `%Y-%m-%d %H:%M:%S` UTC.
+    pub(crate) fn utc(time: &str) -> Self {
+        let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap();
+        let utc = FixedOffset::east_opt(0).unwrap();
+
+        Self(Mutex::new(utc.from_utc_datetime(&time)))
+    }
+
+    pub(crate) fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        *self.0.lock().unwrap()
+    }
+}
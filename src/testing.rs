@@ -0,0 +1,229 @@
+//! Asserting on what application code logs, for tests. Enabled by the `testing` feature.
+
+use std::{cell::RefCell, fmt::Write, rc::Rc, sync::OnceLock};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{intern::Targets, LogEvent};
+
+/// The global logger passing records to the [`LogCapture`]s of the logging thread.
+static ROUTER: Router = Router;
+/// The targets of the captured events, shared by all captures.
+static TARGETS: Targets = Targets::new(true);
+/// Whether [`ROUTER`] could be installed as the global logger.
+static INSTALLED: OnceLock<bool> = OnceLock::new();
+
+thread_local! {
+    /// The live captures of this thread, the newest last.
+    static ACTIVE: RefCell<Vec<Rc<RefCell<Vec<LogEvent>>>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Captures the records logged on the current thread while it is alive, for asserting on
+/// them in tests.
+///
+/// The first capture installs a global logger that only keeps records for the captures of
+/// the thread that logged them, so tests running in parallel do not see each other's
+/// records, and records from threads without a capture are discarded. Nothing is written to
+/// the terminal or the file system, and no [`LoggingBuilder`](crate::LoggingBuilder) is
+/// needed. As the global logger can only be set once, logging cannot also be set up with
+/// [`finish`](crate::LoggingBuilder::finish) in the same process.
+///
+/// While captures are nested, the newest gets the records.
+///
+/// ```
+/// use log::Level;
+/// use logging::LogCapture;
+///
+/// let capture = LogCapture::start();
+/// log::error!("failed to connect to db-1");
+///
+/// capture.assert_logged(Level::Error, "failed to connect");
+/// capture.assert_not_logged(Level::Warn, "retrying");
+/// ```
+pub struct LogCapture {
+    events: Rc<RefCell<Vec<LogEvent>>>,
+}
+
+impl LogCapture {
+    /// Starts capturing the records logged on this thread, at every level.
+    ///
+    /// # Panics
+    ///
+    /// If another global logger is already installed.
+    #[track_caller]
+    pub fn start() -> Self {
+        let installed = *INSTALLED.get_or_init(|| {
+            let installed = log::set_logger(&ROUTER).is_ok();
+            if installed {
+                log::set_max_level(LevelFilter::Trace);
+            }
+
+            installed
+        });
+        assert!(
+            installed,
+            "LogCapture needs to be the global logger, but another logger is already installed"
+        );
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        ACTIVE.with(|active| active.borrow_mut().push(events.clone()));
+
+        Self { events }
+    }
+
+    /// The records captured so far, oldest first.
+    pub fn events(&self) -> Vec<LogEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// The records captured so far as `[LEVEL] target - message` lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.events.borrow().iter().map(line).collect()
+    }
+
+    /// Forgets the records captured so far.
+    pub fn clear(&self) {
+        self.events.borrow_mut().clear();
+    }
+
+    /// Panics, listing the captured records, unless a record at `level` whose message
+    /// contains `text` was logged.
+    #[track_caller]
+    pub fn assert_logged(&self, level: Level, text: &str) {
+        if !self.contains(level, text) {
+            panic!(
+                "Expected a {} record containing {:?}, captured:\n{}",
+                level,
+                text,
+                self.listing()
+            );
+        }
+    }
+
+    /// Panics, listing the captured records, if a record at `level` whose message contains
+    /// `text` was logged.
+    #[track_caller]
+    pub fn assert_not_logged(&self, level: Level, text: &str) {
+        if self.contains(level, text) {
+            panic!(
+                "Expected no {} record containing {:?}, captured:\n{}",
+                level,
+                text,
+                self.listing()
+            );
+        }
+    }
+
+    fn contains(&self, level: Level, text: &str) -> bool {
+        self.events
+            .borrow()
+            .iter()
+            .any(|event| event.level == level && event.message.contains(text))
+    }
+
+    fn listing(&self) -> String {
+        let events = self.events.borrow();
+        if events.is_empty() {
+            return "  (nothing)".to_string();
+        }
+
+        let mut listing = String::new();
+        for event in events.iter() {
+            let _ = write!(listing, "\n  {}", line(event));
+        }
+
+        listing.split_off(1)
+    }
+}
+
+impl Drop for LogCapture {
+    fn drop(&mut self) {
+        let _ = ACTIVE.try_with(|active| {
+            active
+                .borrow_mut()
+                .retain(|events| !Rc::ptr_eq(events, &self.events))
+        });
+    }
+}
+
+fn line(event: &LogEvent) -> String {
+    format!("[{}] {} - {}", event.level, event.target, event.message)
+}
+
+struct Router;
+
+impl Log for Router {
+    fn enabled(&self, _: &Metadata) -> bool {
+        ACTIVE
+            .try_with(|active| !active.borrow().is_empty())
+            .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Formatted before borrowing, in case formatting the message logs too.
+        let event = LogEvent::from_record(record, &TARGETS);
+        let _ = ACTIVE.try_with(|active| {
+            if let Some(events) = active.borrow().last() {
+                events.borrow_mut().push(event);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        thread,
+    };
+
+    use log::Level;
+
+    use super::LogCapture;
+
+    #[test]
+    fn asserts_on_this_thread() {
+        let capture = LogCapture::start();
+        log::error!(target: "app::db", "failed to connect to db-1");
+        log::info!("connected");
+        thread::spawn(|| log::warn!("elsewhere")).join().unwrap();
+
+        capture.assert_logged(Level::Error, "failed to connect");
+        capture.assert_not_logged(Level::Warn, "elsewhere");
+        assert_eq!(
+            capture.lines()[0],
+            "[ERROR] app::db - failed to connect to db-1"
+        );
+        assert_eq!(capture.events().len(), 2);
+
+        {
+            let inner = LogCapture::start();
+            log::debug!("nested");
+            inner.assert_logged(Level::Debug, "nested");
+        }
+        capture.assert_not_logged(Level::Debug, "nested");
+
+        let panic = catch_unwind(AssertUnwindSafe(|| {
+            capture.assert_logged(Level::Error, "timed out")
+        }))
+        .unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(
+            message.starts_with("Expected a ERROR record containing \"timed out\", captured:"),
+            "{}",
+            message
+        );
+        assert!(message.ends_with("\n  [INFO] logging::testing::tests - connected"));
+
+        capture.clear();
+        capture.assert_not_logged(Level::Error, "failed to connect");
+        drop(capture);
+        log::error!("unseen");
+    }
+}
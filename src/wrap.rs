@@ -0,0 +1,117 @@
+//! Wrapping terminal lines for
+//! [`LoggingBuilder::terminal_width`](crate::LoggingBuilder::terminal_width).
+
+use std::io::IsTerminal;
+
+/// The width to wrap terminal lines at: `width`, or the terminal's width if `None`. Lines
+/// are not wrapped when stdout is not a terminal or its width is unknown.
+pub(crate) fn resolve_width(width: Option<usize>) -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+
+    width.or_else(detect_width).filter(|&width| width > 0)
+}
+
+/// The terminal's width in columns, from `COLUMNS` or by asking the terminal.
+fn detect_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(columns);
+    }
+
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return Some(size.ws_col.into());
+        }
+    }
+
+    None
+}
+
+/// Breaks each line of `text` so none is longer than `width` characters, at the last space
+/// where possible. ANSI escape sequences take up no width.
+pub(crate) fn wrap(text: &str, width: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        wrap_line(&mut out, line, width);
+    }
+
+    out
+}
+
+fn wrap_line(out: &mut String, line: &str, width: usize) {
+    let mut column = 0;
+    // Where the last space of the current line is in `out`, and the column after it.
+    let mut space: Option<(usize, usize)> = None;
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        if column == width {
+            if c == ' ' {
+                out.push('\n');
+                column = 0;
+                space = None;
+                continue;
+            }
+            match space.take() {
+                Some((at, after)) => {
+                    out.replace_range(at..at + 1, "\n");
+                    column -= after;
+                }
+                None => {
+                    out.push('\n');
+                    column = 0;
+                }
+            }
+        }
+
+        if c == ' ' {
+            space = Some((out.len(), column + 1));
+        }
+        out.push(c);
+        column += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wrap;
+
+    #[test]
+    fn wraps_at_width() {
+        assert_eq!(wrap("[INFO] app - hello", 40), "[INFO] app - hello");
+        assert_eq!(
+            wrap("[INFO] app - hello world", 12),
+            "[INFO] app -\nhello world"
+        );
+        assert_eq!(
+            wrap("[INFO] app - hello world", 10),
+            "[INFO] app\n- hello\nworld"
+        );
+        assert_eq!(wrap("abcdefghij", 4), "abcd\nefgh\nij");
+        assert_eq!(wrap("one two\nthree four", 7), "one two\nthree\nfour");
+        assert_eq!(
+            wrap("\x1b[31m[ERROR]\x1b[0m app - boom", 13),
+            "\x1b[31m[ERROR]\x1b[0m app -\nboom"
+        );
+    }
+}